@@ -0,0 +1,12 @@
+use arboard::Clipboard;
+
+/// Puts `text` on the system clipboard. On Linux the text stays available after todoln exits
+/// only if a clipboard manager is running to take it over.
+pub fn copy(text: &str) -> Result<(), arboard::Error> {
+    Clipboard::new()?.set_text(text)
+}
+
+/// The text on the system clipboard
+pub fn paste() -> Result<String, arboard::Error> {
+    Clipboard::new()?.get_text()
+}
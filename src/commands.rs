@@ -1,31 +1,184 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
-use std::path::PathBuf;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::io::{self, IsTerminal, Write};
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use clap::{Parser, Subcommand};
+use chrono::{DateTime, Local, NaiveDate, TimeDelta, Utc};
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_mangen::roff::{bold, roman, Roff};
+use clap_mangen::Man;
+use crossterm::cursor::MoveTo;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::style::Print;
+use crossterm::terminal::{self, Clear, ClearType};
+use notify::{PollWatcher, RecursiveMode, Watcher};
+use rand::seq::SliceRandom;
+use regex::RegexBuilder;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use unicode_width::UnicodeWidthStr;
 
 use crate::database::{
+    self,
+    active_session,
+    SessionStart,
+    open_connection,
     establish_connection,
+    establish_read_connection,
+    task_indices_are_contiguous,
     get_tasks_length,
+    get_sorted_tasks_from_db,
+    get_tasks_to_remove_from_db,
+    get_backup_task_count,
+    get_backup_tasks,
+    append_tasks_to_db,
     add_tasks_to_db,
     insert_tasks_to_db,
+    duplicate_task_in_db,
     edit_task_in_db,
+    edit_tasks_in_db,
+    rename_tasks_in_db,
+    apply_edit_all_in_db,
+    get_tasks_from_db,
+    get_task_from_db,
     get_tasks_from_db_and_update_indices,
-    mark_task_in_db_as_done,
+    renormalize_task_indices,
+    mark_tasks_in_db_as_done,
+    mark_tasks_in_db_as_done_by_ids,
     find_tasks_from_db,
+    find_tasks_in_fields_from_db,
+    TaskSubset,
     sort_tasks_in_db,
-    remove_task_from_db,
+    reverse_tasks_in_db,
+    shuffle_tasks_in_db,
+    remove_tasks_from_db,
     delete_tasks_from_db,
     backup_db,
+    is_encrypted,
+    rekey_db,
     restore_db,
+    apply_sync_changes_in_db,
+    get_sync_state_from_db,
+    set_sync_state_in_db,
+    apply_review_in_db,
+    record_usage_in_db,
+    get_usage_from_db,
+    clear_usage_in_db,
+    archive_done_tasks_in_db,
+    get_archived_tasks_from_db,
+    get_completion_log_from_db,
+    get_completions_per_day_from_db,
+    remove_tasks_by_id_from_db,
+    check_db,
+    repair_db,
+    DbProblems,
+    undo_sort_in_db,
+    undo_last_in_db,
+    get_history_from_db,
+    get_trash_from_db,
+    get_subtasks_from_db,
+    get_subtask_progress_from_db,
+    task_indices_are_in_order,
+    set_task_note_in_db,
+    add_dependency_in_db,
+    remove_dependency_in_db,
+    get_open_blockers_from_db,
+    set_task_fields_in_db,
+    get_task_fields_from_db,
+    get_task_by_id,
+    priority_rank,
+    import_tasks_in_db,
+    restore_task_from_trash_in_db,
+    empty_trash_in_db,
+    trash_retention_days,
+    SortUndo,
+    SearchMode,
+    get_tasks_completed_today_from_db,
+    wrapup_tasks_in_db,
+    snapshot_db,
+    snapshots_dir,
+    open_connection_for_quick_read,
+    set_task_due_in_db,
+    set_task_snooze_in_db,
+    get_tasks_due_between,
+    get_tasks_to_notify_from_db,
+    mark_tasks_notified_in_db,
+    get_running_timer_from_db,
+    start_timer_in_db,
+    stop_timer_in_db,
+    get_tracked_time_from_db,
+    get_next_due_from_db,
+    is_locked_error,
+    log_session_in_db,
+    get_sessions_from_db,
+    db_path,
+    context_name,
+    validate_file_path,
+    unlock_db,
+    get_db_stats,
+    vacuum_db,
 };
 
+use crate::clipboard;
+use crate::config::{comment_delimiter, config, config_path, expand_alias, StatusGlyphs, WrapupDone};
+use crate::i18n::tr;
+use crate::editor::{edit_line, is_interactive, pick, AlternateScreenGuard, PickerItem, RawModeGuard};
+use crate::tui;
+
 use crate::utils::{
+    styled,
+    Style,
     print_success,
     print_error,
+    print_warning,
+    ask,
+    ask_secret,
+    clean_name,
+    normalize_name,
+    sanitize,
+    escape_text,
+    parse_timestamp,
+    parse_period,
+    local_midnight,
+    format_duration,
+    format_age,
+    in_period,
+    Period,
+    format_local_timestamp,
     print_title,
     bold_text,
     todo_text,
     done_text,
+    highlight_matches,
+    plain_output,
+    confirm,
+    parse_due,
+    parse_wake,
+    is_snoozed,
+    parse_duration,
+    parse_recurrence,
+    progress_bar,
+    format_clock,
+    title_text,
+    dim_text,
+    wrap_text,
+    truncate_text,
+    empty_text,
+    is_quiet,
+    json_output,
+    print_notice,
+    split_comment,
+    join_comment,
 };
 
 const ABOUT_TEXT: &str = "
@@ -41,10 +194,45 @@ const ABOUT_TEXT: &str = "
 #[derive(Parser)] 
 #[command(author = "Brooklyn Baylis", version = "1.1.1", long_about = ABOUT_TEXT)]
 pub struct Cli {
+    /// Uses the task database at this path instead of the default one
+    #[arg(long, global = true, value_name = "PATH", env = "TODOLN_DB")]
+    pub db: Option<PathBuf>,
+
+    /// Never writes to the database in commands that only read tasks, not even to repair indices
+    #[arg(long, global = true)]
+    pub stable_read: bool,
+
+    /// Runs destructive commands against a context listed in `confirm_context` without asking
+    #[arg(long = "i-know-what-im-doing", global = true)]
+    pub force_context: bool,
+
+    /// Prints only what was asked for and errors: no success messages, titles or "No tasks found."
+    #[arg(long, short, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Also prints the database used, each SQL statement and how long the command took, to stderr
+    #[arg(long, short, global = true)]
+    pub verbose: bool,
+
+    /// Prints no colours or other styling, e.g. to paste a `list --long` table somewhere else
+    #[arg(long, global = true)]
+    pub no_color: bool,
+
+    /// How commands that change tasks report what they did: messages, or one JSON object on stdout
+    #[arg(long, global = true, value_enum, value_name = "FORMAT", default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
+/// What `--format` makes commands print
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Adds new tasks
@@ -53,42 +241,198 @@ pub enum Commands {
         /// The task(s) to add
         #[arg(value_name = "task_names", use_value_delimiter = true,)]
         task_names: Vec<String>,
+
+        /// Adds tasks even if a task with the same name apart from case and spacing exists
+        #[arg(long)]
+        force: bool,
+
+        /// Also adds each non-empty line of the clipboard as a task
+        #[arg(long)]
+        from_clipboard: bool,
+
+        /// Adds the tasks as subtasks of this task
+        #[arg(long, value_name = "task_index")]
+        under: Option<i32>,
+
+        /// Repeats the tasks: every few days or weeks (3d, 2w) or on weekdays (mon, "mon,thu")
+        #[arg(long, value_name = "rule", value_parser = parse_every)]
+        every: Option<String>,
     },
     /// Adds new tasks at a given index
     #[command(name = "insert", visible_aliases = &["ins", "i"], arg_required_else_help = true)]
     Insert {
-        /// The index to insert at
-        #[arg(value_name = "index")]
-        index: i32,
+        /// The index to insert at: 0 or 1 is the top and -1 before the last task; leave it out to add at the end.
+        /// A number given first is always taken as the index, so put names that are numbers after `--`
+        #[arg(value_name = "index", allow_negative_numbers = true)]
+        index: Option<String>,
 
         /// The task(s) to add
         #[arg(value_name = "task_names", use_value_delimiter = true,)]
         task_names: Vec<String>,
+
+        /// Task(s) to add given after `--`, which are never taken for an index: `insert -- 42` adds "42" at the end
+        #[arg(value_name = "task_names", use_value_delimiter = true, last = true)]
+        literal_names: Vec<String>,
+
+        /// Adds tasks even if a task with the same name apart from case and spacing exists
+        #[arg(long)]
+        force: bool,
     },
     /// Changes the name of a task
     #[command(name = "modify", visible_aliases = &["m", "edit"], arg_required_else_help = true)]
     Modify {
         /// The task to modify
+        #[arg(value_name = "task_index", required_unless_present = "renames")]
+        task_index: Option<TaskIndex>,
+
+        /// The new name for the task; in a terminal, leave it out to edit the current name instead
+        #[arg(value_name = "new_name", required_unless_present_any = ["edit_inline", "renames"])]
+        new_name: Option<String>,
+
+        /// Edits the current name in place instead of retyping it, the same as leaving out the new name
+        #[arg(long, conflicts_with = "new_name")]
+        edit_inline: bool,
+
+        /// Renames several tasks at once, e.g. `--set 2="new a" --set 5="new b"`; nothing is renamed if any index is wrong
+        #[arg(long = "set", value_name = "index=name", value_parser = parse_rename, conflicts_with_all = ["task_index", "new_name", "edit_inline"])]
+        renames: Vec<(TaskIndex, String)>,
+
+        /// Keeps the new name even if another task has the same one apart from case and spacing
+        #[arg(long)]
+        force: bool,
+
+        /// Reads the numbers given as task ids, which stay the same when tasks are added, removed or sorted, instead of indices
+        #[arg(long = "id", conflicts_with = "renames")]
+        by_id: bool,
+    },
+    /// Adds text to the end of a task's name
+    #[command(name = "append", visible_aliases = &["app"])]
+    Append {
+        /// The task to change
+        #[arg(value_name = "task_index")]
+        task_index: TaskIndex,
+
+        /// What to add, including any space to separate it
+        #[arg(value_name = "text", allow_hyphen_values = true)]
+        text: String,
+
+        /// Keeps the new name even if another task has the same one apart from case and spacing
+        #[arg(long)]
+        force: bool,
+    },
+    /// Adds text to the start of a task's name
+    #[command(name = "prepend", visible_aliases = &["pre"])]
+    Prepend {
+        /// The task to change
+        #[arg(value_name = "task_index")]
+        task_index: TaskIndex,
+
+        /// What to add, including any space to separate it
+        #[arg(value_name = "text", allow_hyphen_values = true)]
+        text: String,
+
+        /// Keeps the new name even if another task has the same one apart from case and spacing
+        #[arg(long)]
+        force: bool,
+    },
+    /// Adds a copy of a task right after it, not done but with its due date, note and fields
+    #[command(name = "duplicate", visible_aliases = &["dup"], arg_required_else_help = true)]
+    Duplicate {
+        /// The task to copy
         #[arg(value_name = "task_index")]
-        task_index: i32,
+        task_index: TaskIndex,
 
-        /// The new name for the task
-        #[arg(value_name = "new_name")]
-        new_name: String,
+        /// How many copies to add
+        #[arg(long, value_name = "count", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+        times: u16,
     },
+    /// Opens every task in $VISUAL or $EDITOR to reorder, rename, add, remove or tick off
+    #[command(name = "edit-all", visible_aliases = &["bulk-edit"])]
+    EditAll,
     /// Lists tasks
     #[command(name = "list", visible_aliases = &["ls", "l"], arg_required_else_help = true)]
     List {
-        /// The type of tasks to display (All, Todo, Done)
+        /// The type of tasks to display (All, Todo, Done, Snoozed)
         #[arg(value_name = "display_type")]
         display_type: String,
+
+        /// Keeps the list on screen and redraws it whenever the tasks change
+        #[arg(long)]
+        watch: bool,
+
+        /// Shows the comments after `comment_delimiter` in task names, dimmed
+        #[arg(long)]
+        comments: bool,
+
+        /// Also lists snoozed tasks, with when they wake up
+        #[arg(long)]
+        snoozed: bool,
+
+        /// Only lists tasks whose custom field has this value (can be given more than once)
+        #[arg(long = "where", value_name = "key=value", value_parser = parse_field)]
+        field_filters: Vec<(String, String)>,
+
+        /// Only lists tasks completed in this month (2024-06), ISO week (2024-W26), day or range of them
+        #[arg(long, value_name = "period", value_parser = parse_completed_in)]
+        completed_in: Option<Period>,
+
+        /// Shows at most this many tasks
+        #[arg(long, value_name = "count")]
+        limit: Option<usize>,
+
+        /// Skips this many tasks first
+        #[arg(long, value_name = "count", default_value_t = 0)]
+        offset: usize,
+
+        /// Prints the whole list even when it's longer than the terminal, instead of opening $PAGER
+        #[arg(long)]
+        no_pager: bool,
+
+        /// Wraps names too long for the terminal onto more lines instead of cutting them short
+        #[arg(long)]
+        wrap: bool,
+
+        /// Lists the tasks as a table with their status, priority, due date and age
+        #[arg(long, conflicts_with = "wrap")]
+        long: bool,
+
+        /// Lists the tasks under a heading for each tag (the comma-separated `tags` field), status or priority
+        #[arg(long, value_name = "group", value_enum)]
+        group_by: Option<GroupBy>,
     },
     /// Prints tasks as plain text
-    #[command(name = "raw", visible_aliases = &["r", "show"], arg_required_else_help = true)]
+    #[command(name = "raw", visible_aliases = &["r"], arg_required_else_help = true)]
     Raw {
-        /// The type of tasks to display (All, Todo, Done)
+        /// The type of tasks to display (All, Todo, Done, Snoozed)
         #[arg(value_name = "display_type")]
         display_type: String,
+
+        /// Also copies the tasks to the clipboard
+        #[arg(long)]
+        copy: bool,
+
+        /// How to print each task, e.g. "{idx}\t{done:x| }\t{name}", from {idx}, {name}, {indent}, {done}, {due}, {note}, {created}, {completed}, {priority}, {field:key} and text
+        #[arg(long, value_name = "template", default_value = DEFAULT_RAW_TEMPLATE, value_parser = parse_template)]
+        template: RawTemplate,
+    },
+    /// Shows the details of a task
+    #[command(name = "show", visible_aliases = &["view", "details"], arg_required_else_help = true)]
+    Show {
+        /// The task to show
+        #[arg(value_name = "task_index")]
+        task_index: TaskIndex,
+
+        /// Prints the task as a JSON object
+        #[arg(long)]
+        json: bool,
+
+        /// Shows the name exactly as stored, with control characters escaped
+        #[arg(long)]
+        escaped: bool,
+
+        /// Reads the numbers given as task ids, which stay the same when tasks are added, removed or sorted, instead of indices
+        #[arg(long = "id")]
+        by_id: bool,
     },
     /// Lists tasks based on the search term
     #[command(name = "find", visible_aliases = &["f", "search"], arg_required_else_help = true)]
@@ -96,353 +440,6027 @@ pub enum Commands {
         /// The term to search for
         #[arg(value_name = "search_term")]
         search_term: String,
+
+        /// Prints each match as a tab-separated `idx\tdone\tname\tid` line for scripts
+        #[arg(long)]
+        porcelain: bool,
+
+        /// Treats the search term as a regular expression
+        #[arg(long)]
+        regex: bool,
+
+        /// Matches upper and lower case exactly
+        #[arg(long)]
+        case_sensitive: bool,
+
+        /// Also copies the names of the matching tasks to the clipboard
+        #[arg(long)]
+        copy: bool,
+
+        /// Searches only the tasks still todo, only the done ones or all of them
+        #[arg(long = "in", value_name = "tasks", value_parser = ["all", "todo", "done"], default_value = "all")]
+        subset: String,
+
+        /// Shows at most this many tasks
+        #[arg(long, value_name = "count")]
+        limit: Option<usize>,
+
+        /// Skips this many tasks first
+        #[arg(long, value_name = "count", default_value_t = 0)]
+        offset: usize,
+
+        /// Prints the whole list even when it's longer than the terminal, instead of opening $PAGER
+        #[arg(long)]
+        no_pager: bool,
+
+        /// Also searches task notes and/or tags, e.g. `--include notes,tags`
+        #[arg(long, value_name = "fields", value_delimiter = ',')]
+        include: Vec<SearchField>,
+
+        /// Searches only these instead of task names, e.g. `--only notes`
+        #[arg(long, value_name = "fields", value_delimiter = ',', conflicts_with = "include")]
+        only: Vec<SearchField>,
+    },
+    /// Replaces text in the names of every task containing it
+    #[command(name = "rename-all", visible_aliases = &["sub"])]
+    RenameAll {
+        /// The text to replace
+        #[arg(value_name = "pattern")]
+        pattern: String,
+
+        /// What to replace it with; with --regex, $1 or ${name} insert a capture group
+        #[arg(value_name = "replacement")]
+        replacement: String,
+
+        /// Treats the pattern as a regular expression
+        #[arg(long)]
+        regex: bool,
+
+        /// Matches upper and lower case exactly
+        #[arg(long)]
+        case_sensitive: bool,
+
+        /// Renames the tasks without asking first
+        #[arg(short, long)]
+        yes: bool,
     },
     /// Marks task as done
     #[command(name = "done", visible_aliases = &["dn", "complete"], arg_required_else_help = true)]
     Done {
         /// The task(s) to mark as done
-        #[arg(value_name = "task_indices", use_value_delimiter = true)]
-        task_indices: Vec<i32>,
+        #[arg(value_name = "task_indices", use_value_delimiter = true, required_unless_present_any = ["name", "all_todo"])]
+        task_indices: Vec<TaskIndex>,
+
+        /// Marks the task whose name matches instead of giving indices
+        #[arg(short, long, visible_alias = "match", conflicts_with = "task_indices")]
+        name: Option<String>,
+
+        /// Acts on every task matching --name when there is more than one
+        #[arg(long, requires = "name")]
+        all: bool,
+
+        /// Also marks the tasks' open subtasks as done
+        #[arg(long)]
+        cascade: bool,
+
+        /// Marks tasks as done even while tasks they wait for are still open
+        #[arg(long)]
+        force: bool,
+
+        /// Marks tasks that are done already as todo again, and the rest as done
+        #[arg(long, short = 't')]
+        toggle: bool,
+
+        /// Marks every todo task as done
+        #[arg(long, conflicts_with_all = ["task_indices", "name", "toggle"])]
+        all_todo: bool,
+
+        /// Marks more than five tasks as done with --all-todo without asking first
+        #[arg(short, long, requires = "all_todo")]
+        yes: bool,
+
+        /// Reads the numbers given as task ids, which stay the same when tasks are added, removed or sorted, instead of indices
+        #[arg(long = "id", conflicts_with = "name")]
+        by_id: bool,
+    },
+    /// Makes a task wait for other tasks to be done first
+    #[command(name = "block", visible_aliases = &["depend"], arg_required_else_help = true)]
+    Block {
+        /// The task that has to wait
+        #[arg(value_name = "task_index")]
+        task_index: TaskIndex,
+
+        /// The task(s) it waits for
+        #[arg(long, value_name = "task_indices", use_value_delimiter = true, num_args = 1.., required = true)]
+        on: Vec<TaskIndex>,
+
+        /// Stops the task waiting for these tasks instead
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Lists the tasks that wait for tasks that are still open
+    #[command(name = "blocked")]
+    Blocked,
+    /// Sets custom key=value fields on a task; an empty value removes the field
+    #[command(name = "set", visible_aliases = &["field"], arg_required_else_help = true)]
+    Set {
+        /// The task to set the fields of
+        #[arg(value_name = "task_index")]
+        task_index: TaskIndex,
+
+        /// The fields, like ticket=OPS-123
+        #[arg(value_name = "key=value", value_parser = parse_field, required = true)]
+        fields: Vec<(String, String)>,
+    },
+    /// Lists the tasks due today or overdue
+    #[command(name = "today", visible_aliases = &["agenda"])]
+    Today,
+    /// Lists the tasks due in the next seven days under a heading for each day
+    #[command(name = "week")]
+    Week {
+        /// Also lists the tasks without a due date, under "Someday"
+        #[arg(long)]
+        someday: bool,
+    },
+    /// Sends a desktop notification for each task that is due, once a day, and prints them too
+    #[command(name = "notify", visible_aliases = &["remind"])]
+    Notify {
+        /// How far ahead to look for due tasks, e.g. 2d; without it, tasks due today or overdue
+        #[arg(long, value_name = "DURATION")]
+        window: Option<String>,
+
+        /// Sends one notification listing every due task instead of one per task
+        #[arg(long)]
+        summary: bool,
+    },
+    /// Hides a task from `list` and `raw` until a date
+    #[command(name = "snooze", visible_aliases = &["defer"], arg_required_else_help = true)]
+    Snooze {
+        /// The task to snooze
+        #[arg(value_name = "task_index")]
+        task_index: TaskIndex,
+
+        /// When the task reappears: "tomorrow", a weekday ("monday"), a date ("2024-05-01") or a date and time ("2024-05-01 14:00")
+        #[arg(conflicts_with_all = ["duration", "clear"])]
+        until: Option<String>,
+
+        /// How long to hide the task for instead, like 3d or 2w
+        #[arg(long = "for", value_name = "DURATION", conflicts_with = "clear")]
+        duration: Option<String>,
+
+        /// Wakes the task up again straight away
+        #[arg(long)]
+        clear: bool,
+    },
+    /// Sets or clears a task's due date, or lists tasks that are due
+    #[command(name = "due", visible_aliases = &["deadline"])]
+    Due {
+        /// The task to set the due date of
+        task_index: Option<TaskIndex>,
+
+        /// When the task is due: a date ("2024-05-01"), a date and time ("2024-05-01 14:00") or a time from now ("3d")
+        when: Option<String>,
+
+        /// Removes the task's due date
+        #[arg(long, requires = "task_index", conflicts_with = "when")]
+        clear: bool,
+
+        /// Prints nothing and exits with 3 if a task is due soon, or 4 if one is overdue
+        #[arg(long, conflicts_with_all = ["task_index", "clear"])]
+        check: bool,
+
+        /// How far ahead --check looks for tasks that are due soon
+        #[arg(long, value_name = "DURATION", default_value = "1d", requires = "check")]
+        within: String,
+    },
+    /// Sets, prints or clears a longer note on a task
+    #[command(name = "note", visible_aliases = &["n", "describe"], arg_required_else_help = true)]
+    Note {
+        /// The task the note belongs to
+        #[arg(value_name = "task_index")]
+        task_index: TaskIndex,
+
+        /// The new note; prints the current one if left out
+        note: Option<String>,
+
+        /// Removes the task's note
+        #[arg(long, conflicts_with = "note")]
+        clear: bool,
     },
-    /// Sorts tasks (todo -> done)
+    /// Sorts tasks (todo -> done, or by name, creation time or priority)
     #[command(name = "sort", visible_aliases = &["s", "order"])]
-    Sort,
+    Sort {
+        /// What to sort by
+        #[arg(value_name = "key", value_enum, default_value_t = SortKey::Done)]
+        key: SortKey,
+
+        /// Sorts in the opposite order
+        #[arg(long, conflicts_with = "undo")]
+        reverse: bool,
+
+        /// Restores the order from before the last sort
+        #[arg(long)]
+        undo: bool,
+
+        /// Shows which tasks would move, without changing anything
+        #[arg(long, conflicts_with = "undo")]
+        dry_run: bool,
+
+        /// With --dry-run, prints the planned changes as a JSON object
+        #[arg(long, requires = "dry_run")]
+        json: bool,
+    },
+    /// Flips the order of the tasks
+    #[command(name = "reverse", visible_aliases = &["flip"])]
+    Reverse,
+    /// Puts tasks in a random order, leaving done tasks at the bottom
+    #[command(name = "shuffle", visible_aliases = &["mix"])]
+    Shuffle {
+        /// Shuffles done tasks in with the rest
+        #[arg(long)]
+        all: bool,
+    },
+    /// Suggests a random todo task to work on next
+    #[command(name = "pick", visible_aliases = &["random", "roll"])]
+    Pick {
+        /// How many different tasks to suggest
+        #[arg(short = 'n', long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+        count: u16,
+
+        /// Picks from every task, including done, snoozed and blocked ones
+        #[arg(long)]
+        any: bool,
+
+        /// Favours older tasks and tasks with a higher `priority` field
+        #[arg(long)]
+        weighted: bool,
+    },
     /// Removes tasks
     #[command(name = "remove", visible_aliases = &["rm", "del", "delete", "-"], arg_required_else_help = true)]
     Remove {
         /// The task(s) to remove
-        #[arg(value_name = "task_indices", use_value_delimiter = true,)]
-        task_indices: Vec<i32>,
+        #[arg(value_name = "task_indices", use_value_delimiter = true, required_unless_present_any = ["name", "done", "matching"])]
+        task_indices: Vec<TaskIndex>,
+
+        /// Removes the task whose name matches instead of giving indices
+        #[arg(short, long, visible_alias = "match", conflicts_with = "task_indices")]
+        name: Option<String>,
+
+        /// Acts on every task matching --name when there is more than one
+        #[arg(long, requires = "name")]
+        all: bool,
+
+        /// Also removes the tasks' subtasks instead of keeping them as top-level tasks
+        #[arg(long)]
+        cascade: bool,
+
+        /// Shows which tasks would be removed, without changing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// With --dry-run, prints the planned changes as a JSON object
+        #[arg(long, requires = "dry_run")]
+        json: bool,
+
+        /// Removes every task marked as done, like `clear`; with --matching only the done ones among them
+        #[arg(long, conflicts_with_all = ["task_indices", "name"])]
+        done: bool,
+
+        /// Removes every task whose name contains this text, ignoring case
+        #[arg(long, value_name = "text", conflicts_with_all = ["task_indices", "name"])]
+        matching: Option<String>,
+
+        /// Removes more than five tasks with --done or --matching without asking first
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Reads the numbers given as task ids, which stay the same when tasks are added, removed or sorted, instead of indices
+        #[arg(long = "id", conflicts_with = "name")]
+        by_id: bool,
     },
     /// Removes all tasks marked as done
     #[command(name = "clear", visible_aliases = &["cls", "clean"])]
-    Clear,
-    /// Deletes all tasks
+    Clear {
+        /// Keeps this many of the most recently completed tasks
+        #[arg(long, value_name = "N")]
+        keep: Option<usize>,
+
+        /// Shows which tasks would be cleared, without changing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// With --dry-run, prints the planned changes as a JSON object
+        #[arg(long, requires = "dry_run")]
+        json: bool,
+    },
+    /// Deletes all tasks, after saving a snapshot of the database that `restore --last-reset` brings back
     #[command(name = "reset", visible_aliases = &["clearall", "deleteall"])]
-    Reset,
+    Reset {
+        /// Shows which tasks would be deleted, without changing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// With --dry-run, prints the planned changes as a JSON object
+        #[arg(long, requires = "dry_run")]
+        json: bool,
+
+        /// Deletes the tasks without saving a snapshot first
+        #[arg(long)]
+        no_snapshot: bool,
+    },
+    /// Reverses the last change to the tasks, going further back each time it's run
+    #[command(name = "undo", visible_aliases = &["revert"])]
+    Undo {
+        /// Lists the changes that can be undone instead, newest first
+        #[arg(long)]
+        list: bool,
+    },
+    /// Lists removed tasks, or puts them back or deletes them for good
+    #[command(name = "trash", visible_aliases = &["bin"])]
+    Trash {
+        #[command(subcommand)]
+        action: Option<TrashAction>,
+    },
+    /// Encrypts the task database with a passphrase, which todoln then asks for or reads from TODOLN_PASSPHRASE
+    #[command(name = "encrypt")]
+    Encrypt,
+    /// Turns an encrypted task database back into a plain one
+    #[command(name = "decrypt")]
+    Decrypt,
     /// Backs up the task database to the current directory
     #[command(name = "backup", visible_aliases = &["b", "export"])]
-    Backup,
+    Backup {
+        /// Exports the tasks as JSON (todoln_backup.json) instead of copying the database
+        #[arg(long)]
+        json: bool,
+    },
     /// Restores a previously saved backup file
-    #[command(name = "restore", visible_aliases = &["rest", "import"], arg_required_else_help = true)]
+    #[command(name = "restore", visible_aliases = &["rest"], arg_required_else_help = true)]
     Restore {
-        /// The path to the backuped file
-        #[arg(value_name = "backup_path")]
-        backup_path: String,
-    },
-}
+        /// The path to the backuped file; a .json file replaces the tasks with the ones it lists
+        #[arg(value_name = "backup_path", required_unless_present = "last_reset")]
+        backup_path: Option<String>,
 
-pub struct Task {
-    pub id: Option<i32>,
-    pub idx: Option<i32>,
-    pub name: String,
-    pub done: bool,
-}
+        /// Restores the snapshot the last `reset` saved instead of a backup file
+        #[arg(long, conflicts_with = "backup_path")]
+        last_reset: bool,
 
-pub fn add(task_names: &[String]) {
-    let mut conn = establish_connection();
+        /// Shows how many tasks the backup would restore and replace (for a .json file, checking every
+        /// task), without changing anything
+        #[arg(long)]
+        dry_run: bool,
 
-    let tasks_to_add: Vec<Task> = task_names
-        .iter()
-        .filter(|task_name| !task_name.trim().is_empty()) // Filter out empty or whitespace-only names
-        .map(|task_name| Task {
-            id: None,
-            idx: None,
-            name: task_name.clone(),
-            done: false,
-        })
-        .collect();
+        /// With --dry-run, prints the planned changes as a JSON object
+        #[arg(long, requires = "dry_run")]
+        json: bool,
 
-    if tasks_to_add.is_empty() {
-        print_error("Error: No valid tasks provided.");
-    }
+        /// Writes the outcome for each task in a .json file to this file, one tab-separated line per task
+        #[arg(long, value_name = "FILE")]
+        report: Option<PathBuf>,
 
-    add_tasks_to_db(&mut conn, &tasks_to_add);
+        /// Keeps every task of a .json file, even ones named the same as an earlier task apart from case and spacing
+        #[arg(long)]
+        force: bool,
+    },
+    /// Adds tasks from elsewhere to the current ones, leaving those as they are
+    #[command(name = "import", arg_required_else_help = true)]
+    Import {
+        #[command(subcommand)]
+        source: ImportSource,
+    },
+    /// Syncs tasks with a markdown checklist file
+    #[command(name = "sync-md", visible_aliases = &["md"], arg_required_else_help = true)]
+    SyncMd {
+        /// The path to the markdown file
+        #[arg(value_name = "file")]
+        file: String,
 
-    print_success(&format!("Task(s) added successfully: {}", task_names.join(", ")));
-}
+        /// Removes tasks that are not in the file
+        #[arg(long)]
+        prune: bool,
 
-pub fn insert(index: &i32, task_names: &[String]) {
-    let mut conn = establish_connection();
+        /// Previews the changes without applying them
+        #[arg(long)]
+        dry_run: bool,
 
-    if *index < 0 {
-        print_error("Error: Index must be non-negative.");
-    }
+        /// Writes the outcome for each checklist item to this file, one tab-separated line per item
+        #[arg(long, value_name = "FILE")]
+        report: Option<PathBuf>,
+    },
+    /// Keeps a markdown (.md) or JSON (.json) copy of the tasks for git, bringing back edits made to it
+    #[command(name = "sync", arg_required_else_help = true)]
+    Sync {
+        /// The file to sync with, created on the first sync
+        #[arg(value_name = "file")]
+        file: PathBuf,
 
-    let tasks_length = get_tasks_length(&conn);
-    if *index > tasks_length {
-        print_error(&format!("Error: Cannot insert at index {} as the total number of tasks is: {}", *index, tasks_length));
-    }
+        /// Only says whether the file or the tasks changed since the last sync
+        #[arg(long)]
+        status: bool,
+    },
+    /// Moves all tasks marked as done into the archive
+    #[command(name = "archive", visible_aliases = &["arc"])]
+    Archive {
+        /// Lists archived tasks instead
+        #[arg(long)]
+        list: bool,
 
-    let tasks_to_insert: Vec<Task> = task_names
-        .iter()
-        .filter(|task_name| !task_name.trim().is_empty())
-        .enumerate()
-        .map(|(i, task_name)| Task {
-            id: None,
-            idx: Some(*index + i as i32),
-            name: task_name.clone(),
-            done: false,
-        })
-        .collect();
+        /// Only lists tasks completed in this month (2024-06), ISO week (2024-W26), day or range of them
+        #[arg(long, value_name = "period", value_parser = parse_completed_in, requires = "list")]
+        completed_in: Option<Period>,
+    },
+    /// Lists what was completed, newest first and grouped by day, archived and cleared tasks included
+    #[command(name = "log", visible_aliases = &["completed"])]
+    Log {
+        /// Only lists tasks completed from the start of this day (2024-06-01), month or ISO week on
+        #[arg(long, value_name = "date", value_parser = parse_since)]
+        since: Option<DateTime<Utc>>,
+    },
+    /// Charts how many tasks were completed each day, with the current streak and the busiest day
+    #[command(name = "stats", visible_aliases = &["streak"])]
+    Stats {
+        /// Covers the last 7 days instead of the last 30
+        #[arg(long)]
+        weekly: bool,
+    },
+    /// Archives completed tasks, sorts the rest and takes a backup snapshot
+    #[command(name = "wrapup", visible_aliases = &["wrap", "eod"])]
+    Wrapup {
+        /// Leaves completed tasks in the list
+        #[arg(long)]
+        no_archive: bool,
 
-    if tasks_to_insert.is_empty() {
-        print_error("Error: No valid tasks provided.");
-    }
+        /// Keeps the current task order
+        #[arg(long)]
+        no_sort: bool,
 
-    insert_tasks_to_db(&mut conn, index, &tasks_to_insert);
+        /// Skips the backup snapshot
+        #[arg(long)]
+        no_backup: bool,
 
-    print_success(&format!("Task(s) inserted successfully: {}", tasks_to_insert.iter().map(|t| t.name.clone()).collect::<Vec<_>>().join(", ")));
-}
+        /// Runs every step without asking first
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Starts tracking time on a task, stopping the timer of any other task
+    #[command(name = "start", visible_aliases = &["track"], arg_required_else_help = true)]
+    Start {
+        /// The task to track time on
+        #[arg(value_name = "task_index")]
+        task_index: TaskIndex,
+    },
+    /// Stops tracking time on the task started with `start`
+    #[command(name = "stop")]
+    Stop,
+    /// Shows the time tracked on a task, or on every task and day
+    #[command(name = "time", visible_aliases = &["timesheet"])]
+    Time {
+        /// The task to show, by day
+        #[arg(value_name = "task_index")]
+        task_index: Option<TaskIndex>,
 
-pub fn modify(task_index: &i32, new_name: &String) {
-    let mut conn = establish_connection();
+        /// Shows the time tracked per task and per day, which is also what no task index does
+        #[arg(long, conflicts_with = "task_index")]
+        report: bool,
+    },
+    /// Starts a timed focus session on the first few todo tasks
+    #[command(name = "session", visible_aliases = &["focus"], arg_required_else_help = true)]
+    Session {
+        /// How long the session lasts (e.g. 25m, 1h)
+        #[arg(value_name = "duration", required_unless_present = "history")]
+        duration: Option<String>,
 
-    if *task_index <= 0 || *task_index > get_tasks_length(&conn) {
-        print_error(&format!("Error: Invalid index '{}'.", task_index));
-        return;
-    }
+        /// How many todo tasks to focus on
+        #[arg(short = 'n', long, default_value_t = 5, value_parser = clap::value_parser!(u8).range(1..=9))]
+        count: u8,
 
-    if new_name.trim().is_empty() {
-        print_error("Error: New task cannot be empty or whitespace-only.");
-        return;
-    }
+        /// Lists past sessions instead
+        #[arg(long, conflicts_with = "duration")]
+        history: bool,
+    },
+    /// Opens a full-screen task list to browse and edit tasks
+    #[command(name = "ui", visible_aliases = &["interactive", "tui"])]
+    Ui,
+    /// Serves the tasks as a JSON API over HTTP, for launchers and shortcuts on other devices
+    #[command(name = "serve", visible_aliases = &["server", "api"])]
+    Serve {
+        /// The port to listen on
+        #[arg(long, default_value_t = 7777)]
+        port: u16,
 
-    match edit_task_in_db(&mut conn, task_index, new_name) {
-        Ok(_) => print_success(&format!("Task modifed successfully: '{}'", new_name)),
-        Err(e) => print_error(&format!("Failed to modify task {}: {}", task_index, e)),
-    }
-}
+        /// The address to listen on; the default only accepts connections from this machine
+        #[arg(long, default_value = "127.0.0.1")]
+        host: String,
 
-pub enum DisplayType {
-    All,
-    Todo,
-    Done
+        /// Makes requests that change tasks send `Authorization: Bearer <token>`
+        #[arg(long, env = "TODOLN_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+    },
+    /// Steps through every todo task to keep, complete, delete, snooze or rename it
+    #[command(name = "review", visible_aliases = &["weekly"])]
+    Review {
+        /// Applies the decisions made so far when the review is quit early
+        #[arg(long)]
+        apply_partial: bool,
+    },
+    /// Finds tasks with the same name apart from case and spacing
+    #[command(name = "dedupe", visible_aliases = &["dedup"])]
+    Dedupe {
+        /// Removes all but the first task of each duplicate group
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Checks the task database for problems
+    #[command(name = "doctor", visible_aliases = &["check"])]
+    Doctor {
+        /// Repairs the problems that can be fixed
+        #[arg(long)]
+        fix: bool,
+
+        /// Forcibly clears the lock files left behind by a crashed todoln, after asking first
+        #[arg(long, conflicts_with = "fix")]
+        unlock: bool,
+    },
+    /// Shows how much room the task database takes up, then compacts it
+    #[command(name = "maintenance", visible_aliases = &["vacuum"])]
+    Maintenance {
+        /// Only compacts the database once over a quarter of it is unused, and skips it without an error while another todoln has it open
+        #[arg(long)]
+        auto: bool,
+    },
+    /// Renumbers task indices so they run from 1 without gaps
+    #[command(name = "reindex")]
+    Reindex,
+    /// Keeps task indices from shifting across several todoln runs, until `end-session`
+    #[command(name = "begin-session")]
+    BeginSession {
+        /// Ends the session on its own after this long, like 30m or 2h (default: `session_timeout`, or 30m)
+        #[arg(long, value_name = "DURATION")]
+        timeout: Option<String>,
+    },
+    /// Ends the session started by `begin-session` and closes the gaps it left in task indices
+    #[command(name = "end-session")]
+    EndSession,
+    /// Shows where the task database is and whether a scripting session is active
+    #[command(name = "info")]
+    Info,
+    /// Lists the shortcuts defined under [aliases] in the config file
+    #[command(name = "aliases")]
+    Aliases,
+    /// Shows how often each command has been used
+    #[command(name = "usage", visible_aliases = &["u"])]
+    Usage {
+        /// Deletes all recorded usage statistics
+        #[arg(long)]
+        clear: bool,
+    },
+    /// Runs a few commands against a made-up example list, leaving your own tasks alone
+    #[command(name = "demo")]
+    Demo,
+    /// Prints a man page for todoln, e.g. `todoln man > todoln.1`
+    #[command(name = "man", hide = true)]
+    Man,
+    /// Describes every command and argument, for scripts that wrap todoln
+    #[command(name = "introspect")]
+    Introspect {
+        /// Prints the description as JSON
+        #[arg(long)]
+        json: bool,
+    },
 }
 
-impl DisplayType {
-    pub fn from_str(s: &str) -> Option<DisplayType> {
-        match s.trim().to_lowercase().as_str() {
-            "all" => Some(DisplayType::All),
-            "todo" => Some(DisplayType::Todo),
-            "done" => Some(DisplayType::Done),
-            _ => None,
+impl Commands {
+    /// Whether the command only reads tasks, so that `stable_read` keeps it from writing anything
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            Commands::List { .. } | Commands::Raw { .. } | Commands::Show { .. } | Commands::Find { .. } | Commands::Blocked | Commands::Introspect { .. } | Commands::Info | Commands::Aliases | Commands::Man | Commands::Pick { .. } | Commands::Demo | Commands::Log { .. } | Commands::Stats { .. } | Commands::Today | Commands::Week { .. } | Commands::Time { .. } => true,
+            Commands::Due { task_index, .. } => task_index.is_none(),
+            Commands::Note { note, clear, .. } => note.is_none() && !clear,
+            Commands::Archive { list, .. } => *list,
+            Commands::Undo { list } => *list,
+            Commands::Trash { action } => action.is_none(),
+            Commands::Dedupe { apply } => !apply,
+            Commands::Doctor { fix, unlock } => !fix && !unlock,
+            Commands::Session { history, .. } => *history,
+            Commands::Usage { clear } => !clear,
+            Commands::Remove { dry_run, .. } | Commands::Clear { dry_run, .. } | Commands::Reset { dry_run, .. } | Commands::Sort { dry_run, .. } => *dry_run,
+            Commands::Restore { dry_run, .. } | Commands::SyncMd { dry_run, .. } => *dry_run,
+            Commands::Sync { status, .. } => *status,
+            _ => false,
         }
     }
 }
 
-pub fn list(display_type: &str) {
-    let mut conn = establish_connection();
-
-    match get_tasks_from_db_and_update_indices(&mut conn) {
-        Ok(tasks) => {        
-            match DisplayType::from_str(display_type) {
-                Some(display_type) => {
-                    match display_type {
-                        DisplayType::All => {
-                            if tasks.is_empty() {
-                                println!("No tasks found.");
-                                return;
-                            }
-
-                            print_title("Tasks:");
-                            for task in tasks {
-                                if task.done { 
-                                    println!("  [{}] {}", bold_text(&task.idx.unwrap().to_string()), done_text(&task.name));
-                                }
-                                else {
-                                    println!("  [{}] {}", bold_text(&task.idx.unwrap().to_string()), todo_text(&task.name));
-                                };
-                            }
-                        }
-                        DisplayType::Todo => {
-                            let tasks_todo = tasks.iter().filter(|t| !t.done).collect::<Vec<_>>();
-                            if tasks_todo.is_empty() {
-                                println!("No tasks found.");
-                            }
-
-                            print_title("Tasks todo:");
-                            for task in tasks_todo {
-                                println!("  [{}] {}", bold_text(&task.idx.unwrap().to_string()), todo_text(&task.name));
-                            }
-                        }
-                        DisplayType::Done => {
-                            let tasks_done = tasks.iter().filter(|t| t.done).collect::<Vec<_>>();
-                            if tasks_done.is_empty() {
-                                println!("No tasks found.");
-                            }
-                      
-                            print_title("Tasks done:");
-                            for task in tasks_done {
-                                println!("  [{}] {}", bold_text(&task.idx.unwrap().to_string()), done_text(&task.name));
-                            }
-                        }
-                    }
-                }
-                None => {
-                    println!("Invalid display type");
-                }
-            }
+impl Commands {
+    /// Whether the command removes or replaces tasks, so it needs confirming in a protected context
+    pub fn is_destructive(&self) -> bool {
+        match self {
+            Commands::Remove { dry_run, .. } | Commands::Clear { dry_run, .. } | Commands::Reset { dry_run, .. } | Commands::Restore { dry_run, .. } => !dry_run,
+            Commands::SyncMd { prune, dry_run, .. } => *prune && !dry_run,
+            Commands::Sync { status, .. } => !status,
+            Commands::Trash { action } => matches!(action, Some(TrashAction::Empty { .. })),
+            Commands::Dedupe { apply } => *apply,
+            _ => false,
         }
-        Err(e) => print_error(&format!("Failed to retrieve tasks: {}", e)),
     }
 }
 
-pub fn raw(display_type: &str) {
-    let mut conn = establish_connection();
+/// Whether a destructive command may run against the current context: contexts listed in
+/// `confirm_context` need the user to type the context's name, or `force`. Without a terminal to
+/// ask on, it prints an error and returns false.
+pub fn guard_context(command: &Commands, command_name: &str, force: bool) -> bool {
+    let context = context_name();
 
-    match get_tasks_from_db_and_update_indices(&mut conn) {
-        Ok(tasks) => {        
-            if let Some(display_type) = DisplayType::from_str(display_type) {
-                match display_type {
-                    DisplayType::All => {
-                        for task in tasks {
-                            println!("{}", task.name);
-                        }
-                    }
-                    DisplayType::Todo => {
-                        let tasks_todo = tasks.iter().filter(|t| !t.done).collect::<Vec<_>>();
-                        for task in tasks_todo {
-                            println!("{}", task.name);
-                        }
-                    }
-                    DisplayType::Done => {
-                        let tasks_done = tasks.iter().filter(|t| t.done).collect::<Vec<_>>();
-                        for task in tasks_done {
-                            println!("{}", task.name);
-                        }
-                    }
-                }
-            }         
-        }
-        Err(e) => print_error(&format!("Failed to retrieve tasks: {}", e)),
+    if force || !command.is_destructive() || !config().confirm_context.contains(&context) {
+        return true;
     }
-}
-
-pub fn find(search_term: &str) {
-    let mut conn = establish_connection();
 
-    match find_tasks_from_db(&mut conn, search_term) {
-        Ok(tasks_found) => {        
-            for task in tasks_found {
-                println!("{} {}", bold_text(&task.idx.unwrap().to_string()), task.name);
-            }   
-        }
-        Err(e) => print_error(&format!("Failed to find tasks: {}", e)),
+    if !is_interactive() {
+        print_error(&format!("Error: '{}' is a protected context, pass --i-know-what-im-doing to run `{}` against it.", context, command_name));
+        return false;
     }
-}
 
-pub fn done(task_indices: &[i32]) {
-    let mut conn = establish_connection();
+    print_warning(&format!("Warning: `{}` changes the protected context '{}' ({}).", command_name, context, db_path().display()));
 
-    for id in task_indices.iter() {
-        match mark_task_in_db_as_done(&mut conn, id) {
-            Ok(_) => {},
-            Err(e) => {
-                print_error(&format!("Failed to mark task {} as done: {}", id, e));
-                return;
-            }
-        }
+    if ask(&format!("Type '{}' to continue:", context)).trim() != context {
+        print_notice(&tr("nothing-changed", &[]));
+        return false;
     }
 
-    print_success(&format!("Task(s) completed successfully: {}", task_indices.iter().map(|&i| i.to_string()).collect::<Vec<_>>().join(", ")));
+    true
 }
 
-pub fn sort() {
-    let mut conn = establish_connection();
+/// A task index as given on the command line: `4`, or `3.1` for the first subtask of task 3
+#[derive(Clone, Copy)]
+pub struct TaskIndex {
+    index: i32,
+    subtask: Option<usize>,
+    /// Whether `index` is the task's id from `--id`, which stays the same when tasks move
+    by_id: bool,
+}
 
-    match sort_tasks_in_db(&mut conn) {
-        Ok(_) => {},
-        Err(e) => {
-            print_error(&format!("Failed to sort tasks: {}", e));
-            return;
-        }
+impl TaskIndex {
+    /// The same number read as a task id when `by_id`, for commands given `--id`
+    pub fn as_id(self, by_id: bool) -> TaskIndex {
+        TaskIndex { by_id, ..self }
     }
-
-    print_success("Tasks sorted successfully");
 }
 
-pub fn remove(task_indices: &[i32]) {
-    let mut conn = establish_connection();
+impl FromStr for TaskIndex {
+    type Err = String;
 
-    for index in task_indices.iter() {
-        match remove_task_from_db(&mut conn, index) {
-            Ok(_) => {},
-            Err(e) => {
-                print_error(&format!("Failed to remove task {}: {}", index, e));
-                return;
-            }
-        }
-    }
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid task index '{}', use e.g. 4 or 3.1", s);
+        let (index, subtask) = match s.split_once('.') {
+            Some((index, subtask)) => (index, Some(subtask)),
+            None => (s, None),
+        };
 
-    print_success(&format!("Task(s) removed successfully: {}", task_indices.iter().map(|&i| i.to_string()).collect::<Vec<_>>().join(", ")));
+        Ok(TaskIndex {
+            index: index.trim().parse().map_err(|_| invalid())?,
+            subtask: subtask.map(|subtask| subtask.trim().parse().ok().filter(|&subtask| subtask > 0).ok_or_else(invalid)).transpose()?,
+            by_id: false,
+        })
+    }
 }
 
-pub fn clear() {
-    let mut conn = establish_connection();
+/// Turns an index like `3.1` into the index of that subtask, or an id from `--id` into the index
+/// that task has now, printing an error and returning `None` if there's no such task
+fn resolve_task_index(conn: &Connection, task_index: &TaskIndex) -> Option<i32> {
+    if task_index.by_id {
+        return resolve_task_id(conn, task_index);
+    }
 
-    match get_tasks_from_db_and_update_indices(&mut conn) {
-        Ok(tasks) => {
-            let completed_tasks: Vec<_> = tasks.iter().filter(|t| t.done).collect();
+    let Some(subtask) = task_index.subtask else {
+        return Some(task_index.index);
+    };
 
-            for task in &completed_tasks.clone() {
-                match remove_task_from_db(&mut conn, &task.idx.unwrap()) {
-                    Ok(_) => {},
-                    Err(e) => print_error(&format!("Failed to remove task {}: {}", &task.idx.unwrap(), e)),
-                }
+    match get_subtasks_from_db(conn, &task_index.index) {
+        Ok(subtasks) => match subtasks.get(subtask - 1) {
+            Some(task) => task.idx,
+            None => {
+                print_error(&format!("Error: Task {} has no subtask {}.", task_index.index, subtask));
+                None
             }
-
-            print_success(&format!("Completed task(s) cleared successfully: {}", completed_tasks.iter().map(|t| t.name.to_string()).collect::<Vec<_>>().join(", ")));
         },
-        Err(e) => print_error(&format!("Failed to retrieve tasks: {}", e)),
+        Err(e) => {
+            print_error(&format!("Failed to retrieve the subtasks of task {}: {}", task_index.index, e));
+            None
+        }
     }
 }
 
-pub fn reset() {
-    let mut conn = establish_connection();
-    
-    if let Err(e) = delete_tasks_from_db(&mut conn) {
-        print_error(&format!("Failed to delete all tasks: {}", e));
-        return;
+fn resolve_task_id(conn: &Connection, task_index: &TaskIndex) -> Option<i32> {
+    if let Some(subtask) = task_index.subtask {
+        print_error(&tr("invalid-id", &[&format!("{}.{}", task_index.index, subtask)]));
+        return None;
     }
 
-    print_success("Tasks reset successfully");
+    match get_task_by_id(conn, &task_index.index) {
+        Ok(Some(Task { idx: Some(idx), .. })) => Some(idx),
+        Ok(_) => {
+            print_error(&tr("no-task-with-id", &[&task_index.index]));
+            None
+        }
+        Err(e) => {
+            print_error(&format!("Failed to retrieve the task with id {}: {}", task_index.index, e));
+            None
+        }
+    }
 }
 
-pub fn backup() {
+fn resolve_task_indices(conn: &Connection, task_indices: &[TaskIndex]) -> Option<Vec<i32>> {
+    task_indices.iter().map(|task_index| resolve_task_index(conn, task_index)).collect()
+}
+
+#[derive(Subcommand)]
+pub enum TrashAction {
+    /// Puts a removed task back at the end of the task list
+    #[command(name = "restore", visible_aliases = &["restore-task"], arg_required_else_help = true)]
+    Restore {
+        /// The task to restore, as numbered by `todoln trash`
+        #[arg(value_name = "trash_index")]
+        trash_index: usize,
+    },
+    /// Deletes tasks that have been in the trash for longer than `trash_retention_days` for good
+    #[command(name = "empty", visible_aliases = &["purge"])]
+    Empty {
+        /// Deletes every task in the trash, however recently it was removed
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ImportSource {
+    /// Appends the tasks of another todoln database, like a `todoln backup`, that aren't in the list yet
+    #[command(name = "db", arg_required_else_help = true)]
+    Db {
+        /// The database file to take the tasks from
+        #[arg(value_name = "path")]
+        path: PathBuf,
+    },
+}
+
+#[derive(Default, Clone)]
+pub struct Task {
+    pub id: Option<i32>,
+    pub idx: Option<i32>,
+    pub name: String,
+    pub done: bool,
+    pub completed_at: Option<String>,
+    pub due_at: Option<String>,
+    pub note: Option<String>,
+    pub parent_id: Option<i32>,
+    pub recurrence: Option<String>,
+    pub snoozed_until: Option<String>,
+    pub created_at: Option<String>,
+}
+
+/// Where `find` looks for the search term
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum SearchField {
+    /// The task's name
+    Name,
+    /// The task's note
+    #[value(name = "notes", alias = "note")]
+    Note,
+    /// The comma-separated `tags` field
+    #[value(name = "tags", alias = "tag")]
+    Tags,
+}
+
+impl SearchField {
+    /// The fields `find --include` and `--only` ask for: names and `include`, or just `only`
+    pub fn chosen(include: &[SearchField], only: &[SearchField]) -> Vec<SearchField> {
+        let fields = if only.is_empty() { [&[SearchField::Name], include].concat() } else { only.to_vec() };
+        fields.iter().enumerate().filter(|(i, field)| !fields[..*i].contains(field)).map(|(_, field)| *field).collect()
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SearchField::Name => "name",
+            SearchField::Note => "note",
+            SearchField::Tags => "tags",
+        }
+    }
+}
+
+/// What `list --group-by` puts tasks under a heading by
+#[derive(Clone, Copy, ValueEnum)]
+pub enum GroupBy {
+    /// Each tag in the `tags` field, so a task with several tags is listed under each
+    Tag,
+    /// Todo, then done
+    Status,
+    /// The `priority` field, in the order `sort priority` uses
+    Priority,
+}
+
+/// What `sort` orders tasks by
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SortKey {
+    /// Todo tasks before done ones
+    Done,
+    /// Name, ignoring case
+    Name,
+    /// When the task was added, oldest first
+    Created,
+    /// The `priority` field: 1, 2, ... then high, medium, low, then tasks without one
+    Priority,
+}
+
+fn parse_every(s: &str) -> Result<String, String> {
+    parse_recurrence(s).ok_or_else(|| format!("invalid rule '{}', use e.g. 3d, 2w, mon or \"mon,thu\"", s))
+}
+
+fn parse_completed_in(s: &str) -> Result<Period, String> {
+    parse_period(s).ok_or_else(|| String::from("use a month (2024-06), an ISO week (2024-W26), a day (2024-06-03) or a range of them (2024-06-01..2024-06-15)"))
+}
+
+fn parse_since(s: &str) -> Result<DateTime<Utc>, String> {
+    parse_period(s).map(|(start, _)| start).ok_or_else(|| String::from("use a day (2024-06-01), a month (2024-06) or an ISO week (2024-W26)"))
+}
+
+/// Parses an `index=name` pair given to `modify --set`
+fn parse_rename(s: &str) -> Result<(TaskIndex, String), String> {
+    let Some((index, name)) = s.split_once('=') else {
+        return Err(format!("'{}' isn't an index=name pair", s));
+    };
+
+    Ok((index.parse()?, name.to_string()))
+}
+
+/// Splits a custom field given as `key=value`, checking that the key looks like an identifier
+fn parse_field(s: &str) -> Result<(String, String), String> {
+    let Some((key, value)) = s.split_once('=') else {
+        return Err(format!("'{}' isn't a key=value pair", s));
+    };
+
+    let mut chars = key.chars();
+    let valid_key = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+
+    if !valid_key {
+        return Err(format!("invalid field name '{}', use letters, digits, '_', '-' and '.', starting with a letter or '_'", key));
+    }
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// What `raw` prints for each task unless given `--template`
+pub const DEFAULT_RAW_TEMPLATE: &str = "{indent}{name}";
+
+const TEMPLATE_PLACEHOLDERS: &str = "{idx}, {name}, {indent}, {done}, {done:yes|no}, {due}, {note}, {created}, {completed}, {priority}, {field:key}";
+
+/// How `raw` prints each task, parsed from `--template`
+#[derive(Clone)]
+pub struct RawTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+impl RawTemplate {
+    fn uses_fields(&self) -> bool {
+        self.parts.iter().any(|part| matches!(part, TemplatePart::Field(_)))
+    }
+
+    /// Fills in the placeholders for `task`; `fields` maps a task id and field name to its value
+    pub fn render(&self, task: &Task, indented: bool, fields: &HashMap<(i32, String), String>) -> String {
+        let timestamp = |s: &Option<String>| s.as_deref().map(format_local_timestamp).unwrap_or_default();
+
+        self.parts.iter()
+            .map(|part| match part {
+                TemplatePart::Text(text) => text.clone(),
+                TemplatePart::Idx => index_label(task),
+                TemplatePart::Name => sanitize(&task.name),
+                TemplatePart::Indent => String::from(if indented { "  " } else { "" }),
+                TemplatePart::Done(None) => (task.done as i32).to_string(),
+                TemplatePart::Done(Some((done, todo))) => if task.done { done.clone() } else { todo.clone() },
+                TemplatePart::Due => timestamp(&task.due_at),
+                TemplatePart::Note => task.note.as_deref().map(sanitize).unwrap_or_default(),
+                TemplatePart::Created => timestamp(&task.created_at),
+                TemplatePart::Completed => timestamp(&task.completed_at),
+                TemplatePart::Field(key) => fields.get(&(task.id.unwrap(), key.clone())).map(|value| sanitize(value)).unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// One piece of a `raw --template`
+#[derive(Clone)]
+enum TemplatePart {
+    Text(String),
+    Idx,
+    Name,
+    /// Two spaces for a subtask whose parent is listed too
+    Indent,
+    /// `0`/`1`, or the first text for done tasks and the second for todo ones
+    Done(Option<(String, String)>),
+    Due,
+    Note,
+    Created,
+    Completed,
+    Field(String),
+}
+
+/// Splits a template into text and placeholders, unescaping `\t`, `\n` and `\\` in the text and
+/// reading `{{` and `}}` as literal braces
+pub fn parse_template(s: &str) -> Result<RawTemplate, String> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.peek() {
+                Some('t') => { chars.next(); text.push('\t'); }
+                Some('n') => { chars.next(); text.push('\n'); }
+                Some('\\') => { chars.next(); text.push('\\'); }
+                _ => text.push('\\'),
+            },
+            '{' if chars.peek() == Some(&'{') => { chars.next(); text.push('{'); }
+            '}' if chars.peek() == Some(&'}') => { chars.next(); text.push('}'); }
+            '}' => return Err(String::from("unmatched '}', write '}}' for a literal brace")),
+            '{' => {
+                let mut placeholder = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    placeholder.push(c);
+                }
+
+                if !closed {
+                    return Err(format!("unclosed placeholder '{{{}', write '{{{{' for a literal brace", placeholder));
+                }
+
+                if !text.is_empty() {
+                    parts.push(TemplatePart::Text(mem::take(&mut text)));
+                }
+                parts.push(parse_placeholder(&placeholder)?);
+            }
+            c => text.push(c),
+        }
+    }
+
+    if !text.is_empty() {
+        parts.push(TemplatePart::Text(text));
+    }
+
+    Ok(RawTemplate { parts })
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<TemplatePart, String> {
+    let unknown = || format!("unknown placeholder '{{{}}}', use {}", placeholder, TEMPLATE_PLACEHOLDERS);
+
+    let part = match placeholder.split_once(':') {
+        Some(("done", pair)) => {
+            let Some((done, todo)) = pair.split_once('|') else {
+                return Err(format!("'{{{}}}' needs two texts, the one for done tasks first, e.g. {{done:x| }}", placeholder));
+            };
+            TemplatePart::Done(Some((done.to_string(), todo.to_string())))
+        }
+        Some(("field", key)) if !key.is_empty() => TemplatePart::Field(key.to_string()),
+        Some(_) => return Err(unknown()),
+        None => match placeholder {
+            "idx" => TemplatePart::Idx,
+            "name" => TemplatePart::Name,
+            "indent" => TemplatePart::Indent,
+            "done" => TemplatePart::Done(None),
+            "due" => TemplatePart::Due,
+            "note" => TemplatePart::Note,
+            "created" => TemplatePart::Created,
+            "completed" => TemplatePart::Completed,
+            "priority" => TemplatePart::Field(String::from("priority")),
+            _ => return Err(unknown()),
+        },
+    };
+
+    Ok(part)
+}
+
+/// A task as `backup --json` writes it and a JSON restore reads it back
+#[derive(Serialize, Deserialize)]
+pub struct ExportedTask {
+    pub name: String,
+    #[serde(default)]
+    pub done: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub due_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Position of the parent task in the export, counting from 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<usize>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recurrence: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snoozed_until: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Export {
+    tasks: Vec<ExportedTask>,
+}
+
+/// What was decided for a task during `review`, applied once the review is over
+pub enum ReviewDecision {
+    Done { id: i32, name: String },
+    Remove { id: i32, name: String },
+    Snooze { id: i32, name: String, due_at: String },
+    Rename { id: i32, name: String, new_name: String },
+}
+
+/// A line of the file saved from `edit-all`, with the task it was worked out to stand for, if any
+pub struct EditedTask {
+    pub id: Option<i32>,
+    pub name: String,
+    pub done: bool,
+}
+
+pub enum SyncChange {
+    Add { name: String, done: bool },
+    SetDone { id: i32, name: String, done: bool },
+    Rename { id: i32, name: String, old_name: String },
+    Remove { id: i32, name: String },
+}
+
+/// What an import did, or with `--dry-run` would do, with one row of the imported file
+pub enum ImportOutcome {
+    Add,
+    MarkDone,
+    MarkTodo,
+    SkipDuplicate,
+    ParseError(String),
+}
+
+impl ImportOutcome {
+    fn label(&self) -> &'static str {
+        match self {
+            ImportOutcome::Add => "add",
+            ImportOutcome::MarkDone => "mark-done",
+            ImportOutcome::MarkTodo => "mark-todo",
+            ImportOutcome::SkipDuplicate => "skip-duplicate",
+            ImportOutcome::ParseError(_) => "parse-error",
+        }
+    }
+}
+
+/// The outcome of every row of an imported file, shared by all importers so `--report` files and
+/// summary lines look the same whichever format was imported
+#[derive(Default)]
+pub struct ImportReport {
+    /// Line number, task name (or the line itself if it couldn't be parsed) and outcome
+    rows: Vec<(usize, String, ImportOutcome)>,
+}
+
+impl ImportReport {
+    fn push(&mut self, line: usize, name: &str, outcome: ImportOutcome) {
+        self.rows.push((line, name.to_string(), outcome));
+    }
+
+    fn duplicates(&self) -> usize {
+        self.rows.iter().filter(|(_, _, outcome)| matches!(outcome, ImportOutcome::SkipDuplicate)).count()
+    }
+
+    fn errors(&self) -> usize {
+        self.rows.iter().filter(|(_, _, outcome)| matches!(outcome, ImportOutcome::ParseError(_))).count()
+    }
+
+    fn print_errors(&self) {
+        for (line, _, outcome) in &self.rows {
+            if let ImportOutcome::ParseError(reason) = outcome {
+                print_warning(&format!("Line {}: {}", line, reason));
+            }
+        }
+    }
+
+    /// The end of the summary line, naming the rows that were skipped or couldn't be parsed
+    fn skipped_summary(&self) -> String {
+        let mut summary = String::new();
+        if self.duplicates() > 0 {
+            summary.push_str(&format!(", {} skipped as duplicates", self.duplicates()));
+        }
+        if self.errors() > 0 {
+            summary.push_str(&format!(", {} could not be parsed", self.errors()));
+        }
+        summary
+    }
+
+    /// Writes one tab-separated line per row: line number, outcome, name and the reason for parse
+    /// errors. Outcomes start with "would-" unless the import was `applied`.
+    fn write(&self, path: &Path, applied: bool) -> Result<(), String> {
+        let mut contents = String::from("line\toutcome\tname\treason\n");
+
+        for (line, name, outcome) in &self.rows {
+            let label = match outcome {
+                ImportOutcome::ParseError(_) => outcome.label().to_string(),
+                _ if applied => outcome.label().to_string(),
+                _ => format!("would-{}", outcome.label()),
+            };
+            let reason = match outcome {
+                ImportOutcome::ParseError(reason) => sanitize(reason),
+                _ => String::new(),
+            };
+            contents.push_str(&format!("{}\t{}\t{}\t{}\n", line, label, sanitize(name), reason));
+        }
+
+        fs::write(path, contents).map_err(|e| format!("Failed to write report '{}': {}", path.display(), e))
+    }
+}
+
+/// The task in `existing` with the same name as `name` apart from case and spacing
+fn similar_task<'a>(existing: &'a [Task], name: &str) -> Option<&'a Task> {
+    let normalized = normalize_name(name);
+    existing.iter().find(|t| normalize_name(&t.name) == normalized)
+}
+
+/// Cleans up the spacing of `names` and pairs each with why `add` won't take it without `--force`:
+/// a task or an earlier name in the batch that it matches apart from case and spacing
+pub fn find_duplicate_names(existing: &[Task], names: &[String]) -> Vec<(String, Option<String>)> {
+    let mut checked: Vec<(String, Option<String>)> = Vec::new();
+
+    for name in names.iter().map(|name| clean_name(name)) {
+        let duplicate_of = match similar_task(existing, &name) {
+            Some(task) => Some(format!("a similar task already exists at index {} ('{}')", index_label(task), task.name)),
+            None if checked.iter().any(|(other, _)| normalize_name(other) == normalize_name(&name)) => Some(String::from("it is given twice")),
+            None => None,
+        };
+        checked.push((name, duplicate_of));
+    }
+
+    checked
+}
+
+/// Cleans up the spacing of `names` and, unless `force` is set, drops (with an error) those that
+/// match an existing task or an earlier name in the batch apart from case and spacing
+fn check_duplicates(existing: &[Task], names: Vec<String>, force: bool) -> Vec<String> {
+    let mut checked: Vec<String> = Vec::new();
+    let mut skipped = false;
+
+    for (name, duplicate_of) in find_duplicate_names(existing, &names) {
+        match duplicate_of {
+            Some(duplicate_of) if !force => {
+                print_error(&format!("Error: '{}' not saved, {}.", name, duplicate_of));
+                skipped = true;
+            }
+            _ => checked.push(name),
+        }
+    }
+
+    if skipped {
+        print_warning("Run with --force to keep similar names anyway.");
+    }
+
+    checked
+}
+
+pub fn add(task_names: &[String], force: bool, from_clipboard: bool, under: &Option<i32>, every: &Option<String>) {
+    let mut task_names = task_names.to_vec();
+    if from_clipboard {
+        match clipboard::paste() {
+            Ok(text) => task_names.extend(text.lines().map(String::from)),
+            Err(e) => {
+                print_error(&format!("Failed to read the clipboard: {}", e));
+                return;
+            }
+        }
+    }
+
+    let mut conn = establish_connection();
+
+    let parent_id = match under {
+        Some(parent_index) => match get_task_from_db(&conn, parent_index) {
+            Ok(Some(Task { parent_id: Some(_), .. })) => {
+                print_error(&format!("Error: Task {} is a subtask itself, subtasks can't have subtasks of their own.", parent_index));
+                return;
+            }
+            Ok(Some(parent)) => parent.id,
+            Ok(None) => {
+                print_error(&tr("invalid-index", &[parent_index]));
+                return;
+            }
+            Err(e) => {
+                print_error(&format!("Failed to retrieve task {}: {}", parent_index, e));
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let valid_names: Vec<String> = task_names
+        .iter()
+        .filter(|task_name| !task_name.trim().is_empty()) // Filter out empty or whitespace-only names
+        .cloned()
+        .collect();
+
+    if valid_names.is_empty() {
+        print_error(&tr("no-valid-tasks", &[]));
+        return;
+    }
+
+    let existing_tasks = match get_tasks_from_db(&conn) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    let tasks_to_add: Vec<Task> = check_duplicates(&existing_tasks, valid_names, force)
+        .into_iter()
+        .map(|task_name| Task {
+            name: task_name,
+            parent_id,
+            recurrence: every.clone(),
+            ..Default::default()
+        })
+        .collect();
+
+    if tasks_to_add.is_empty() {
+        print_notice(&tr("no-tasks-added", &[]));
+        return;
+    }
+
+    let created_ids = match add_tasks_to_db(&mut conn, &tasks_to_add) {
+        Ok(created_ids) => created_ids,
+        Err(e) => {
+            print_error(&format!("Failed to add tasks: {}", e));
+            return;
+        }
+    };
+
+    print_success(&tr("tasks-added", &[&tasks_to_add.iter().map(|t| t.name.clone()).collect::<Vec<_>>().join(", ")]));
+    print_added_result(&conn, "add", &created_ids);
+}
+
+/// The index `insert` gives the first new task among `length` tasks: `index` if it's in 1..=length + 1,
+/// the top for 0, counted from the end for negative ones and the end if there's no index
+fn insert_position(index: Option<i32>, length: i32) -> Result<i32, String> {
+    match index {
+        None => Ok(length + 1),
+        Some(0) => Ok(1),
+        Some(index) if (1..=length + 1).contains(&index) => Ok(index),
+        Some(index) if (-length..0).contains(&index) => Ok(length + 1 + index),
+        Some(index) => Err(format!("Error: Cannot insert at index {} as the total number of tasks is: {}", index, length)),
+    }
+}
+
+pub fn insert(index: &Option<String>, task_names: &[String], literal_names: &[String], force: bool) {
+    let mut conn = establish_connection();
+
+    // Without an index the first argument is already a task name
+    let (index, mut task_names) = match index.as_deref().map(|index| (index, index.trim().parse::<i32>())) {
+        Some((_, Ok(index))) => (Some(index), task_names.to_vec()),
+        Some((index, Err(_))) => (None, index.split(',').map(String::from).chain(task_names.iter().cloned()).collect()),
+        None => (None, task_names.to_vec()),
+    };
+    task_names.extend(literal_names.iter().cloned());
+
+    if let (Some(index), true) = (index, task_names.is_empty()) {
+        print_error(&tr("insert-index-without-names", &[&index]));
+        return;
+    }
+
+    let index = &match insert_position(index, get_tasks_length(&conn)) {
+        Ok(index) => index,
+        Err(e) => {
+            print_error(&e);
+            return;
+        }
+    };
+
+    let valid_names: Vec<String> = task_names
+        .iter()
+        .filter(|task_name| !task_name.trim().is_empty())
+        .cloned()
+        .collect();
+
+    if valid_names.is_empty() {
+        print_error(&tr("no-valid-tasks", &[]));
+        return;
+    }
+
+    let existing_tasks = match get_tasks_from_db(&conn) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    let tasks_to_insert: Vec<Task> = check_duplicates(&existing_tasks, valid_names, force)
+        .into_iter()
+        .enumerate()
+        .map(|(i, task_name)| Task {
+            idx: Some(*index + i as i32),
+            name: task_name,
+            ..Default::default()
+        })
+        .collect();
+
+    if tasks_to_insert.is_empty() {
+        print_notice(&tr("no-tasks-inserted", &[]));
+        return;
+    }
+
+    let created_ids = match insert_tasks_to_db(&mut conn, index, &tasks_to_insert) {
+        Ok(created_ids) => created_ids,
+        Err(e) => {
+            print_error(&format!("Failed to insert tasks: {}", e));
+            return;
+        }
+    };
+
+    if let Err(e) = renormalize_task_indices(&mut conn) {
+        print_error(&format!("Failed to update task indices: {}", e));
+        return;
+    }
+
+    print_success(&tr("tasks-inserted", &[&tasks_to_insert.iter().map(|t| t.name.clone()).collect::<Vec<_>>().join(", ")]));
+    print_added_result(&conn, "insert", &created_ids);
+}
+
+pub fn modify(task_index: &Option<TaskIndex>, new_name: &Option<String>, renames: &[(TaskIndex, String)], force: bool) {
+    if !renames.is_empty() {
+        return modify_many(renames, force);
+    }
+
+    let mut conn = establish_connection();
+
+    let task_index = &match task_index {
+        Some(task_index) => match resolve_task_index(&conn, task_index) {
+            Some(task_index) => task_index,
+            None => return,
+        },
+        None => pick_tasks(&conn, false, false)[0],
+    };
+
+    let Some(current_name) = current_task_name(&conn, task_index) else {
+        return;
+    };
+
+    let new_name = match new_name {
+        Some(new_name) => new_name.clone(),
+        None => {
+            if !is_interactive() {
+                print_error("Error: Give the new name, editing the current one needs an interactive terminal.");
+                return;
+            }
+
+            // The comment is kept as it is, so only the name itself is edited
+            let initial = match comment_delimiter() {
+                Some(delimiter) => split_comment(&current_name, delimiter).0,
+                None => &current_name,
+            };
+
+            match edit_line(&format!("[{}] ", task_index), initial) {
+                Ok(Some(edited_name)) => edited_name,
+                Ok(None) => {
+                    print_notice(&tr("modification-cancelled", &[]));
+                    return;
+                }
+                Err(e) => {
+                    print_error(&format!("Failed to read the new name: {}", e));
+                    return;
+                }
+            }
+        }
+    };
+
+    rename_task(&mut conn, task_index, keep_comment(&current_name, new_name), force);
+}
+
+/// Keeps the old comment unless the new name brings its own, where an empty one removes it
+fn keep_comment(current_name: &str, new_name: String) -> String {
+    match comment_delimiter() {
+        Some(delimiter) => match split_comment(&new_name, delimiter) {
+            (name, Some(comment)) => join_comment(name, Some(comment), delimiter),
+            (name, None) => join_comment(name, split_comment(current_name, delimiter).1, delimiter),
+        },
+        None => new_name,
+    }
+}
+
+/// Renames every task in `renames` as one change, after checking all of them
+fn modify_many(renames: &[(TaskIndex, String)], force: bool) {
+    let mut conn = establish_connection();
+
+    let tasks = match get_tasks_from_db(&conn) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    let mut changes: Vec<(Task, String)> = Vec::new();
+    let mut invalid = false;
+
+    for (task_index, new_name) in renames {
+        let Some(index) = resolve_task_index(&conn, task_index) else {
+            invalid = true;
+            continue;
+        };
+
+        let Some(task) = tasks.iter().find(|t| t.idx == Some(index)) else {
+            print_error(&tr("invalid-index", &[&index]));
+            invalid = true;
+            continue;
+        };
+
+        if changes.iter().any(|(t, _)| t.id == task.id) {
+            print_error(&format!("Error: Task {} is given more than once.", index));
+            invalid = true;
+        } else if new_name.trim().is_empty() {
+            print_error(&format!("Error: The new name of task {} cannot be empty or whitespace-only.", index));
+            invalid = true;
+        }
+
+        changes.push((task.clone(), keep_comment(&task.name, new_name.clone())));
+    }
+
+    if invalid {
+        return;
+    }
+
+    let other_tasks: Vec<Task> = tasks.iter().filter(|t| !changes.iter().any(|(task, _)| task.id == t.id)).cloned().collect();
+    let names = check_duplicates(&other_tasks, changes.iter().map(|(_, name)| name.clone()).collect(), force);
+    if names.len() < changes.len() {
+        return;
+    }
+
+    let changes: Vec<(Task, String)> = changes.into_iter().map(|(task, _)| task).zip(names).collect();
+
+    if let Err(e) = edit_tasks_in_db(&mut conn, &changes) {
+        print_error(&format!("Failed to modify tasks: {}", e));
+        return;
+    }
+
+    print_success(&tr("tasks-modified", &[&changes.len()]));
+    if !is_quiet() {
+        for (task, new_name) in &changes {
+            println!("  [{}] {} -> {}", index_label(task), sanitize(&task.name), sanitize(new_name));
+        }
+    }
+
+    let modified: Vec<serde_json::Value> = changes.iter()
+        .map(|(task, new_name)| serde_json::json!({ "id": task.id, "idx": task.idx, "old_name": task.name, "name": new_name }))
+        .collect();
+    print_result(serde_json::json!({ "action": "modify", "tasks": modified }));
+}
+
+/// The name of the task at `task_index`, or `None` after printing why there is none
+fn current_task_name(conn: &Connection, task_index: &i32) -> Option<String> {
+    if *task_index <= 0 || *task_index > get_tasks_length(conn) {
+        print_error(&tr("invalid-index", &[&task_index]));
+        return None;
+    }
+
+    match get_task_from_db(conn, task_index) {
+        Ok(Some(task)) => Some(task.name),
+        Ok(None) => {
+            print_error(&tr("invalid-index", &[&task_index]));
+            None
+        }
+        Err(e) => {
+            print_error(&format!("Failed to retrieve task {}: {}", task_index, e));
+            None
+        }
+    }
+}
+
+/// Saves `new_name` for the task at `task_index`, unless it is blank or (without `force`) names
+/// another task too
+fn rename_task(conn: &mut Connection, task_index: &i32, new_name: String, force: bool) {
+    if new_name.trim().is_empty() {
+        print_error(&tr("new-name-empty", &[]));
+        return;
+    }
+
+    let (task, other_tasks): (Vec<Task>, Vec<Task>) = match get_tasks_from_db(conn) {
+        Ok(tasks) => tasks.into_iter().partition(|t| t.idx == Some(*task_index)),
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    let Some(new_name) = check_duplicates(&other_tasks, vec![new_name], force).pop() else {
+        return;
+    };
+
+    match edit_task_in_db(conn, task_index, &new_name) {
+        Ok(_) => {
+            print_success(&tr("task-modified", &[&new_name]));
+            let (id, old_name) = (task.first().and_then(|t| t.id), task.first().map(|t| t.name.as_str()));
+            print_result(serde_json::json!({ "action": "modify", "tasks": [{ "id": id, "idx": task_index, "old_name": old_name, "name": new_name }] }));
+        },
+        Err(e) => print_error(&format!("Failed to modify task {}: {}", task_index, e)),
+    }
+}
+
+/// The editor `edit-all` opens: $VISUAL, then $EDITOR, then the system's usual one
+fn editor_command() -> String {
+    ["VISUAL", "EDITOR"].iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| String::from(if cfg!(windows) { "notepad" } else { "vi" }))
+}
+
+/// The pager long lists open in: $PAGER, or `less -R` so colours come through
+fn pager_command() -> String {
+    env::var("PAGER").ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| String::from("less -R"))
+}
+
+/// Prints `output`, through the pager if stdout is a terminal the output doesn't fit on. Falls
+/// back to printing it directly if the pager can't be started.
+fn print_paged(output: &str, no_pager: bool) {
+    // A height of 0 means the terminal doesn't know its size, so don't guess
+    let too_long = terminal::size().is_ok_and(|(_, height)| height > 0 && output.lines().count() >= height as usize);
+
+    if no_pager || !too_long || !io::stdout().is_terminal() {
+        print!("{}", output);
+        return;
+    }
+
+    let pager = pager_command();
+    let mut words = pager.split_whitespace();
+    let mut command = process::Command::new(words.next().unwrap_or_default());
+    command.args(words).stdin(process::Stdio::piped());
+
+    // A $PAGER of plain `less` would show the colour codes as text
+    if env::var_os("LESS").is_none() {
+        command.env("LESS", "R");
+    }
+
+    let Ok(mut child) = command.spawn() else {
+        print!("{}", output);
+        return;
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager closes its end when quit early, which isn't an error here
+        stdin.write_all(output.as_bytes()).ok();
+    }
+    child.wait().ok();
+}
+
+/// Writes tasks the way `edit-all` shows them: one per line, done ones starting with "x " as in
+/// todo.txt and subtasks indented under their parent
+fn format_edit_lines(tasks: &[Task]) -> String {
+    let ids: HashSet<i32> = tasks.iter().filter_map(|t| t.id).collect();
+
+    tasks.iter()
+        .map(|task| {
+            let indent = if task.parent_id.is_some_and(|parent_id| ids.contains(&parent_id)) { "  " } else { "" };
+            let mark = if task.done { "x " } else { "" };
+            format!("{}{}{}\n", indent, mark, task.name)
+        })
+        .collect()
+}
+
+/// Reads back an `edit-all` file as (name, done) pairs, skipping blank lines
+pub fn parse_edit_lines(contents: &str) -> Vec<(String, bool)> {
+    contents.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.strip_prefix("x ") {
+            Some(name) => (name.trim().to_string(), true),
+            None => (line.to_string(), false),
+        })
+        .collect()
+}
+
+/// Works out which of `tasks` each edited line stands for. Lines in the same order as before are
+/// matched up first, then lines moved elsewhere unchanged, then left over lines are taken as
+/// renames of the left over tasks between the same matched lines. Any other line is a new task,
+/// and tasks matched by no line were deleted.
+pub fn match_edit_lines(tasks: &[Task], lines: &[(String, bool)]) -> Vec<EditedTask> {
+    let (n, m) = (tasks.len(), lines.len());
+    let same = |i: usize, j: usize| tasks[i].name.trim() == lines[j].0;
+
+    // Longest common subsequence of the names, to find the lines that kept their place
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if same(i, j) { lengths[i + 1][j + 1] + 1 } else { lengths[i + 1][j].max(lengths[i][j + 1]) };
+        }
+    }
+
+    let mut line_task: Vec<Option<usize>> = vec![None; m];
+    let mut task_matched = vec![false; n];
+    let mut anchors = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if same(i, j) {
+            line_task[j] = Some(i);
+            task_matched[i] = true;
+            anchors.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    // Lines moved without being changed
+    for (j, task) in line_task.iter_mut().enumerate().filter(|(_, task)| task.is_none()) {
+        if let Some(i) = (0..n).find(|&i| !task_matched[i] && same(i, j)) {
+            *task = Some(i);
+            task_matched[i] = true;
+        }
+    }
+
+    // Renames: pair up what is left between each two lines that kept their place, the most alike
+    // first and then in order
+    anchors.push((n, m));
+    let (mut previous_i, mut previous_j) = (0, 0);
+    for &(anchor_i, anchor_j) in &anchors {
+        let left_tasks: Vec<usize> = (previous_i..anchor_i).filter(|&i| !task_matched[i]).collect();
+        let left_lines: Vec<usize> = (previous_j..anchor_j).filter(|&j| line_task[j].is_none()).collect();
+
+        let mut pairs: Vec<(usize, usize, usize)> = left_tasks.iter()
+            .flat_map(|&i| left_lines.iter().map(move |&j| (i, j)))
+            .map(|(i, j)| (shared_words(&tasks[i].name, &lines[j].0), i, j))
+            .filter(|&(shared, _, _)| shared > 0)
+            .collect();
+        pairs.sort_by(|a, b| b.0.cmp(&a.0).then((a.1, a.2).cmp(&(b.1, b.2))));
+
+        for (_, i, j) in pairs {
+            if !task_matched[i] && line_task[j].is_none() {
+                line_task[j] = Some(i);
+                task_matched[i] = true;
+            }
+        }
+
+        let left_tasks: Vec<usize> = left_tasks.into_iter().filter(|&i| !task_matched[i]).collect();
+        let left_lines: Vec<usize> = left_lines.into_iter().filter(|&j| line_task[j].is_none()).collect();
+        for (i, j) in left_tasks.into_iter().zip(left_lines) {
+            line_task[j] = Some(i);
+            task_matched[i] = true;
+        }
+
+        (previous_i, previous_j) = (anchor_i + 1, anchor_j + 1);
+    }
+
+    lines.iter()
+        .zip(line_task)
+        .map(|((name, done), i)| EditedTask { id: i.and_then(|i| tasks[i].id), name: name.clone(), done: *done })
+        .collect()
+}
+
+/// How many words two names have in common, ignoring case
+fn shared_words(a: &str, b: &str) -> usize {
+    let words: HashSet<String> = a.split_whitespace().map(str::to_lowercase).collect();
+    b.split_whitespace().map(str::to_lowercase).collect::<HashSet<_>>().intersection(&words).count()
+}
+
+/// What applying `edited` changes, like "2 added, 1 removed, 3 renamed"
+fn edit_all_summary(tasks: &[Task], edited: &[EditedTask]) -> String {
+    let by_id: HashMap<i32, &Task> = tasks.iter().map(|t| (t.id.unwrap(), t)).collect();
+    let kept: Vec<(&Task, &EditedTask)> = edited.iter().filter_map(|e| Some((*by_id.get(&e.id?)?, e))).collect();
+
+    let kept_order: Vec<i32> = kept.iter().map(|(task, _)| task.id.unwrap()).collect();
+    let original_order: Vec<i32> = tasks.iter().map(|t| t.id.unwrap()).filter(|id| kept_order.contains(id)).collect();
+
+    let counts = [
+        (edited.len() - kept.len(), "added"),
+        (tasks.len() - kept.len(), "removed"),
+        (kept.iter().filter(|(task, e)| task.name.trim() != e.name).count(), "renamed"),
+        (kept.iter().filter(|(task, e)| !task.done && e.done).count(), "marked done"),
+        (kept.iter().filter(|(task, e)| task.done && !e.done).count(), "marked todo"),
+    ];
+
+    let mut parts: Vec<String> = counts.iter().filter(|(count, _)| *count > 0).map(|(count, what)| format!("{} {}", count, what)).collect();
+    if kept_order != original_order {
+        parts.push(String::from("reordered"));
+    }
+
+    parts.join(", ")
+}
+
+/// The temporary file `edit-all` opens in the editor. It is only readable by the user, is never
+/// an existing file or symlink planted at its path, and is removed when dropped.
+struct EditFile(PathBuf);
+
+impl EditFile {
+    fn create(contents: &str) -> Result<Self, (PathBuf, io::Error)> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut attempts = 0;
+        loop {
+            let path = env::temp_dir().join(format!("todoln-edit-{}-{:08x}.txt", process::id(), rand::random::<u32>()));
+            let mut file = match options.open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempts < 10 => {
+                    attempts += 1;
+                    continue;
+                }
+                Err(e) => return Err((path, e)),
+            };
+
+            let edit_file = EditFile(path);
+            return match file.write_all(contents.as_bytes()) {
+                Ok(_) => Ok(edit_file),
+                Err(e) => Err((edit_file.0.clone(), e)),
+            };
+        }
+    }
+}
+
+impl Drop for EditFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.0).ok();
+    }
+}
+
+pub fn edit_all() {
+    let mut conn = establish_connection();
+
+    let tasks = match get_tasks_from_db_and_update_indices(&mut conn) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    if let Some(task) = tasks.iter().find(|t| t.name.contains(['\n', '\r'])) {
+        print_error(&format!("Error: Task {} has a line break in its name, so it can't be edited as one line.", task.idx.unwrap()));
+        return;
+    }
+
+    let original = format_edit_lines(&tasks);
+    let file = match EditFile::create(&original) {
+        Ok(file) => file,
+        Err((path, e)) => {
+            print_error(&format!("Failed to write '{}': {}", path.display(), e));
+            return;
+        }
+    };
+    let path = file.0.clone();
+
+    let editor = editor_command();
+    let mut words = editor.split_whitespace();
+    let status = process::Command::new(words.next().unwrap_or_default()).args(words).arg(&path).status();
+    let edited = fs::read_to_string(&path);
+    drop(file);
+
+    match status {
+        Ok(status) if status.success() => {},
+        Ok(status) => {
+            let reason = status.code().map_or_else(|| String::from("was stopped"), |code| format!("exited with status {}", code));
+            print_error(&format!("Error: '{}' {}, so nothing was changed.", editor, reason));
+            return;
+        }
+        Err(e) => {
+            print_error(&format!("Failed to start '{}': {}", editor, e));
+            return;
+        }
+    }
+
+    let edited = match edited {
+        Ok(edited) => edited,
+        Err(e) => {
+            print_error(&format!("Failed to read '{}': {}", path.display(), e));
+            return;
+        }
+    };
+
+    let edited = match_edit_lines(&tasks, &parse_edit_lines(&edited));
+    let summary = edit_all_summary(&tasks, &edited);
+
+    if summary.is_empty() {
+        print_notice(&tr("no-changes-made", &[]));
+        return;
+    }
+
+    match apply_edit_all_in_db(&mut conn, &edited, &summary) {
+        Ok(_) => print_success(&format!("Tasks updated successfully: {}", summary)),
+        Err(e) => print_error(&format!("Failed to update tasks: {}", e)),
+    }
+}
+
+/// Adds `text` to the end of a task's name, or with `prepend` to its start. A trailing comment
+/// stays at the end.
+pub fn extend_name(task_index: &TaskIndex, text: &str, prepend: bool, force: bool) {
+    let mut conn = establish_connection();
+    let Some(task_index) = &resolve_task_index(&conn, task_index) else {
+        return;
+    };
+
+    let Some(current_name) = current_task_name(&conn, task_index) else {
+        return;
+    };
+
+    let (name, comment) = match comment_delimiter() {
+        Some(delimiter) => split_comment(&current_name, delimiter),
+        None => (current_name.as_str(), None),
+    };
+    let name = if prepend { format!("{}{}", text, name) } else { format!("{}{}", name, text) };
+
+    let new_name = match comment_delimiter() {
+        Some(delimiter) => join_comment(&name, comment, delimiter),
+        None => name,
+    };
+
+    rename_task(&mut conn, task_index, new_name, force);
+}
+
+/// Names for `times` copies of `name`: "name (copy)", "name (copy 2)" and so on, skipping any
+/// that another task already has and keeping a trailing comment at the end
+fn copy_names(name: &str, existing: &[Task], times: usize) -> Vec<String> {
+    let (name, comment) = match comment_delimiter() {
+        Some(delimiter) => split_comment(name, delimiter),
+        None => (name, None),
+    };
+    let taken: HashSet<String> = existing.iter().map(|t| normalize_name(&t.name)).collect();
+
+    (1..)
+        .map(|n| if n == 1 { format!("{} (copy)", name) } else { format!("{} (copy {})", name, n) })
+        .map(|name| match comment_delimiter() {
+            Some(delimiter) => join_comment(&name, comment, delimiter),
+            None => name,
+        })
+        .filter(|name| !taken.contains(&normalize_name(name)))
+        .take(times)
+        .collect()
+}
+
+pub fn duplicate(task_index: &TaskIndex, times: usize) {
+    let mut conn = establish_connection();
+    let Some(task_index) = &resolve_task_index(&conn, task_index) else {
+        return;
+    };
+
+    let task = match get_task_from_db(&conn, task_index) {
+        Ok(Some(task)) => task,
+        Ok(None) => {
+            print_error(&tr("invalid-index", &[&task_index]));
+            return;
+        }
+        Err(e) => {
+            print_error(&format!("Failed to retrieve task {}: {}", task_index, e));
+            return;
+        }
+    };
+
+    let existing_tasks = match get_tasks_from_db(&conn) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    let names = copy_names(&task.name, &existing_tasks, times);
+    if let Err(e) = duplicate_task_in_db(&mut conn, &task, &names) {
+        print_error(&format!("Failed to duplicate task {}: {}", task_index, e));
+        return;
+    }
+
+    if let Err(e) = renormalize_task_indices(&mut conn) {
+        print_error(&format!("Failed to update task indices: {}", e));
+        return;
+    }
+
+    print_success(&tr("tasks-added", &[&names.join(", ")]));
+}
+
+pub enum DisplayType {
+    All,
+    Todo,
+    Done,
+    Snoozed,
+}
+
+impl DisplayType {
+    pub fn parse(s: &str) -> Option<DisplayType> {
+        match s.trim().to_lowercase().as_str() {
+            "all" => Some(DisplayType::All),
+            "todo" => Some(DisplayType::Todo),
+            "done" => Some(DisplayType::Done),
+            "snoozed" => Some(DisplayType::Snoozed),
+            _ => None,
+        }
+    }
+}
+
+/// The part of a filtered list that `--offset` and `--limit` leave
+#[derive(Clone, Copy, Default)]
+pub struct Page {
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl Page {
+    fn apply<T>(&self, items: Vec<T>) -> Vec<T> {
+        items.into_iter().skip(self.offset).take(self.limit.unwrap_or(usize::MAX)).collect()
+    }
+
+    /// How many of `total` items come after the page, if any
+    fn remaining(&self, total: usize) -> Option<usize> {
+        let end = self.offset.saturating_add(self.limit?);
+        (total > end).then(|| total - end)
+    }
+
+    /// A dimmed line saying how many of `total` items come after the page, if any
+    fn more_hint(&self, total: usize) -> Option<String> {
+        let end = self.offset.saturating_add(self.limit?);
+        self.remaining(total).map(|more| dim_text(&format!("{} more task(s), see --offset {}", more, end)))
+    }
+}
+
+/// Renders a task the way `list` shows it, with the index right-aligned to `idx_width`
+pub fn format_task_row(task: &Task, idx_width: usize) -> String {
+    let name = if task.done { done_text(&task.name) } else { todo_text(&task.name) };
+    format_task_row_with_name(task, &index_label(task), idx_width, &name)
+}
+
+fn format_task_row_with_name(task: &Task, label: &str, idx_width: usize, styled_name: &str) -> String {
+    let idx = bold_text(&format!("{:>idx_width$}", label));
+    let note_marker = if task.note.is_some() { format!(" {}", dim_text("*")) } else { String::new() };
+    format!("  [{}] {}{}", idx, styled_name, note_marker)
+}
+
+/// A task's index as shown to the user, or "-" for a row that has none until it is repaired,
+/// which commands that only read (with `stable_read`) can come across
+fn index_label(task: &Task) -> String {
+    task.idx.map_or(String::from("-"), |idx| idx.to_string())
+}
+
+pub fn idx_width(tasks: &[&Task]) -> usize {
+    tasks.iter().map(|t| index_label(t).len()).max().unwrap_or(1)
+}
+
+fn print_task_rows(tasks: &[&Task]) {
+    let idx_width = idx_width(tasks);
+
+    for task in tasks {
+        println!("{}", format_task_row(task, idx_width));
+    }
+}
+
+/// Renders a task for `list`, leaving out or dimming the comment in its name
+fn format_list_row(task: &Task, label: &str, idx_width: usize, comments: bool) -> String {
+    format_task_row_with_name(task, label, idx_width, &styled_list_name(task, comments))
+}
+
+/// A task's name styled as done or todo, followed by its dimmed comment with `comments`
+fn styled_list_name(task: &Task, comments: bool) -> String {
+    let (name, comment) = match comment_delimiter() {
+        Some(delimiter) => split_comment(&task.name, delimiter),
+        None => (task.name.as_str(), None),
+    };
+    let mut styled_name = if task.done { done_text(name) } else { todo_text(name) };
+
+    if let (Some(delimiter), Some(comment)) = (comment_delimiter(), comment.filter(|comment| comments && !comment.is_empty())) {
+        styled_name.push_str(&format!(" {}", dim_text(&format!("{} {}", delimiter, comment))));
+    }
+
+    styled_name
+}
+
+/// The glyph `list` shows before a task's name, from `status_glyphs`
+fn status_glyph(task: &Task) -> Option<(Style, &'static str)> {
+    let (done, todo) = match config().status_glyphs {
+        StatusGlyphs::Unicode => ("✔", "·"),
+        StatusGlyphs::Ascii => ("x", "-"),
+        StatusGlyphs::None => return None,
+    };
+
+    Some(if task.done { (Style::Success, done) } else { (Style::Dim, todo) })
+}
+
+/// The fewest columns a name is cut down to, however narrow the terminal
+const MIN_NAME_WIDTH: usize = 10;
+
+/// A `list` row fitted to `options.width`: the name cut short with "…", or with `options.wrap`
+/// carried on over lines indented past the index column. `suffixes` follow the name, each in its
+/// own style.
+fn format_fitted_row(task: &Task, label: &str, idx_width: usize, indent: &str, suffixes: &[(Style, String)], options: &ListOptions) -> String {
+    let glyph = status_glyph(task).map_or(String::new(), |(style, glyph)| format!("{} ", styled(style, glyph)));
+
+    let Some(width) = options.width else {
+        let suffix: String = suffixes.iter().map(|(style, text)| format!(" {}", styled(*style, text))).collect();
+        let name = format!("{}{}", glyph, styled_list_name(task, options.comments));
+        return format!("{}{}{}", indent, format_task_row_with_name(task, label, idx_width, &name), suffix);
+    };
+
+    let (name, comment) = match comment_delimiter() {
+        Some(delimiter) => split_comment(&task.name, delimiter),
+        None => (task.name.as_str(), None),
+    };
+    let name = sanitize(name);
+    let comment = comment_delimiter()
+        .zip(comment.filter(|comment| options.comments && !comment.is_empty()))
+        .map(|(delimiter, comment)| sanitize(&format!("{} {}", delimiter, comment)));
+    let name_style = if task.done { Style::Done } else { Style::Todo };
+
+    let prefix = format!("{}  [{}] {}", indent, bold_text(&format!("{:>idx_width$}", label)), glyph);
+    let prefix_width = indent.width() + idx_width.max(label.width()) + 5 + status_glyph(task).map_or(0, |(_, glyph)| glyph.width() + 1);
+
+    let mut tail: Vec<(Style, String)> = Vec::new();
+    if task.note.is_some() {
+        tail.push((Style::Dim, String::from("*")));
+    }
+    tail.extend(suffixes.iter().map(|(style, text)| (*style, sanitize(text))));
+
+    if !options.wrap {
+        let tail_width: usize = tail.iter().map(|(_, text)| 1 + text.width()).sum();
+        let budget = width.saturating_sub(prefix_width + tail_width).max(MIN_NAME_WIDTH);
+
+        let mut fitted = styled(name_style, &truncate_text(&name, budget));
+        if let Some(comment) = comment.filter(|_| name.width() + 2 < budget) {
+            fitted.push_str(&format!(" {}", dim_text(&truncate_text(&comment, budget - name.width() - 1))));
+        }
+        let tail: String = tail.iter().map(|(style, text)| format!(" {}", styled(*style, text))).collect();
+
+        return format!("{}{}{}", prefix, fitted, tail);
+    }
+
+    // Each line is styled on its own so a done task's strikethrough doesn't run into the indent
+    let budget = width.saturating_sub(prefix_width).max(MIN_NAME_WIDTH);
+    let mut lines: Vec<(String, usize)> = Vec::new();
+    let mut append = |text: &str, style: Style| {
+        for (i, piece) in wrap_text(text, budget).into_iter().enumerate() {
+            let piece_width = piece.width();
+            match lines.last_mut() {
+                Some((line, used)) if i == 0 && *used + 1 + piece_width <= budget => {
+                    line.push_str(&format!(" {}", styled(style, &piece)));
+                    *used += 1 + piece_width;
+                }
+                _ => lines.push((styled(style, &piece), piece_width)),
+            }
+        }
+    };
+
+    append(&name, name_style);
+    if let Some(comment) = &comment {
+        append(comment, Style::Dim);
+    }
+    for (style, text) in &tail {
+        append(text, *style);
+    }
+
+    let continuation = " ".repeat(prefix_width);
+    lines.iter()
+        .enumerate()
+        .map(|(i, (line, _))| format!("{}{}", if i == 0 { prefix.as_str() } else { continuation.as_str() }, line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Labels each task with its index, or with its parent's index and its position among the
+/// parent's subtasks (like `3.1`) if it is a subtask
+fn task_labels(tasks: &[Task]) -> HashMap<i32, String> {
+    let parent_indices: HashMap<i32, i32> = tasks.iter().filter_map(|t| Some((t.id?, t.idx?))).collect();
+    let mut subtask_counts: HashMap<i32, usize> = HashMap::new();
+    let mut labels = HashMap::new();
+
+    for task in tasks {
+        let label = match task.parent_id.and_then(|parent_id| Some((parent_id, parent_indices.get(&parent_id)?))) {
+            Some((parent_id, parent_idx)) => {
+                let count = subtask_counts.entry(parent_id).or_default();
+                *count += 1;
+                format!("{}.{}", parent_idx, count)
+            }
+            None => index_label(task),
+        };
+        labels.insert(task.id.unwrap(), label);
+    }
+
+    labels
+}
+
+/// The open tasks `task` waits for
+fn blocked_by<'a>(blockers: &'a [(i32, Task)], task: &Task) -> Vec<&'a Task> {
+    blockers.iter().filter(|(task_id, _)| Some(*task_id) == task.id).map(|(_, blocker)| blocker).collect()
+}
+
+/// The ids of the tasks `list` and `raw` leave out: those snoozed until later, and their subtasks
+fn snoozed_ids(tasks: &[Task]) -> HashSet<i32> {
+    let snoozed: HashSet<i32> = tasks.iter().filter(|t| is_snoozed(t.snoozed_until.as_deref())).filter_map(|t| t.id).collect();
+
+    tasks.iter()
+        .filter(|t| snoozed.contains(&t.id.unwrap()) || t.parent_id.is_some_and(|parent_id| snoozed.contains(&parent_id)))
+        .filter_map(|t| t.id)
+        .collect()
+}
+
+/// How `list` shows the tasks it lists
+#[derive(Clone, Copy, Default)]
+pub struct ListOptions {
+    /// Shows the comments after `comment_delimiter` in task names
+    pub comments: bool,
+    /// Also lists snoozed tasks
+    pub snoozed: bool,
+    pub page: Page,
+    /// Continues long names on the next lines instead of cutting them short
+    pub wrap: bool,
+    /// The terminal's width, or `None` when not printing to one so names are left as they are
+    pub width: Option<usize>,
+    /// Shows a table with more details instead of one task per line
+    pub long: bool,
+    /// Lists the tasks in sections instead of one list
+    pub group_by: Option<GroupBy>,
+}
+
+/// Everything `list` shows, read in one go
+struct ListData {
+    tasks: Vec<Task>,
+    blockers: Vec<(i32, Task)>,
+    /// Done and total subtasks by parent id
+    progress: HashMap<i32, (usize, usize)>,
+    /// The tasks matching `--where` and `--completed-in`, if either was given
+    matching: Option<HashSet<i32>>,
+    /// The task whose timer is running, with for how many seconds
+    tracked: Option<(i32, i64)>,
+    /// The `priority` field by task id
+    priorities: HashMap<i32, String>,
+    /// The tags in the `tags` field by task id
+    tags: HashMap<i32, Vec<String>>,
+}
+
+fn load_list(conn: &Connection, field_filters: &[(String, String)], completed_in: Option<Period>) -> Result<ListData, rusqlite::Error> {
+    let tasks = get_tasks_from_db(conn)?;
+    let mut matching = tasks_matching_fields(conn, field_filters)?;
+
+    if let Some(period) = completed_in {
+        let completed = tasks.iter().filter(|t| in_period(t.completed_at.as_deref(), period)).filter_map(|t| t.id);
+        matching = Some(match matching {
+            Some(ids) => completed.filter(|id| ids.contains(id)).collect(),
+            None => completed.collect(),
+        });
+    }
+
+    let mut priorities = HashMap::new();
+    let mut tags = HashMap::new();
+    for (task_id, key, value) in get_task_fields_from_db(conn)? {
+        match key.as_str() {
+            "priority" => { priorities.insert(task_id, value); },
+            "tags" => { tags.insert(task_id, parse_tags(&value)); },
+            _ => {},
+        }
+    }
+
+    Ok(ListData {
+        tasks,
+        blockers: get_open_blockers_from_db(conn)?,
+        progress: get_subtask_progress_from_db(conn)?.into_iter().map(|(parent_id, done, total)| (parent_id, (done, total))).collect(),
+        matching,
+        tracked: get_running_timer_from_db(conn)?.and_then(|(task, seconds)| Some((task.id?, seconds))),
+        priorities,
+        tags,
+    })
+}
+
+/// The tags in a `tags` field, e.g. `work, home`, without blanks or repeats
+fn parse_tags(value: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in value.split(',').map(str::trim).filter(|tag| !tag.is_empty()) {
+        if !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+/// The sections `list --group-by` prints, as a heading and its tasks in list order, leaving out
+/// empty ones. Tasks without a tag or priority go in a last "Other" section
+fn group_tasks<'a>(tasks: &[&'a Task], group_by: GroupBy, priorities: &HashMap<i32, String>, tags: &HashMap<i32, Vec<String>>) -> Vec<(String, Vec<&'a Task>)> {
+    let mut sections: Vec<(String, Vec<&'a Task>)> = Vec::new();
+    let mut other: Vec<&'a Task> = Vec::new();
+
+    match group_by {
+        GroupBy::Status => {
+            let (done, todo): (Vec<&Task>, Vec<&Task>) = tasks.iter().partition(|t| t.done);
+            sections.push((tr("group-todo", &[]), todo));
+            sections.push((tr("group-done", &[]), done));
+        },
+        GroupBy::Priority => {
+            let mut groups: BTreeMap<(u8, i64, String), (String, Vec<&Task>)> = BTreeMap::new();
+            for &task in tasks {
+                match priorities.get(&task.id.unwrap()) {
+                    Some(priority) => groups.entry(priority_rank(Some(priority))).or_insert_with(|| (priority.trim().to_string(), Vec::new())).1.push(task),
+                    None => other.push(task),
+                }
+            }
+            sections.extend(groups.into_values().map(|(priority, tasks)| (tr("group-priority", &[&sanitize(&priority)]), tasks)));
+        },
+        GroupBy::Tag => {
+            let mut groups: BTreeMap<&str, Vec<&Task>> = BTreeMap::new();
+            for &task in tasks {
+                match tags.get(&task.id.unwrap()).filter(|tags| !tags.is_empty()) {
+                    Some(task_tags) => task_tags.iter().for_each(|tag| groups.entry(tag.as_str()).or_default().push(task)),
+                    None => other.push(task),
+                }
+            }
+            sections.extend(groups.into_iter().map(|(tag, tasks)| (tr("group-tag", &[&sanitize(tag)]), tasks)));
+        },
+    }
+
+    sections.push((tr("group-other", &[]), other));
+    sections.retain(|(_, tasks)| !tasks.is_empty());
+    sections
+}
+
+/// Builds exactly what `list` prints, so `list --watch` can redraw the same output. Snoozed
+/// tasks are left out unless `options.snoozed` is set or only they are listed.
+fn render_list(data: &ListData, display_type: &DisplayType, options: ListOptions) -> String {
+    let ListOptions { snoozed: show_snoozed, page, .. } = options;
+    let ListData { tasks, blockers, progress, matching, tracked, priorities, tags } = data;
+    let snoozed = snoozed_ids(tasks);
+
+    let (title, shown): (&str, Vec<&Task>) = match display_type {
+        DisplayType::All => ("title-tasks", tasks.iter().collect()),
+        DisplayType::Todo => ("title-tasks-todo", tasks.iter().filter(|t| !t.done).collect()),
+        DisplayType::Done => ("title-tasks-done", tasks.iter().filter(|t| t.done).collect()),
+        DisplayType::Snoozed => ("title-tasks-snoozed", tasks.iter().filter(|t| snoozed.contains(&t.id.unwrap())).collect()),
+    };
+    let show_snoozed = show_snoozed || matches!(display_type, DisplayType::Snoozed);
+    let shown: Vec<&Task> = shown.into_iter().filter(|t| matching.as_ref().map_or(true, |ids| ids.contains(&t.id.unwrap()))).collect();
+    let listed_done = shown.iter().filter(|t| t.done).count();
+    let (shown, hidden): (Vec<&Task>, Vec<&Task>) = shown.into_iter().partition(|t| show_snoozed || !snoozed.contains(&t.id.unwrap()));
+    let total = shown.len();
+    let shown = page.apply(shown);
+
+    let hidden_hint = if hidden.is_empty() {
+        String::new()
+    } else {
+        format!("\n{}\n", dim_text(&format!("{} snoozed task(s) hidden, see `todoln list snoozed`", hidden.len())))
+    };
+
+    let mut output = String::new();
+
+    if shown.is_empty() {
+        output.push_str(&empty_text(&tr("no-tasks-found", &[])));
+        if !is_quiet() {
+            output.push_str(&hidden_hint);
+        }
+        return output;
+    }
+
+    if !is_quiet() && options.group_by.is_none() {
+        output.push_str(&format!("{}\n\n", title_text(&tr(title, &[]))));
+    }
+
+    let labels = task_labels(tasks);
+    let is_subtask = |task: &Task| labels[&task.id.unwrap()].contains('.');
+    let label_width = |subtasks: bool| shown.iter().filter(|&&t| is_subtask(t) == subtasks).map(|t| labels[&t.id.unwrap()].len()).max().unwrap_or(1);
+    let (idx_width, subtask_width) = (label_width(false), label_width(true));
+
+    let list_row = |task: &Task| {
+        let label = &labels[&task.id.unwrap()];
+        let mut suffixes: Vec<(Style, String)> = Vec::new();
+
+        if let Some((done, total)) = progress.get(&task.id.unwrap()) {
+            suffixes.push((Style::Dim, format!("[{}/{}]", done, total)));
+        }
+
+        if let Some((_, seconds)) = tracked.filter(|(id, _)| Some(*id) == task.id) {
+            suffixes.push((Style::Success, format!("(tracking {})", format_duration(seconds))));
+        }
+
+        if let Some(rule) = &task.recurrence {
+            suffixes.push((Style::Dim, format!("(every {})", rule)));
+        }
+
+        if let Some(wake) = task.snoozed_until.as_deref().filter(|wake| is_snoozed(Some(wake))) {
+            suffixes.push((Style::Dim, format!("(snoozed until {})", format_local_timestamp(wake))));
+        }
+
+        let blocked_by: Vec<&str> = blocked_by(blockers, task).iter().map(|blocker| labels[&blocker.id.unwrap()].as_str()).collect();
+        if !task.done && !blocked_by.is_empty() {
+            suffixes.push((Style::Dim, format!("(blocked by {})", blocked_by.join(", "))));
+        }
+
+        let row = if is_subtask(task) {
+            format_fitted_row(task, label, subtask_width, "  ", &suffixes, &options)
+        } else {
+            format_fitted_row(task, label, idx_width, "", &suffixes, &options)
+        };
+
+        format!("{}\n", row)
+    };
+
+    let render_rows = |tasks: &[&Task]| if options.long {
+        render_long_list(tasks, &labels, priorities, options.width)
+    } else {
+        tasks.iter().map(|task| list_row(task)).collect()
+    };
+
+    match options.group_by {
+        None => output.push_str(&render_rows(&shown)),
+        Some(group_by) => {
+            for (i, (heading, tasks)) in group_tasks(&shown, group_by, priorities, tags).iter().enumerate() {
+                if !is_quiet() {
+                    output.push_str(&format!("{}{}\n\n", if i > 0 { "\n" } else { "" }, title_text(heading)));
+                }
+                output.push_str(&render_rows(tasks));
+            }
+        },
+    }
+
+    if let Some(more_hint) = page.more_hint(total) {
+        output.push_str(&format!("\n{}\n", more_hint));
+    }
+
+    output.push_str(&hidden_hint);
+
+    if matches!(display_type, DisplayType::All) && !is_quiet() {
+        let listed = total + hidden.len();
+        output.push_str(&format!("\n{}\n", dim_text(&tr("list-summary", &[&listed_done, &listed, &((listed_done * 100 + listed / 2) / listed)]))));
+    }
+
+    output
+}
+
+/// The rows of `list --long`: a table with a column for each detail that any of `tasks` has,
+/// where the names are cut short first to fit in `width`
+fn render_long_list(tasks: &[&Task], labels: &HashMap<i32, String>, priorities: &HashMap<i32, String>, width: Option<usize>) -> String {
+    let now = Utc::now();
+    let rows: Vec<[(Style, String); 6]> = tasks.iter().map(|task| {
+        let id = task.id.unwrap();
+        let indent = if labels[&id].contains('.') { "  " } else { "" };
+        let overdue = !task.done && task.due_at.as_deref().and_then(parse_timestamp).is_some_and(|due| due <= now);
+
+        [
+            (Style::Bold, labels[&id].clone()),
+            (Style::Todo, String::from(if task.done { "[x]" } else { "[ ]" })),
+            (Style::Todo, priorities.get(&id).map_or(String::new(), |priority| sanitize(priority))),
+            (if task.done { Style::Done } else { Style::Todo }, format!("{}{}", indent, sanitize(&task.name))),
+            (if overdue { Style::Error } else { Style::Todo }, task.due_at.as_deref().map_or(String::new(), format_local_timestamp)),
+            (Style::Dim, task.created_at.as_deref().and_then(parse_timestamp).map_or(String::new(), |created| format_age((now - created).num_seconds()))),
+        ]
+    }).collect();
+
+    let headers = ["#", "", "Priority", "Name", "Due", "Age"];
+    let mut widths: Vec<usize> = (0..headers.len())
+        .map(|column| rows.iter().map(|row| row[column].1.width()).max().unwrap_or(0))
+        .collect();
+    // The status column is always shown, the other details only when some task has them
+    let shown: Vec<usize> = (0..headers.len()).filter(|&column| column == 1 || column == 3 || widths[column] > 0).collect();
+
+    for &column in &shown {
+        widths[column] = widths[column].max(headers[column].width());
+    }
+
+    if let Some(width) = width {
+        let others: usize = shown.iter().filter(|&&column| column != 3).map(|&column| widths[column] + 2).sum();
+        widths[3] = widths[3].min(width.saturating_sub(others + 2).max(MIN_NAME_WIDTH));
+    }
+
+    let line = |cells: Vec<(Style, String)>| -> String {
+        let cells: Vec<String> = shown.iter().zip(cells).map(|(&column, (style, text))| {
+            let text = if column == 3 { truncate_text(&text, widths[column]) } else { text };
+            let padding = " ".repeat(widths[column] - text.width());
+
+            // A subtask's indent is kept out of the styling so a done one isn't struck through
+            let name = text.trim_start();
+            let cell = if name.is_empty() { text.clone() } else { format!("{}{}", &text[..text.len() - name.len()], styled(style, name)) };
+
+            // Indices are right-aligned as in the plain list, everything else left-aligned
+            if column == 0 { format!("{}{}", padding, cell) } else { format!("{}{}", cell, padding) }
+        }).collect();
+
+        format!("  {}", cells.join("  ")).trim_end().to_string()
+    };
+
+    let mut output = String::new();
+    output.push_str(&format!("{}\n", line(shown.iter().map(|&column| (Style::Bold, headers[column].to_string())).collect())));
+
+    for row in rows {
+        let cells: Vec<(Style, String)> = row.into_iter().enumerate().filter(|(column, _)| shown.contains(column)).map(|(_, cell)| cell).collect();
+        output.push_str(&format!("{}\n", line(cells)));
+    }
+
+    output
+}
+
+/// Redraws the list whenever the database changes, until q, Esc or Ctrl-C is pressed. Only reads
+/// between changes, so other todoln invocations can keep modifying tasks.
+fn watch_list(display_type: &DisplayType, field_filters: &[(String, String)], completed_in: Option<Period>, options: ListOptions) -> Result<(), String> {
+    let conn = open_connection();
+    let db_path = validate_file_path(&db_path(), false).map_err(|e| e.to_string())?;
+    let watch_dir = db_path.parent().map(PathBuf::from).unwrap_or_default();
+    let db_name = db_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let wal_name = format!("{}-wal", db_name);
+
+    let (sender, receiver) = mpsc::channel::<notify::Result<notify::Event>>();
+
+    // Some file systems (network mounts, WSL) don't deliver notifications, so fall back to polling
+    let mut watcher: Box<dyn Watcher> = match notify::recommended_watcher(sender.clone()) {
+        Ok(watcher) => Box::new(watcher),
+        Err(_) => Box::new(PollWatcher::new(sender.clone(), notify::Config::default().with_poll_interval(Duration::from_secs(1))).map_err(|e| e.to_string())?),
+    };
+
+    if watcher.watch(&watch_dir, RecursiveMode::NonRecursive).is_err() {
+        watcher = Box::new(PollWatcher::new(sender, notify::Config::default().with_poll_interval(Duration::from_secs(1))).map_err(|e| e.to_string())?);
+        watcher.watch(&watch_dir, RecursiveMode::NonRecursive).map_err(|e| e.to_string())?;
+    }
+
+    let mut stdout = io::stdout();
+    let _raw_mode = RawModeGuard::enable().map_err(|e| e.to_string())?;
+    let _screen = AlternateScreenGuard::enter(&mut stdout).map_err(|e| e.to_string())?;
+
+    let mut redraw = true;
+
+    loop {
+        if redraw {
+            let options = ListOptions { width: terminal_width(), ..options };
+            let output = match load_list(&conn, field_filters, completed_in) {
+                Ok(data) => render_list(&data, display_type, options),
+                Err(e) => format!("Failed to retrieve tasks: {}\n", e),
+            };
+
+            // Raw mode doesn't turn \n into a new line at the start of the row
+            execute!(stdout, Clear(ClearType::All), MoveTo(0, 0), Print(output.replace('\n', "\r\n"))).map_err(|e| e.to_string())?;
+            redraw = false;
+        }
+
+        if event::poll(Duration::from_millis(100)).map_err(|e| e.to_string())? {
+            match event::read().map_err(|e| e.to_string())? {
+                Event::Key(KeyEvent { code, modifiers, kind: KeyEventKind::Press, .. }) => match code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                    _ => {},
+                },
+                Event::Resize(_, _) => redraw = true,
+                _ => {},
+            }
+        }
+
+        while let Ok(event) = receiver.try_recv() {
+            let touches_db = event.is_ok_and(|event| {
+                event.paths.iter().any(|path| path.file_name().is_some_and(|name| name == db_name.as_str() || name == wal_name.as_str()))
+            });
+            redraw |= touches_db;
+        }
+    }
+}
+
+/// How many columns the terminal stdout goes to has, or `None` if it isn't a terminal
+fn terminal_width() -> Option<usize> {
+    io::stdout().is_terminal()
+        .then(|| terminal::size().ok())
+        .flatten()
+        .filter(|(columns, _)| *columns > 0)
+        .map(|(columns, _)| columns as usize)
+}
+
+/// The ids of the tasks that have every one of `field_filters`, or `None` if there are no filters
+fn tasks_matching_fields(conn: &Connection, field_filters: &[(String, String)]) -> Result<Option<HashSet<i32>>, rusqlite::Error> {
+    if field_filters.is_empty() {
+        return Ok(None);
+    }
+
+    let fields = get_task_fields_from_db(conn)?;
+    let mut matching: Option<HashSet<i32>> = None;
+
+    for (key, value) in field_filters {
+        let ids: HashSet<i32> = fields.iter().filter(|(_, k, v)| k == key && v == value).map(|(task_id, _, _)| *task_id).collect();
+        matching = Some(match matching {
+            Some(matching) => matching.intersection(&ids).copied().collect(),
+            None => ids,
+        });
+    }
+
+    Ok(matching)
+}
+
+pub fn list(display_type: &str, watch: bool, field_filters: &[(String, String)], completed_in: Option<Period>, options: ListOptions, no_pager: bool) {
+    let Some(display_type) = DisplayType::parse(display_type) else {
+        println!("Invalid display type");
+        return;
+    };
+
+    if watch {
+        if !is_interactive() {
+            print_error("Error: --watch needs a terminal.");
+            process::exit(1);
+        }
+
+        if let Err(e) = watch_list(&display_type, field_filters, completed_in, options) {
+            print_error(&format!("Failed to watch the task database: {}", e));
+            process::exit(1);
+        }
+        return;
+    }
+
+    let conn = establish_read_connection();
+    let options = ListOptions { width: terminal_width(), ..options };
+
+    match load_list(&conn, field_filters, completed_in) {
+        Ok(data) => print_paged(&render_list(&data, &display_type, options), no_pager),
+        Err(e) => print_error(&tr("failed-to-retrieve-tasks", &[&e])),
+    }
+}
+
+pub fn raw(display_type: &str, copy: bool, template: &RawTemplate) {
+    let conn = establish_read_connection();
+
+    let fields: HashMap<(i32, String), String> = if template.uses_fields() {
+        match get_task_fields_from_db(&conn) {
+            Ok(fields) => fields.into_iter().map(|(task_id, key, value)| ((task_id, key), value)).collect(),
+            Err(e) => {
+                print_error(&format!("Failed to retrieve task fields: {}", e));
+                return;
+            }
+        }
+    } else {
+        HashMap::new()
+    };
+
+    match get_tasks_from_db(&conn) {
+        Ok(tasks) => {        
+            let ids: HashSet<i32> = tasks.iter().filter_map(|t| t.id).collect();
+            let snoozed = snoozed_ids(&tasks);
+            let raw_line = |task: &Task| {
+                let indented = task.parent_id.is_some_and(|parent_id| ids.contains(&parent_id));
+                template.render(task, indented, &fields)
+            };
+
+            if let Some(display_type) = DisplayType::parse(display_type) {
+                let lines: Vec<String> = tasks.iter()
+                    .filter(|t| {
+                        let is_snoozed = snoozed.contains(&t.id.unwrap());
+                        match display_type {
+                            DisplayType::All => !is_snoozed,
+                            DisplayType::Todo => !t.done && !is_snoozed,
+                            DisplayType::Done => t.done && !is_snoozed,
+                            DisplayType::Snoozed => is_snoozed,
+                        }
+                    })
+                    .map(raw_line)
+                    .collect();
+
+                // Piped output stays empty, so scripts don't read the notice as a task
+                if lines.is_empty() && io::stdout().is_terminal() {
+                    print!("{}", empty_text(&tr("no-tasks-found", &[])));
+                }
+
+                for line in &lines {
+                    println!("{}", line);
+                }
+
+                if copy {
+                    copy_lines(&lines);
+                }
+            }         
+        }
+        Err(e) => print_error(&tr("failed-to-retrieve-tasks", &[&e])),
+    }
+}
+
+/// Puts `lines` on the clipboard, one per line
+fn copy_lines(lines: &[String]) {
+    let mut text = lines.join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+
+    if let Err(e) = clipboard::copy(&text) {
+        print_error(&format!("Failed to copy to the clipboard: {}", e));
+    }
+}
+
+pub fn show(task_index: &TaskIndex, json: bool, escaped: bool) {
+    let conn = establish_read_connection();
+    let Some(task_index) = &resolve_task_index(&conn, task_index) else {
+        return;
+    };
+
+    let task = match get_task_from_db(&conn, task_index) {
+        Ok(Some(task)) => task,
+        Ok(None) => {
+            print_error(&tr("no-task-at-index", &[task_index]));
+            process::exit(1);
+        }
+        Err(e) => {
+            print_error(&format!("Failed to retrieve task {}: {}", task_index, e));
+            process::exit(1);
+        }
+    };
+
+    let custom_fields: BTreeMap<String, String> = match get_task_fields_from_db(&conn) {
+        Ok(fields) => fields.into_iter().filter(|(task_id, _, _)| Some(*task_id) == task.id).map(|(_, key, value)| (key, value)).collect(),
+        Err(e) => {
+            print_error(&format!("Failed to retrieve the fields of task {}: {}", task_index, e));
+            process::exit(1);
+        }
+    };
+
+    if json {
+        let completed_at = task.completed_at.as_deref().and_then(parse_timestamp).map(|t| t.to_rfc3339());
+        let due_at = task.due_at.as_deref().and_then(parse_timestamp).map(|t| t.to_rfc3339());
+        let created_at = task.created_at.as_deref().and_then(parse_timestamp).map(|t| t.to_rfc3339());
+        let snoozed_until = task.snoozed_until.as_deref().filter(|wake| is_snoozed(Some(wake))).and_then(parse_timestamp).map(|t| t.to_rfc3339());
+        let object = serde_json::json!({
+            "id": task.id,
+            "idx": task.idx,
+            "name": task.name,
+            "done": task.done,
+            "created_at": created_at,
+            "completed_at": completed_at,
+            "due_at": due_at,
+            "note": task.note,
+            "recurrence": task.recurrence,
+            "snoozed_until": snoozed_until,
+            "fields": custom_fields,
+        });
+        println!("{}", object);
+        return;
+    }
+
+    // The name is what was asked for, so unlike other titles it shows with `--quiet` too
+    println!("{}", title_text(&task.name));
+    println!();
+
+    let mut fields: Vec<(&str, String)> = vec![
+        ("Index", task.idx.unwrap().to_string()),
+        ("Status", String::from(if task.done { "done" } else { "todo" })),
+    ];
+
+    if let Some(created_at) = &task.created_at {
+        fields.push(("Created", format_local_timestamp(created_at)));
+    }
+
+    if let Some(completed_at) = &task.completed_at {
+        fields.push(("Completed", format_local_timestamp(completed_at)));
+    }
+
+    if let Some(due_at) = &task.due_at {
+        fields.push(("Due", format_local_timestamp(due_at)));
+    }
+
+    if let Some(wake) = task.snoozed_until.as_deref().filter(|wake| is_snoozed(Some(wake))) {
+        fields.push(("Snoozed", format!("until {} (hidden from list and raw until then)", format_local_timestamp(wake))));
+    }
+
+    let progress = get_subtask_progress_from_db(&conn).map(|progress| progress.into_iter().find(|(parent_id, _, _)| Some(*parent_id) == task.id));
+    match progress {
+        Ok(Some((_, done, total))) => fields.push(("Progress", format!("{} {}/{} subtasks done", progress_bar(done, total, 20), done, total))),
+        Ok(None) => {},
+        Err(e) => {
+            print_error(&format!("Failed to retrieve the subtasks of task {}: {}", task_index, e));
+            process::exit(1);
+        }
+    }
+
+    if let Some(rule) = &task.recurrence {
+        fields.push(("Repeats", format!("every {} (completing it adds a fresh copy, due at the next repeat)", rule)));
+    }
+
+    if escaped {
+        fields.push(("Raw name", escape_text(&task.name)));
+    }
+
+    let label_width = fields.iter().map(|(label, _)| label.len()).chain(custom_fields.keys().map(String::len)).max().unwrap_or(0) + 1;
+    for (label, value) in fields {
+        println!("  {} {}", bold_text(&format!("{:<label_width$}", format!("{}:", label))), value);
+    }
+
+    if !custom_fields.is_empty() {
+        println!();
+        for (key, value) in &custom_fields {
+            println!("  {} {}", bold_text(&format!("{:<label_width$}", format!("{}:", key))), sanitize(value));
+        }
+    }
+
+    if let Some(note) = &task.note {
+        println!();
+        print_note(note);
+    }
+}
+
+/// Prints a note indented and wrapped to the terminal
+fn print_note(note: &str) {
+    let width = terminal::size().map_or(80, |(columns, _)| columns as usize);
+
+    for line in wrap_text(note, width.saturating_sub(4)) {
+        println!("  {}", sanitize(&line));
+    }
+}
+
+pub fn note(task_index: &TaskIndex, note: &Option<String>, clear: bool) {
+    let mut conn = if note.is_none() && !clear { establish_read_connection() } else { establish_connection() };
+    let Some(task_index) = &resolve_task_index(&conn, task_index) else {
+        return;
+    };
+
+    let Some(note) = note else {
+        if clear {
+            match set_task_note_in_db(&mut conn, task_index, None) {
+                Ok(false) => print_error(&tr("no-task-at-index", &[task_index])),
+                Ok(true) => print_success(&format!("Note of task {} removed successfully", task_index)),
+                Err(e) => print_error(&format!("Failed to remove the note of task {}: {}", task_index, e)),
+            }
+            return;
+        }
+
+        match get_task_from_db(&conn, task_index) {
+            Ok(Some(Task { note: Some(note), .. })) => print_note(&note),
+            Ok(Some(_)) => print_notice(&tr("task-has-no-note", &[task_index])),
+            Ok(None) => print_error(&tr("no-task-at-index", &[task_index])),
+            Err(e) => print_error(&format!("Failed to retrieve task {}: {}", task_index, e)),
+        }
+        return;
+    };
+
+    if note.trim().is_empty() {
+        print_error("Error: Notes cannot be empty or whitespace-only, use --clear to remove one.");
+        return;
+    }
+
+    match set_task_note_in_db(&mut conn, task_index, Some(note)) {
+        Ok(false) => print_error(&tr("no-task-at-index", &[task_index])),
+        Ok(true) => print_success(&format!("Note of task {} set successfully", task_index)),
+        Err(e) => print_error(&format!("Failed to set the note of task {}: {}", task_index, e)),
+    }
+}
+
+/// How `find` and `rename-all` match `search_term`, or `None` after printing why the regex is invalid
+fn search_mode(search_term: &str, regex: bool, case_sensitive: bool) -> Option<SearchMode> {
+    if !regex {
+        return Some(SearchMode::Substring { case_sensitive });
+    }
+
+    match RegexBuilder::new(search_term).case_insensitive(!case_sensitive).build() {
+        Ok(regex) => Some(SearchMode::Regex(regex)),
+        Err(e) => {
+            print_error("Error: Invalid regular expression");
+            for line in e.to_string().lines() {
+                print_error(line);
+            }
+            None
+        }
+    }
+}
+
+/// How `find` shows the tasks it finds
+#[derive(Clone)]
+pub struct FindOptions {
+    /// Prints each match as a tab-separated `idx\tdone\tname\tid` line for scripts
+    pub porcelain: bool,
+    /// Also copies the names of the matching tasks to the clipboard
+    pub copy: bool,
+    pub page: Page,
+    pub no_pager: bool,
+    /// Where to look for the search term
+    pub fields: Vec<SearchField>,
+}
+
+/// A field of a task that `find` found the search term in, its text and the byte ranges matched
+type FieldHit<'a> = (SearchField, &'a str, Vec<(usize, usize)>);
+
+/// How many characters of a note `find` shows on either side of the first match
+const EXCERPT_CONTEXT: usize = 20;
+
+/// The part of `text` around the first of the byte `ranges`, on one line and with `…` where it
+/// was cut off, along with the ranges that fall inside it moved to match
+fn excerpt(text: &str, ranges: &[(usize, usize)]) -> (String, Vec<(usize, usize)>) {
+    let (first_start, first_end) = ranges.first().copied().unwrap_or_default();
+    let start = text[..first_start].char_indices().rev().nth(EXCERPT_CONTEXT - 1).map_or(0, |(i, _)| i);
+    let end = text[first_end..].char_indices().nth(EXCERPT_CONTEXT).map_or(text.len(), |(i, _)| first_end + i);
+
+    let prefix = if start > 0 { "…" } else { "" };
+    let suffix = if end < text.len() { "…" } else { "" };
+    // Line breaks and tabs become spaces of the same length, so the ranges still line up
+    let window = text[start..end].replace(['\n', '\r', '\t'], " ");
+
+    let shifted = ranges.iter()
+        .filter(|(range_start, range_end)| *range_start >= start && *range_end <= end)
+        .map(|(range_start, range_end)| (range_start - start + prefix.len(), range_end - start + prefix.len()))
+        .collect();
+
+    (format!("{}{}{}", prefix, window, suffix), shifted)
+}
+
+pub fn find(search_term: &str, regex: bool, case_sensitive: bool, subset: &str, options: FindOptions) {
+    let FindOptions { porcelain, copy, page, no_pager, fields } = options;
+
+    let Some(mode) = search_mode(search_term, regex, case_sensitive) else {
+        return;
+    };
+
+    let Some(subset) = TaskSubset::parse(subset) else {
+        print_error(&format!("Error: Invalid task subset '{}', expected all, todo or done.", subset));
+        return;
+    };
+
+    let conn = establish_read_connection();
+
+    let tags: HashMap<i32, String> = if fields.contains(&SearchField::Tags) {
+        match get_task_fields_from_db(&conn) {
+            Ok(task_fields) => task_fields.into_iter().filter(|(_, key, _)| key == "tags").map(|(task_id, _, value)| (task_id, value)).collect(),
+            Err(e) => {
+                print_error(&format!("Failed to find tasks: {}", e));
+                return;
+            }
+        }
+    } else {
+        HashMap::new()
+    };
+
+    match find_tasks_in_fields_from_db(&conn, search_term, &mode, subset, &fields) {
+        Ok(tasks_found) => {        
+            let total = tasks_found.len();
+            let tasks_found = page.apply(tasks_found);
+            let names: Vec<String> = tasks_found.iter().map(|task| sanitize(&task.name)).collect();
+
+            if tasks_found.is_empty() && !porcelain {
+                print!("{}", empty_text(&tr("no-matches", &[&search_term])));
+            } else if porcelain {
+                for task in &tasks_found {
+                    println!("{}\t{}\t{}\t{}", index_label(task), task.done as i32, sanitize(&task.name), task.id.unwrap());
+                }
+            } else {
+                let idx_width = idx_width(&tasks_found.iter().collect::<Vec<_>>());
+                let color = !plain_output() && io::stdout().is_terminal();
+                let mut output = String::new();
+
+                for task in &tasks_found {
+                    let hits: Vec<FieldHit> = fields.iter()
+                        .filter_map(|field| {
+                            let text = match field {
+                                SearchField::Name => Some(task.name.as_str()),
+                                SearchField::Note => task.note.as_deref(),
+                                SearchField::Tags => tags.get(&task.id.unwrap()).map(String::as_str),
+                            }?;
+                            let ranges = mode.match_ranges(search_term, text);
+                            (!ranges.is_empty()).then_some((*field, text, ranges))
+                        })
+                        .collect();
+
+                    let name_ranges = hits.iter().find(|(field, _, _)| *field == SearchField::Name).map_or(&[][..], |(_, _, ranges)| ranges);
+                    let mut row = format_task_row_with_name(task, &index_label(task), idx_width, &highlight_matches(&task.name, task.done, name_ranges, color));
+
+                    if task.done {
+                        row.push_str(&format!(" {}", dim_text("(done)")));
+                    }
+                    if let Some(wake) = task.snoozed_until.as_deref().filter(|wake| is_snoozed(Some(wake))) {
+                        row.push_str(&format!(" {}", dim_text(&format!("(snoozed until {})", format_local_timestamp(wake)))));
+                    }
+                    // Searching names alone, every match is in the name and needs no label
+                    if fields != [SearchField::Name] {
+                        let labels: Vec<&str> = hits.iter().map(|(field, _, _)| field.label()).collect();
+                        row.push_str(&format!(" {}", dim_text(&format!("[{}]", labels.join(", ")))));
+                    }
+                    output.push_str(&format!("{}\n", row));
+
+                    for (field, text, ranges) in hits.iter().filter(|(field, _, _)| *field != SearchField::Name) {
+                        let (text, ranges) = if *field == SearchField::Note { excerpt(text, ranges) } else { (text.to_string(), ranges.clone()) };
+                        output.push_str(&format!("{}{} {}\n", " ".repeat(idx_width + 5), dim_text(&format!("{}:", field.label())), highlight_matches(&text, false, &ranges, color)));
+                    }
+                }
+
+                let mut summary = format!("{} {}", total, if total == 1 { "match" } else { "matches" });
+                if let Some(more) = page.remaining(total) {
+                    summary.push_str(&format!(", {} more not shown", more));
+                }
+                output.push_str(&format!("\n{}\n", dim_text(&summary)));
+
+                print_paged(&output, no_pager);
+            }
+
+            if copy {
+                copy_lines(&names);
+            }
+        }
+        Err(e) => print_error(&format!("Failed to find tasks: {}", e)),
+    }
+}
+
+pub fn rename_all(pattern: &str, replacement: &str, regex: bool, case_sensitive: bool, yes: bool) {
+    let Some(mode) = search_mode(pattern, regex, case_sensitive) else {
+        return;
+    };
+
+    let mut conn = establish_connection();
+
+    let renames: Vec<(Task, String)> = match find_tasks_from_db(&conn, pattern, &mode, TaskSubset::All) {
+        Ok(tasks) => tasks.into_iter()
+            .map(|task| {
+                let new_name = mode.replace(pattern, &task.name, replacement);
+                (task, new_name)
+            })
+            .filter(|(task, new_name)| *new_name != task.name)
+            .collect(),
+        Err(e) => {
+            print_error(&format!("Failed to find tasks: {}", e));
+            return;
+        }
+    };
+
+    if renames.is_empty() {
+        print_notice(&tr("no-names-contain", &[&pattern]));
+        return;
+    }
+
+    let idx_width = idx_width(&renames.iter().map(|(task, _)| task).collect::<Vec<_>>());
+    for (task, new_name) in &renames {
+        let styled_name = format!("{} {} {}", todo_text(&task.name), dim_text("->"), todo_text(new_name));
+        println!("{}", format_task_row_with_name(task, &task.idx.unwrap().to_string(), idx_width, &styled_name));
+    }
+
+    if let Some((task, _)) = renames.iter().find(|(_, new_name)| new_name.trim().is_empty()) {
+        print_error(&format!("Error: Task {} would be left without a name, so nothing was renamed.", task.idx.unwrap()));
+        return;
+    }
+
+    if !yes && !confirm(&format!("Rename {} task(s)?", renames.len())) {
+        print_notice(&tr("nothing-renamed", &[]));
+        return;
+    }
+
+    match rename_tasks_in_db(&mut conn, &renames, &format!("{} -> {} in {} task(s)", pattern, replacement, renames.len())) {
+        Ok(_) => print_success(&format!("{} task(s) renamed successfully", renames.len())),
+        Err(e) => print_error(&format!("Failed to rename tasks: {}", e)),
+    }
+}
+
+/// Turns `--name` into task indices. A task named exactly `name` (ignoring case) or a single task
+/// containing it is used directly; several matches are printed and only accepted with `all`.
+/// Exits with status 1 when nothing suitable matches.
+fn resolve_tasks_by_name(conn: &Connection, name: &str, all: bool) -> Vec<i32> {
+    let mode = SearchMode::Substring { case_sensitive: false };
+
+    let matches = match find_tasks_from_db(conn, name, &mode, TaskSubset::All) {
+        Ok(matches) => matches,
+        Err(e) => {
+            print_error(&format!("Failed to find tasks: {}", e));
+            process::exit(1);
+        }
+    };
+
+    if matches.is_empty() {
+        print_error(&format!("Error: No task matches '{}'", name));
+        process::exit(1);
+    }
+
+    if all {
+        return matches.iter().map(|t| t.idx.unwrap()).collect();
+    }
+
+    let exact: Vec<&Task> = matches.iter().filter(|t| t.name.to_lowercase() == name.to_lowercase()).collect();
+
+    if exact.len() == 1 {
+        return vec![exact[0].idx.unwrap()];
+    }
+
+    if matches.len() == 1 {
+        return vec![matches[0].idx.unwrap()];
+    }
+
+    print_error(&format!("Error: {} tasks match '{}', pass --all to act on all of them:", matches.len(), name));
+    print_task_rows(&matches.iter().collect::<Vec<_>>());
+    process::exit(1);
+}
+
+/// Adds a line to the top-level help saying what a bare `todoln` runs
+pub fn describe_default_command(command: clap::Command, default: &str) -> clap::Command {
+    command.long_about(format!("{}\n\n  Running todoln without a command runs `{}` (see `default_command` in the config file)", ABOUT_TEXT.trim_end_matches('\r'), default))
+}
+
+/// Lets `done`, `remove` and `modify` run without task arguments so they can open the task
+/// picker instead, and `modify` without a new name so it can edit the current one in place. Only
+/// applied when attached to a terminal, otherwise the arguments stay required.
+pub fn allow_task_picker(command: clap::Command) -> clap::Command {
+    command
+        .mut_subcommand("done", |done| {
+            done.arg_required_else_help(false).mut_arg("task_indices", |arg| arg.required_unless_present(clap::builder::Resettable::Reset))
+        })
+        .mut_subcommand("remove", |remove| {
+            remove.arg_required_else_help(false).mut_arg("task_indices", |arg| arg.required_unless_present(clap::builder::Resettable::Reset))
+        })
+        .mut_subcommand("modify", |modify| {
+            modify
+                .arg_required_else_help(false)
+                .mut_arg("task_index", |arg| arg.required(false))
+                .mut_arg("new_name", |arg| arg.required_unless_present(clap::builder::Resettable::Reset))
+        })
+}
+
+/// Opens the task picker over all tasks, or only unfinished ones with `open_only`, and returns
+/// the chosen indices. Exits without touching anything when there's nothing to pick or it's cancelled.
+fn pick_tasks(conn: &Connection, open_only: bool, multi: bool) -> Vec<i32> {
+    let tasks: Vec<Task> = match get_tasks_from_db(conn) {
+        Ok(tasks) => tasks.into_iter().filter(|t| !open_only || !t.done).collect(),
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            process::exit(1);
+        }
+    };
+
+    if tasks.is_empty() {
+        print_notice(&tr("no-tasks-to-pick-from", &[]));
+        process::exit(0);
+    }
+
+    let idx_width = idx_width(&tasks.iter().collect::<Vec<_>>());
+    let items: Vec<PickerItem> = tasks.iter()
+        .map(|task| PickerItem { label: format_task_row(task, idx_width), text: task.name.clone() })
+        .collect();
+
+    match pick("Filter:", &items, multi) {
+        Ok(Some(chosen)) => chosen.iter().map(|&i| tasks[i].idx.unwrap()).collect(),
+        Ok(None) => {
+            print_notice(&tr("cancelled", &[]));
+            process::exit(0);
+        }
+        Err(e) => {
+            print_error(&format!("Failed to run the task picker: {}", e));
+            process::exit(1);
+        }
+    }
+}
+
+fn task_indices_or_name(conn: &Connection, task_indices: &[i32], name: &Option<String>, all: bool, open_only: bool) -> Vec<i32> {
+    match name {
+        Some(name) => resolve_tasks_by_name(conn, name, all),
+        None if task_indices.is_empty() => pick_tasks(conn, open_only, true),
+        None => task_indices.to_vec(),
+    }
+}
+
+pub fn done(task_indices: &[TaskIndex], name: &Option<String>, all: bool, cascade: bool, force: bool, toggle: bool) {
+    let mut conn = establish_connection();
+    let Some(task_indices) = resolve_task_indices(&conn, task_indices) else {
+        return;
+    };
+    let task_indices = &mut task_indices_or_name(&conn, &task_indices, name, all, !toggle);
+
+    let (tasks, blockers) = match get_tasks_from_db(&conn).and_then(|tasks| Ok((tasks, get_open_blockers_from_db(&conn)?))) {
+        Ok(result) => result,
+        Err(e) => {
+            print_error(&format!("Failed to retrieve task dependencies: {}", e));
+            return;
+        }
+    };
+    // Tasks that are done already are left alone or, with --toggle, marked todo without the checks below
+    let is_done = |index: &i32| tasks.iter().any(|t| t.idx == Some(*index) && t.done);
+
+    for index in task_indices.clone() {
+        if is_done(&index) {
+            continue;
+        }
+
+        let open_subtasks: Vec<i32> = match get_subtasks_from_db(&conn, &index) {
+            Ok(subtasks) => subtasks.iter().filter(|t| !t.done).map(|t| t.idx.unwrap()).filter(|i| !task_indices.contains(i)).collect(),
+            Err(e) => {
+                print_error(&format!("Failed to retrieve the subtasks of task {}: {}", index, e));
+                return;
+            }
+        };
+
+        if open_subtasks.is_empty() {
+            continue;
+        }
+
+        if cascade {
+            task_indices.extend(open_subtasks);
+        } else {
+            print_warning(&format!("Warning: Task {} still has {} open subtask(s), use --cascade to complete them too.", index, open_subtasks.len()));
+        }
+    }
+
+    // Blockers completed in the same run don't count
+    let still_open = |blocker: &Task, completing: &[i32]| !completing.contains(&blocker.idx.unwrap());
+    let blocked_before: Vec<&Task> = tasks.iter().filter(|t| !t.done && !blocked_by(&blockers, t).is_empty()).collect();
+
+    if !force {
+        let completing = task_indices.clone();
+        task_indices.retain(|index| {
+            let Some(task) = tasks.iter().find(|t| t.idx == Some(*index) && !t.done) else {
+                return true;
+            };
+            let waiting_for: Vec<String> = blocked_by(&blockers, task).into_iter().filter(|t| still_open(t, &completing)).map(|t| t.idx.unwrap().to_string()).collect();
+
+            if waiting_for.is_empty() {
+                return true;
+            }
+
+            print_warning(&format!("Warning: Task {} is blocked by {}, use --force to complete it anyway.", index, waiting_for.join(", ")));
+            false
+        });
+
+        if task_indices.is_empty() {
+            process::exit(1);
+        }
+    }
+
+    let report = match mark_tasks_in_db_as_done(&mut conn, task_indices, toggle) {
+        Ok(report) => report,
+        Err(e) => {
+            print_error(&format!("Failed to mark tasks as done: {}", e));
+            return;
+        }
+    };
+
+    let join = |indices: &[i32]| indices.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(", ");
+    let tasks_json = |indices: &[i32]| -> Vec<serde_json::Value> {
+        indices.iter().filter_map(|index| tasks.iter().find(|t| t.idx == Some(*index))).map(task_json).collect()
+    };
+
+    print_result(serde_json::json!({
+        "action": if toggle { "toggle" } else { "done" },
+        "tasks": tasks_json(&report.completed),
+        (if toggle { "todo" } else { "already_done" }): tasks_json(&report.already_done),
+        "not_found": report.not_found,
+    }));
+
+    for index in &report.not_found {
+        print_error(&tr("invalid-index", &[&index]));
+    }
+    if !report.already_done.is_empty() {
+        if toggle {
+            print_success(&tr("tasks-marked-todo", &[&join(&report.already_done)]));
+        } else {
+            for index in &report.already_done {
+                print_warning(&tr("task-already-done", &[index]));
+            }
+        }
+    }
+    if report.completed.is_empty() {
+        return;
+    }
+
+    let unblocked: Vec<String> = blocked_before.iter()
+        .filter(|t| !report.completed.contains(&t.idx.unwrap()) && blocked_by(&blockers, t).into_iter().all(|blocker| !still_open(blocker, &report.completed)))
+        .map(|t| format!("{} ({})", t.idx.unwrap(), t.name))
+        .collect();
+
+    print_success(&tr("tasks-completed", &[&join(&report.completed)]));
+
+    if !unblocked.is_empty() && !is_quiet() {
+        println!("Unblocked: {}", sanitize(&unblocked.join(", ")));
+    }
+
+    complete_finished_parents(&mut conn, &tasks, &report.completed);
+}
+
+/// Offers to complete (or with `auto_complete_parents`, completes) the parents whose last open
+/// subtasks were among `completed`
+fn complete_finished_parents(conn: &mut Connection, tasks: &[Task], completed: &[i32]) {
+    let progress = match get_subtask_progress_from_db(conn) {
+        Ok(progress) => progress,
+        Err(e) => {
+            print_error(&format!("Failed to retrieve the progress of subtasks: {}", e));
+            return;
+        }
+    };
+
+    let parent_ids: HashSet<i32> = tasks.iter().filter(|t| completed.contains(&t.idx.unwrap())).filter_map(|t| t.parent_id).collect();
+    let finished: Vec<&Task> = tasks.iter()
+        .filter(|t| !t.done && !completed.contains(&t.idx.unwrap()) && parent_ids.contains(&t.id.unwrap()))
+        .filter(|t| progress.iter().any(|&(parent_id, done, total)| Some(parent_id) == t.id && done == total))
+        .collect();
+
+    for parent in finished {
+        let index = parent.idx.unwrap();
+
+        let complete = config().auto_complete_parents
+            || (is_interactive() && confirm(&format!("All subtasks of task {} ({}) are done. Mark it as done too?", index, parent.name)));
+
+        if !complete {
+            if !is_interactive() {
+                println!("All subtasks of task {} are done.", index);
+            }
+            continue;
+        }
+
+        match mark_tasks_in_db_as_done(conn, &[index], false) {
+            Ok(_) => print_success(&tr("tasks-completed", &[&index])),
+            Err(e) => print_error(&format!("Failed to mark task {} as done: {}", index, e)),
+        }
+    }
+}
+
+pub fn block(task_index: &TaskIndex, on: &[TaskIndex], remove: bool) {
+    let conn = establish_connection();
+    let Some(task_index) = resolve_task_index(&conn, task_index) else {
+        return;
+    };
+    let Some(blocker_indices) = resolve_task_indices(&conn, on) else {
+        return;
+    };
+
+    let get_task = |index: &i32| match get_task_from_db(&conn, index) {
+        Ok(Some(task)) => task,
+        Ok(None) => {
+            print_error(&tr("invalid-index", &[&index]));
+            process::exit(1);
+        }
+        Err(e) => {
+            print_error(&format!("Failed to retrieve task {}: {}", index, e));
+            process::exit(1);
+        }
+    };
+
+    let task = get_task(&task_index);
+    let task_id = task.id.unwrap();
+
+    for blocker_index in blocker_indices {
+        let blocker = get_task(&blocker_index);
+        let blocker_id = blocker.id.unwrap();
+
+        if blocker_id == task_id {
+            print_error(&format!("Error: Task {} can't wait for itself.", task_index));
+            continue;
+        }
+
+        if remove {
+            match remove_dependency_in_db(&conn, &task_id, &blocker_id) {
+                Ok(true) => print_success(&format!("Task {} no longer waits for task {}", task_index, blocker_index)),
+                Ok(false) => print_warning(&format!("Warning: Task {} doesn't wait for task {}.", task_index, blocker_index)),
+                Err(e) => print_error(&format!("Failed to remove the dependency on task {}: {}", blocker_index, e)),
+            }
+            continue;
+        }
+
+        match add_dependency_in_db(&conn, &task_id, &blocker_id) {
+            Ok(true) => print_success(&format!("Task {} now waits for task {}", task_index, blocker_index)),
+            Ok(false) => print_error(&format!("Error: Task {} already waits for task {}, so it can't block it.", blocker_index, task_index)),
+            Err(e) => print_error(&format!("Failed to add the dependency on task {}: {}", blocker_index, e)),
+        }
+    }
+}
+
+pub fn set(task_index: &TaskIndex, fields: &[(String, String)]) {
+    let mut conn = establish_connection();
+    let Some(task_index) = &resolve_task_index(&conn, task_index) else {
+        return;
+    };
+
+    match set_task_fields_in_db(&mut conn, task_index, fields) {
+        Ok(false) => print_error(&tr("no-task-at-index", &[task_index])),
+        Ok(true) => {
+            let (removed, set): (Vec<_>, Vec<_>) = fields.iter().partition(|(_, value)| value.is_empty());
+
+            if !set.is_empty() {
+                print_success(&format!("Field(s) of task {} set successfully: {}", task_index, set.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>().join(", ")));
+            }
+            if !removed.is_empty() {
+                print_success(&format!("Field(s) of task {} removed successfully: {}", task_index, removed.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>().join(", ")));
+            }
+        }
+        Err(e) => print_error(&format!("Failed to set the fields of task {}: {}", task_index, e)),
+    }
+}
+
+pub fn blocked() {
+    let conn = establish_read_connection();
+
+    let (tasks, blockers) = match get_tasks_from_db(&conn).and_then(|tasks| Ok((tasks, get_open_blockers_from_db(&conn)?))) {
+        Ok(result) => result,
+        Err(e) => {
+            print_error(&format!("Failed to retrieve task dependencies: {}", e));
+            return;
+        }
+    };
+
+    let blocked: Vec<&Task> = tasks.iter().filter(|t| !t.done && !blocked_by(&blockers, t).is_empty()).collect();
+
+    if blocked.is_empty() {
+        print_notice(&tr("no-blocked-tasks", &[]));
+        return;
+    }
+
+    print_title(&tr("title-blocked", &[]));
+
+    let idx_width = idx_width(&blocked);
+    for task in blocked {
+        let waiting_for: Vec<String> = blocked_by(&blockers, task).iter().map(|t| format!("{} ({})", index_label(t), t.name)).collect();
+        println!("{} {}", format_task_row(task, idx_width), dim_text(&format!("blocked by {}", sanitize(&waiting_for.join(", ")))));
+    }
+}
+
+/// Exit statuses of `due --check`, for shell scripts
+const DUE_SOON_STATUS: i32 = 3;
+const OVERDUE_STATUS: i32 = 4;
+
+fn check_due(within: &str) {
+    let within = match parse_duration(within) {
+        Some(within) => within,
+        None => {
+            print_error(&format!("Error: Invalid duration '{}', use e.g. 30m, 12h, 14d or 2w", within));
+            process::exit(1);
+        }
+    };
+
+    // Runs from shell start-up scripts, so a busy database counts as nothing due rather than a wait
+    let next_due = match open_connection_for_quick_read().and_then(|conn| conn.map(|conn| get_next_due_from_db(&conn)).transpose()) {
+        Ok(next_due) => next_due.flatten(),
+        Err(e) if is_locked_error(&e) => None,
+        Err(e) => {
+            print_error(&format!("Failed to check due tasks: {}", e));
+            process::exit(1);
+        }
+    };
+
+    let Some(next_due) = next_due.as_deref().and_then(parse_timestamp) else {
+        return;
+    };
+
+    let now = Utc::now();
+    if next_due <= now {
+        process::exit(OVERDUE_STATUS);
+    }
+    if (next_due - now).num_seconds() <= within {
+        process::exit(DUE_SOON_STATUS);
+    }
+}
+
+pub fn due(task_index: &Option<TaskIndex>, when: &Option<String>, clear: bool, check: bool, within: &str) {
+    if check {
+        check_due(within);
+        return;
+    }
+
+    let conn = if task_index.is_none() { establish_read_connection() } else { establish_connection() };
+
+    let task_index = match task_index.as_ref().map(|task_index| resolve_task_index(&conn, task_index)) {
+        Some(None) => return,
+        resolved => resolved.flatten(),
+    };
+
+    let Some(task_index) = task_index else {
+        let tasks = match get_tasks_due_between(&conn, None, None) {
+            Ok(tasks) => tasks,
+            Err(e) => {
+                print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+                return;
+            }
+        };
+
+        if tasks.is_empty() {
+            print_notice(&tr("no-due-dates", &[]));
+            return;
+        }
+
+        print_title(&tr("title-due", &[]));
+
+        let now = Utc::now();
+        let idx_width = idx_width(&tasks.iter().collect::<Vec<_>>());
+        for task in &tasks {
+            let due_at = task.due_at.as_deref().unwrap_or_default();
+            let overdue = parse_timestamp(due_at).is_some_and(|t| t <= now);
+            let label = if overdue { "overdue since" } else { "due" };
+
+            println!("{} ({} {})", format_task_row(task, idx_width), label, format_local_timestamp(due_at));
+        }
+        return;
+    };
+
+    let due_at = if clear {
+        None
+    } else {
+        let Some(when) = when else {
+            print_error("Error: Give a due date for the task, or --clear to remove it");
+            return;
+        };
+
+        match parse_due(when) {
+            Some(due_at) => Some(due_at),
+            None => {
+                print_error(&format!("Error: Invalid due date '{}', use e.g. 2024-05-01, \"2024-05-01 14:00\" or 3d", when));
+                return;
+            }
+        }
+    };
+
+    match set_task_due_in_db(&conn, &task_index, due_at.as_deref()) {
+        Ok(false) => print_error(&tr("no-task-at-index", &[&task_index])),
+        Ok(true) => match due_at {
+            Some(due_at) => print_success(&format!("Task {} is due {}", task_index, format_local_timestamp(&due_at))),
+            None => print_success(&format!("Due date of task {} removed successfully", task_index)),
+        },
+        Err(e) => print_error(&format!("Failed to set the due date of task {}: {}", task_index, e)),
+    }
+}
+
+/// Tasks `today` and `week` show: open ones due before `end`, or with `someday` every open one,
+/// leaving out snoozed tasks. Comes with the labels and index width to print them like `list`.
+fn agenda_tasks(conn: &Connection, end: Option<DateTime<Utc>>, someday: bool) -> Option<(Vec<Task>, HashMap<i32, String>, usize)> {
+    let tasks = match get_tasks_from_db(conn) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return None;
+        }
+    };
+    let due = match get_tasks_due_between(conn, None, end) {
+        Ok(due) => due,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return None;
+        }
+    };
+
+    let snoozed = snoozed_ids(&tasks);
+    let labels = task_labels(&tasks);
+    let undated = tasks.iter().filter(|t| someday && !t.done && t.due_at.is_none()).cloned();
+
+    let agenda: Vec<Task> = due.into_iter().chain(undated).filter(|t| !snoozed.contains(&t.id.unwrap())).collect();
+    let idx_width = agenda.iter().filter_map(|t| labels.get(&t.id?)).map(String::len).max().unwrap_or(1);
+
+    Some((agenda, labels, idx_width))
+}
+
+/// A task row of `today` or `week`: overdue tasks marked in red, ones due today in yellow
+fn format_agenda_row(task: &Task, labels: &HashMap<i32, String>, idx_width: usize) -> String {
+    let row = format_list_row(task, &labels[&task.id.unwrap()], idx_width, false);
+    let Some(due_at) = task.due_at.as_deref().and_then(parse_timestamp) else {
+        return row;
+    };
+
+    let local = due_at.with_timezone(&Local);
+    // A due date given as a plain day is due at the very end of it, which isn't worth showing
+    let time = if local.format("%H:%M:%S").to_string() == "23:59:59" { String::new() } else { local.format(" at %H:%M").to_string() };
+
+    if due_at <= Utc::now() {
+        format!("{} {}", row, styled(Style::Error, &format!("(overdue since {})", format_local_timestamp(task.due_at.as_deref().unwrap_or_default()))))
+    } else if local.date_naive() == Local::now().date_naive() {
+        format!("{} {}", row, styled(Style::Warning, &format!("(due today{})", time)))
+    } else if !time.is_empty() {
+        format!("{} {}", row, dim_text(&format!("(due{})", time)))
+    } else {
+        row
+    }
+}
+
+pub fn today() {
+    let conn = establish_read_connection();
+
+    let end = Local::now().date_naive().succ_opt().and_then(local_midnight);
+    let Some((tasks, labels, idx_width)) = agenda_tasks(&conn, end, false) else {
+        return;
+    };
+
+    if tasks.is_empty() {
+        print_notice(&tr("nothing-due-today", &[]));
+        return;
+    }
+
+    print_title(&tr("title-due-today", &[]));
+    for task in &tasks {
+        println!("{}", format_agenda_row(task, &labels, idx_width));
+    }
+}
+
+pub fn week(someday: bool) {
+    let conn = establish_read_connection();
+
+    let today = Local::now().date_naive();
+    let end = today.checked_add_days(chrono::Days::new(7)).and_then(local_midnight);
+    let Some((tasks, labels, idx_width)) = agenda_tasks(&conn, end, someday) else {
+        return;
+    };
+
+    if tasks.is_empty() {
+        print_notice(&tr("nothing-due-this-week", &[]));
+        return;
+    }
+
+    // Overdue tasks go with today and undated ones at the end, so only days with tasks get a heading
+    let mut heading: Option<String> = None;
+    for task in &tasks {
+        let day = task.due_at.as_deref().and_then(parse_timestamp).map(|t| t.with_timezone(&Local).date_naive().max(today));
+        let title = day.map_or_else(|| tr("title-someday", &[]), day_title);
+
+        if heading.as_ref() != Some(&title) {
+            if heading.is_some() {
+                println!();
+            }
+            print_title(&title);
+            heading = Some(title);
+        }
+
+        println!("{}", format_agenda_row(task, &labels, idx_width));
+    }
+}
+
+/// The line `notify` prints and sends for a due task
+fn reminder_text(task: &Task) -> String {
+    let due_at = task.due_at.as_deref().unwrap_or_default();
+
+    if parse_timestamp(due_at).is_some_and(|t| t <= Utc::now()) {
+        format!("{} (overdue since {})", sanitize(&task.name), format_local_timestamp(due_at))
+    } else {
+        format!("{} (due {})", sanitize(&task.name), format_local_timestamp(due_at))
+    }
+}
+
+fn send_notification(title: &str, body: &str) {
+    // Without a notification daemon the printed reminders are all there is, so failures are ignored
+    notify_rust::Notification::new().appname("todoln").summary(title).body(body).show().ok();
+}
+
+pub fn notify(window: &Option<String>, summary: bool) {
+    let end = match window {
+        Some(window) => match parse_duration(window).and_then(TimeDelta::try_seconds) {
+            Some(window) => Utc::now() + window,
+            None => {
+                print_error(&format!("Error: Invalid duration '{}', use e.g. 12h, 2d or 1w", window));
+                process::exit(1);
+            }
+        },
+        None => match Local::now().date_naive().succ_opt().and_then(local_midnight) {
+            Some(end) => end,
+            None => return,
+        },
+    };
+
+    let mut conn = establish_connection();
+
+    let tasks = match get_tasks_to_notify_from_db(&conn, end) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            process::exit(1);
+        }
+    };
+
+    if tasks.is_empty() {
+        return;
+    }
+
+    let reminders: Vec<String> = tasks.iter().map(reminder_text).collect();
+    for reminder in &reminders {
+        println!("{}", reminder);
+    }
+
+    if summary {
+        send_notification(&format!("{} task(s) due", tasks.len()), &reminders.join("\n"));
+    } else {
+        for reminder in &reminders {
+            send_notification("Task due", reminder);
+        }
+    }
+
+    if let Err(e) = mark_tasks_notified_in_db(&mut conn, &tasks) {
+        print_error(&format!("Failed to remember the tasks that were notified: {}", e));
+    }
+}
+
+pub fn snooze(task_index: &TaskIndex, until: &Option<String>, duration: &Option<String>, clear: bool) {
+    let conn = establish_connection();
+    let Some(task_index) = &resolve_task_index(&conn, task_index) else {
+        return;
+    };
+
+    let snoozed_until = if clear {
+        None
+    } else {
+        let parsed = match (until, duration) {
+            (Some(until), _) => parse_wake(until).ok_or_else(|| format!("Error: Invalid date '{}', use e.g. tomorrow, monday, 2024-05-01 or \"2024-05-01 14:00\"", until)),
+            (None, Some(duration)) => parse_duration(duration).and_then(|_| parse_due(duration)).ok_or_else(|| format!("Error: Invalid duration '{}', use e.g. 3d or 2w", duration)),
+            (None, None) => Err(String::from("Error: Give a date to snooze the task until, --for a duration, or --clear to wake it up")),
+        };
+
+        match parsed {
+            Ok(snoozed_until) => Some(snoozed_until),
+            Err(e) => {
+                print_error(&e);
+                return;
+            }
+        }
+    };
+
+    match set_task_snooze_in_db(&conn, task_index, snoozed_until.as_deref()) {
+        Ok(false) => print_error(&tr("no-task-at-index", &[task_index])),
+        Ok(true) => match snoozed_until {
+            Some(wake) => print_success(&format!("Task {} is snoozed until {}", task_index, format_local_timestamp(&wake))),
+            None => print_success(&format!("Task {} woken up successfully", task_index)),
+        },
+        Err(e) => print_error(&format!("Failed to snooze task {}: {}", task_index, e)),
+    }
+}
+
+pub fn sort(key: SortKey, reverse: bool, undo: bool, dry_run: bool, json: bool) {
+    if dry_run {
+        // A dry run must leave the database exactly as it is, so it doesn't even repair indices
+        let conn = open_connection();
+        match get_sorted_tasks_from_db(&conn, key, reverse) {
+            Ok(sorted) => print_sort_plan(&sorted, json),
+            Err(e) => print_error(&format!("Failed to sort tasks: {}", e)),
+        }
+        return;
+    }
+
+    let mut conn = establish_connection();
+
+    if undo {
+        match undo_sort_in_db(&mut conn) {
+            Ok(SortUndo::Restored) => print_success("Previous task order restored successfully"),
+            Ok(SortUndo::NothingToUndo) => print_error("Error: There is no sort to undo."),
+            Ok(SortUndo::OrderChanged) => print_error("Error: Tasks have been added, removed or moved since the last sort, so its previous order can no longer be restored."),
+            Err(e) => print_error(&format!("Failed to undo sort: {}", e)),
+        }
+        return;
+    }
+
+    match sort_tasks_in_db(&mut conn, key, reverse) {
+        Ok(_) => {},
+        Err(e) => {
+            print_error(&format!("Failed to sort tasks: {}", e));
+            return;
+        }
+    }
+
+    print_success(&tr("tasks-sorted", &[]));
+}
+
+/// Prints which tasks `sort --dry-run` would move, from their index to their position in `sorted`
+fn print_sort_plan(sorted: &[Task], json: bool) {
+    let moves: Vec<(&Task, usize)> = sorted.iter()
+        .enumerate()
+        .map(|(i, task)| (task, i + 1))
+        .filter(|(task, new_idx)| task.idx != Some(*new_idx as i32))
+        .collect();
+
+    if json {
+        let moves: Vec<serde_json::Value> = moves.iter()
+            .map(|(task, new_idx)| serde_json::json!({ "id": task.id, "idx": task.idx, "new_idx": new_idx, "name": task.name }))
+            .collect();
+        println!("{}", serde_json::json!({ "action": "sort", "moves": moves }));
+        return;
+    }
+
+    if moves.is_empty() {
+        print_notice(&tr("already-in-that-order", &[]));
+        return;
+    }
+
+    println!("Would move {} of {} task(s):", moves.len(), sorted.len());
+    let idx_width = idx_width(&moves.iter().map(|(task, _)| *task).collect::<Vec<_>>());
+    for (task, new_idx) in &moves {
+        println!("  [{}] -> [{}] {}", bold_text(&format!("{:>idx_width$}", index_label(task))), new_idx, sanitize(&task.name));
+    }
+}
+
+/// A task as the JSON output of plans and `--format json` describes it
+fn task_json(task: &Task) -> serde_json::Value {
+    serde_json::json!({ "id": task.id, "idx": task.idx, "name": task.name })
+}
+
+/// Prints what a command did as one JSON object, with `--format json`
+fn print_result(result: serde_json::Value) {
+    if json_output() {
+        println!("{}", result);
+    }
+}
+
+/// Prints the `--format json` result of adding the tasks with `ids`, at the indices they have now
+fn print_added_result(conn: &Connection, action: &str, ids: &[i32]) {
+    if !json_output() {
+        return;
+    }
+
+    match get_tasks_from_db(conn) {
+        Ok(tasks) => {
+            let added: Vec<serde_json::Value> = ids.iter().filter_map(|id| tasks.iter().find(|t| t.id == Some(*id))).map(task_json).collect();
+            print_result(serde_json::json!({ "action": action, "tasks": added }));
+        }
+        Err(e) => print_error(&tr("failed-to-retrieve-tasks", &[&e])),
+    }
+}
+
+/// Prints the tasks a dry run of `action` would remove, as "Would remove: [3] buy milk, ..." or
+/// with `json` as a JSON object. The messages are `would-<action>` and `nothing-would-<action>`.
+fn print_removal_plan(action: &str, tasks: &[&Task], json: bool) {
+    if json {
+        let tasks: Vec<serde_json::Value> = tasks.iter().map(|task| task_json(task)).collect();
+        println!("{}", serde_json::json!({ "action": action, "tasks": tasks }));
+        return;
+    }
+
+    if tasks.is_empty() {
+        print_notice(&tr(&format!("nothing-would-{}", action), &[]));
+        return;
+    }
+
+    let names: Vec<String> = tasks.iter().map(|task| format!("[{}] {}", index_label(task), sanitize(&task.name))).collect();
+    println!("{}", tr(&format!("would-{}", action), &[&names.join(", ")]));
+}
+
+pub fn reverse() {
+    let mut conn = establish_connection();
+
+    match reverse_tasks_in_db(&mut conn) {
+        Ok(_) => print_success("Tasks reversed successfully"),
+        Err(e) => print_error(&format!("Failed to reverse tasks: {}", e)),
+    }
+}
+
+pub fn shuffle(all: bool) {
+    let mut conn = establish_connection();
+
+    match shuffle_tasks_in_db(&mut conn, all) {
+        Ok(_) => print_success("Tasks shuffled successfully"),
+        Err(e) => print_error(&format!("Failed to shuffle tasks: {}", e)),
+    }
+}
+
+/// How likely `pick --weighted` is to choose a task: one more for each day since it was created,
+/// times three for high priority (or 1) and two for medium (or 2)
+fn pick_weight(task: &Task, priority: Option<&str>) -> f64 {
+    let age_days = task.created_at.as_deref()
+        .and_then(parse_timestamp)
+        .map_or(0, |created_at| (Utc::now() - created_at).num_days().max(0));
+
+    let factor = match priority_rank(priority) {
+        (0, number, _) => (4 - number).clamp(1, 3),
+        (1, level, _) => 3 - level,
+        _ => 1,
+    };
+
+    (1 + age_days) as f64 * factor as f64
+}
+
+pub fn pick_random(count: u16, any: bool, weighted: bool) {
+    let conn = establish_read_connection();
+
+    let (tasks, blockers, fields) = match (get_tasks_from_db(&conn), get_open_blockers_from_db(&conn), get_task_fields_from_db(&conn)) {
+        (Ok(tasks), Ok(blockers), Ok(fields)) => (tasks, blockers, fields),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    let snoozed = snoozed_ids(&tasks);
+    let candidates: Vec<&Task> = tasks.iter()
+        .filter(|t| any || (!t.done && !snoozed.contains(&t.id.unwrap()) && blocked_by(&blockers, t).is_empty()))
+        .collect();
+
+    if candidates.is_empty() {
+        print_notice(&tr("no-tasks-to-pick", &[]));
+        return;
+    }
+
+    let priorities: HashMap<i32, String> = fields.into_iter()
+        .filter(|(_, key, _)| key == "priority")
+        .map(|(task_id, _, value)| (task_id, value))
+        .collect();
+
+    let mut rng = rand::thread_rng();
+    let picked: Vec<&Task> = if weighted {
+        let weight = |task: &&Task| pick_weight(task, priorities.get(&task.id.unwrap()).map(String::as_str));
+        match candidates.choose_multiple_weighted(&mut rng, count as usize, weight) {
+            Ok(picked) => picked.copied().collect(),
+            Err(e) => {
+                print_error(&format!("Failed to pick tasks: {}", e));
+                return;
+            }
+        }
+    } else {
+        candidates.choose_multiple(&mut rng, count as usize).copied().collect()
+    };
+
+    let labels = task_labels(&tasks);
+    let label_width = picked.iter().map(|t| labels[&t.id.unwrap()].len()).max().unwrap_or(1);
+
+    for task in picked {
+        println!("{}", format_list_row(task, &labels[&task.id.unwrap()], label_width, false));
+    }
+}
+
+/// Bulk `done` and `remove` ask before changing more tasks than this at once
+const BULK_CONFIRM_LIMIT: usize = 5;
+
+/// The tasks `done --all-todo` and `remove --done` or `--matching` act on: the ones in `subset`
+/// whose name contains `matching`, if given. Prints them, and asks first when there are more than
+/// `BULK_CONFIRM_LIMIT` unless `yes`. `None` when there's nothing to do or the answer was no.
+fn bulk_tasks(conn: &Connection, subset: TaskSubset, matching: &Option<String>, verb: &str, yes: bool) -> Option<Vec<Task>> {
+    let mode = SearchMode::Substring { case_sensitive: false };
+
+    let tasks = match find_tasks_from_db(conn, matching.as_deref().unwrap_or_default(), &mode, subset) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return None;
+        }
+    };
+
+    if tasks.is_empty() {
+        print!("{}", empty_text(&tr("no-tasks-found", &[])));
+        return None;
+    }
+
+    if !json_output() {
+        print_task_rows(&tasks.iter().collect::<Vec<_>>());
+    }
+
+    if tasks.len() > BULK_CONFIRM_LIMIT && !yes && !confirm(&format!("{} {} tasks?", verb, tasks.len())) {
+        print_notice(&tr("nothing-changed", &[]));
+        return None;
+    }
+
+    Some(tasks)
+}
+
+/// `done --all-todo`: marks every todo task done in one go
+pub fn done_all_todo(yes: bool) {
+    let mut conn = establish_connection();
+
+    let Some(tasks) = bulk_tasks(&conn, TaskSubset::Todo, &None, "Complete", yes) else {
+        return;
+    };
+
+    let completed = match mark_tasks_in_db_as_done_by_ids(&mut conn, &tasks.iter().map(|t| t.id.unwrap()).collect::<Vec<_>>()) {
+        Ok(completed) => completed,
+        Err(e) => {
+            print_error(&format!("Failed to mark tasks as done: {}", e));
+            return;
+        }
+    };
+
+    print_success(&tr("tasks-completed", &[&completed.iter().map(|t| t.idx.unwrap().to_string()).collect::<Vec<_>>().join(", ")]));
+    print_result(serde_json::json!({ "action": "done", "tasks": completed.iter().map(task_json).collect::<Vec<_>>(), "already_done": [], "not_found": [] }));
+}
+
+/// `remove --done` and `remove --matching`: removes every task in the filtered set at once
+pub fn remove_matching(done: bool, matching: &Option<String>, cascade: bool, dry_run: bool, json: bool, yes: bool) {
+    let mut conn = if dry_run { open_connection() } else { establish_connection() };
+    let subset = if done { TaskSubset::Done } else { TaskSubset::All };
+
+    if dry_run {
+        let mode = SearchMode::Substring { case_sensitive: false };
+        let matches = find_tasks_from_db(&conn, matching.as_deref().unwrap_or_default(), &mode, subset)
+            .and_then(|tasks| get_tasks_to_remove_from_db(&conn, &tasks.iter().map(|t| t.idx.unwrap()).collect::<Vec<_>>(), cascade));
+        match matches {
+            Ok(removed) => print_removal_plan("remove", &removed.iter().collect::<Vec<_>>(), json),
+            Err(e) => print_error(&tr("failed-to-retrieve-tasks", &[&e])),
+        }
+        return;
+    }
+
+    let Some(tasks) = bulk_tasks(&conn, subset, matching, "Remove", yes) else {
+        return;
+    };
+
+    if let Err(e) = remove_tasks_by_id_from_db(&mut conn, "remove", &tasks.iter().map(|t| t.id.unwrap()).collect::<Vec<_>>(), cascade) {
+        print_error(&format!("Failed to remove tasks: {}", e));
+        return;
+    }
+
+    print_success(&tr("tasks-removed", &[&tasks.iter().map(|t| t.idx.unwrap().to_string()).collect::<Vec<_>>().join(", ")]));
+    print_result(serde_json::json!({ "action": "remove", "tasks": tasks.iter().map(task_json).collect::<Vec<_>>() }));
+}
+
+pub fn remove(task_indices: &[TaskIndex], name: &Option<String>, all: bool, cascade: bool, dry_run: bool, json: bool) {
+    let mut conn = if dry_run { open_connection() } else { establish_connection() };
+    let Some(task_indices) = resolve_task_indices(&conn, task_indices) else {
+        return;
+    };
+    let mut task_indices = task_indices_or_name(&conn, &task_indices, name, all, false);
+
+    // Indices no task has are reported and the rest are still removed
+    let tasks = match get_tasks_from_db(&conn) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+    task_indices.retain(|&index| {
+        let found = tasks.iter().any(|t| t.idx == Some(index));
+        if !found {
+            print_error(&tr("invalid-index", &[&index]));
+        }
+        found
+    });
+    if task_indices.is_empty() {
+        return;
+    }
+    let task_indices = &task_indices;
+
+    let removed = match get_tasks_to_remove_from_db(&conn, task_indices, cascade) {
+        Ok(removed) => removed,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    if dry_run {
+        print_removal_plan("remove", &removed.iter().collect::<Vec<_>>(), json);
+        return;
+    }
+
+    // What is actually removed, in case another todoln run changed the list since it was read
+    let removed = match remove_tasks_from_db(&mut conn, task_indices, cascade) {
+        Ok(removed) => removed,
+        Err(e) => {
+            print_error(&format!("Failed to remove tasks: {}", e));
+            return;
+        }
+    };
+
+    print_success(&tr("tasks-removed", &[&task_indices.iter().map(|&i| i.to_string()).collect::<Vec<_>>().join(", ")]));
+    print_result(serde_json::json!({ "action": "remove", "tasks": removed.iter().map(task_json).collect::<Vec<_>>() }));
+}
+
+pub fn clear(keep: Option<usize>, dry_run: bool, json: bool) {
+    let mut conn = if dry_run { open_connection() } else { establish_connection() };
+    let tasks = if dry_run { get_tasks_from_db(&conn) } else { get_tasks_from_db_and_update_indices(&mut conn) };
+
+    match tasks {
+        Ok(tasks) => {
+            let mut completed_tasks: Vec<&Task> = tasks.iter().filter(|t| t.done).collect();
+            let keep = keep.unwrap_or(0);
+
+            if completed_tasks.is_empty() && !dry_run {
+                print_notice(&tr("no-completed-tasks-to-clear", &[]));
+                print_result(serde_json::json!({ "action": "clear", "count": 0, "tasks": [] }));
+                return;
+            }
+
+            if keep > 0 {
+                // Newest completions first; tasks without a timestamp and ties go by highest index
+                completed_tasks.sort_by(|a, b| b.completed_at.cmp(&a.completed_at).then(b.idx.cmp(&a.idx)));
+
+                let mut removed: Vec<&Task> = completed_tasks.iter().skip(keep).copied().collect();
+
+                if removed.is_empty() && !dry_run {
+                    print_notice(&tr("keeping-all-completed", &[&completed_tasks.len()]));
+                    print_result(serde_json::json!({ "action": "clear", "count": 0, "tasks": [] }));
+                    return;
+                }
+
+                removed.sort_by_key(|t| t.idx);
+                completed_tasks = removed;
+            }
+
+            if dry_run {
+                print_removal_plan("clear", &completed_tasks, json);
+                return;
+            }
+
+            if keep > 0 && !is_quiet() {
+                print_title(&tr("title-clearing-keeping", &[&completed_tasks.len(), &keep]));
+                print_task_rows(&completed_tasks);
+                println!();
+            }
+
+            let ids: Vec<i32> = completed_tasks.iter().map(|t| t.id.unwrap()).collect();
+
+            if let Err(e) = remove_tasks_by_id_from_db(&mut conn, "clear", &ids, false) {
+                print_error(&format!("Failed to clear completed tasks: {}", e));
+                return;
+            }
+
+            print_success(&tr("completed-tasks-cleared", &[&completed_tasks.len(), &completed_tasks.iter().map(|t| t.name.to_string()).collect::<Vec<_>>().join(", ")]));
+            print_result(serde_json::json!({
+                "action": "clear",
+                "count": completed_tasks.len(),
+                "tasks": completed_tasks.iter().map(|task| task_json(task)).collect::<Vec<_>>(),
+            }));
+        },
+        Err(e) => print_error(&tr("failed-to-retrieve-tasks", &[&e])),
+    }
+}
+
+pub fn reset(dry_run: bool, json: bool, no_snapshot: bool) {
+    if dry_run {
+        let conn = open_connection();
+        match get_tasks_from_db(&conn) {
+            Ok(tasks) => print_removal_plan("reset", &tasks.iter().collect::<Vec<_>>(), json),
+            Err(e) => print_error(&tr("failed-to-retrieve-tasks", &[&e])),
+        }
+        return;
+    }
+
+    let mut conn = establish_connection();
+    let count = get_tasks_length(&conn);
+
+    let snapshot_path = if no_snapshot || count == 0 {
+        None
+    } else {
+        match save_snapshot(&conn, RESET_SNAPSHOT_PREFIX) {
+            Ok(path) => Some(path),
+            Err(e) => {
+                print_error(&format!("{}, nothing was deleted (pass --no-snapshot to reset anyway)", e));
+                return;
+            }
+        }
+    };
+
+    if let Err(e) = delete_tasks_from_db(&mut conn) {
+        print_error(&format!("Failed to delete all tasks: {}", e));
+        return;
+    }
+
+    match &snapshot_path {
+        Some(path) => print_success(&tr("tasks-reset-snapshot", &[&path.display()])),
+        None => print_success(&tr("tasks-reset", &[])),
+    }
+    print_result(serde_json::json!({ "action": "reset", "count": count, "snapshot": snapshot_path }));
+}
+
+/// What the snapshots `reset` saves start with, to tell them apart from the ones `wrapup` saves
+const RESET_SNAPSHOT_PREFIX: &str = "reset";
+
+/// Saves a copy of the whole database as `<prefix>-<timestamp>.db` in the snapshots directory,
+/// and returns where it went
+fn save_snapshot(conn: &Connection, prefix: &str) -> Result<PathBuf, String> {
+    let dir = snapshots_dir();
+    if let Err(e) = fs::create_dir_all(&dir) {
+        return Err(format!("Failed to create the snapshot directory '{}': {}", dir.display(), e));
+    }
+
+    // `VACUUM INTO` won't overwrite, and a snapshot from earlier in the same second is stale anyway
+    let path = dir.join(format!("{}-{}.db", prefix, Local::now().format("%Y%m%d-%H%M%S")));
+    fs::remove_file(&path).ok();
+
+    match snapshot_db(conn, &path) {
+        Ok(_) => Ok(path),
+        Err(e) => Err(format!("Failed to save backup snapshot {}", e)),
+    }
+}
+
+/// The newest snapshot saved by `reset`, going by the timestamp in its name
+fn last_reset_snapshot() -> Option<PathBuf> {
+    let prefix = format!("{}-", RESET_SNAPSHOT_PREFIX);
+
+    fs::read_dir(snapshots_dir()).ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "db"))
+        .filter(|path| path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(&prefix)))
+        .max()
+}
+
+pub fn undo(list: bool) {
+    let mut conn = if list { establish_read_connection() } else { establish_connection() };
+
+    if list {
+        match get_history_from_db(&conn) {
+            Ok(history) => {
+                if history.is_empty() {
+                    print_notice(&tr("nothing-to-undo", &[]));
+                    return;
+                }
+
+                print_title(&tr("title-history", &[]));
+                for entry in history {
+                    println!("  {}  {:<7}  {}", bold_text(&entry.created_at), entry.operation, sanitize(&entry.summary));
+                }
+            }
+            Err(e) => print_error(&format!("Failed to retrieve the history: {}", e)),
+        }
+        return;
+    }
+
+    match undo_last_in_db(&mut conn) {
+        Ok(Some(entry)) => {
+            let message = match entry.operation.as_str() {
+                "add" | "insert" | "sync-md" if entry.summary.is_empty() => format!("Undid {}", entry.operation),
+                "add" | "insert" | "duplicate" | "append" => format!("Undid {}, removed: {}", entry.operation, entry.summary),
+                "remove" | "clear" | "dedupe" | "reset" => format!("Undid {}, restored: {}", entry.operation, entry.summary),
+                "restore" => format!("Undid restore, moved back to the trash: {}", entry.summary),
+                "sort" | "reverse" | "shuffle" | "reorder" => format!("Undid {}, previous task order restored", entry.operation),
+                _ => format!("Undid {}: {}", entry.operation, entry.summary),
+            };
+            print_success(&message);
+        }
+        Ok(None) => print_error("Error: There is nothing to undo."),
+        Err(e) => print_error(&format!("Failed to undo: {}", e)),
+    }
+}
+
+pub fn trash(action: &Option<TrashAction>) {
+    let mut conn = if action.is_none() { establish_read_connection() } else { establish_connection() };
+
+    match action {
+        None => match get_trash_from_db(&conn) {
+            Ok(trash) => {
+                if trash.is_empty() {
+                    print_notice(&tr("trash-empty", &[]));
+                    return;
+                }
+
+                print_title(&tr("title-trash", &[]));
+
+                let idx_width = trash.len().to_string().len();
+                for (i, (mut task, deleted_at)) in trash.into_iter().enumerate() {
+                    task.idx = Some(i as i32 + 1);
+                    println!("{} (removed {})", format_task_row(&task, idx_width), format_local_timestamp(&deleted_at));
+                }
+            }
+            Err(e) => print_error(&format!("Failed to retrieve the trash: {}", e)),
+        },
+        Some(TrashAction::Restore { trash_index }) => match restore_task_from_trash_in_db(&mut conn, *trash_index) {
+            Ok(Some(name)) => print_success(&format!("Task restored successfully: {}", name)),
+            Ok(None) => print_error(&format!("Error: There is no task {} in the trash.", trash_index)),
+            Err(e) => print_error(&format!("Failed to restore task {} from the trash: {}", trash_index, e)),
+        },
+        Some(TrashAction::Empty { all }) => {
+            let older_than = if *all { None } else { Some(trash_retention_days() as i64 * 24 * 60 * 60) };
+
+            match empty_trash_in_db(&conn, older_than) {
+                Ok(0) if *all => print_notice(&tr("trash-already-empty", &[])),
+                Ok(0) => print_notice(&tr("nothing-old-in-trash", &[&trash_retention_days()])),
+                Ok(count) => print_success(&format!("{} task(s) deleted from the trash for good", count)),
+                Err(e) => print_error(&format!("Failed to empty the trash: {}", e)),
+            }
+        }
+    }
+}
+
+/// Writes every task with its custom fields to `path` as JSON
+fn export_json(path: &PathBuf) -> Result<(), String> {
+    let conn = establish_read_connection();
+
+    let tasks = get_tasks_from_db(&conn).map_err(|e| format!("Failed to retrieve tasks: {}", e))?;
+    let fields = get_task_fields_from_db(&conn).map_err(|e| format!("Failed to retrieve task fields: {}", e))?;
+
+    let positions: HashMap<i32, usize> = tasks.iter().enumerate().map(|(i, t)| (t.id.unwrap(), i + 1)).collect();
+    let to_rfc3339 = |timestamp: &Option<String>| timestamp.as_deref().and_then(parse_timestamp).map(|t| t.to_rfc3339());
+
+    let export = Export {
+        tasks: tasks.iter().map(|task| ExportedTask {
+            name: task.name.clone(),
+            done: task.done,
+            completed_at: to_rfc3339(&task.completed_at),
+            due_at: to_rfc3339(&task.due_at),
+            note: task.note.clone(),
+            parent: task.parent_id.and_then(|parent_id| positions.get(&parent_id).copied()),
+            fields: fields.iter().filter(|(task_id, _, _)| Some(*task_id) == task.id).map(|(_, key, value)| (key.clone(), value.clone())).collect(),
+            recurrence: task.recurrence.clone(),
+            snoozed_until: to_rfc3339(&task.snoozed_until),
+            created_at: to_rfc3339(&task.created_at),
+        }).collect(),
+    };
+
+    let contents = serde_json::to_string_pretty(&export).map_err(|e| format!("Failed to export tasks: {}", e))?;
+    fs::write(path, contents + "\n").map_err(|e| format!("Failed to write '{}': {}", path.display(), e))
+}
+
+/// The line each task object of a `backup --json` file starts on, found by tracking how deeply
+/// nested each brace is outside of strings
+fn json_task_lines(contents: &str) -> Vec<usize> {
+    let (mut lines, mut depth, mut line, mut in_string, mut escaped) = (Vec::new(), 0, 1, false, false);
+
+    for c in contents.chars() {
+        match c {
+            '\n' => line += 1,
+            _ if in_string => {
+                match c {
+                    '\\' if !escaped => escaped = true,
+                    '"' if !escaped => in_string = false,
+                    _ => escaped = false,
+                }
+                continue;
+            }
+            '"' => in_string = true,
+            '{' | '[' => {
+                if c == '{' && depth == 2 {
+                    lines.push(line);
+                }
+                depth += 1;
+            }
+            '}' | ']' => depth -= 1,
+            _ => {},
+        }
+        escaped = false;
+    }
+
+    lines
+}
+
+/// Checks a task read from a `backup --json` file and brings its values into the form the
+/// database stores
+fn check_exported_task(task: &mut ExportedTask) -> Result<(), String> {
+    // Stored the way SQLite's datetime('now') writes them, in UTC
+    let from_rfc3339 = |timestamp: &mut Option<String>| -> Result<(), String> {
+        if let Some(value) = timestamp {
+            let parsed = DateTime::parse_from_rfc3339(value).map_err(|e| format!("invalid timestamp '{}': {}", value, e))?;
+            *value = parsed.with_timezone(&Utc).format("%Y-%m-%d %H:%M:%S").to_string();
+        }
+        Ok(())
+    };
+
+    if task.name.trim().is_empty() {
+        return Err(String::from("empty task name"));
+    }
+    if let Some((key, _)) = task.fields.iter().find(|(key, value)| parse_field(&format!("{}={}", key, value)).is_err()) {
+        return Err(format!("invalid field name '{}'", key));
+    }
+    if let Some(rule) = &task.recurrence {
+        task.recurrence = Some(parse_recurrence(rule).ok_or_else(|| format!("invalid recurrence '{}'", rule))?);
+    }
+    from_rfc3339(&mut task.completed_at)?;
+    from_rfc3339(&mut task.due_at)?;
+    from_rfc3339(&mut task.snoozed_until)?;
+    from_rfc3339(&mut task.created_at)
+}
+
+/// Reads tasks written by `backup --json` and replaces the current ones with them, unless it's a
+/// `dry_run`. Nothing is imported if any task can't be parsed. Unless `force` is set, a task named
+/// like an earlier one with the same parent is skipped and its subtasks go to that one instead.
+fn import_json(path: &str, dry_run: bool, json: bool, force: bool, report_path: &Option<PathBuf>) -> Result<(), String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+    let export: Export = serde_json::from_str(&contents).map_err(|e| format!("Failed to parse '{}': {}", path, e))?;
+    let lines = json_task_lines(&contents);
+
+    let mut report = ImportReport::default();
+    let mut tasks: Vec<ExportedTask> = Vec::new();
+    // Where each task of the file ends up in `tasks`, counting from 1
+    let mut positions: Vec<usize> = Vec::new();
+
+    for (i, mut task) in export.tasks.into_iter().enumerate() {
+        let line = lines.get(i).copied().unwrap_or_default();
+        if let Err(reason) = check_exported_task(&mut task) {
+            report.push(line, &task.name, ImportOutcome::ParseError(format!("task {}: {}", i + 1, reason)));
+            positions.push(0);
+            continue;
+        }
+
+        task.name = clean_name(&task.name);
+        task.parent = task.parent.and_then(|parent| positions.get(parent.checked_sub(1)?).copied()).filter(|&parent| parent > 0);
+
+        let earlier = tasks.iter().position(|t| t.parent == task.parent && normalize_name(&t.name) == normalize_name(&task.name));
+        match earlier {
+            Some(earlier) if !force => {
+                report.push(line, &task.name, ImportOutcome::SkipDuplicate);
+                positions.push(earlier + 1);
+            }
+            _ => {
+                report.push(line, &task.name, ImportOutcome::Add);
+                tasks.push(task);
+                positions.push(tasks.len());
+            }
+        }
+    }
+    if !json {
+        report.print_errors();
+    }
+
+    let applied = !dry_run && report.errors() == 0;
+    if applied {
+        let mut conn = establish_connection();
+        import_tasks_in_db(&mut conn, &tasks).map_err(|e| format!("Failed to import tasks from '{}': {}", path, e))?;
+    }
+
+    if let Some(report_path) = report_path {
+        report.write(report_path, applied)?;
+    }
+
+    let summary = format!("{} added{}", tasks.len(), report.skipped_summary());
+
+    if json {
+        let conn = open_connection();
+        let rows: Vec<serde_json::Value> = report.rows.iter()
+            .map(|(line, name, outcome)| serde_json::json!({ "line": line, "name": name, "outcome": outcome.label() }))
+            .collect();
+        println!("{}", serde_json::json!({ "action": "restore", "source": path, "tasks": tasks.len(), "replacing": get_tasks_length(&conn), "rows": rows }));
+        return Ok(());
+    }
+
+    if dry_run {
+        print_restore_plan(path, tasks.len(), false);
+        println!("Dry run: {}", summary);
+        if report.errors() > 0 {
+            print_warning("A real run imports nothing until the tasks that could not be parsed are fixed.");
+        }
+        return Ok(());
+    }
+
+    if !applied {
+        return Err(format!("Nothing was imported because {} task(s) in '{}' could not be parsed", report.errors(), path));
+    }
+
+    print_success(&format!("Tasks imported successfully: {}", summary));
+    Ok(())
+}
+
+pub fn import(source: &ImportSource) {
+    match source {
+        ImportSource::Db { path } => import_db(path),
+    }
+}
+
+/// Appends the tasks of the todoln database at `path` with their done state, in its order,
+/// leaving out the ones whose names are already in the list apart from case and spacing
+fn import_db(path: &Path) {
+    let backup_tasks = match get_backup_tasks(&path.to_string_lossy()) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&format!("Failed to read the tasks to import: {}", e));
+            return;
+        }
+    };
+
+    let mut conn = establish_connection();
+    let mut names: HashSet<String> = match get_tasks_from_db(&conn) {
+        Ok(tasks) => tasks.iter().map(|t| normalize_name(&t.name)).collect(),
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    let mut new_tasks: Vec<(String, bool)> = Vec::new();
+    let mut skipped: Vec<String> = Vec::new();
+    for (name, done) in backup_tasks {
+        if names.insert(normalize_name(&name)) {
+            new_tasks.push((name, done));
+        } else {
+            skipped.push(name);
+        }
+    }
+
+    let created_ids = if new_tasks.is_empty() {
+        Vec::new()
+    } else {
+        match append_tasks_to_db(&mut conn, &new_tasks) {
+            Ok(created_ids) => created_ids,
+            Err(e) => {
+                print_error(&format!("Failed to import tasks from '{}': {}", path.display(), e));
+                return;
+            }
+        }
+    };
+
+    // The names come from another file, so they are sanitized like any task name shown
+    if new_tasks.is_empty() {
+        print_notice(&tr("no-tasks-imported", &[]));
+    } else {
+        let imported: Vec<String> = new_tasks.iter().map(|(name, _)| sanitize(name)).collect();
+        print_success(&format!("Task(s) imported successfully: {}", imported.join(", ")));
+    }
+    if !skipped.is_empty() {
+        let skipped_names: Vec<String> = skipped.iter().map(|name| sanitize(name)).collect();
+        print_notice(&tr("import-skipped", &[&skipped.len(), &skipped_names.join(", ")]));
+    }
+
+    if json_output() {
+        let tasks = get_tasks_from_db(&conn).unwrap_or_default();
+        let imported: Vec<serde_json::Value> = created_ids.iter().filter_map(|id| tasks.iter().find(|t| t.id == Some(*id))).map(task_json).collect();
+        print_result(serde_json::json!({ "action": "import", "tasks": imported, "skipped": skipped }));
+    }
+}
+
+pub fn backup(json: bool) {
+    if json && is_encrypted(&db_path()) {
+        print_error("Error: The task database is encrypted and a JSON backup would hold the tasks in plain text; back up without --json to get an encrypted copy.");
+        return;
+    }
+
+    if json {
+        match env::current_dir() {
+            Ok(current_dir) => match export_json(&current_dir.join("todoln_backup.json")) {
+                Ok(_) => print_success("Tasks exported successfully"),
+                Err(e) => print_error(&e),
+            },
+            Err(_) => print_error("Failed to get current directory"),
+        }
+        return;
+    }
+
     if let Ok(mut current_dir) = env::current_dir() {
         current_dir.push("todoln_backup.db");
         let backup_path = current_dir.to_str().expect("Invalid Unicode in current path");
 
-        if let Err(e) = backup_db(backup_path) {
-            print_error(&format!("Failed to backup database: {}", e));
+        if let Err(e) = backup_db(backup_path) {
+            print_error(&format!("Failed to backup database {}", e));
+            return;
+        }
+
+        print_success("Task database backuped successfully");
+    } else {
+        print_error("Failed to get current directory");
+    }
+}
+
+const NO_ENCRYPTION: &str = "Error: This todoln was built without encryption support; build it with `cargo build --release --features encryption`.";
+
+/// Asks for a new passphrase twice, or takes it from `TODOLN_PASSPHRASE`, printing an error and
+/// returning `None` if it's empty or the two don't match
+fn new_passphrase() -> Option<String> {
+    let passphrase = match env::var("TODOLN_PASSPHRASE") {
+        Ok(passphrase) => passphrase,
+        Err(_) => {
+            let passphrase = ask_secret("New passphrase:");
+            if !passphrase.is_empty() && ask_secret("Repeat the passphrase:") != passphrase {
+                print_error("Error: The passphrases don't match, nothing was changed.");
+                return None;
+            }
+            passphrase
+        }
+    };
+
+    if passphrase.is_empty() {
+        print_error("Error: The passphrase can't be empty.");
+        return None;
+    }
+
+    Some(passphrase)
+}
+
+pub fn encrypt() {
+    if !cfg!(feature = "encryption") {
+        print_error(NO_ENCRYPTION);
+        return;
+    }
+    if is_encrypted(&db_path()) {
+        print_error("Error: The task database is already encrypted.");
+        return;
+    }
+
+    let conn = establish_connection();
+    let Some(passphrase) = new_passphrase() else {
+        return;
+    };
+
+    match rekey_db(conn, &db_path(), &passphrase) {
+        Ok(_) => print_success("Task database encrypted successfully; todoln asks for the passphrase from now on, unless it's in TODOLN_PASSPHRASE"),
+        Err(e) => print_error(&format!("Failed to encrypt the task database {}", e)),
+    }
+}
+
+pub fn decrypt() {
+    if !is_encrypted(&db_path()) {
+        print_error("Error: The task database isn't encrypted.");
+        return;
+    }
+    if !cfg!(feature = "encryption") {
+        print_error(NO_ENCRYPTION);
+        return;
+    }
+
+    let conn = establish_connection();
+    match rekey_db(conn, &db_path(), "") {
+        Ok(_) => print_success("Task database decrypted successfully"),
+        Err(e) => print_error(&format!("Failed to decrypt the task database {}", e)),
+    }
+}
+
+pub fn restore(backup_path: &Option<String>, last_reset: bool, dry_run: bool, json: bool, force: bool, report: &Option<PathBuf>) {
+    let backup_path = if last_reset {
+        match last_reset_snapshot() {
+            Some(path) => Some(path.to_string_lossy().into_owned()),
+            None => {
+                print_error(&format!("Error: No snapshot from `reset` found in '{}'.", snapshots_dir().display()));
+                return;
+            }
+        }
+    } else {
+        backup_path.clone()
+    };
+    let Some(mut backup_path) = backup_path else {
+        return;
+    };
+    let backup_path_buf = PathBuf::from(&backup_path);
+
+    if !backup_path_buf.is_absolute() {
+        if let Ok(mut current_dir) = env::current_dir() {
+            current_dir.push(backup_path_buf);
+            backup_path = current_dir.to_string_lossy().into_owned();
+        } else {
+            print_error("Failed to get current directory");
+            return;
+        }
+    }
+
+    if backup_path.to_lowercase().ends_with(".json") {
+        if let Err(e) = import_json(&backup_path, dry_run, json, force, report) {
+            print_error(&e);
+        }
+        return;
+    }
+
+    if force || report.is_some() {
+        print_error("Error: --force and --report only work with .json backups");
+        return;
+    }
+
+    if dry_run {
+        match get_backup_task_count(&backup_path) {
+            Ok(count) => print_restore_plan(&backup_path, count, json),
+            Err(e) => print_error(&format!("Failed to read backup {}", e)),
+        }
+        return;
+    }
+
+    if let Err(e) = restore_db(&backup_path) {
+        print_error(&format!("Failed to restore database {}", e));
+        return;
+    }
+
+    print_success("Task database restored successfully");
+}
+
+/// Prints what `restore --dry-run` would do: restore `count` tasks from `source` in place of the
+/// current ones
+fn print_restore_plan(source: &str, count: usize, json: bool) {
+    let conn = open_connection();
+    let replacing = get_tasks_length(&conn);
+
+    if json {
+        println!("{}", serde_json::json!({ "action": "restore", "source": source, "tasks": count, "replacing": replacing }));
+        return;
+    }
+
+    println!("Would restore {} task(s) from {}, replacing {} current task(s).", count, source, replacing);
+}
+
+/// A checklist item's name and whether it's ticked, or why it couldn't be read
+type ChecklistItem = Result<(String, bool), String>;
+
+/// The checklist items of a markdown file with their line numbers, or why a line that looks like
+/// an item couldn't be read. The trimmed line comes with each item.
+fn parse_md_checklist(contents: &str) -> Vec<(usize, &str, ChecklistItem)> {
+    let mut items = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        let rest = match line.strip_prefix("- ").or(line.strip_prefix("* ")).or(line.strip_prefix("+ ")) {
+            Some(rest) => rest.trim_start(),
+            None => continue,
+        };
+
+        let (done, name) = if let Some(name) = rest.strip_prefix("[ ]") {
+            (false, name)
+        } else if let Some(name) = rest.strip_prefix("[x]").or(rest.strip_prefix("[X]")) {
+            (true, name)
+        } else {
+            // A single character in brackets is a checkbox that was mistyped, anything else is just a list item
+            let mut chars = rest.chars();
+            if let (Some('['), Some(mark), Some(']')) = (chars.next(), chars.next(), chars.next()) {
+                if chars.next().map_or(true, char::is_whitespace) {
+                    items.push((i + 1, line, Err(format!("unknown checkbox '[{}]', use '[ ]' or '[x]'", mark))));
+                }
+            }
+            continue;
+        };
+
+        let name = name.trim();
+        if name.is_empty() {
+            items.push((i + 1, line, Err(String::from("empty task name"))));
+            continue;
+        }
+
+        items.push((i + 1, line, Ok((name.to_string(), done))));
+    }
+
+    items
+}
+
+pub fn sync_md(file: &str, prune: bool, dry_run: bool, report_path: &Option<PathBuf>) {
+    let contents = match fs::read_to_string(file) {
+        Ok(contents) => contents,
+        Err(e) => {
+            print_error(&format!("Failed to read '{}': {}", file, e));
+            return;
+        }
+    };
+
+    let items = parse_md_checklist(&contents);
+
+    // A dry run must leave the database exactly as it is, so it doesn't even repair indices
+    let mut conn = if dry_run { open_connection() } else { establish_connection() };
+    let tasks = if dry_run { get_tasks_from_db(&conn) } else { get_tasks_from_db_and_update_indices(&mut conn) };
+
+    let tasks = match tasks {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    let mut changes: Vec<SyncChange> = Vec::new();
+    let mut report = ImportReport::default();
+    // Names as compared, so spacing and case differences count as the same task
+    let mut names: Vec<String> = Vec::new();
+
+    for (line, text, item) in &items {
+        let (name, done) = match item {
+            Ok((name, done)) => (name, *done),
+            Err(reason) => {
+                report.push(*line, text, ImportOutcome::ParseError(reason.clone()));
+                continue;
+            }
+        };
+
+        let name = &clean_name(name);
+        if names.contains(&normalize_name(name)) {
+            report.push(*line, name, ImportOutcome::SkipDuplicate);
+            continue;
+        }
+        names.push(normalize_name(name));
+
+        match similar_task(&tasks, name) {
+            Some(task) if task.done != done => {
+                changes.push(SyncChange::SetDone { id: task.id.unwrap(), name: name.clone(), done });
+                report.push(*line, name, if done { ImportOutcome::MarkDone } else { ImportOutcome::MarkTodo });
+            }
+            Some(_) => report.push(*line, name, ImportOutcome::SkipDuplicate),
+            None => {
+                changes.push(SyncChange::Add { name: name.clone(), done });
+                report.push(*line, name, ImportOutcome::Add);
+            }
+        }
+    }
+
+    if prune {
+        for task in tasks.iter().filter(|t| !names.contains(&normalize_name(&t.name))) {
+            changes.push(SyncChange::Remove { id: task.id.unwrap(), name: task.name.clone() });
+        }
+    }
+
+    report.print_errors();
+
+    let write_report = |applied: bool| match report_path {
+        Some(report_path) => report.write(report_path, applied).map_err(|e| print_error(&e)).is_ok(),
+        None => true,
+    };
+
+    if changes.is_empty() {
+        if write_report(false) {
+            println!("Tasks are already in sync with '{}'.", file);
+        }
+        return;
+    }
+
+    print_title(&tr("title-changes", &[]));
+    let (mut added, mut done, mut undone, mut removed) = (0, 0, 0, 0);
+    for change in &changes {
+        match change {
+            SyncChange::Add { name, .. } => { added += 1; println!("  + {}", todo_text(name)); },
+            SyncChange::SetDone { name, done: true, .. } => { done += 1; println!("  x {}", done_text(name)); },
+            SyncChange::SetDone { name, done: false, .. } => { undone += 1; println!("  o {}", todo_text(name)); },
+            SyncChange::Remove { name, .. } => { removed += 1; println!("  - {}", todo_text(name)); },
+            SyncChange::Rename { .. } => {},
+        }
+    }
+    println!();
+
+    let summary = format!("{} added, {} marked done, {} marked todo, {} removed{}", added, done, undone, removed, report.skipped_summary());
+
+    if dry_run {
+        if write_report(false) {
+            println!("Dry run: {}", summary);
+        }
+        return;
+    }
+
+    if let Err(e) = apply_sync_changes_in_db(&mut conn, "sync-md", &changes) {
+        print_error(&format!("Failed to sync tasks: {}", e));
+        return;
+    }
+
+    write_report(true);
+    print_success(&format!("Tasks synced successfully: {}", summary));
+}
+
+/// A task as `sync` writes it to its file. Items added by hand there have no id yet.
+#[derive(Serialize, Deserialize)]
+struct SyncItem {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<i32>,
+    name: String,
+    #[serde(default)]
+    done: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SyncFile {
+    tasks: Vec<SyncItem>,
+}
+
+const SYNC_ID_PREFIX: &str = "<!-- id:";
+
+/// The tasks as `sync` writes them: in list order, one `- [ ] name <!-- id:3 -->` line each, or
+/// as pretty-printed JSON for a .json file
+fn render_sync_file(tasks: &[Task], json: bool) -> String {
+    if json {
+        let items = tasks.iter().map(|t| SyncItem { id: t.id, name: t.name.clone(), done: t.done }).collect();
+        return format!("{}\n", serde_json::to_string_pretty(&SyncFile { tasks: items }).unwrap());
+    }
+
+    tasks.iter()
+        .map(|t| format!("- [{}] {} {}{} -->\n", if t.done { "x" } else { " " }, clean_name(&t.name), SYNC_ID_PREFIX, t.id.unwrap()))
+        .collect()
+}
+
+fn parse_sync_file(contents: &str, json: bool) -> Result<Vec<SyncItem>, String> {
+    if json {
+        return serde_json::from_str::<SyncFile>(contents).map(|file| file.tasks).map_err(|e| e.to_string());
+    }
+
+    let mut items = Vec::new();
+    for (line, _, item) in parse_md_checklist(contents) {
+        let (name, done) = item.map_err(|reason| format!("line {}: {}", line, reason))?;
+
+        let (name, id) = match name.rsplit_once(SYNC_ID_PREFIX) {
+            Some((name, id)) => match id.trim_end_matches("-->").trim().parse() {
+                Ok(id) => (name.trim().to_string(), Some(id)),
+                Err(_) => return Err(format!("line {}: unreadable task id '{}'", line, id)),
+            },
+            None => (name, None),
+        };
+        items.push(SyncItem { id, name, done });
+    }
+
+    Ok(items)
+}
+
+/// Works out which edits made to the file since `base`, the contents `sync` last wrote, to bring
+/// into the database, matching tasks by id and new items by name. A task changed in both places
+/// in different ways is a conflict: it's described in the second list and left alone.
+fn plan_sync(base: &[SyncItem], file: &[SyncItem], tasks: &[Task]) -> (Vec<SyncChange>, Vec<String>) {
+    let mut changes = Vec::new();
+    let mut conflicts = Vec::new();
+
+    for before in base {
+        let Some(id) = before.id else { continue };
+        let task = tasks.iter().find(|t| t.id == Some(id));
+        let after = file.iter().find(|item| item.id == Some(id));
+        let db_changed = task.map(|t| t.name != before.name || t.done != before.done);
+
+        match (after, task) {
+            (None, None) => {},
+            (None, Some(task)) if db_changed == Some(true) => conflicts.push(format!("'{}' was removed from the file but changed in todoln", task.name)),
+            (None, Some(task)) => changes.push(SyncChange::Remove { id, name: task.name.clone() }),
+            (Some(after), None) if after.name != before.name || after.done != before.done => {
+                conflicts.push(format!("'{}' was changed in the file but removed in todoln", after.name));
+            },
+            (Some(_), None) => {},
+            (Some(after), Some(task)) => {
+                let renamed = after.name != before.name && after.name != task.name;
+                let flipped = after.done != before.done && after.done != task.done;
+
+                if (renamed && task.name != before.name) || (flipped && task.done != before.done) {
+                    conflicts.push(format!("'{}' was changed both in the file (as '{}') and in todoln", task.name, after.name));
+                    continue;
+                }
+                if renamed {
+                    changes.push(SyncChange::Rename { id, name: after.name.clone(), old_name: task.name.clone() });
+                }
+                if flipped {
+                    changes.push(SyncChange::SetDone { id, name: after.name.clone(), done: after.done });
+                }
+            },
+        }
+    }
+
+    let known = |id: Option<i32>| id.is_some() && base.iter().any(|before| before.id == id);
+    let mut names: Vec<String> = Vec::new();
+    for item in file.iter().filter(|item| !known(item.id)) {
+        let name = clean_name(&item.name);
+        if name.is_empty() || names.contains(&normalize_name(&name)) {
+            continue;
+        }
+        names.push(normalize_name(&name));
+
+        match similar_task(tasks, &name) {
+            Some(task) if task.done != item.done => changes.push(SyncChange::SetDone { id: task.id.unwrap(), name, done: item.done }),
+            Some(_) => {},
+            None => changes.push(SyncChange::Add { name, done: item.done }),
+        }
+    }
+
+    (changes, conflicts)
+}
+
+pub fn sync(file: &Path, status: bool) {
+    // The file is remembered by its absolute path, so syncing it from another directory still counts
+    let dir = file.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let path = match fs::canonicalize(dir) {
+        Ok(dir) => dir.join(file.file_name().unwrap_or_default()),
+        Err(e) => {
+            print_error(&format!("Failed to find the directory of '{}': {}", file.display(), e));
+            return;
+        }
+    };
+    let key = path.to_string_lossy().into_owned();
+    let json = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => Some(contents),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            print_error(&format!("Failed to read '{}': {}", file.display(), e));
+            return;
+        }
+    };
+
+    let mut conn = if status { establish_read_connection() } else { establish_connection() };
+    let (tasks, base) = match get_tasks_from_db(&conn).and_then(|tasks| Ok((tasks, get_sync_state_from_db(&conn, &key)?))) {
+        Ok(result) => result,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    if status {
+        let file_changed = contents != base;
+        let tasks_changed = base.as_deref() != Some(render_sync_file(&tasks, json).as_str());
+        println!("{}", match (&base, file_changed, tasks_changed) {
+            (None, _, _) => format!("'{}' hasn't been synced yet.", file.display()),
+            (_, false, false) => format!("Tasks are in sync with '{}'.", file.display()),
+            (_, true, false) => format!("'{}' has changes to bring into todoln.", file.display()),
+            (_, false, true) => format!("The tasks have changes to write to '{}'.", file.display()),
+            (_, true, true) => format!("Both '{}' and the tasks changed since the last sync.", file.display()),
+        });
+        return;
+    }
+
+    let parse = |contents: &str| parse_sync_file(contents, json).map_err(|e| print_error(&format!("Error: Failed to read '{}': {}; nothing was synced.", file.display(), e)));
+    let base_items = base.as_deref().and_then(|base| parse_sync_file(base, json).ok()).unwrap_or_default();
+    let (changes, conflicts) = match contents.as_deref() {
+        Some(contents) if Some(contents) != base.as_deref() => match parse(contents) {
+            Ok(items) => plan_sync(&base_items, &items, &tasks),
+            Err(()) => return,
+        },
+        _ => (Vec::new(), Vec::new()),
+    };
+
+    if !changes.is_empty() {
+        if let Err(e) = apply_sync_changes_in_db(&mut conn, "sync", &changes) {
+            print_error(&format!("Failed to sync tasks: {}", e));
+            return;
+        }
+
+        print_title(&tr("title-changes", &[]));
+        for change in &changes {
+            match change {
+                SyncChange::Add { name, .. } => println!("  + {}", todo_text(name)),
+                SyncChange::SetDone { name, done: true, .. } => println!("  x {}", done_text(name)),
+                SyncChange::SetDone { name, done: false, .. } => println!("  o {}", todo_text(name)),
+                SyncChange::Rename { name, old_name, .. } => println!("  ~ {} {} {}", todo_text(old_name), dim_text("->"), todo_text(name)),
+                SyncChange::Remove { name, .. } => println!("  - {}", todo_text(name)),
+            }
+        }
+        println!();
+    }
+
+    if !conflicts.is_empty() {
+        for conflict in &conflicts {
+            print_error(&format!("Conflict: {}, so it was left as it is.", conflict));
+        }
+        print_error(&format!("Error: '{}' wasn't updated; make the file and todoln agree on these tasks and sync again.", file.display()));
+        return;
+    }
+
+    let tasks = match get_tasks_from_db(&conn) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+    let rendered = render_sync_file(&tasks, json);
+
+    if contents.as_deref() != Some(rendered.as_str()) {
+        if let Err(e) = fs::write(&path, &rendered) {
+            print_error(&format!("Failed to write '{}': {}", file.display(), e));
+            return;
+        }
+    }
+    if let Err(e) = set_sync_state_in_db(&conn, &key, &rendered) {
+        print_error(&format!("Failed to record the sync: {}", e));
+        return;
+    }
+
+    print_success(&format!("Tasks synced with '{}': {} change(s) brought in", file.display(), changes.len()));
+}
+
+pub fn record_usage(command: &str) {
+    if command == "usage" {
+        return;
+    }
+
+    let conn = establish_connection();
+
+    // Statistics are best-effort and must never get in the way of the actual command
+    record_usage_in_db(&conn, command).ok();
+}
+
+pub fn reindex() {
+    if let Some(session) = active_session() {
+        print_error(&format!("Error: Process {} has a scripting session open, so indices stay as they are until `todoln end-session`.", session.pid));
+        return;
+    }
+
+    let mut conn = open_connection();
+
+    match task_indices_are_contiguous(&conn).and_then(|contiguous| Ok(contiguous && task_indices_are_in_order(&conn)?)) {
+        Ok(true) => {
+            print_notice(&tr("indices-in-order", &[]));
+            return;
+        }
+        Ok(false) => {},
+        Err(e) => {
+            print_error(&format!("Failed to check task indices: {}", e));
+            return;
+        }
+    }
+
+    match renormalize_task_indices(&mut conn) {
+        Ok(_) => print_success("Task indices renumbered successfully"),
+        Err(e) => print_error(&format!("Failed to update task indices: {}", e)),
+    }
+}
+
+pub fn begin_session(timeout: &Option<String>) {
+    let timeout = timeout.as_deref().or(config().session_timeout.as_deref()).unwrap_or("30m");
+    let Some(seconds) = parse_duration(timeout).filter(|&seconds| seconds > 0) else {
+        print_error(&format!("Error: Invalid timeout '{}', use e.g. 30m or 2h", timeout));
+        process::exit(1);
+    };
+
+    // Settle the indices first, so the session starts from the numbering `list` shows
+    let mut conn = establish_connection();
+    if let Err(e) = renormalize_task_indices(&mut conn) {
+        print_error(&format!("Failed to update task indices: {}", e));
+        process::exit(1);
+    }
+
+    match database::begin_session(seconds) {
+        Ok(SessionStart::Started(session)) => {
+            print_success(&format!("Session started, task indices stay as they are until `todoln end-session` or {}", format_local_timestamp(&session.expires_at)));
+        }
+        Ok(SessionStart::AlreadyActive(Some(session))) => {
+            print_error(&format!(
+                "Error: Process {} already has a session open since {} (it times out at {}).",
+                session.pid, format_local_timestamp(&session.started_at), format_local_timestamp(&session.expires_at),
+            ));
+            process::exit(1);
+        }
+        Ok(SessionStart::AlreadyActive(None)) => {
+            print_error("Error: Another session is being started right now.");
+            process::exit(1);
+        }
+        Err(e) => {
+            print_error(&format!("Failed to start a session {}", e));
+            process::exit(1);
+        }
+    }
+}
+
+pub fn end_session() {
+    match database::end_session() {
+        Ok(Some(_)) => {},
+        Ok(None) => {
+            print_notice(&tr("no-session", &[]));
+            return;
+        }
+        Err(e) => {
+            print_error(&format!("Failed to end the session {}", e));
+            process::exit(1);
+        }
+    }
+
+    // Closes the gaps left while the session kept indices in place
+    establish_connection();
+    print_success("Session ended successfully");
+}
+
+pub fn info() {
+    let conn = establish_read_connection();
+
+    let tasks = match get_tasks_from_db(&conn) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+    let done = tasks.iter().filter(|t| t.done).count();
+
+    let session = match active_session() {
+        Some(session) => format!(
+            "held by process {} since {} (times out at {})",
+            session.pid, format_local_timestamp(&session.started_at), format_local_timestamp(&session.expires_at),
+        ),
+        None => String::from("none"),
+    };
+
+    print_title(&tr("title-info", &[]));
+
+    let fields = [
+        ("Database", db_path().display().to_string()),
+        ("Config", config_path().display().to_string()),
+        ("Tasks", format!("{} ({} todo, {} done)", tasks.len(), tasks.len() - done, done)),
+        ("Encrypted", String::from(if is_encrypted(&db_path()) { "yes" } else { "no" })),
+        ("Session", session),
+    ];
+
+    for (label, value) in fields {
+        println!("  {} {}", bold_text(&format!("{:<10}", format!("{}:", label))), sanitize(&value));
+    }
+}
+
+/// Whether `name` is one of todoln's own commands or their aliases, which `[aliases]` can't replace
+pub fn is_builtin_command(name: &str) -> bool {
+    name == "help" || Cli::command().find_subcommand(name).is_some()
+}
+
+pub fn aliases() {
+    let aliases = &config().aliases;
+
+    if aliases.is_empty() {
+        print!("{}", empty_text(&tr("no-aliases", &[&config_path().display()])));
+        return;
+    }
+
+    print_title(&tr("title-aliases", &[]));
+
+    let name_width = aliases.keys().map(|name| UnicodeWidthStr::width(name.as_str())).max().unwrap_or(0);
+
+    for (name, replacement) in aliases {
+        let problem = if is_builtin_command(name) {
+            Some(String::from("todoln already has a command with that name"))
+        } else {
+            expand_alias(aliases, name, is_builtin_command).err()
+        };
+        let problem = problem.map_or(String::new(), |problem| format!(" {}", dim_text(&format!("(ignored, {})", problem))));
+
+        println!("  {} = {}{}", bold_text(&format!("{:<name_width$}", name)), sanitize(&replacement.join(" ")), problem);
+    }
+}
+
+pub fn usage(clear: bool) {
+    let conn = if clear { establish_connection() } else { establish_read_connection() };
+
+    if clear {
+        if let Err(e) = clear_usage_in_db(&conn) {
+            print_error(&format!("Failed to clear usage statistics: {}", e));
+            return;
+        }
+
+        print_success("Usage statistics cleared successfully");
+        return;
+    }
+
+    if !config().usage_stats {
+        println!("Usage statistics are disabled. Set 'usage_stats = true' in '{}' to enable them.", config_path().display());
+    }
+
+    match get_usage_from_db(&conn) {
+        Ok(usage) => {
+            if usage.is_empty() {
+                print_notice(&tr("no-usage", &[]));
+                return;
+            }
+
+            let width = usage.iter().map(|(command, _, _)| command.len()).max().unwrap_or(0).max("Command".len());
+
+            print_title(&tr("title-usage", &[]));
+            println!("  {}  {}  {}", bold_text(&format!("{:<width$}", "Command")), bold_text(&format!("{:>7}", "Total")), bold_text(&format!("{:>7}", "Today")));
+            for (command, total, today) in usage {
+                println!("  {:<width$}  {:>7}  {:>7}", command, total, today);
+            }
+        }
+        Err(e) => print_error(&format!("Failed to retrieve usage statistics: {}", e)),
+    }
+}
+
+/// Version of the `introspect --json` output. Adding commands or fields keeps it, anything that
+/// could break an existing reader (renamed or removed fields, changed types) raises it.
+pub const INTROSPECT_VERSION: u32 = 1;
+
+fn describe_arg(arg: &clap::Arg) -> serde_json::Value {
+    serde_json::json!({
+        "id": arg.get_id().as_str(),
+        "long": arg.get_long(),
+        "short": arg.get_short(),
+        "aliases": arg.get_visible_aliases().unwrap_or_default(),
+        "positional": arg.is_positional(),
+        "takes_value": arg.get_action().takes_values(),
+        "multiple": matches!(arg.get_action(), ArgAction::Append) || arg.get_num_args().is_some_and(|range| range.max_values() > 1),
+        "required": arg.is_required_set(),
+        "global": arg.is_global_set(),
+        "value_names": arg.get_value_names().filter(|_| arg.get_action().takes_values()).unwrap_or_default().iter().map(|name| name.as_str()).collect::<Vec<_>>(),
+        "default_values": arg.get_default_values().iter().map(|value| value.to_string_lossy()).collect::<Vec<_>>(),
+        "help": arg.get_help().map(|help| help.to_string()),
+    })
+}
+
+/// The command's description for `introspect --json`, with its subcommands nested inside
+pub fn describe_command(command: &clap::Command) -> serde_json::Value {
+    serde_json::json!({
+        "name": command.get_name(),
+        "aliases": command.get_visible_aliases().collect::<Vec<_>>(),
+        "about": command.get_about().map(|about| about.to_string()),
+        "arguments": command.get_arguments().filter(|arg| !arg.is_hide_set()).map(describe_arg).collect::<Vec<_>>(),
+        "subcommands": command.get_subcommands().filter(|subcommand| !subcommand.is_hide_set()).map(describe_command).collect::<Vec<_>>(),
+    })
+}
+
+pub fn introspect(json: bool) {
+    let command = Cli::command();
+
+    if json {
+        let mut description = describe_command(&command);
+        description["format_version"] = serde_json::json!(INTROSPECT_VERSION);
+        description["version"] = serde_json::json!(command.get_version());
+        println!("{}", description);
+        return;
+    }
+
+    for subcommand in command.get_subcommands().filter(|subcommand| !subcommand.is_hide_set()) {
+        let aliases: Vec<&str> = subcommand.get_visible_aliases().collect();
+        let aliases = if aliases.is_empty() { String::new() } else { format!(" {}", dim_text(&format!("({})", aliases.join(", ")))) };
+        println!("{}{}", bold_text(subcommand.get_name()), aliases);
+
+        for arg in subcommand.get_arguments().filter(|arg| !arg.is_hide_set()) {
+            let name = match arg.get_long() {
+                Some(long) => format!("--{}", long),
+                None => format!("<{}>", arg.get_value_names().and_then(|names| names.first()).map_or(arg.get_id().as_str(), |name| name.as_str())),
+            };
+            println!("  {}", name);
+        }
+    }
+}
+
+const MAN_NAME: &str = "a Blazingly Fast and minimal task organiser written in rust";
+const MAN_DESCRIPTION: &str = "todoln keeps a list of tasks in a SQLite database and refers to them \
+by their index, as shown by `todoln list`. Without a command it lists all tasks.";
+
+/// What `todoln man` adds after the commands, for what `--help` doesn't make obvious
+const MAN_EXAMPLES: &[(&str, &str)] = &[
+    ("todoln add \"buy milk\" \"walk the dog\"", "Adds two tasks."),
+    ("todoln add \"buy milk,walk the dog\"", "Also adds two tasks: commas separate task names as well, so a name given as an argument can't contain one."),
+    ("todoln add --under 2 \"book flights\"", "Adds a subtask to task 2."),
+    ("todoln done 1,3 5", "Marks tasks 1, 3 and 5 as done. Task indices can be given one per argument, separated by commas, or both."),
+    ("todoln remove 2.1", "Removes the first subtask of task 2."),
+    ("todoln insert -1 \"reply to emails\"", "Adds a task before the last one."),
+    ("todoln insert -- 1984", "Adds a task named \"1984\" at the end. A number given first to `insert` is its index, so task names that are numbers go after `--`."),
+    ("todoln find milk --in todo", "Lists the todo tasks with \"milk\" in their name."),
+];
+
+pub fn man() {
+    let mut page = Vec::new();
+
+    match render_man_page(&mut page) {
+        Ok(()) => print!("{}", String::from_utf8_lossy(&page)),
+        Err(e) => print_error(&format!("Failed to render the man page: {}", e)),
+    }
+}
+
+fn render_man_page(page: &mut Vec<u8>) -> io::Result<()> {
+    let mut command = Cli::command().about(MAN_NAME).long_about(MAN_DESCRIPTION).disable_help_subcommand(true);
+    command.build();
+
+    let man = Man::new(command.clone());
+    man.render_title(page)?;
+    man.render_name_section(page)?;
+    man.render_synopsis_section(page)?;
+    man.render_description_section(page)?;
+    man.render_options_section(page)?;
+
+    for subcommand in command.get_subcommands().filter(|subcommand| !subcommand.is_hide_set()) {
+        render_man_command(page, subcommand)?;
+    }
+
+    let mut examples = Roff::new();
+    examples.control("SH", ["EXAMPLES"]);
+    for (example, description) in MAN_EXAMPLES {
+        examples.control("TP", []).text([bold(*example)]).text([roman(*description)]);
+    }
+    examples.to_writer(page)?;
+
+    man.render_version_section(page)?;
+    man.render_authors_section(page)
+}
+
+/// A section for one command, with its synopsis and options as subsections. The global options
+/// are left out as the page lists them once at the top.
+fn render_man_command(page: &mut Vec<u8>, subcommand: &clap::Command) -> io::Result<()> {
+    let globals: Vec<clap::Id> = subcommand.get_arguments().filter(|arg| arg.is_global_set()).map(|arg| arg.get_id().clone()).collect();
+    let subcommand = globals.iter().fold(subcommand.clone(), |subcommand, id| subcommand.mut_arg(id, |arg| arg.hide(true)));
+
+    let mut roff = Roff::new();
+    roff.control("SH", [format!("TODOLN {}", subcommand.get_name().to_uppercase()).as_str()]);
+
+    if let Some(about) = subcommand.get_long_about().or(subcommand.get_about()) {
+        roff.text([roman(about.to_string())]);
+    }
+
+    let aliases: Vec<&str> = subcommand.get_visible_aliases().collect();
+    if !aliases.is_empty() {
+        roff.control("PP", []).text([roman("Aliases: "), bold(aliases.join(", "))]);
+    }
+    roff.to_writer(page)?;
+
+    // clap_mangen renders each part as a section of its own page, so they're turned into
+    // subsections of the command's section
+    let man = Man::new(subcommand.clone());
+    let mut parts = Vec::new();
+    man.render_synopsis_section(&mut parts)?;
+    if subcommand.get_arguments().any(|arg| !arg.is_hide_set()) {
+        man.render_options_section(&mut parts)?;
+    }
+
+    for line in String::from_utf8_lossy(&parts).lines() {
+        match line.strip_prefix(".SH ") {
+            Some(title) => writeln!(page, ".SS {}", title)?,
+            None => writeln!(page, "{}", line)?,
+        }
+    }
+
+    Ok(())
+}
+
+pub fn archive(list: bool, completed_in: Option<Period>) {
+    let mut conn = if list { establish_read_connection() } else { establish_connection() };
+
+    if list {
+        match get_archived_tasks_from_db(&conn, completed_in) {
+            Ok(archived) => {
+                if archived.is_empty() {
+                    print_notice(&tr("no-archived-tasks", &[]));
+                    return;
+                }
+
+                print_title(&tr("title-archived", &[]));
+                for (name, completed_at) in archived {
+                    match completed_at {
+                        Some(completed_at) => println!("  {} (completed {})", done_text(&name), completed_at),
+                        None => println!("  {}", done_text(&name)),
+                    }
+                }
+            }
+            Err(e) => print_error(&format!("Failed to retrieve archived tasks: {}", e)),
+        }
+        return;
+    }
+
+    match archive_done_tasks_in_db(&mut conn, None) {
+        Ok(0) => print_notice(&tr("nothing-to-archive", &[])),
+        Ok(count) => print_success(&format!("{} completed task(s) archived successfully", count)),
+        Err(e) => print_error(&format!("Failed to archive tasks: {}", e)),
+    }
+}
+
+/// How `log` and `week` head the tasks of `day`
+fn day_title(day: NaiveDate) -> String {
+    let today = Local::now().date_naive();
+
+    if day == today {
+        tr("title-today", &[])
+    } else if today.pred_opt() == Some(day) {
+        tr("title-yesterday", &[])
+    } else if today.succ_opt() == Some(day) {
+        tr("title-tomorrow", &[])
+    } else {
+        day.format("%A %Y-%m-%d:").to_string()
+    }
+}
+
+pub fn log(since: Option<DateTime<Utc>>) {
+    let conn = establish_read_connection();
+
+    let completed = match get_completion_log_from_db(&conn, since) {
+        Ok(completed) => completed,
+        Err(e) => {
+            print_error(&format!("Failed to retrieve completed tasks: {}", e));
+            return;
+        }
+    };
+
+    if completed.is_empty() {
+        print_notice(&tr("no-completed-tasks", &[]));
+        return;
+    }
+
+    let mut day: Option<NaiveDate> = None;
+    for (name, completed_at) in completed {
+        if day != Some(completed_at.date()) {
+            if day.is_some() {
+                println!();
+            }
+            day = Some(completed_at.date());
+            print_title(&day_title(completed_at.date()));
+        }
+
+        println!("  {} {}", dim_text(&completed_at.format("%H:%M").to_string()), done_text(&name));
+    }
+}
+
+/// How many days in a row up to today at least one task was completed, from completion counts per
+/// day. A streak that reached yesterday still counts until today is over.
+fn completion_streak(per_day: &HashMap<NaiveDate, i64>, today: NaiveDate) -> usize {
+    let start = if per_day.contains_key(&today) { Some(today) } else { today.pred_opt() };
+
+    start.into_iter()
+        .flat_map(|start| start.iter_days().rev())
+        .take_while(|day| per_day.contains_key(day))
+        .count()
+}
+
+pub fn stats(weekly: bool) {
+    let conn = establish_read_connection();
+
+    let per_day: HashMap<NaiveDate, i64> = match get_completions_per_day_from_db(&conn) {
+        Ok(days) => days.into_iter().collect(),
+        Err(e) => {
+            print_error(&format!("Failed to retrieve completed tasks: {}", e));
+            return;
+        }
+    };
+
+    let span = if weekly { 7 } else { 30 };
+    let today = Local::now().date_naive();
+    let days: Vec<(NaiveDate, i64)> = (0..span)
+        .rev()
+        .filter_map(|ago| today.checked_sub_days(chrono::Days::new(ago)))
+        .map(|day| (day, per_day.get(&day).copied().unwrap_or(0)))
+        .collect();
+
+    let most = days.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    let count_width = most.to_string().len();
+
+    print_title(&tr("title-completed-last-days", &[&span]));
+
+    // Bars fill what the terminal has left after the date and the count
+    let bar_width = io::stdout().is_terminal()
+        .then(|| terminal::size().ok().filter(|(columns, _)| *columns > 0).map_or(80, |(columns, _)| columns as usize).saturating_sub(2 + 9 + 1 + 1 + count_width).max(1));
+
+    for (day, count) in &days {
+        let label = day.format("%a %m-%d");
+        match bar_width {
+            Some(bar_width) if most > 0 => {
+                let length = (*count as usize * bar_width + most as usize - 1) / most as usize;
+                println!("  {} {} {:>count_width$}", label, "#".repeat(length), count);
+            }
+            _ => println!("  {} {:>count_width$}", label, count),
+        }
+    }
+
+    println!();
+
+    let streak = completion_streak(&per_day, today);
+    println!("  Current streak: {} day{}", streak, if streak == 1 { "" } else { "s" });
+
+    match days.iter().filter(|(_, count)| *count > 0).max_by_key(|(day, count)| (*count, *day)) {
+        Some((day, count)) => println!("  Busiest day:    {} ({} task{})", day.format("%A %Y-%m-%d"), count, if *count == 1 { "" } else { "s" }),
+        None => println!("  Busiest day:    none, nothing was completed"),
+    }
+}
+
+pub fn wrapup(no_archive: bool, no_sort: bool, no_backup: bool, yes: bool) {
+    let mut conn = establish_connection();
+
+    let completed_today = match get_tasks_completed_today_from_db(&conn) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    let done_count = match get_tasks_from_db(&conn) {
+        Ok(tasks) => tasks.iter().filter(|t| t.done).count(),
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    if completed_today.is_empty() {
+        print_notice(&tr("none-completed-today", &[]));
+    } else {
+        print_title(&tr("title-completed-today", &[]));
+        print_task_rows(&completed_today.iter().collect::<Vec<_>>());
+    }
+    println!();
+
+    let done_action = if no_archive || done_count == 0 { None } else { Some(config().wrapup_done) };
+
+    let mut steps = Vec::new();
+    match done_action {
+        Some(WrapupDone::Archive) => steps.push(format!("archive {} completed task(s)", done_count)),
+        Some(WrapupDone::Clear) => steps.push(format!("clear {} completed task(s)", done_count)),
+        None => {},
+    }
+    if !no_sort {
+        steps.push(String::from("sort the remaining tasks"));
+    }
+    if !no_backup {
+        steps.push(format!("save a backup snapshot to {}", snapshots_dir().display()));
+    }
+
+    if steps.is_empty() {
+        print_notice(&tr("nothing-to-do", &[]));
+        return;
+    }
+
+    println!("This will:");
+    for step in &steps {
+        println!("  - {}", step);
+    }
+
+    if !yes && !confirm("Continue?") {
+        print_notice(&tr("wrapup-cancelled", &[]));
+        return;
+    }
+
+    match wrapup_tasks_in_db(&mut conn, done_action, !no_sort) {
+        Ok(count) => {
+            match done_action {
+                Some(WrapupDone::Archive) => print_success(&format!("{} completed task(s) archived successfully", count)),
+                Some(WrapupDone::Clear) => print_success(&format!("{} completed task(s) cleared successfully", count)),
+                None => {},
+            }
+            if !no_sort {
+                print_success(&tr("tasks-sorted", &[]));
+            }
+        }
+        Err(e) => {
+            print_error(&format!("Failed to wrap up tasks, nothing was changed: {}", e));
             return;
         }
+    }
 
-        print_success("Task database backuped successfully");
+    if no_backup {
+        return;
+    }
+
+    match save_snapshot(&conn, "todoln") {
+        Ok(snapshot_path) => print_success(&format!("Backup snapshot saved to {}", snapshot_path.display())),
+        Err(e) => print_error(&e),
+    }
+}
+
+/// Sleeps through a session when there's no terminal to draw on (Ctrl-C ends it early), then
+/// works out which tasks were completed in the meantime
+fn wait_for_session(conn: &Connection, duration: Duration) -> (Duration, Vec<Task>) {
+    let done_before: Vec<i32> = match get_tasks_from_db(conn) {
+        Ok(tasks) => tasks.iter().filter(|t| t.done).map(|t| t.id.unwrap()).collect(),
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            process::exit(1);
+        }
+    };
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler_flag = Arc::clone(&interrupted);
+    ctrlc::set_handler(move || handler_flag.store(true, Ordering::SeqCst)).ok();
+
+    let start = Instant::now();
+    while start.elapsed() < duration && !interrupted.load(Ordering::SeqCst) {
+        thread::sleep((duration - start.elapsed()).min(Duration::from_millis(200)));
+    }
+
+    let completed = match get_tasks_from_db(conn) {
+        Ok(tasks) => tasks.into_iter().filter(|t| t.done && !done_before.contains(&t.id.unwrap())).collect(),
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            Vec::new()
+        }
+    };
+
+    (start.elapsed().min(duration), completed)
+}
+
+pub fn start(task_index: &TaskIndex) {
+    let mut conn = establish_connection();
+    let Some(task_index) = &resolve_task_index(&conn, task_index) else {
+        return;
+    };
+
+    let task = match get_task_from_db(&conn, task_index) {
+        Ok(Some(task)) => task,
+        Ok(None) => {
+            print_error(&tr("invalid-index", &[&task_index]));
+            return;
+        }
+        Err(e) => {
+            print_error(&format!("Failed to retrieve task {}: {}", task_index, e));
+            return;
+        }
+    };
+
+    if task.done {
+        print_error(&format!("Error: Task {} is already done.", task_index));
+        return;
+    }
+
+    match get_running_timer_from_db(&conn) {
+        Ok(Some((running, seconds))) if running.id == task.id => {
+            print_notice(&tr("already-tracking", &[task_index, &task.name, &format_duration(seconds)]));
+            return;
+        }
+        Ok(_) => {},
+        Err(e) => {
+            print_error(&format!("Failed to retrieve the running timer: {}", e));
+            return;
+        }
+    }
+
+    match start_timer_in_db(&mut conn, &task) {
+        Ok(stopped) => {
+            if let Some((stopped, seconds)) = stopped {
+                print_notice(&tr("stopped-tracking", &[&stopped.name, &format_duration(seconds)]));
+            }
+            print_success(&format!("Started tracking time on task {}: {}", task_index, task.name));
+        }
+        Err(e) => print_error(&format!("Failed to start tracking time on task {}: {}", task_index, e)),
+    }
+}
+
+pub fn stop() {
+    let conn = establish_connection();
+
+    match stop_timer_in_db(&conn) {
+        Ok(Some((task, seconds))) => print_success(&format!("Stopped tracking '{}' after {}", task.name, format_duration(seconds))),
+        Ok(None) => print_notice(&tr("no-timer", &[])),
+        Err(e) => print_error(&format!("Failed to stop the timer: {}", e)),
+    }
+}
+
+pub fn time(task_index: &Option<TaskIndex>) {
+    let conn = establish_read_connection();
+
+    let task_index = match task_index.as_ref().map(|task_index| resolve_task_index(&conn, task_index)) {
+        Some(None) => return,
+        resolved => resolved.flatten(),
+    };
+
+    let task = match task_index {
+        Some(task_index) => match get_task_from_db(&conn, &task_index) {
+            Ok(Some(task)) => Some(task),
+            Ok(None) => {
+                print_error(&tr("invalid-index", &[&task_index]));
+                return;
+            }
+            Err(e) => {
+                print_error(&format!("Failed to retrieve task {}: {}", task_index, e));
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let (tracked, tasks, running) = match (
+        get_tracked_time_from_db(&conn, task.as_ref().and_then(|task| task.id)),
+        get_tasks_from_db(&conn),
+        get_running_timer_from_db(&conn),
+    ) {
+        (Ok(tracked), Ok(tasks), Ok(running)) => (tracked, tasks, running),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            print_error(&format!("Failed to retrieve tracked time: {}", e));
+            return;
+        }
+    };
+
+    if tracked.is_empty() {
+        print_notice(&tr("no-time-tracked", &[]));
+        return;
+    }
+
+    let running_id = running.and_then(|(task, _)| task.id);
+    let mut per_day: Vec<(NaiveDate, i64)> = Vec::new();
+    for (_, day, seconds) in &tracked {
+        match per_day.last_mut() {
+            Some((last, total)) if last == day => *total += seconds,
+            _ => per_day.push((*day, *seconds)),
+        }
+    }
+
+    if let Some(task) = task {
+        let total: i64 = per_day.iter().map(|(_, seconds)| seconds).sum();
+        let running = if running_id == task.id { format!(" {}", styled(Style::Success, "(running)")) } else { String::new() };
+
+        println!("{}", title_text(&tr("title-time-on-task", &[&task.name])));
+        println!();
+        for (day, seconds) in &per_day {
+            println!("  {}  {}", day.format("%Y-%m-%d"), format_duration(*seconds));
+        }
+        println!();
+        println!("  {}  {}{}", bold_text("Total:    "), format_duration(total), running);
+        return;
+    }
+
+    let labels = task_labels(&tasks);
+    let mut totals: HashMap<i32, i64> = HashMap::new();
+    for (task_id, _, seconds) in &tracked {
+        *totals.entry(*task_id).or_default() += seconds;
+    }
+    let mut per_task: Vec<(&Task, i64)> = tasks.iter().filter_map(|t| Some((t, *totals.get(&t.id?)?))).collect();
+    per_task.sort_by_key(|(_, seconds)| Reverse(*seconds));
+
+    let idx_width = per_task.iter().map(|(t, _)| labels[&t.id.unwrap()].len()).max().unwrap_or(1);
+
+    print_title(&tr("title-time-per-task", &[]));
+    for (task, seconds) in &per_task {
+        let running = if running_id == task.id { format!(" {}", styled(Style::Success, "(running)")) } else { String::new() };
+        println!("{} {}{}", format_list_row(task, &labels[&task.id.unwrap()], idx_width, false), dim_text(&format_duration(*seconds)), running);
+    }
+
+    println!();
+    print_title(&tr("title-time-per-day", &[]));
+    for (day, seconds) in &per_day {
+        println!("  {}  {}", day.format("%Y-%m-%d"), format_duration(*seconds));
+    }
+}
+
+pub fn session(duration: &Option<String>, count: u8, history: bool) {
+    let mut conn = if history { establish_read_connection() } else { establish_connection() };
+
+    if history {
+        let sessions = match get_sessions_from_db(&conn) {
+            Ok(sessions) => sessions,
+            Err(e) => {
+                print_error(&format!("Failed to retrieve sessions: {}", e));
+                return;
+            }
+        };
+
+        if sessions.is_empty() {
+            print_notice(&tr("no-focus-sessions", &[]));
+            return;
+        }
+
+        print_title(&tr("title-focus-sessions", &[]));
+        for (started_at, length, names) in sessions {
+            println!("  {}  {}  {} task(s) completed", bold_text(&started_at), format_clock(length as u64), names.len());
+            for name in names {
+                println!("      {}", done_text(&name));
+            }
+        }
+        return;
+    }
+
+    let duration = duration.as_deref().unwrap_or_default();
+    let seconds = match parse_duration(duration) {
+        Some(seconds) if seconds > 0 => seconds as u64,
+        _ => {
+            print_error(&format!("Error: Invalid duration '{}', use e.g. 30m, 12h, 14d or 2w", duration));
+            return;
+        }
+    };
+
+    let tasks: Vec<Task> = match get_tasks_from_db(&conn) {
+        Ok(tasks) => tasks.into_iter().filter(|t| !t.done).take(count as usize).collect(),
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    if tasks.is_empty() {
+        print_notice(&tr("nothing-to-focus-on", &[]));
+        return;
+    }
+
+    let (elapsed, completed) = if is_interactive() {
+        match tui::run_session(&conn, &tasks, Duration::from_secs(seconds)) {
+            Ok((elapsed, completed)) => (elapsed, tasks.into_iter().zip(completed).filter(|(_, done)| *done).map(|(task, _)| task).collect()),
+            Err(e) => {
+                print_error(&format!("Failed to run the focus session: {}", e));
+                return;
+            }
+        }
     } else {
-        print_error("Failed to get current directory");
+        print_title(&tr("title-focus-session", &[&format_clock(seconds)]));
+        print_task_rows(&tasks.iter().collect::<Vec<_>>());
+        println!();
+
+        wait_for_session(&conn, Duration::from_secs(seconds))
+    };
+
+    let elapsed_text = format_clock(elapsed.as_secs());
+
+    if completed.is_empty() {
+        println!("Session finished after {}, no tasks completed.", elapsed_text);
+    } else {
+        print_title(&tr("title-focus-finished", &[&elapsed_text, &completed.len()]));
+        for task in &completed {
+            println!("  {}", done_text(&task.name));
+        }
+    }
+
+    if let Err(e) = log_session_in_db(&mut conn, elapsed.as_secs() as i64, &completed) {
+        print_error(&format!("Failed to save the session to the history: {}", e));
     }
 }
 
-pub fn restore(backup_path: String) {
-    let mut backup_path = backup_path.clone();
-    let backup_path_buf = PathBuf::from(&backup_path);
+pub fn ui() {
+    if !is_interactive() {
+        print_error("Error: The interactive mode needs a terminal.");
+        process::exit(1);
+    }
 
-    if !backup_path_buf.is_absolute() {
-        if let Ok(mut current_dir) = env::current_dir() {
-            current_dir.push(backup_path_buf);
-            backup_path = current_dir.to_string_lossy().into_owned();
-        } else {
-            print_error("Failed to get current directory");
+    if let Err(e) = tui::run(establish_connection()) {
+        print_error(&format!("Failed to run the interactive mode: {}", e));
+        process::exit(1);
+    }
+}
+
+pub fn review(apply_partial: bool) {
+    if !is_interactive() {
+        print_error("Error: The review needs a terminal.");
+        process::exit(1);
+    }
+
+    let mut conn = establish_connection();
+
+    let tasks: Vec<Task> = match get_tasks_from_db(&conn) {
+        Ok(tasks) => tasks.into_iter().filter(|t| !t.done).collect(),
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
             return;
         }
+    };
+
+    if tasks.is_empty() {
+        print_notice(&tr("nothing-to-review", &[]));
+        return;
     }
 
-    if let Err(e) = restore_db(&backup_path) {
-        print_error(&format!("Failed to restore database: {}", e));
+    let (decisions, reviewed) = match tui::run_review(&tasks) {
+        Ok(result) => result,
+        Err(e) => {
+            print_error(&format!("Failed to run the review: {}", e));
+            process::exit(1);
+        }
+    };
+
+    if reviewed < tasks.len() && !apply_partial {
+        println!("Review stopped after {} of {} task(s), no changes applied (use --apply-partial to keep them).", reviewed, tasks.len());
         return;
     }
 
-    print_success("Task database restored successfully");
+    let (mut done, mut removed, mut snoozed, mut renamed) = (0, 0, 0, 0);
+    for decision in &decisions {
+        match decision {
+            ReviewDecision::Done { name, .. } => { done += 1; println!("  x {}", done_text(name)); },
+            ReviewDecision::Remove { name, .. } => { removed += 1; println!("  - {}", todo_text(name)); },
+            ReviewDecision::Snooze { name, due_at, .. } => { snoozed += 1; println!("  z {} (due {})", todo_text(name), format_local_timestamp(due_at)); },
+            ReviewDecision::Rename { name, new_name, .. } => { renamed += 1; println!("  ~ {} -> {}", todo_text(name), todo_text(new_name)); },
+        }
+    }
+    if !decisions.is_empty() {
+        println!();
+    }
+
+    let kept = reviewed - decisions.len();
+    let summary = format!("{} kept, {} marked done, {} removed, {} snoozed, {} renamed", kept, done, removed, snoozed, renamed);
+
+    if !decisions.is_empty() {
+        if let Err(e) = apply_review_in_db(&mut conn, &decisions) {
+            print_error(&format!("Failed to apply the review: {}", e));
+            return;
+        }
+    }
+
+    print_success(&format!("Reviewed {} task(s): {}", reviewed, summary));
+}
+
+pub fn dedupe(apply: bool) {
+    let mut conn = if apply { establish_connection() } else { establish_read_connection() };
+
+    let tasks = match get_tasks_from_db(&conn) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            print_error(&tr("failed-to-retrieve-tasks", &[&e]));
+            return;
+        }
+    };
+
+    // Group tasks by normalized name, keeping the order of first appearance
+    let mut groups: Vec<(String, Vec<&Task>)> = Vec::new();
+    for task in &tasks {
+        let normalized = normalize_name(&task.name);
+        match groups.iter_mut().find(|(key, _)| key == &normalized) {
+            Some((_, group)) => group.push(task),
+            None => groups.push((normalized, vec![task])),
+        }
+    }
+
+    let duplicate_groups: Vec<&Vec<&Task>> = groups.iter().map(|(_, group)| group).filter(|group| group.len() > 1).collect();
+
+    if duplicate_groups.is_empty() {
+        print_notice(&tr("no-duplicates", &[]));
+        return;
+    }
+
+    let idx_width = idx_width(&tasks.iter().collect::<Vec<_>>());
+
+    print_title(&tr("title-duplicates", &[]));
+    for group in &duplicate_groups {
+        for (i, task) in group.iter().enumerate() {
+            let marker = if i == 0 { "keep" } else { "remove" };
+            println!("{} ({})", format_task_row(task, idx_width), marker);
+        }
+        println!();
+    }
+
+    if !apply {
+        println!("Run with --apply to remove the duplicates.");
+        return;
+    }
+
+    let ids: Vec<i32> = duplicate_groups.iter().flat_map(|group| group.iter().skip(1).map(|t| t.id.unwrap())).collect();
+
+    match remove_tasks_by_id_from_db(&mut conn, "dedupe", &ids, false) {
+        Ok(_) => print_success(&format!("{} duplicate task(s) removed successfully", ids.len())),
+        Err(e) => print_error(&format!("Failed to remove duplicate tasks: {}", e)),
+    }
+}
+
+fn describe_problems(problems: &DbProblems) -> Vec<String> {
+    let mut descriptions: Vec<String> = Vec::new();
+
+    for message in &problems.integrity_errors {
+        descriptions.push(format!("Integrity check: {}", message));
+    }
+
+    if problems.foreign_key_violations > 0 {
+        descriptions.push(format!("{} orphaned row(s) referencing missing tasks", problems.foreign_key_violations));
+    }
+
+    for task in &problems.null_indices {
+        descriptions.push(format!("Task '{}' has no index", task.name));
+    }
+
+    for (task, idx) in &problems.out_of_range_indices {
+        descriptions.push(format!("Task '{}' has index {}, outside 1 to {}", task.name, idx, i32::MAX));
+    }
+
+    for idx in &problems.duplicate_indices {
+        descriptions.push(format!("Index {} is used by more than one task", idx));
+    }
+
+    if problems.missing_indices > 0 {
+        descriptions.push(format!("{} missing position(s) in the task ordering", problems.missing_indices));
+    }
+
+    for task in &problems.exact_duplicates {
+        descriptions.push(format!("Task [{}] '{}' is an exact duplicate", index_label(task), task.name));
+    }
+
+    descriptions
+}
+
+fn unlock() {
+    print_warning(&format!("Warning: This clears the locks on '{}' even if another todoln is still using it.", db_path().display()));
+
+    if !confirm("Make sure no other todoln is running. Clear the locks?") {
+        print_notice(&tr("nothing-changed", &[]));
+        return;
+    }
+
+    match unlock_db() {
+        Ok(removed) if removed.is_empty() => print_success("No lock files found, the database is unlocked"),
+        Ok(removed) => {
+            for path in removed {
+                println!("  Removed '{}'", path.display());
+            }
+            print_success("Database unlocked successfully");
+        }
+        Err(e) => {
+            print_error(&format!("Failed to unlock the database {}", e));
+            process::exit(1);
+        }
+    }
+}
+
+pub fn doctor(fix: bool, unlock: bool) {
+    if unlock {
+        self::unlock();
+        return;
+    }
+
+    let mut conn = open_connection();
+
+    let problems = match check_db(&conn) {
+        Ok(problems) => problems,
+        Err(e) => {
+            print_error(&format!("Failed to check the database: {}", e));
+            process::exit(1);
+        }
+    };
+
+    if problems.is_empty() {
+        print_success("No problems found.");
+        return;
+    }
+
+    print_title(&tr("title-found-problems", &[]));
+    for description in describe_problems(&problems) {
+        println!("  - {}", sanitize(&description));
+    }
+    println!();
+
+    if !fix {
+        println!("Run with --fix to repair them.");
+        process::exit(1);
+    }
+
+    if let Err(e) = repair_db(&mut conn, &problems) {
+        print_error(&format!("Failed to repair the database: {}", e));
+        process::exit(1);
+    }
+
+    let remaining = match check_db(&conn) {
+        Ok(remaining) => remaining,
+        Err(e) => {
+            print_error(&format!("Failed to check the database: {}", e));
+            process::exit(1);
+        }
+    };
+
+    let remaining_descriptions = describe_problems(&remaining);
+
+    print_title(&tr("title-fixed-problems", &[]));
+    for description in describe_problems(&problems).into_iter().filter(|d| !remaining_descriptions.contains(d)) {
+        println!("  - {}", sanitize(&description));
+    }
+    println!();
+
+    if !remaining.is_empty() {
+        print_title(&tr("title-remaining-problems", &[]));
+        for description in remaining_descriptions {
+            println!("  - {}", sanitize(&description));
+        }
+        println!();
+
+        print_error("Some problems could not be fixed.");
+        process::exit(1);
+    }
+
+    print_success("Database repaired successfully");
+}
+
+/// The share of free pages above which `maintenance --auto` compacts the database
+const AUTO_VACUUM_FREE_SHARE: f64 = 0.25;
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+pub fn maintenance(auto: bool) {
+    let db_path = db_path();
+    let stats = |conn: &Connection| match get_db_stats(conn, &db_path) {
+        Ok(stats) => Some(stats),
+        Err(e) => {
+            print_error(&format!("Failed to read the size of the database: {}", e));
+            None
+        }
+    };
+
+    let Some(before) = stats(&open_connection()) else {
+        return;
+    };
+
+    print_title(&tr("title-database", &[]));
+    println!("  {} {} ({} pages of {} bytes)", bold_text(&format!("{:<11}", "Size:")), format_size(before.file_size), before.page_count, before.page_size);
+    println!("  {} {} ({:.0}%)", bold_text(&format!("{:<11}", "Free pages:")), before.freelist_count, before.free_share() * 100.0);
+    println!();
+
+    if auto && before.free_share() <= AUTO_VACUUM_FREE_SHARE {
+        print_notice(&tr("no-maintenance-needed", &[]));
+        return;
+    }
+
+    match vacuum_db(&db_path) {
+        Ok(_) => {},
+        Err(e) if is_locked_error(&e) && auto => {
+            println!("Another todoln has the database open, skipped compacting it.");
+            return;
+        }
+        Err(e) if is_locked_error(&e) => {
+            print_error("Error: Another todoln has the database open, close it and try again.");
+            return;
+        }
+        Err(e) => {
+            print_error(&format!("Failed to compact the database: {}", e));
+            return;
+        }
+    }
+
+    let Some(after) = stats(&open_connection()) else {
+        return;
+    };
+    print_success(&format!("Database compacted from {} to {}", format_size(before.file_size), format_size(after.file_size)));
 }
\ No newline at end of file
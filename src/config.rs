@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use dirs::config_dir;
+use serde::Deserialize;
+
+use crate::utils::{print_error, print_warning};
+
+/// What `wrapup` does with the tasks completed today
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapupDone {
+    #[default]
+    Archive,
+    Clear,
+}
+
+/// What `list` puts before each task name to show whether it's done, besides its colour
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusGlyphs {
+    /// ✔ and ·
+    #[default]
+    Unicode,
+    /// x and -
+    Ascii,
+    None,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    pub usage_stats: bool,
+    pub auto_archive_done_after: Option<String>,
+    pub journal_mode: Option<String>,
+    pub busy_timeout: Option<u64>,
+    pub wrapup_done: WrapupDone,
+    pub stable_read: bool,
+    pub trash_retention_days: Option<u64>,
+    pub comment_delimiter: Option<String>,
+    pub auto_complete_parents: bool,
+    pub session_timeout: Option<String>,
+    pub confirm_context: Vec<String>,
+    pub aliases: BTreeMap<String, Vec<String>>,
+    pub default_command: Option<String>,
+    pub language: Option<String>,
+    pub status_glyphs: StatusGlyphs,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+pub fn config_path() -> PathBuf {
+    let mut config_path = config_dir().unwrap_or_default();
+    config_path.push("Todoln");
+    config_path.push("config.toml");
+    config_path
+}
+
+fn load_config() -> Config {
+    let config_path = config_path();
+
+    let contents = match fs::read_to_string(&config_path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Config::default(),
+        Err(e) => {
+            print_error(&format!("Failed to read config file '{}': {}", config_path.display(), e));
+            return Config::default();
+        }
+    };
+
+    match toml::from_str(&contents) {
+        Ok(config) => config,
+        Err(e) => {
+            print_error(&format!("Failed to parse config file '{}': {}", config_path.display(), e));
+            Config::default()
+        }
+    }
+}
+
+/// Uses `config` instead of the config file for the rest of the run, if it hasn't been read yet
+pub fn set_config(config: Config) {
+    CONFIG.set(config).ok();
+}
+
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(load_config)
+}
+
+/// What a bare `todoln` runs, if configured to run something other than `list all`
+pub fn default_command() -> Option<&'static str> {
+    config().default_command.as_deref().filter(|command| !command.trim().is_empty())
+}
+
+/// What separates a task name from a trailing comment, if comments are turned on
+pub fn comment_delimiter() -> Option<&'static str> {
+    config().comment_delimiter.as_deref().filter(|delimiter| !delimiter.trim().is_empty())
+}
+
+/// What the alias `name` expands to, following aliases that start with another alias. `None` when
+/// `name` is no alias or `is_builtin` says it's a todoln command, which aliases can't replace. The
+/// error says what's wrong with the alias, to follow "Alias 'name' ".
+pub fn expand_alias(aliases: &BTreeMap<String, Vec<String>>, name: &str, is_builtin: impl Fn(&str) -> bool) -> Result<Option<Vec<String>>, String> {
+    if !aliases.contains_key(name) {
+        return Ok(None);
+    }
+
+    if is_builtin(name) {
+        print_warning(&format!("Warning: Alias '{}' is ignored as todoln already has a command with that name.", name));
+        return Ok(None);
+    }
+
+    let mut chain = vec![name.to_string()];
+    let mut args: Vec<String> = Vec::new();
+
+    loop {
+        let current = chain.last().unwrap();
+        let Some((first, rest)) = aliases[current].split_first() else {
+            return Err(if current == name { String::from("expands to nothing") } else { format!("goes through '{}', which expands to nothing", current) });
+        };
+
+        args.splice(0..0, rest.iter().cloned());
+
+        if chain.contains(first) {
+            chain.push(first.clone());
+            return Err(format!("refers back to itself: {}", chain.join(" -> ")));
+        }
+
+        if is_builtin(first) || !aliases.contains_key(first) {
+            args.insert(0, first.clone());
+            return Ok(Some(args));
+        }
+
+        chain.push(first.clone());
+    }
+}
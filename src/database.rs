@@ -1,256 +1,2951 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
 use std::fs;
-use std::io;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::slice;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
 
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeDelta, Utc};
 use dirs::data_local_dir;
-use rusqlite::{Connection, Error, Result, params};
+use rand::seq::SliceRandom;
+use regex::Regex;
+use rusqlite::backup::{Backup, StepResult};
+use rusqlite::config::DbConfig;
+use rusqlite::{ffi, Connection, DatabaseName, Error, ErrorCode, OpenFlags, Result, Row, Transaction, TransactionBehavior, params};
+use serde::{Deserialize, Serialize};
 
-use crate::commands::Task;
+use crate::commands::{EditedTask, ExportedTask, ReviewDecision, SearchField, SortKey, SyncChange, Task};
+use crate::config::{config, WrapupDone};
+use crate::i18n::tr;
+use crate::utils::{ask_secret, next_occurrence, parse_duration, parse_timestamp, print_error, print_notice, print_verbose, print_warning, verbosity, Period, Verbosity};
 
-pub fn establish_connection() -> Connection {
-    let mut db_path = data_local_dir().unwrap_or_default();
-    db_path.push("Todoln");
-    db_path.push("todoln.db");
-
-    if let Some(parent) = db_path.parent() {
-        std::fs::create_dir_all(parent).expect("Failed to create data directory");
-    }
-
-    match Connection::open(&db_path) {
-        Ok(conn) => {
-            if let Err(e) = conn.execute(
-                "CREATE TABLE IF NOT EXISTS tasks (
-                    id INTEGER PRIMARY KEY,
-                    idx INTEGER UNIQUE,
-                    name TEXT NOT NULL UNIQUE,
-                    done INTEGER DEFAULT 0
-                )",
-                (),
-            ) {
-                panic!("Failed to create table: {}", e);
+#[derive(Debug)]
+pub enum TaskError {
+    Sqlite(Error),
+    Task { name: String, source: Error },
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaskError::Sqlite(e) => write!(f, "{}", e),
+            TaskError::Task { name, source } => write!(f, "'{}': {}", name, source),
+        }
+    }
+}
+
+impl From<Error> for TaskError {
+    fn from(e: Error) -> Self {
+        TaskError::Sqlite(e)
+    }
+}
+
+// Schema changes applied on top of the original tables, in order. The database's
+// `user_version` records how many of them have already run.
+const MIGRATIONS: &[&str] = &[
+    // 1: Allow duplicate task names (SQLite can't drop a constraint in place)
+    "CREATE TABLE tasks_new (
+        id INTEGER PRIMARY KEY,
+        idx INTEGER UNIQUE,
+        name TEXT NOT NULL,
+        done INTEGER DEFAULT 0
+    );
+    INSERT INTO tasks_new (id, idx, name, done) SELECT id, idx, name, done FROM tasks;
+    DROP TABLE tasks;
+    ALTER TABLE tasks_new RENAME TO tasks;",
+    // 2: Completion timestamps, the archive and key/value metadata
+    "ALTER TABLE tasks ADD COLUMN completed_at TEXT;
+    UPDATE tasks SET completed_at = datetime('now') WHERE done;
+    CREATE INDEX tasks_done_completed_at ON tasks (done, completed_at);
+    CREATE TABLE archive (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        completed_at TEXT,
+        archived_at TEXT NOT NULL
+    );
+    CREATE TABLE metadata (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );",
+    // 3: Due dates
+    "ALTER TABLE tasks ADD COLUMN due_at TEXT;
+    CREATE INDEX tasks_done_due_at ON tasks (done, due_at);",
+    // 4: Focus session history
+    "CREATE TABLE sessions (
+        id INTEGER PRIMARY KEY,
+        started_at TEXT NOT NULL,
+        duration INTEGER NOT NULL
+    );
+    CREATE TABLE session_tasks (
+        session_id INTEGER NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+        name TEXT NOT NULL
+    );",
+    // 5: Undo history, with the full rows of the tasks each operation changed or deleted
+    "CREATE TABLE history (
+        id INTEGER PRIMARY KEY,
+        created_at TEXT NOT NULL,
+        operation TEXT NOT NULL,
+        summary TEXT NOT NULL,
+        task_order TEXT NOT NULL,
+        created_ids TEXT NOT NULL DEFAULT ''
+    );
+    CREATE TABLE history_tasks (
+        history_id INTEGER NOT NULL REFERENCES history (id),
+        id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        done INTEGER NOT NULL,
+        completed_at TEXT,
+        due_at TEXT
+    );
+    CREATE INDEX history_tasks_history_id ON history_tasks (history_id);",
+    // 6: The trash, where removed tasks are kept for a while
+    "CREATE TABLE trash (
+        id INTEGER PRIMARY KEY,
+        task_id INTEGER NOT NULL,
+        name TEXT NOT NULL,
+        done INTEGER NOT NULL,
+        completed_at TEXT,
+        due_at TEXT,
+        deleted_at TEXT NOT NULL
+    );
+    CREATE INDEX trash_deleted_at ON trash (deleted_at);",
+    // 7: Notes, kept with the copies of tasks in the history and the trash too
+    "ALTER TABLE tasks ADD COLUMN note TEXT;
+    ALTER TABLE history_tasks ADD COLUMN note TEXT;
+    ALTER TABLE trash ADD COLUMN note TEXT;",
+    // 8: Subtasks
+    "ALTER TABLE tasks ADD COLUMN parent_id INTEGER REFERENCES tasks (id);
+    ALTER TABLE history_tasks ADD COLUMN parent_id INTEGER;
+    ALTER TABLE trash ADD COLUMN parent_id INTEGER;",
+    // 9: Tasks that wait for other tasks to be done
+    "CREATE TABLE dependencies (
+        task_id INTEGER NOT NULL REFERENCES tasks (id),
+        blocker_id INTEGER NOT NULL REFERENCES tasks (id),
+        PRIMARY KEY (task_id, blocker_id)
+    );
+    CREATE INDEX dependencies_blocker_id ON dependencies (blocker_id);",
+    // 10: Custom key/value fields on tasks
+    "CREATE TABLE task_fields (
+        task_id INTEGER NOT NULL REFERENCES tasks (id),
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (task_id, key)
+    );",
+    // 11: Recurring tasks
+    "ALTER TABLE tasks ADD COLUMN recurrence TEXT;
+    ALTER TABLE history_tasks ADD COLUMN recurrence TEXT;
+    ALTER TABLE trash ADD COLUMN recurrence TEXT;",
+    // 12: Snoozed tasks
+    "ALTER TABLE tasks ADD COLUMN snoozed_until TEXT;
+    ALTER TABLE history_tasks ADD COLUMN snoozed_until TEXT;
+    ALTER TABLE trash ADD COLUMN snoozed_until TEXT;",
+    // 13: Creation timestamps (tasks from before this stay NULL and sort by id instead)
+    "ALTER TABLE tasks ADD COLUMN created_at TEXT;
+    ALTER TABLE history_tasks ADD COLUMN created_at TEXT;
+    ALTER TABLE trash ADD COLUMN created_at TEXT;",
+    // 14: When `notify` last sent a reminder for a task
+    "ALTER TABLE tasks ADD COLUMN notified_at TEXT;",
+    // 15: Time tracking. Every way of completing a task stops its timer through the trigger.
+    "CREATE TABLE time_entries (
+        id INTEGER PRIMARY KEY,
+        task_id INTEGER NOT NULL REFERENCES tasks (id),
+        started_at TEXT NOT NULL,
+        stopped_at TEXT
+    );
+    CREATE INDEX time_entries_task_id ON time_entries (task_id);
+    CREATE TRIGGER time_entries_stop_when_done AFTER UPDATE OF done ON tasks WHEN NEW.done AND NOT OLD.done
+    BEGIN
+        UPDATE time_entries SET stopped_at = datetime('now') WHERE task_id = NEW.id AND stopped_at IS NULL;
+    END;",
+    // 16: Undo history keeps where each task it copied was, and its fields and dependencies, instead
+    // of the order of every task. Entries from before this have `with_links` unset.
+    "ALTER TABLE history_tasks ADD COLUMN idx INTEGER;
+    ALTER TABLE history ADD COLUMN with_links INTEGER NOT NULL DEFAULT 0;
+    CREATE TABLE history_task_fields (
+        history_id INTEGER NOT NULL REFERENCES history (id),
+        task_id INTEGER NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL
+    );
+    CREATE INDEX history_task_fields_history_id ON history_task_fields (history_id);
+    CREATE TABLE history_dependencies (
+        history_id INTEGER NOT NULL REFERENCES history (id),
+        task_id INTEGER NOT NULL,
+        blocker_id INTEGER NOT NULL
+    );
+    CREATE INDEX history_dependencies_history_id ON history_dependencies (history_id);",
+];
+
+const TASK_COLUMNS: &str = "id, idx, name, done, completed_at, due_at, note, parent_id, recurrence, snoozed_until, created_at";
+
+/// The task indices todoln can address. Rows outside them, from a database edited by hand or
+/// by another program, are read as having no index, so the next change renumbers them.
+const INDEX_RANGE: RangeInclusive<i64> = 1..=i32::MAX as i64;
+
+/// Sorts rows without a usable index after the rest, in the order they were added
+const TASK_ORDER: &str = "idx IS NULL OR idx NOT BETWEEN 1 AND 2147483647, idx ASC, id ASC";
+
+fn task_from_row(row: &Row) -> Result<Task> {
+    let idx: Option<i64> = row.get(1)?;
+
+    Ok(Task {
+        id: row.get(0)?,
+        idx: idx.filter(|idx| INDEX_RANGE.contains(idx)).map(|idx| idx as i32),
+        name: row.get(2)?,
+        done: row.get(3)?,
+        completed_at: row.get(4)?,
+        due_at: row.get(5)?,
+        note: row.get(6)?,
+        parent_id: row.get(7)?,
+        recurrence: row.get(8)?,
+        snoozed_until: row.get(9)?,
+        created_at: row.get(10)?,
+    })
+}
+
+fn migrate_db(conn: &mut Connection) -> Result<(), Error> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = write_transaction(conn)?;
+
+        // Another todoln may have run this migration while this one waited for the lock
+        if transaction.query_row("PRAGMA user_version", [], |row| row.get::<_, usize>(0))? > i {
+            continue;
+        }
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", i + 1)?;
+        transaction.commit()?;
+    }
+
+    Ok(())
+}
+
+pub fn is_locked_error(e: &Error) -> bool {
+    matches!(e.sqlite_error_code(), Some(ErrorCode::DatabaseBusy) | Some(ErrorCode::DatabaseLocked))
+}
+
+/// How many more times a write transaction is tried when another process still holds the write
+/// lock once the busy timeout has run out
+const BUSY_RETRIES: u32 = 3;
+
+/// First pause before retrying a busy write transaction, doubled after each attempt
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Starts a transaction that takes the write lock at once (`BEGIN IMMEDIATE`), so the tasks and
+/// their order it reads can't change before it writes indices back. Two todoln runs changing tasks
+/// at the same time take turns instead of numbering tasks from different snapshots of the list.
+fn write_transaction(conn: &Connection) -> Result<Transaction<'_>, Error> {
+    let mut delay = BUSY_RETRY_DELAY;
+
+    for _ in 0..BUSY_RETRIES {
+        match Transaction::new_unchecked(conn, TransactionBehavior::Immediate) {
+            Err(e) if is_locked_error(&e) => {
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+
+    Transaction::new_unchecked(conn, TransactionBehavior::Immediate)
+}
+
+fn connection_failed(context: &str, path: &Path, e: Error) -> ! {
+    if is_locked_error(&e) {
+        print_error(&format!("Error: The task database '{}' is locked by another todoln process. Try again in a moment.", path.display()));
+    } else if e.sqlite_error_code() == Some(ErrorCode::NotADatabase) && is_encrypted(path) && passphrase_tried() {
+        print_error(&format!("Error: Wrong passphrase for the task database '{}'.", path.display()));
+    } else if e.sqlite_error_code() == Some(ErrorCode::NotADatabase) && is_encrypted(path) && !cfg!(feature = "encryption") {
+        print_error(&format!("{} {}", context, FileError::Database { path: path.to_path_buf(), source: e }));
+        print_warning("If it is an encrypted task database, this todoln was built without encryption support (see `cargo build --features encryption`).");
+    } else {
+        print_error(&format!("{} {}", context, FileError::Database { path: path.to_path_buf(), source: e }));
+    }
+
+    process::exit(1);
+}
+
+/// How many symbolic links are followed when resolving a path before giving up on it as a loop
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+#[derive(Debug)]
+pub enum PathError {
+    NotFound(PathBuf),
+    IsDirectory(PathBuf),
+    NotRegularFile(PathBuf),
+    ParentNotDirectory(PathBuf),
+    ParentNotWritable(PathBuf),
+    TooManySymlinks(PathBuf),
+    Io(PathBuf, io::Error),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathError::NotFound(path) => write!(f, "'{}': no such file", path.display()),
+            PathError::IsDirectory(path) => write!(f, "'{}': path is a directory", path.display()),
+            PathError::NotRegularFile(path) => write!(f, "'{}': not a regular file", path.display()),
+            PathError::ParentNotDirectory(path) => write!(f, "'{}': parent is not a directory", path.display()),
+            PathError::ParentNotWritable(path) => write!(f, "'{}': parent directory is not writable", path.display()),
+            PathError::TooManySymlinks(path) => write!(f, "'{}': too many symlink levels", path.display()),
+            PathError::Io(path, e) => write!(f, "'{}': {}", path.display(), e),
+        }
+    }
+}
+
+/// A failure reading or writing the database file or a copy of it, naming the paths involved
+#[derive(Debug)]
+pub enum FileError {
+    Path(PathError),
+    Database { path: PathBuf, source: Error },
+    Remove { path: PathBuf, source: io::Error },
+    Write { path: PathBuf, source: io::Error },
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileError::Path(e) => write!(f, "{}", e),
+            FileError::Database { path, source } => write!(f, "'{}': {}", path.display(), source),
+            FileError::Remove { path, source } => write!(f, "'{}': {}", path.display(), source),
+            FileError::Write { path, source } => write!(f, "'{}': {}", path.display(), source),
+        }
+    }
+}
+
+impl From<PathError> for FileError {
+    fn from(e: PathError) -> Self {
+        FileError::Path(e)
+    }
+}
+
+/// Follows the symlinks at `path`, up to `MAX_SYMLINK_DEPTH` of them, and checks that it ends at a
+/// regular file or, with `may_create`, at a missing file that can be created: its parent, if it
+/// exists, has to be a directory that allows writing. Returns the resolved path.
+pub fn validate_file_path(path: &Path, may_create: bool) -> Result<PathBuf, PathError> {
+    let mut resolved = path.to_path_buf();
+
+    for _ in 0..=MAX_SYMLINK_DEPTH {
+        let metadata = match fs::symlink_metadata(&resolved) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                let parent = resolved.parent().filter(|parent| !parent.as_os_str().is_empty());
+                let parent_metadata = parent.map(fs::metadata);
+
+                if matches!(parent_metadata, Some(Ok(ref metadata)) if !metadata.is_dir()) {
+                    return Err(PathError::ParentNotDirectory(path.to_path_buf()));
+                }
+
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(PathError::Io(path.to_path_buf(), e));
+                }
+
+                if !may_create {
+                    return Err(PathError::NotFound(path.to_path_buf()));
+                }
+
+                // Missing parent directories are created along with the file
+                return match parent_metadata {
+                    Some(Ok(metadata)) if metadata.permissions().readonly() => Err(PathError::ParentNotWritable(path.to_path_buf())),
+                    Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => Err(PathError::Io(path.to_path_buf(), e)),
+                    _ => Ok(resolved),
+                };
+            }
+        };
+
+        let file_type = metadata.file_type();
+
+        if file_type.is_symlink() {
+            let target = fs::read_link(&resolved).map_err(|e| PathError::Io(path.to_path_buf(), e))?;
+            resolved = match resolved.parent() {
+                Some(parent) => parent.join(target),
+                None => target,
+            };
+            continue;
+        }
+
+        if file_type.is_dir() {
+            return Err(PathError::IsDirectory(path.to_path_buf()));
+        }
+
+        if !file_type.is_file() {
+            return Err(PathError::NotRegularFile(path.to_path_buf()));
+        }
+
+        return Ok(resolved);
+    }
+
+    Err(PathError::TooManySymlinks(path.to_path_buf()))
+}
+
+static DB_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Uses the database at `path` instead of the default one for the rest of the run
+pub fn set_db_path(path: PathBuf) {
+    DB_PATH.set(path).ok();
+}
+
+/// Where `demo` keeps its tasks: an in-memory database that lasts while a connection to it is open
+const DEMO_DB: &str = "file:todoln-demo?mode=memory&cache=shared";
+
+static DEMO: AtomicBool = AtomicBool::new(false);
+
+/// Points every connection for the rest of the run at a fresh in-memory database instead of the
+/// task database. It lasts for as long as the returned connection stays open.
+pub fn start_demo() -> Connection {
+    DEMO.store(true, Ordering::Relaxed);
+    open_connection()
+}
+
+pub fn db_path() -> PathBuf {
+    // Files kept next to the database, like the session, go to a directory nothing else uses
+    if DEMO.load(Ordering::Relaxed) {
+        return env::temp_dir().join("todoln-demo").join("demo.db");
+    }
+
+    match DB_PATH.get() {
+        Some(path) => path.clone(),
+        None => data_local_dir().unwrap_or_default().join("Todoln").join("todoln.db"),
+    }
+}
+
+/// The name `confirm_context` knows the task database by: its file name without the extension,
+/// so `todoln` for the default database and `work` for `--db ~/work.db`
+pub fn context_name() -> String {
+    db_path().file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// Where `wrapup` keeps its backup snapshots, next to the task database
+pub fn snapshots_dir() -> PathBuf {
+    db_path().parent().map(Path::to_path_buf).unwrap_or_default().join("snapshots")
+}
+
+/// A lock file older than this was left behind by a todoln process that is gone
+const STALE_LOCK_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// How every unencrypted SQLite database file starts
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// Whether the database file at `path` is encrypted, told apart from a plain one by its first bytes.
+/// A missing or empty file isn't, but neither is every other file: a corrupt one looks the same.
+pub fn is_encrypted(path: &Path) -> bool {
+    let mut header = [0; 16];
+    fs::File::open(path).and_then(|mut file| file.read_exact(&mut header)).is_ok() && &header != SQLITE_HEADER
+}
+
+static PASSPHRASE: OnceLock<String> = OnceLock::new();
+
+/// The passphrase of an encrypted database: `TODOLN_PASSPHRASE`, or else asked for once per run
+fn passphrase() -> &'static str {
+    PASSPHRASE.get_or_init(|| env::var("TODOLN_PASSPHRASE").unwrap_or_else(|_| ask_secret("Passphrase for the task database:")))
+}
+
+/// Whether the passphrase has been asked for, or read from `TODOLN_PASSPHRASE`, during this run
+fn passphrase_tried() -> bool {
+    PASSPHRASE.get().is_some()
+}
+
+fn read_schema(conn: &Connection) -> Result<(), Error> {
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |_| Ok(()))
+}
+
+/// Opens the database at `path` with `flags`, reading the schema so a file that isn't a database
+/// fails here, before anything is written. Only if it can't be read as a plain database, and this
+/// todoln has encryption support, is it opened again with the passphrase.
+fn open_with_flags(path: &Path, flags: OpenFlags) -> Result<Connection, Error> {
+    let conn = Connection::open_with_flags(path, flags)?;
+
+    match read_schema(&conn) {
+        Err(e) if cfg!(feature = "encryption") && e.sqlite_error_code() == Some(ErrorCode::NotADatabase) && is_encrypted(path) => {
+            drop(conn);
+            let conn = Connection::open_with_flags(path, flags)?;
+            conn.pragma_update(None, "key", passphrase())?;
+            read_schema(&conn)?;
+            Ok(conn)
+        }
+        result => result.map(|_| conn),
+    }
+}
+
+/// Writes a copy of the open database, encrypted with `passphrase` or plain if it's empty, and
+/// puts it in place of the database at `db_path` once it's complete. Closes `conn` first, so other
+/// connections should be closed too. Needs SQLCipher.
+pub fn rekey_db(conn: Connection, db_path: &Path, passphrase: &str) -> Result<(), FileError> {
+    let copy_path = sidecar_path(db_path, ".rekey");
+    let database_error = |source| FileError::Database { path: copy_path.clone(), source };
+
+    match fs::remove_file(&copy_path) {
+        Ok(_) => {},
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+        Err(source) => return Err(FileError::Remove { path: copy_path, source }),
+    }
+
+    let export = || -> Result<(), Error> {
+        conn.execute("ATTACH DATABASE ?1 AS rekeyed KEY ?2", params![copy_path.to_string_lossy(), passphrase])?;
+        conn.query_row("SELECT sqlcipher_export('rekeyed')", [], |_| Ok(()))?;
+
+        // The export copies tables but not the schema version
+        let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        conn.pragma_update(Some(DatabaseName::Attached("rekeyed")), "user_version", version)?;
+        conn.execute("DETACH DATABASE rekeyed", [])?;
+        Ok(())
+    };
+    if let Err(e) = export() {
+        fs::remove_file(&copy_path).ok();
+        return Err(database_error(e));
+    }
+
+    // Closing the last connection folds the write-ahead log into the old file and removes it
+    drop(conn);
+
+    fs::rename(&copy_path, db_path).map_err(|source| FileError::Write { path: db_path.to_path_buf(), source })
+}
+
+/// How long the startup check waits for another process before assuming it is alive
+const LIVENESS_PROBE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A file SQLite or todoln keeps next to the database, such as `todoln.db-wal`
+fn sidecar_path(db_path: &Path, suffix: &str) -> PathBuf {
+    let mut path = db_path.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Whether no other connection has the database open, found by briefly asking for an exclusive lock.
+/// Dropping the returned connection then checkpoints and removes the write-ahead log.
+fn probe_exclusive(db_path: &Path) -> Result<Connection, Error> {
+    let conn = open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+    conn.busy_timeout(LIVENESS_PROBE_TIMEOUT)?;
+    conn.pragma_update(None, "locking_mode", "EXCLUSIVE")?;
+    conn.execute_batch("BEGIN EXCLUSIVE; COMMIT;")?;
+    Ok(conn)
+}
+
+/// Cleans up after a todoln process that crashed: folds a write-ahead log nobody is using back
+/// into the database and removes lock files too old to belong to a live process
+fn recover_after_crash(db_path: &Path) {
+    let wal_path = sidecar_path(db_path, "-wal");
+
+    if wal_path.metadata().is_ok_and(|metadata| metadata.len() > 0) {
+        if let Ok(conn) = probe_exclusive(db_path) {
+            drop(conn);
+
+            if !wal_path.exists() {
+                print_notice(&tr("recovered-journal", &[&db_path.display()]));
             }
+        }
+    }
+
+    let lock_path = sidecar_path(db_path, ".lock");
+    let stale = lock_path.metadata()
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age > STALE_LOCK_AGE));
+
+    if stale && fs::remove_file(&lock_path).is_ok() {
+        print_notice(&tr("removed-stale-lock", &[&lock_path.display()]));
+    }
+}
+
+/// Forcibly removes the lock file and the shared-memory index, whether or not another process
+/// still uses them, then checkpoints any write-ahead log into the database so no change is lost.
+/// Returns the files that were removed.
+pub fn unlock_db() -> Result<Vec<PathBuf>, FileError> {
+    let db_path = validate_file_path(&db_path(), false)?;
+    let mut removed = Vec::new();
+
+    for path in [sidecar_path(&db_path, ".lock"), sidecar_path(&db_path, "-shm")] {
+        match fs::remove_file(&path) {
+            Ok(_) => removed.push(path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(source) => return Err(FileError::Remove { path, source }),
+        }
+    }
+
+    let checkpoint = || -> Result<(), Error> {
+        let conn = open_with_flags(&db_path, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+        conn.busy_timeout(LIVENESS_PROBE_TIMEOUT)?;
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+    };
+    checkpoint().map_err(|source| FileError::Database { path: db_path.clone(), source })?;
+
+    Ok(removed)
+}
+
+/// How much room the database takes up, and how much of it is free pages left behind by changes
+pub struct DbStats {
+    /// The database file and its write-ahead log together, in bytes
+    pub file_size: u64,
+    pub page_size: u64,
+    pub page_count: u64,
+    pub freelist_count: u64,
+}
+
+impl DbStats {
+    /// The share of the database's pages that are free, from 0 to 1
+    pub fn free_share(&self) -> f64 {
+        if self.page_count == 0 { 0.0 } else { self.freelist_count as f64 / self.page_count as f64 }
+    }
+}
+
+pub fn get_db_stats(conn: &Connection, db_path: &Path) -> Result<DbStats, Error> {
+    let pragma = |name: &str| conn.query_row(&format!("PRAGMA {}", name), [], |row| row.get::<_, i64>(0)).map(|value| value as u64);
+    let file_size = [db_path.to_path_buf(), sidecar_path(db_path, "-wal")].iter()
+        .filter_map(|path| path.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum();
+
+    Ok(DbStats { file_size, page_size: pragma("page_size")?, page_count: pragma("page_count")?, freelist_count: pragma("freelist_count")? })
+}
+
+/// Rebuilds the database file without its free pages and updates the statistics SQLite plans
+/// queries with. Fails at once with `SQLITE_BUSY` if another process has the database open,
+/// rather than waiting for it.
+pub fn vacuum_db(db_path: &Path) -> Result<(), Error> {
+    let conn = probe_exclusive(db_path)?;
+    conn.execute_batch("VACUUM; PRAGMA optimize;")
+}
+
+/// A scripting session started by `begin-session`. While it lasts, indices are never renumbered
+/// behind a script's back: gaps left by removed tasks stay and new tasks go at the end.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    /// The process that started the session (on Unix the script or shell that ran todoln)
+    pub pid: u32,
+    pub started_at: String,
+    pub expires_at: String,
+}
+
+pub enum SessionStart {
+    Started(Session),
+    /// Another session holds the lock; `None` if it was only just being started
+    AlreadyActive(Option<Session>),
+}
+
+fn session_path() -> PathBuf {
+    sidecar_path(&db_path(), ".session")
+}
+
+fn read_session(path: &Path) -> Option<Session> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+/// The session in progress, if any. A session that has run past its timeout is ended on the way,
+/// so a script that never called `end-session` can't keep indices fixed for good.
+pub fn active_session() -> Option<Session> {
+    let path = session_path();
+    let session = read_session(&path)?;
+
+    if parse_timestamp(&session.expires_at).is_some_and(|expires_at| expires_at > Utc::now()) {
+        return Some(session);
+    }
+
+    if fs::remove_file(&path).is_ok() {
+        print_notice(&tr("session-timed-out", &[&session.pid]));
+    }
+    None
+}
+
+/// Starts a session lasting `timeout` seconds, unless another one is still active. The session
+/// file is created atomically, so of two scripts starting a session at once only one gets it.
+pub fn begin_session(timeout: i64) -> Result<SessionStart, FileError> {
+    let path = session_path();
+
+    if let Some(session) = active_session() {
+        return Ok(SessionStart::AlreadyActive(Some(session)));
+    }
+    // Left behind unreadable, e.g. by a crash while it was written
+    if path.exists() && read_session(&path).is_none() {
+        fs::remove_file(&path).map_err(|source| FileError::Remove { path: path.clone(), source })?;
+    }
+
+    #[cfg(unix)]
+    let pid = std::os::unix::process::parent_id();
+    #[cfg(not(unix))]
+    let pid = process::id();
+
+    let now = Utc::now();
+    let session = Session {
+        pid,
+        started_at: now.format("%Y-%m-%d %H:%M:%S").to_string(),
+        expires_at: (now + TimeDelta::seconds(timeout)).format("%Y-%m-%d %H:%M:%S").to_string(),
+    };
 
-            conn
+    let mut file = match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            return Ok(SessionStart::AlreadyActive(read_session(&path)));
         }
+        Err(source) => return Err(FileError::Write { path, source }),
+    };
+
+    let contents = serde_json::to_string(&session).unwrap_or_default();
+    file.write_all(contents.as_bytes()).map_err(|source| FileError::Write { path, source })?;
+
+    Ok(SessionStart::Started(session))
+}
+
+/// Ends the session in progress. Returns it, or `None` if there was none.
+pub fn end_session() -> Result<Option<Session>, FileError> {
+    let path = session_path();
+    let session = active_session();
+
+    match fs::remove_file(&path) {
+        Ok(_) => Ok(session),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(source) => Err(FileError::Remove { path, source }),
+    }
+}
+
+/// Opens the database and brings its schema up to date, without touching any task data
+pub fn open_connection() -> Connection {
+    open_database(false)
+}
+
+/// Opens the database. With `stable` nothing that a crashed todoln left behind is cleaned up, and
+/// closing the connection leaves the write-ahead log as it is instead of folding it into the file.
+fn open_database(stable: bool) -> Connection {
+    if DEMO.load(Ordering::Relaxed) {
+        return match Connection::open(DEMO_DB) {
+            Ok(conn) => prepare_connection(conn, Path::new(DEMO_DB)),
+            Err(e) => connection_failed("Failed to create", Path::new(DEMO_DB), e),
+        };
+    }
+
+    let db_path = match validate_file_path(&db_path(), true) {
+        Ok(db_path) => db_path,
         Err(e) => {
-            panic!("Failed to connect to the database: {}", e);
+            print_error(&format!("Error: Invalid database path {}", e));
+            process::exit(1);
+        }
+    };
+
+    if let Some(parent) = db_path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        if let Err(e) = fs::create_dir_all(parent) {
+            print_error(&format!("Failed to create the data directory '{}': {}", parent.display(), e));
+            process::exit(1);
+        }
+    }
+
+    if !stable {
+        recover_after_crash(&db_path);
+    }
+
+    let conn = match open_with_flags(&db_path, OpenFlags::default()) {
+        Ok(conn) => conn,
+        Err(e) => connection_failed("Failed to connect to", &db_path, e),
+    };
+    if stable {
+        if let Err(e) = conn.set_db_config(DbConfig::SQLITE_DBCONFIG_NO_CKPT_ON_CLOSE, true) {
+            connection_failed("Failed to set up", &db_path, e);
+        }
+    }
+
+    prepare_connection(conn, &db_path)
+}
+
+/// Prints a statement run with `--verbose`: what kind it is, the table it works on and, if it was
+/// slow enough to measure, how long it took. Values aren't shown, so task names stay out of the log.
+fn log_statement(sql: &str, duration: Duration) {
+    let words: Vec<String> = sql.split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !(c.is_alphanumeric() || c == '_')).to_lowercase())
+        .collect();
+    let kind = words.first().map_or("", String::as_str);
+
+    // The table comes after the first of these, e.g. `INSERT INTO tasks` or `SELECT ... FROM tasks`
+    let table = words.iter()
+        .position(|word| matches!(word.as_str(), "into" | "from" | "update" | "table" | "index" | "trigger"))
+        .and_then(|i| words[i + 1..].iter().find(|word| !matches!(word.as_str(), "if" | "not" | "exists" | "")));
+
+    let mut line = format!("sql {}", kind);
+    if let Some(table) = table {
+        line.push_str(&format!(" {}", table));
+    }
+    // SQLite times statements to the millisecond, so faster ones would all read 0
+    if duration >= Duration::from_millis(1) {
+        line.push_str(&format!(" ({:.1?})", duration));
+    }
+    print_verbose(&line);
+}
+
+/// Sets the timeouts and journal mode of a freshly opened connection and brings the schema up to date
+fn prepare_connection(mut conn: Connection, db_path: &Path) -> Connection {
+    if verbosity() == Verbosity::Verbose {
+        print_verbose(&format!("Using the database at '{}'", db_path.display()));
+        conn.profile(Some(log_statement));
+    }
+
+    let busy_timeout = config().busy_timeout.unwrap_or(5000);
+    if let Err(e) = conn.busy_timeout(Duration::from_millis(busy_timeout)) {
+        connection_failed("Failed to set the busy timeout of", db_path, e);
+    }
+
+    // WAL lets readers and a writer work at the same time; it persists in the file so
+    // this is a no-op after the first run
+    let journal_mode = config().journal_mode.as_deref().unwrap_or("wal");
+    if let Err(e) = conn.pragma_update_and_check(None, "journal_mode", journal_mode, |row| row.get::<_, String>(0)) {
+        connection_failed("Failed to set the journal mode of", db_path, e);
+    }
+
+    if let Err(e) = init_db(&mut conn) {
+        connection_failed("Failed to set up the tables in", db_path, e);
+    }
+
+    conn
+}
+
+/// Creates the tables in a new database and brings an older one up to date. Every connection
+/// todoln opens goes through here, and so can one opened with `Connection::open_in_memory`.
+pub fn init_db(conn: &mut Connection) -> Result<(), Error> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS tasks (
+            id INTEGER PRIMARY KEY,
+            idx INTEGER UNIQUE,
+            name TEXT NOT NULL UNIQUE,
+            done INTEGER DEFAULT 0
+        );
+        CREATE TABLE IF NOT EXISTS usage (
+            command TEXT NOT NULL,
+            day TEXT NOT NULL,
+            count INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (command, day)
+        );",
+    )?;
+
+    migrate_db(conn)
+}
+
+/// Opens the existing database read-only for a quick check that gives up at once instead of
+/// waiting for another process's lock. Returns `None` if there's no usable database, or it predates
+/// due dates. Never creates, migrates or reindexes anything.
+pub fn open_connection_for_quick_read() -> Result<Option<Connection>, Error> {
+    let db_path = match validate_file_path(&db_path(), false) {
+        Ok(db_path) => db_path,
+        Err(_) => return Ok(None),
+    };
+
+    let conn = open_with_flags(&db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    conn.busy_timeout(Duration::ZERO)?;
+
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version < 3 {
+        return Ok(None);
+    }
+
+    Ok(Some(conn))
+}
+
+/// How long removed tasks stay in the trash
+pub fn trash_retention_days() -> u64 {
+    config().trash_retention_days.unwrap_or(30)
+}
+
+pub fn establish_connection() -> Connection {
+    let mut conn = open_connection();
+
+    // Close any gaps left behind by an interrupted write
+    if let Err(e) = renormalize_task_indices(&mut conn) {
+        connection_failed("Failed to repair task indices in", &db_path(), e);
+    }
+
+    if let Some(after) = &config().auto_archive_done_after {
+        match parse_duration(after) {
+            Some(seconds) => match auto_archive_done_tasks(&mut conn, seconds) {
+                Ok(0) => {},
+                Ok(count) => print_notice(&tr("auto-archived", &[&count])),
+                Err(e) => print_warning(&format!("Warning: Failed to auto-archive tasks: {}", e)),
+            },
+            None => print_warning(&format!("Warning: Invalid auto_archive_done_after value '{}'.", after)),
         }
     }
+
+    match auto_empty_trash(&conn, trash_retention_days() as i64 * 24 * 60 * 60) {
+        Ok(0) => {},
+        Ok(count) => print_notice(&tr("trash-purged", &[&count, &trash_retention_days()])),
+        Err(e) => print_warning(&format!("Warning: Failed to empty old tasks from the trash: {}", e)),
+    }
+
+    conn
+}
+
+static STABLE_READ: AtomicBool = AtomicBool::new(false);
+
+/// Turns on `stable_read` for the rest of the run, on top of the config setting
+pub fn set_stable_read() {
+    STABLE_READ.store(true, Ordering::Relaxed);
+}
+
+pub fn stable_read() -> bool {
+    STABLE_READ.load(Ordering::Relaxed) || config().stable_read
+}
+
+/// Opens the database for a command that only reads tasks. With `stable_read` the file is never
+/// written: index repair, auto-archiving and cleaning up after a crash are left to the next command
+/// that changes tasks, or to `reindex`. Schema migrations after upgrading todoln are the one exception.
+pub fn establish_read_connection() -> Connection {
+    if stable_read() {
+        open_database(true)
+    } else {
+        establish_connection()
+    }
+}
+
+pub fn update_task_indices(conn: &Connection, tasks: &[Task]) -> Result<(), Error>{
+    conn.execute("UPDATE tasks SET idx = NULL", [])?;
+
+    for (i, task) in tasks.iter().enumerate() {
+        conn.execute("UPDATE tasks SET idx = ?1 WHERE id = ?2", params![i as i64 + 1, task.id])?;
+    }
+
+    Ok(())
+}
+
+pub fn shift_task_indices(conn: &Connection, index: &i32, size: &i32) -> Result<(), Error>  {
+    // SQLite checks UNIQUE(idx) row by row, so move the shifted rows out of the way
+    // through negative indices first, then flip them back
+    conn.execute("UPDATE tasks SET idx = -(idx + ?1) WHERE idx >= ?2", params![size, index])?;
+    conn.execute("UPDATE tasks SET idx = -idx WHERE idx < 0", [])?;
+
+    Ok(())
+}
+
+pub fn get_tasks_length(conn: &Connection) -> i32 {
+    match conn.query_row("SELECT COUNT(*) FROM tasks", (), |row| row.get::<_, i64>(0)) {
+        Ok(count) => {
+            if count > i32::MAX as i64 {
+                eprintln!("Warning: Count exceeds i32 range. Truncating to i32::MAX.");
+                i32::MAX
+            } else {
+                count as i32
+            }
+        },
+        Err(e) => {
+            panic!("Failed to get the length of tasks: {}", e);
+        }
+    }
+}
+
+/// Adds `task` with the next free index, so appending doesn't renumber anything. Subtasks, and
+/// any task whose index is somehow taken already, are added without one and placed by the
+/// reindex that follows; returns whether `task` needs that reindex.
+fn add_task_to_db(conn: &Connection, task: &Task) -> Result<bool, TaskError> {
+    let insert = |idx: &str| conn.execute(
+        &format!("INSERT INTO tasks (idx, name, parent_id, recurrence, created_at) VALUES ({}, ?1, ?2, ?3, datetime('now'))", idx),
+        params![&task.name, &task.parent_id, &task.recurrence],
+    );
+
+    let result = match task.parent_id {
+        Some(_) => insert("NULL").map(|_| true),
+        None => match insert("(SELECT COALESCE(MAX(idx), 0) + 1 FROM tasks)") {
+            Err(e) if e.sqlite_error_code() == Some(ErrorCode::ConstraintViolation) => insert("NULL").map(|_| true),
+            result => result.map(|_| false),
+        },
+    };
+
+    result.map_err(|e| TaskError::Task { name: task.name.clone(), source: e })
+}
+
+/// Adds `tasks`, as names and whether they are done, at the end as one `append` that `undo`
+/// takes back, and returns the ids they were given
+pub fn append_tasks_to_db(conn: &mut Connection, tasks: &[(String, bool)]) -> Result<Vec<i32>, Error> {
+    let transaction = write_transaction(conn)?;
+    let names: Vec<&str> = tasks.iter().map(|(name, _)| name.as_str()).collect();
+    let history_id = record_history(&transaction, "append", &names.join(", "), &[])?;
+
+    let mut created_ids = Vec::new();
+    for (name, done) in tasks {
+        transaction.execute(
+            "INSERT INTO tasks (idx, name, done, completed_at, created_at)
+            VALUES ((SELECT COALESCE(MAX(idx), 0) + 1 FROM tasks), ?1, ?2, CASE WHEN ?2 THEN datetime('now') END, datetime('now'))",
+            params![name, done],
+        )?;
+        created_ids.push(transaction.last_insert_rowid() as i32);
+    }
+
+    record_created_tasks(&transaction, history_id, &created_ids)?;
+    transaction.commit()?;
+
+    renormalize_task_indices(conn)?;
+
+    Ok(created_ids)
+}
+
+/// Adds `tasks` at the end and returns the ids they were given
+pub fn add_tasks_to_db(conn: &mut Connection, tasks: &[Task]) -> Result<Vec<i32>, TaskError> {
+    // Dropping the transaction on error rolls back the whole batch
+    let transaction = write_transaction(conn)?;
+    let history_id = record_history(&transaction, "add", &join_names(tasks), &[])?;
+
+    let mut created_ids = Vec::new();
+    let mut needs_reindex = false;
+    for task in tasks {
+        needs_reindex |= add_task_to_db(&transaction, task)?;
+        created_ids.push(transaction.last_insert_rowid() as i32);
+    }
+
+    record_created_tasks(&transaction, history_id, &created_ids)?;
+    transaction.commit()?;
+
+    if needs_reindex {
+        renormalize_task_indices(conn)?;
+    }
+
+    Ok(created_ids)
+}
+
+fn insert_task_to_db(conn: &Connection, task: &Task) -> Result<(), TaskError> {
+    match conn.execute(
+        "INSERT INTO tasks (idx, name, created_at) VALUES (?1, ?2, datetime('now'))",
+        params![&task.idx, &task.name],
+    ) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(TaskError::Task { name: task.name.clone(), source: e }),
+    }
+}
+
+/// Adds `tasks` from index `idx` on, moving the tasks there down, and returns the ids they were given
+pub fn insert_tasks_to_db(conn: &mut Connection, idx: &i32, tasks: &[Task]) -> Result<Vec<i32>, TaskError> {
+    // Shifting and inserting share one transaction so a crash can't leave a gap
+    let transaction = write_transaction(conn)?;
+    let history_id = record_history(&transaction, "insert", &join_names(tasks), &[])?;
+
+    shift_task_indices(&transaction, idx, &(tasks.len() as i32))?;
+
+    let mut created_ids = Vec::new();
+    for task in tasks {
+        insert_task_to_db(&transaction, task)?;
+        created_ids.push(transaction.last_insert_rowid() as i32);
+    }
+
+    record_created_tasks(&transaction, history_id, &created_ids)?;
+    transaction.commit()?;
+
+    Ok(created_ids)
+}
+
+/// Adds a copy of `task` under each of `names` straight after it (after its subtasks, for a
+/// top-level task), with its due date, note, recurrence, snooze, fields and blockers but not done
+pub fn duplicate_task_in_db(conn: &mut Connection, task: &Task, names: &[String]) -> Result<(), Error> {
+    let transaction = write_transaction(conn)?;
+    let history_id = record_history(&transaction, "duplicate", &names.join(", "), &[])?;
+
+    let last: i64 = transaction.query_row("SELECT MAX(idx) FROM tasks WHERE id = ?1 OR parent_id = ?1", [task.id], |row| row.get(0))?;
+    shift_task_indices(&transaction, &(last as i32 + 1), &(names.len() as i32))?;
+
+    let mut created_ids = Vec::new();
+    for (i, name) in names.iter().enumerate() {
+        transaction.execute(
+            "INSERT INTO tasks (idx, name, due_at, note, parent_id, recurrence, snoozed_until, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, datetime('now'))",
+            params![last + 1 + i as i64, name, task.due_at, task.note, task.parent_id, task.recurrence, task.snoozed_until],
+        )?;
+        let id = transaction.last_insert_rowid() as i32;
+
+        transaction.execute("INSERT INTO task_fields (task_id, key, value) SELECT ?1, key, value FROM task_fields WHERE task_id = ?2", params![id, task.id])?;
+        transaction.execute("INSERT INTO dependencies (task_id, blocker_id) SELECT ?1, blocker_id FROM dependencies WHERE task_id = ?2", params![id, task.id])?;
+        created_ids.push(id);
+    }
+
+    record_created_tasks(&transaction, history_id, &created_ids)?;
+    transaction.commit()?;
+
+    Ok(())
+}
+
+pub fn get_tasks_from_db(conn: &Connection) -> Result<Vec<Task>> {
+    // Rows without a usable index (freshly added or out of range) go after the ordered ones
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM tasks ORDER BY {}", TASK_COLUMNS, TASK_ORDER))?;
+    let rows = stmt.query_map([], task_from_row)?;
+
+    let tasks: Vec<Task> = rows.map(|row| row.unwrap()).collect();
+    Ok(tasks)
+}
+
+pub fn get_task_from_db(conn: &Connection, task_index: &i32) -> Result<Option<Task>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM tasks WHERE idx = ?1", TASK_COLUMNS))?;
+    let mut rows = stmt.query_map([task_index], task_from_row)?;
+
+    rows.next().transpose()
+}
+
+pub fn get_task_by_id(conn: &Connection, task_id: &i32) -> Result<Option<Task>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM tasks WHERE id = ?1", TASK_COLUMNS))?;
+    let mut rows = stmt.query_map([task_id], task_from_row)?;
+
+    rows.next().transpose()
+}
+
+/// The tasks with `task_ids` in list order, read with one SELECT
+fn get_tasks_by_ids(conn: &Connection, task_ids: &[i32]) -> Result<Vec<Task>> {
+    let ids: Vec<String> = task_ids.iter().map(|id| id.to_string()).collect();
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM tasks WHERE id IN ({}) ORDER BY {}", TASK_COLUMNS, ids.join(", "), TASK_ORDER))?;
+    let rows = stmt.query_map([], task_from_row)?;
+
+    rows.collect()
+}
+
+/// A WHERE condition matching exactly `tasks`, by id
+fn ids_condition(tasks: &[Task]) -> String {
+    let ids: Vec<String> = tasks.iter().map(|t| t.id.unwrap().to_string()).collect();
+    format!("id IN ({})", ids.join(", "))
+}
+
+/// How many subtasks each parent has done and in total, as (parent id, done, total)
+pub fn get_subtask_progress_from_db(conn: &Connection) -> Result<Vec<(i32, usize, usize)>, Error> {
+    let mut stmt = conn.prepare("SELECT parent_id, SUM(done), COUNT(*) FROM tasks WHERE parent_id IS NOT NULL GROUP BY parent_id")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect()
+}
+
+/// The subtasks of the task at `task_index`, in order
+pub fn get_subtasks_from_db(conn: &Connection, task_index: &i32) -> Result<Vec<Task>, Error> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM tasks WHERE parent_id = (SELECT id FROM tasks WHERE idx = ?1) ORDER BY idx ASC",
+        TASK_COLUMNS,
+    ))?;
+    let rows = stmt.query_map([task_index], task_from_row)?;
+
+    let tasks: Vec<Task> = rows.map(|row| row.unwrap()).collect();
+    Ok(tasks)
+}
+
+/// The tasks at `task_indices` that exist, in the order given
+fn get_tasks_at(conn: &Connection, task_indices: &[i32]) -> Result<Vec<Task>, Error> {
+    let mut tasks = Vec::new();
+
+    for index in task_indices {
+        if let Some(task) = get_task_from_db(conn, index)? {
+            tasks.push(task);
+        }
+    }
+
+    Ok(tasks)
+}
+
+pub fn task_indices_are_contiguous(conn: &Connection) -> Result<bool, Error> {
+    conn.query_row(
+        "SELECT COUNT(*), COUNT(DISTINCT idx), COALESCE(MIN(idx), 1), COALESCE(MAX(idx), 0) FROM tasks",
+        [],
+        |row| {
+            let (count, distinct, min, max): (i64, i64, i64, i64) = (row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?);
+            Ok(count == distinct && min == 1 && max == count)
+        },
+    )
+}
+
+/// Orders tasks so that each one's subtasks directly follow it, keeping their order otherwise.
+/// Subtasks whose parent is gone count as top-level tasks.
+fn group_subtasks(tasks: Vec<Task>) -> Vec<Task> {
+    let ids: Vec<Option<i32>> = tasks.iter().map(|t| t.id).collect();
+    let (mut subtasks, top_level): (Vec<Task>, Vec<Task>) = tasks.into_iter().partition(|t| t.parent_id.is_some() && ids.contains(&t.parent_id));
+
+    let mut grouped = Vec::new();
+    for task in top_level {
+        let id = task.id;
+        grouped.push(task);
+
+        let (children, rest) = subtasks.into_iter().partition(|t| t.parent_id == id);
+        grouped.extend(children);
+        subtasks = rest;
+    }
+
+    // Only left over if a subtask's parent is itself a subtask, which `add --under` refuses
+    grouped.extend(subtasks);
+    grouped
+}
+
+/// Whether indices are 1..N with every task's subtasks right after it
+pub fn task_indices_are_in_order(conn: &Connection) -> Result<bool, Error> {
+    let tasks = group_subtasks(get_tasks_from_db(conn)?);
+    Ok(tasks.iter().enumerate().all(|(i, t)| t.idx.map(i64::from) == Some(i as i64 + 1)))
+}
+
+/// Gives tasks without an index the next free ones, in the order they were added, and leaves
+/// every other index alone
+fn assign_missing_indices(conn: &Connection) -> Result<(), Error> {
+    let transaction = write_transaction(conn)?;
+
+    let mut stmt = transaction.prepare("SELECT id FROM tasks WHERE idx IS NULL ORDER BY id ASC")?;
+    let ids = stmt.query_map([], |row| row.get::<_, i32>(0))?.collect::<Result<Vec<i32>, Error>>()?;
+    drop(stmt);
+
+    for id in ids {
+        transaction.execute("UPDATE tasks SET idx = (SELECT COALESCE(MAX(idx), 0) + 1 FROM tasks) WHERE id = ?1", [id])?;
+    }
+    transaction.commit()
+}
+
+pub fn renormalize_task_indices(conn: &mut Connection) -> Result<(), Error> {
+    // A scripting session relies on the indices it has seen staying put
+    if active_session().is_some() {
+        return assign_missing_indices(conn);
+    }
+
+    // Skip the write entirely when indices are already 1..N and grouped
+    if task_indices_are_contiguous(conn)? && task_indices_are_in_order(conn)? {
+        return Ok(());
+    }
+
+    // The order is read again under the write lock, so another todoln that changed the tasks in
+    // the meantime doesn't get its change numbered from a stale list
+    let transaction = write_transaction(conn)?;
+    let tasks = group_subtasks(get_tasks_from_db(&transaction)?);
+    update_task_indices(&transaction, &tasks)?;
+    transaction.commit()?;
+
+    Ok(())
+}
+
+pub fn get_tasks_from_db_and_update_indices(conn: &mut Connection) -> Result<Vec<Task>> {
+    renormalize_task_indices(conn)?;
+    get_tasks_from_db(conn)
+}
+
+pub enum SearchMode {
+    Substring { case_sensitive: bool },
+    Regex(Regex),
+}
+
+impl SearchMode {
+    /// Byte ranges of `name` matched by `query`, mirroring how the database matches it
+    /// (LIKE only folds ASCII case)
+    pub fn match_ranges(&self, query: &str, name: &str) -> Vec<(usize, usize)> {
+        match self {
+            SearchMode::Substring { case_sensitive } => {
+                if query.is_empty() {
+                    return Vec::new();
+                }
+
+                let (haystack, needle) = if *case_sensitive {
+                    (name.to_string(), query.to_string())
+                } else {
+                    (name.to_ascii_lowercase(), query.to_ascii_lowercase())
+                };
+
+                haystack.match_indices(&needle).map(|(start, m)| (start, start + m.len())).collect()
+            }
+            SearchMode::Regex(regex) => {
+                regex.find_iter(name).filter(|m| !m.is_empty()).map(|m| (m.start(), m.end())).collect()
+            }
+        }
+    }
+
+    /// `name` with every part matched by `query` replaced by `replacement`, in which a regex can
+    /// refer to its capture groups as `$1` or `${name}`
+    pub fn replace(&self, query: &str, name: &str, replacement: &str) -> String {
+        if let SearchMode::Regex(regex) = self {
+            return regex.replace_all(name, replacement).into_owned();
+        }
+
+        let mut replaced = String::new();
+        let mut last = 0;
+
+        for (start, end) in self.match_ranges(query, name) {
+            replaced.push_str(&name[last..start]);
+            replaced.push_str(replacement);
+            last = end;
+        }
+
+        replaced.push_str(&name[last..]);
+        replaced
+    }
+}
+
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+/// Which tasks `find_tasks_from_db` searches
+#[derive(Clone, Copy, PartialEq)]
+pub enum TaskSubset {
+    All,
+    Todo,
+    Done,
+}
+
+impl TaskSubset {
+    pub fn parse(s: &str) -> Option<TaskSubset> {
+        match s.trim().to_lowercase().as_str() {
+            "all" => Some(TaskSubset::All),
+            "todo" => Some(TaskSubset::Todo),
+            "done" => Some(TaskSubset::Done),
+            _ => None,
+        }
+    }
+
+    fn condition(&self) -> &'static str {
+        match self {
+            TaskSubset::All => "1",
+            TaskSubset::Todo => "done = 0",
+            TaskSubset::Done => "done = 1",
+        }
+    }
+}
+
+pub fn find_tasks_from_db(conn: &Connection, query: &str, mode: &SearchMode, subset: TaskSubset) -> Result<Vec<Task>, Error> {
+    find_tasks_in_fields_from_db(conn, query, mode, subset, &[SearchField::Name])
+}
+
+/// Tasks with `query` in any of `fields`, each listed once however many of them it's found in
+pub fn find_tasks_in_fields_from_db(conn: &Connection, query: &str, mode: &SearchMode, subset: TaskSubset, fields: &[SearchField]) -> Result<Vec<Task>, Error> {
+    let columns: Vec<&str> = fields.iter()
+        .map(|field| match field {
+            SearchField::Name => "name",
+            SearchField::Note => "note",
+            SearchField::Tags => "tags.value",
+        })
+        .collect();
+    let from = "tasks LEFT JOIN task_fields AS tags ON tags.task_id = tasks.id AND tags.key = 'tags'";
+    let any_column = |condition: &str| columns.iter().map(|column| condition.replace("{}", column)).collect::<Vec<_>>().join(" OR ");
+
+    let tasks_found: Vec<Task> = match mode {
+        SearchMode::Substring { case_sensitive: false } => {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT DISTINCT {} FROM {} WHERE ({}) AND {} ORDER BY {}",
+                TASK_COLUMNS, from, any_column("{} LIKE ?1 ESCAPE '\\'"), subset.condition(), TASK_ORDER,
+            ))?;
+            let pattern = format!("%{}%", escape_like(query));
+
+            let rows = stmt.query_map([&pattern], task_from_row)?;
+            rows.map(|row| row.unwrap()).collect()
+        }
+        SearchMode::Substring { case_sensitive: true } => {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT DISTINCT {} FROM {} WHERE ({}) AND {} ORDER BY {}",
+                TASK_COLUMNS, from, any_column("instr({}, ?1) > 0"), subset.condition(), TASK_ORDER,
+            ))?;
+
+            let rows = stmt.query_map([query], task_from_row)?;
+            rows.map(|row| row.unwrap()).collect()
+        }
+        SearchMode::Regex(regex) => {
+            let mut stmt = conn.prepare(&format!("SELECT {}, tags.value FROM {} WHERE {} ORDER BY {}", TASK_COLUMNS, from, subset.condition(), TASK_ORDER))?;
+
+            let rows = stmt.query_map([], |row| Ok((task_from_row(row)?, row.get::<_, Option<String>>(11)?)))?;
+            rows.map(|row| row.unwrap())
+                .filter(|(task, tags)| fields.iter().any(|field| {
+                    let text = match field {
+                        SearchField::Name => Some(task.name.as_str()),
+                        SearchField::Note => task.note.as_deref(),
+                        SearchField::Tags => tags.as_deref(),
+                    };
+                    text.is_some_and(|text| regex.is_match(text))
+                }))
+                .map(|(task, _)| task)
+                .collect()
+        }
+    };
+
+    Ok(tasks_found)
+}
+
+/// Sets or (with `None`) clears a task's due date. Returns whether a task at `task_index` exists.
+pub fn set_task_due_in_db(conn: &Connection, task_index: &i32, due_at: Option<&str>) -> Result<bool, Error> {
+    let transaction = write_transaction(conn)?;
+
+    let Some(task) = get_task_from_db(&transaction, task_index)? else {
+        return Ok(false);
+    };
+
+    record_history(&transaction, "due", &task.name, slice::from_ref(&task))?;
+    transaction.execute("UPDATE tasks SET due_at = ?1 WHERE idx = ?2", params![due_at, task_index])?;
+    transaction.commit()?;
+
+    Ok(true)
+}
+
+/// Hides a task until `snoozed_until`, or (with `None`) wakes it up again. Returns whether a task
+/// at `task_index` exists.
+pub fn set_task_snooze_in_db(conn: &Connection, task_index: &i32, snoozed_until: Option<&str>) -> Result<bool, Error> {
+    let transaction = write_transaction(conn)?;
+
+    let Some(task) = get_task_from_db(&transaction, task_index)? else {
+        return Ok(false);
+    };
+
+    record_history(&transaction, "snooze", &task.name, slice::from_ref(&task))?;
+    transaction.execute("UPDATE tasks SET snoozed_until = ?1 WHERE idx = ?2", params![snoozed_until, task_index])?;
+    transaction.commit()?;
+
+    Ok(true)
+}
+
+/// Sets or (with `None`) clears a task's note. Returns whether a task at `task_index` exists.
+pub fn set_task_note_in_db(conn: &mut Connection, task_index: &i32, note: Option<&str>) -> Result<bool, Error> {
+    let transaction = write_transaction(conn)?;
+
+    let Some(task) = get_task_from_db(&transaction, task_index)? else {
+        return Ok(false);
+    };
+
+    record_history(&transaction, "note", &task.name, slice::from_ref(&task))?;
+    transaction.execute("UPDATE tasks SET note = ?1 WHERE idx = ?2", params![note, task_index])?;
+    transaction.commit()?;
+
+    Ok(true)
+}
+
+/// Open tasks with a due date from `start` until before `end`, soonest first. Either end can be
+/// left open, so without `start` overdue tasks are included.
+pub fn get_tasks_due_between(conn: &Connection, start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) -> Result<Vec<Task>, Error> {
+    let format = |t: DateTime<Utc>| t.format("%Y-%m-%d %H:%M:%S").to_string();
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM tasks
+        WHERE done = 0 AND due_at IS NOT NULL AND (?1 IS NULL OR due_at >= ?1) AND (?2 IS NULL OR due_at < ?2)
+        ORDER BY due_at ASC, idx ASC",
+        TASK_COLUMNS,
+    ))?;
+    let rows = stmt.query_map(params![start.map(format), end.map(format)], task_from_row)?;
+
+    let tasks: Vec<Task> = rows.map(|row| row.unwrap()).collect();
+    Ok(tasks)
+}
+
+/// Open, awake tasks due before `end` that `notify` hasn't reminded about yet today, soonest first
+pub fn get_tasks_to_notify_from_db(conn: &Connection, end: DateTime<Utc>) -> Result<Vec<Task>, Error> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM tasks
+        WHERE done = 0 AND due_at < ?1
+            AND (snoozed_until IS NULL OR snoozed_until <= datetime('now'))
+            AND (notified_at IS NULL OR date(notified_at, 'localtime') < date('now', 'localtime'))
+        ORDER BY due_at ASC, idx ASC",
+        TASK_COLUMNS,
+    ))?;
+    let rows = stmt.query_map([end.format("%Y-%m-%d %H:%M:%S").to_string()], task_from_row)?;
+
+    rows.collect()
+}
+
+pub fn mark_tasks_notified_in_db(conn: &mut Connection, tasks: &[Task]) -> Result<(), Error> {
+    let transaction = write_transaction(conn)?;
+
+    for task in tasks {
+        transaction.execute("UPDATE tasks SET notified_at = datetime('now') WHERE id = ?1", [task.id])?;
+    }
+
+    transaction.commit()
+}
+
+/// The earliest due date of any unfinished task, answered from the (done, due_at) index
+pub fn get_next_due_from_db(conn: &Connection) -> Result<Option<String>, Error> {
+    conn.query_row("SELECT MIN(due_at) FROM tasks WHERE done = 0 AND due_at IS NOT NULL", [], |row| row.get(0))
+}
+
+pub fn edit_task_in_db(conn: &mut Connection, task_index: &i32, new_name: &String) -> Result<(), Error> {
+    let transaction = write_transaction(conn)?;
+
+    if let Some(task) = get_task_from_db(&transaction, task_index)? {
+        record_history(&transaction, "modify", &format!("{} -> {}", task.name, new_name), &[task])?;
+        transaction.execute("UPDATE tasks SET name = ?1 WHERE idx = ?2", params![new_name, task_index])?;
+    }
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Gives each task in `renames` its new name, as one `modify` that `undo` takes back at once
+pub fn edit_tasks_in_db(conn: &mut Connection, renames: &[(Task, String)]) -> Result<(), Error> {
+    let summary: Vec<String> = renames.iter().map(|(task, new_name)| format!("{} -> {}", task.name, new_name)).collect();
+    let tasks: Vec<Task> = renames.iter().map(|(task, _)| task.clone()).collect();
+
+    let transaction = write_transaction(conn)?;
+    record_history(&transaction, "modify", &summary.join(", "), &tasks)?;
+
+    for (task, new_name) in renames {
+        transaction.execute("UPDATE tasks SET name = ?1 WHERE id = ?2", params![new_name, task.id])?;
+    }
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Renames the tasks matched by `rename-all`, recorded under `summary` so one `undo` takes
+/// back every rename at once
+pub fn rename_tasks_in_db(conn: &mut Connection, renames: &[(Task, String)], summary: &str) -> Result<(), Error> {
+    let transaction = write_transaction(conn)?;
+
+    let tasks: Vec<Task> = renames.iter().map(|(task, _)| task.clone()).collect();
+    record_history(&transaction, "rename-all", summary, &tasks)?;
+
+    for (task, new_name) in renames {
+        transaction.execute("UPDATE tasks SET name = ?1 WHERE id = ?2", params![new_name, task.id])?;
+    }
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Adds a fresh copy of every done task that still has a recurrence rule, due at the rule's next
+/// occurrence, and hands the rule over to the copy so the completed task can be cleared like any
+/// other. Returns the ids of the copies.
+fn respawn_recurring_tasks(conn: &Connection) -> Result<Vec<i32>, Error> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM tasks WHERE done AND recurrence IS NOT NULL ORDER BY idx ASC", TASK_COLUMNS))?;
+    let tasks = stmt.query_map([], task_from_row)?.collect::<Result<Vec<Task>, Error>>()?;
+
+    let mut created_ids = Vec::new();
+    for task in tasks {
+        let due_at = task.recurrence.as_deref().and_then(next_occurrence);
+
+        conn.execute(
+            "INSERT INTO tasks (idx, name, due_at, note, parent_id, recurrence, created_at)
+            VALUES ((SELECT COALESCE(MAX(idx), 0) + 1 FROM tasks), ?1, ?2, ?3, ?4, ?5, datetime('now'))",
+            params![task.name, due_at, task.note, task.parent_id, task.recurrence],
+        )?;
+        let id = conn.last_insert_rowid() as i32;
+
+        conn.execute("INSERT INTO task_fields (task_id, key, value) SELECT ?1, key, value FROM task_fields WHERE task_id = ?2", params![id, task.id])?;
+        conn.execute("UPDATE tasks SET recurrence = NULL WHERE id = ?1", [task.id])?;
+        created_ids.push(id);
+    }
+
+    if !created_ids.is_empty() && active_session().is_none() {
+        update_task_indices(conn, &group_subtasks(get_tasks_from_db(conn)?))?;
+    }
+
+    Ok(created_ids)
+}
+
+/// What `mark_tasks_in_db_as_done` did with each of the indices it was given
+#[derive(Default)]
+pub struct DoneReport {
+    /// Tasks that were todo and are done now
+    pub completed: Vec<i32>,
+    /// Tasks that were done already, and are todo again if they were toggled
+    pub already_done: Vec<i32>,
+    /// Indices no task has
+    pub not_found: Vec<i32>,
+}
+
+/// Marks the tasks at `task_indices` as done, or with `toggle` marks the done ones todo again
+pub fn mark_tasks_in_db_as_done(conn: &mut Connection, task_indices: &[i32], toggle: bool) -> Result<DoneReport, Error> {
+    let transaction = write_transaction(conn)?;
+    let mut report = DoneReport::default();
+
+    let mut seen = HashSet::new();
+    let task_indices: Vec<i32> = task_indices.iter().copied().filter(|index| seen.insert(*index)).collect();
+
+    let tasks = get_tasks_at(&transaction, &task_indices)?;
+    report.not_found = task_indices.iter().copied().filter(|&index| !tasks.iter().any(|t| t.idx == Some(index))).collect();
+
+    let changing: Vec<Task> = tasks.iter().filter(|t| toggle || !t.done).cloned().collect();
+    if changing.is_empty() {
+        report.already_done = tasks.iter().map(|t| t.idx.unwrap()).collect();
+        return Ok(report);
+    }
+    let history_id = record_history(&transaction, if toggle { "toggle" } else { "done" }, &join_names(&changing), &changing)?;
+
+    for task in &tasks {
+        let changed = transaction.execute(
+            "UPDATE tasks SET done = true, completed_at = COALESCE(completed_at, datetime('now')) WHERE id = ?1 AND NOT done",
+            [task.id],
+        )?;
+
+        if changed > 0 {
+            report.completed.push(task.idx.unwrap());
+            continue;
+        }
+
+        if toggle {
+            transaction.execute("UPDATE tasks SET done = false, completed_at = NULL WHERE id = ?1", [task.id])?;
+        }
+        report.already_done.push(task.idx.unwrap());
+    }
+
+    let created_ids = respawn_recurring_tasks(&transaction)?;
+    record_created_tasks(&transaction, history_id, &created_ids)?;
+
+    transaction.commit()?;
+
+    Ok(report)
+}
+
+/// Marks the todo tasks among `task_ids` done with one UPDATE, in one transaction, and returns
+/// them as they were before
+pub fn mark_tasks_in_db_as_done_by_ids(conn: &mut Connection, task_ids: &[i32]) -> Result<Vec<Task>, Error> {
+    let transaction = write_transaction(conn)?;
+
+    let tasks: Vec<Task> = get_tasks_by_ids(&transaction, task_ids)?.into_iter().filter(|t| !t.done).collect();
+    if tasks.is_empty() {
+        return Ok(tasks);
+    }
+    let history_id = record_history(&transaction, "done", &join_names(&tasks), &tasks)?;
+
+    transaction.execute(
+        &format!("UPDATE tasks SET done = true, completed_at = COALESCE(completed_at, datetime('now')) WHERE NOT done AND {}", ids_condition(&tasks)),
+        [],
+    )?;
+
+    let created_ids = respawn_recurring_tasks(&transaction)?;
+    record_created_tasks(&transaction, history_id, &created_ids)?;
+
+    transaction.commit()?;
+
+    Ok(tasks)
+}
+
+pub fn mark_task_in_db_as_done_by_id(conn: &Connection, task_id: &i32) -> Result<(), Error> {
+    let transaction = write_transaction(conn)?;
+
+    if let Some(task) = get_task_by_id(&transaction, task_id)? {
+        let history_id = record_history(&transaction, "done", &task.name, slice::from_ref(&task))?;
+        transaction.execute(
+            "UPDATE tasks SET done = true, completed_at = COALESCE(completed_at, datetime('now')) WHERE id = ?1",
+            [task_id],
+        )?;
+
+        let created_ids = respawn_recurring_tasks(&transaction)?;
+        record_created_tasks(&transaction, history_id, &created_ids)?;
+    }
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+pub fn mark_task_in_db_as_todo(conn: &mut Connection, task_index: &i32) -> Result<(), Error> {
+    let transaction = write_transaction(conn)?;
+
+    if let Some(task) = get_task_from_db(&transaction, task_index)? {
+        record_history(&transaction, "todo", &task.name, slice::from_ref(&task))?;
+        transaction.execute("UPDATE tasks SET done = false, completed_at = NULL WHERE idx = ?1", [task_index])?;
+    }
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Makes the task with `task_id` wait for the one with `blocker_id`. Returns `false`, changing
+/// nothing, if the blocker already waits for the task, directly or through other tasks.
+pub fn add_dependency_in_db(conn: &Connection, task_id: &i32, blocker_id: &i32) -> Result<bool, Error> {
+    let cycle: bool = conn.query_row(
+        "WITH RECURSIVE waits_for(id) AS (
+            SELECT ?1
+            UNION
+            SELECT dependencies.blocker_id FROM dependencies JOIN waits_for ON dependencies.task_id = waits_for.id
+        )
+        SELECT EXISTS (SELECT 1 FROM waits_for WHERE id = ?2)",
+        [blocker_id, task_id],
+        |row| row.get(0),
+    )?;
+
+    if cycle {
+        return Ok(false);
+    }
+
+    conn.execute("INSERT OR IGNORE INTO dependencies (task_id, blocker_id) VALUES (?1, ?2)", [task_id, blocker_id])?;
+    Ok(true)
+}
+
+/// Returns whether the task with `task_id` waited for the one with `blocker_id`
+pub fn remove_dependency_in_db(conn: &Connection, task_id: &i32, blocker_id: &i32) -> Result<bool, Error> {
+    Ok(conn.execute("DELETE FROM dependencies WHERE task_id = ?1 AND blocker_id = ?2", [task_id, blocker_id])? > 0)
+}
+
+/// Seconds between a time entry's start and its end, or now for the running one
+const ENTRY_SECONDS: &str = "strftime('%s', COALESCE(stopped_at, datetime('now'))) - strftime('%s', started_at)";
+
+/// The task whose timer is running, with how many seconds it has been running
+pub fn get_running_timer_from_db(conn: &Connection) -> Result<Option<(Task, i64)>, Error> {
+    let columns = TASK_COLUMNS.split(", ").map(|column| format!("tasks.{}", column)).collect::<Vec<_>>().join(", ");
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}, {} FROM time_entries JOIN tasks ON tasks.id = time_entries.task_id WHERE stopped_at IS NULL",
+        columns, ENTRY_SECONDS,
+    ))?;
+    let mut rows = stmt.query_map([], |row| Ok((task_from_row(row)?, row.get::<_, i64>(11)?)))?;
+
+    rows.next().transpose()
+}
+
+/// Stops the running timer. Returns its task and how many seconds it ran, or `None` if no timer
+/// was running.
+pub fn stop_timer_in_db(conn: &Connection) -> Result<Option<(Task, i64)>, Error> {
+    let running = get_running_timer_from_db(conn)?;
+    conn.execute("UPDATE time_entries SET stopped_at = datetime('now') WHERE stopped_at IS NULL", [])?;
+    Ok(running)
+}
+
+/// Starts a timer on `task`, stopping any other one first. Returns the stopped timer like
+/// `stop_timer_in_db`.
+pub fn start_timer_in_db(conn: &mut Connection, task: &Task) -> Result<Option<(Task, i64)>, Error> {
+    let transaction = write_transaction(conn)?;
+
+    let stopped = stop_timer_in_db(&transaction)?;
+    transaction.execute("INSERT INTO time_entries (task_id, started_at) VALUES (?1, datetime('now'))", [task.id])?;
+
+    transaction.commit()?;
+
+    Ok(stopped)
+}
+
+/// Seconds tracked per task and local day, newest day first, for every task or just `task_id`.
+/// Time is counted on the day its entry started, and the running entry counts up to now.
+pub fn get_tracked_time_from_db(conn: &Connection, task_id: Option<i32>) -> Result<Vec<(i32, NaiveDate, i64)>, Error> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT task_id, date(started_at, 'localtime') AS day, SUM({}) FROM time_entries
+        WHERE ?1 IS NULL OR task_id = ?1
+        GROUP BY task_id, day ORDER BY day DESC, task_id ASC",
+        ENTRY_SECONDS,
+    ))?;
+    let rows = stmt.query_map([task_id], |row| Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?)))?;
+
+    let mut tracked = Vec::new();
+    for row in rows {
+        let (task_id, day, seconds) = row?;
+        if let Ok(day) = NaiveDate::parse_from_str(&day, "%Y-%m-%d") {
+            tracked.push((task_id, day, seconds));
+        }
+    }
+
+    Ok(tracked)
+}
+
+/// Every open task some other task waits for, paired with the id of the waiting task
+pub fn get_open_blockers_from_db(conn: &Connection) -> Result<Vec<(i32, Task)>, Error> {
+    let columns = TASK_COLUMNS.split(", ").map(|column| format!("tasks.{}", column)).collect::<Vec<_>>().join(", ");
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}, dependencies.task_id FROM dependencies JOIN tasks ON tasks.id = dependencies.blocker_id
+        WHERE NOT tasks.done ORDER BY tasks.idx ASC",
+        columns,
+    ))?;
+    let rows = stmt.query_map([], |row| Ok((row.get(11)?, task_from_row(row)?)))?;
+    rows.collect()
+}
+
+/// Sets the custom fields of the task at `task_index`, removing those set to an empty value.
+/// Returns `false` if there's no task at that index.
+pub fn set_task_fields_in_db(conn: &mut Connection, task_index: &i32, fields: &[(String, String)]) -> Result<bool, Error> {
+    let transaction = write_transaction(conn)?;
+
+    let Some(task) = get_task_from_db(&transaction, task_index)? else {
+        return Ok(false);
+    };
+
+    for (key, value) in fields {
+        if value.is_empty() {
+            transaction.execute("DELETE FROM task_fields WHERE task_id = ?1 AND key = ?2", params![task.id, key])?;
+        } else {
+            transaction.execute("INSERT OR REPLACE INTO task_fields (task_id, key, value) VALUES (?1, ?2, ?3)", params![task.id, key, value])?;
+        }
+    }
+
+    transaction.commit()?;
+
+    Ok(true)
+}
+
+/// The custom fields of every task, ordered by key, as (task id, key, value)
+pub fn get_task_fields_from_db(conn: &Connection) -> Result<Vec<(i32, String, String)>, Error> {
+    let mut stmt = conn.prepare("SELECT task_id, key, value FROM task_fields ORDER BY key ASC")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect()
+}
+
+/// Replaces every task with `tasks`, moving the current ones into the trash, as one operation
+/// that `undo` can take back
+pub fn import_tasks_in_db(conn: &mut Connection, tasks: &[ExportedTask]) -> Result<(), Error> {
+    let transaction = write_transaction(conn)?;
+
+    let current = get_tasks_from_db(&transaction)?;
+    let history_id = record_history(&transaction, "import", &format!("{} task(s)", tasks.len()), &current)?;
+    move_tasks_to_trash(&transaction, "true")?;
+
+    let mut created_ids: Vec<i32> = Vec::new();
+    for (i, task) in tasks.iter().enumerate() {
+        let parent_id = task.parent.and_then(|parent| created_ids.get(parent.checked_sub(1)?).copied());
+
+        transaction.execute(
+            "INSERT INTO tasks (idx, name, done, completed_at, due_at, note, parent_id, recurrence, snoozed_until, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, COALESCE(?10, datetime('now')))",
+            params![i as i64 + 1, task.name, task.done, task.completed_at, task.due_at, task.note, parent_id, task.recurrence, task.snoozed_until, task.created_at],
+        )?;
+        let id = transaction.last_insert_rowid() as i32;
+
+        for (key, value) in &task.fields {
+            transaction.execute("INSERT INTO task_fields (task_id, key, value) VALUES (?1, ?2, ?3)", params![id, key, value])?;
+        }
+
+        created_ids.push(id);
+    }
+
+    record_created_tasks(&transaction, history_id, &created_ids)?;
+    transaction.commit()?;
+
+    renormalize_task_indices(conn)?;
+
+    Ok(())
+}
+
+fn format_order(ids: &[i32]) -> String {
+    ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn parse_order(s: &str) -> Vec<i32> {
+    s.split(',').filter_map(|id| id.parse().ok()).collect()
+}
+
+fn get_task_order(conn: &Connection) -> Result<Vec<i32>, Error> {
+    Ok(get_tasks_from_db(conn)?.iter().map(|t| t.id.unwrap()).collect())
+}
+
+/// Where a task's `priority` field puts it: numbers in ascending order (so 1 comes first), then
+/// high, medium and low, then any other value alphabetically and tasks without one last
+pub fn priority_rank(priority: Option<&str>) -> (u8, i64, String) {
+    let Some(priority) = priority.map(|p| p.trim().to_lowercase()) else {
+        return (3, 0, String::new());
+    };
+
+    if let Ok(number) = priority.parse::<i64>() {
+        return (0, number, String::new());
+    }
+
+    match priority.as_str() {
+        "high" | "h" => (1, 0, String::new()),
+        "medium" | "med" | "m" => (1, 1, String::new()),
+        "low" | "l" => (1, 2, String::new()),
+        _ => (2, 0, priority),
+    }
+}
+
+/// The tasks in the order `sort` would put them in, subtasks following their parent, so each
+/// task's new index is its position counting from 1
+pub fn get_sorted_tasks_from_db(conn: &Connection, key: SortKey, reverse: bool) -> Result<Vec<Task>, Error> {
+    let mut tasks = get_tasks_from_db(conn)?;
+
+    let priorities: HashMap<i32, String> = get_task_fields_from_db(conn)?
+        .into_iter()
+        .filter(|(_, key, _)| key == "priority")
+        .map(|(task_id, _, value)| (task_id, value))
+        .collect();
+
+    tasks.sort_by(|a, b| {
+        let ordering = match key {
+            SortKey::Done => a.done.cmp(&b.done),
+            SortKey::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            // Tasks from before creation times were recorded have none and come first, by id
+            SortKey::Created => (&a.created_at, a.id).cmp(&(&b.created_at, b.id)),
+            SortKey::Priority => {
+                let rank = |task: &Task| priority_rank(priorities.get(&task.id.unwrap()).map(String::as_str));
+                rank(a).cmp(&rank(b))
+            }
+        };
+        if reverse { ordering.reverse() } else { ordering }
+    });
+
+    Ok(group_subtasks(tasks))
+}
+
+/// Orders the tasks by `key`, reversed with `reverse`. Tasks that compare equal keep their
+/// current order, and subtasks stay with their parent, sorted among its other subtasks.
+fn sort_tasks(conn: &Connection, key: SortKey, reverse: bool) -> Result<(), ReorderError> {
+    let tasks = get_sorted_tasks_from_db(conn, key, reverse)?;
+
+    // Remember the order before and after sorting so `sort --undo` can tell whether it still applies
+    let previous_order = get_task_order(conn)?;
+    apply_task_order(conn, &tasks.iter().map(|t| t.id.unwrap()).collect::<Vec<_>>())?;
+    let sorted_order = get_task_order(conn)?;
+    set_metadata(conn, "last_order", &format!("{}|{}", format_order(&previous_order), format_order(&sorted_order)))?;
+
+    Ok(())
+}
+
+pub fn sort_tasks_in_db(conn: &mut Connection, key: SortKey, reverse: bool) -> Result<(), ReorderError> {
+    let transaction = write_transaction(conn)?;
+    let history_id = record_history(&transaction, "sort", "", &[])?;
+    record_task_order(&transaction, history_id)?;
+    sort_tasks(&transaction, key, reverse)?;
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Why a new task order was refused: it has to name every task exactly once, by id
+#[derive(Debug)]
+pub enum ReorderError {
+    Missing(Vec<i32>),
+    Duplicate(Vec<i32>),
+    Unknown(Vec<i32>),
+    Database(Error),
+}
+
+impl fmt::Display for ReorderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReorderError::Missing(ids) => write!(f, "the new order leaves out task id(s) {}", format_order(ids)),
+            ReorderError::Duplicate(ids) => write!(f, "the new order lists task id(s) {} more than once", format_order(ids)),
+            ReorderError::Unknown(ids) => write!(f, "the new order lists task id(s) {} that don't exist", format_order(ids)),
+            ReorderError::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<Error> for ReorderError {
+    fn from(e: Error) -> Self {
+        ReorderError::Database(e)
+    }
+}
+
+/// Gives the tasks indices in `order`, a list of task ids that has to match the current tasks
+/// exactly. Subtasks are moved to follow their parent, in the order given among their siblings.
+/// Every command that reorders tasks goes through here; the caller provides the transaction.
+pub fn apply_task_order(conn: &Connection, order: &[i32]) -> Result<(), ReorderError> {
+    let mut tasks: HashMap<i32, Task> = get_tasks_from_db(conn)?.into_iter().map(|t| (t.id.unwrap(), t)).collect();
+
+    let mut seen = HashSet::new();
+    let mut duplicates: Vec<i32> = order.iter().copied().filter(|id| !seen.insert(*id)).collect();
+    duplicates.sort_unstable();
+    duplicates.dedup();
+
+    let mut unknown: Vec<i32> = seen.iter().copied().filter(|id| !tasks.contains_key(id)).collect();
+    unknown.sort_unstable();
+
+    let mut missing: Vec<i32> = tasks.keys().copied().filter(|id| !seen.contains(id)).collect();
+    missing.sort_unstable();
+
+    if !unknown.is_empty() {
+        return Err(ReorderError::Unknown(unknown));
+    }
+    if !duplicates.is_empty() {
+        return Err(ReorderError::Duplicate(duplicates));
+    }
+    if !missing.is_empty() {
+        return Err(ReorderError::Missing(missing));
+    }
+
+    let ordered: Vec<Task> = order.iter().filter_map(|id| tasks.remove(id)).collect();
+    update_task_indices(conn, &group_subtasks(ordered))?;
+
+    Ok(())
+}
+
+/// Puts the tasks in `order` (see `apply_task_order`) as one operation, named `operation` in the
+/// history, that `undo` can take back
+pub fn reorder_tasks_in_db(conn: &mut Connection, operation: &str, order: &[i32]) -> Result<(), ReorderError> {
+    let transaction = write_transaction(conn)?;
+    let history_id = record_history(&transaction, operation, "", &[])?;
+    record_task_order(&transaction, history_id)?;
+    apply_task_order(&transaction, order)?;
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Reorders the tasks as `reorder` leaves them, see `reorder_tasks_in_db`
+fn reorder_current_tasks(conn: &mut Connection, operation: &str, reorder: impl FnOnce(&mut Vec<Task>)) -> Result<(), ReorderError> {
+    let mut tasks = get_tasks_from_db_and_update_indices(conn)?;
+    reorder(&mut tasks);
+
+    reorder_tasks_in_db(conn, operation, &tasks.iter().map(|t| t.id.unwrap()).collect::<Vec<_>>())
+}
+
+pub fn reverse_tasks_in_db(conn: &mut Connection) -> Result<(), ReorderError> {
+    reorder_current_tasks(conn, "reverse", |tasks| tasks.reverse())
+}
+
+/// Puts the tasks in a random order. Unless `include_done` is set, done tasks keep their order
+/// below all the todo ones.
+pub fn shuffle_tasks_in_db(conn: &mut Connection, include_done: bool) -> Result<(), ReorderError> {
+    reorder_current_tasks(conn, "shuffle", |tasks| {
+        let mut rng = rand::thread_rng();
+
+        if include_done {
+            tasks.shuffle(&mut rng);
+            return;
+        }
+
+        let (mut todo, done): (Vec<Task>, Vec<Task>) = mem::take(tasks).into_iter().partition(|t| !t.done);
+        todo.shuffle(&mut rng);
+        *tasks = todo.into_iter().chain(done).collect();
+    })
+}
+
+pub enum SortUndo {
+    Restored,
+    NothingToUndo,
+    OrderChanged,
+}
+
+pub fn undo_sort_in_db(conn: &mut Connection) -> Result<SortUndo, ReorderError> {
+    let transaction = write_transaction(conn)?;
+
+    let last_order = match get_metadata(&transaction, "last_order")? {
+        Some(last_order) => last_order,
+        None => return Ok(SortUndo::NothingToUndo),
+    };
+
+    let (previous_order, sorted_order) = match last_order.split_once('|') {
+        Some((previous, sorted)) => (parse_order(previous), parse_order(sorted)),
+        None => return Ok(SortUndo::NothingToUndo),
+    };
+
+    if get_task_order(&transaction)? != sorted_order {
+        return Ok(SortUndo::OrderChanged);
+    }
+
+    apply_task_order(&transaction, &previous_order)?;
+    transaction.execute("DELETE FROM metadata WHERE key = 'last_order'", [])?;
+
+    transaction.commit()?;
+
+    Ok(SortUndo::Restored)
+}
+
+/// The subtasks of `tasks` that aren't among them
+fn subtasks_of(conn: &Connection, tasks: &[Task]) -> Result<Vec<Task>, Error> {
+    let ids: Vec<Option<i32>> = tasks.iter().map(|t| t.id).collect();
+
+    Ok(get_tasks_from_db(conn)?
+        .into_iter()
+        .filter(|t| t.parent_id.is_some() && ids.contains(&t.parent_id) && !ids.contains(&t.id))
+        .collect())
+}
+
+/// The tasks `remove_tasks_from_db` would remove, in index order, without changing anything
+pub fn get_tasks_to_remove_from_db(conn: &Connection, task_indices: &[i32], with_subtasks: bool) -> Result<Vec<Task>, Error> {
+    let mut tasks = get_tasks_at(conn, task_indices)?;
+    if with_subtasks {
+        tasks.extend(subtasks_of(conn, &tasks)?);
+    }
+    tasks.sort_by_key(|t| t.idx);
+
+    Ok(tasks)
+}
+
+/// Removes the tasks `select` picks once the write lock is held, along with their subtasks, or
+/// with `with_subtasks` off, leaves the subtasks behind as top-level tasks. Returns the removed
+/// tasks in index order.
+fn remove_tasks(
+    conn: &mut Connection,
+    operation: &str,
+    select: impl FnOnce(&Connection) -> Result<Vec<Task>, Error>,
+    with_subtasks: bool,
+) -> Result<Vec<Task>, Error> {
+    let transaction = write_transaction(conn)?;
+
+    let tasks = select(&transaction)?;
+    let subtasks = subtasks_of(&transaction, &tasks)?;
+
+    let (mut removed, promoted) = if with_subtasks {
+        (tasks.into_iter().chain(subtasks).collect(), Vec::new())
+    } else {
+        (tasks, subtasks)
+    };
+
+    if !removed.is_empty() {
+        let affected: Vec<Task> = removed.iter().chain(&promoted).cloned().collect();
+        record_history(&transaction, operation, &join_names(&removed), &affected)?;
+    }
+
+    for task in &promoted {
+        transaction.execute("UPDATE tasks SET parent_id = NULL WHERE id = ?1", [task.id])?;
+    }
+
+    // One statement, so subtasks and their parents leave together without breaking the foreign key
+    move_tasks_to_trash(&transaction, &ids_condition(&removed))?;
+
+    transaction.commit()?;
+
+    renormalize_task_indices(conn)?;
+
+    removed.sort_by_key(|t| t.idx);
+    Ok(removed)
+}
+
+/// Removes the tasks at `task_indices` as they are once the write lock is held, so a change made
+/// by another todoln run in the meantime can't make an index point at a different task. Returns
+/// the removed tasks in index order.
+pub fn remove_tasks_from_db(conn: &mut Connection, task_indices: &[i32], with_subtasks: bool) -> Result<Vec<Task>, Error> {
+    remove_tasks(conn, "remove", |conn| get_tasks_at(conn, task_indices), with_subtasks)
+}
+
+/// Removes the tasks with `task_ids`, and their subtasks too `with_subtasks`, recording them in
+/// the history under `operation`
+pub fn remove_tasks_by_id_from_db(conn: &mut Connection, operation: &str, task_ids: &[i32], with_subtasks: bool) -> Result<(), Error> {
+    remove_tasks(conn, operation, |conn| get_tasks_by_ids(conn, task_ids), with_subtasks)?;
+    Ok(())
+}
+
+pub fn delete_tasks_from_db(conn: &mut Connection) -> Result<(), Error> {
+    let transaction = write_transaction(conn)?;
+
+    let tasks = get_tasks_from_db(&transaction)?;
+    if !tasks.is_empty() {
+        record_history(&transaction, "reset", &join_names(&tasks), &tasks)?;
+    }
+
+    move_tasks_to_trash(&transaction, "true")?;
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// How many operations `undo` can walk back
+const HISTORY_LIMIT: i64 = 50;
+
+fn join_names(tasks: &[Task]) -> String {
+    tasks.iter().map(|t| t.name.as_str()).collect::<Vec<_>>().join(", ")
 }
 
-pub fn update_task_indices(conn: &Connection, tasks: &[Task]) -> Result<(), Error>{
-    conn.execute("UPDATE tasks SET idx = NULL", [])?;
+/// Records what `operation` is about to change so `undo` can reverse it: the full rows of the
+/// `affected` tasks as they are now, with their index, fields and dependencies. The other tasks
+/// keep their order, so it isn't recorded unless `record_task_order` is called too. Runs in the
+/// same transaction as the change. Returns the entry's id for `record_created_tasks`.
+fn record_history(conn: &Connection, operation: &str, summary: &str, affected: &[Task]) -> Result<i64, Error> {
+    conn.execute(
+        "INSERT INTO history (created_at, operation, summary, task_order, with_links) VALUES (datetime('now'), ?1, ?2, '', 1)",
+        params![operation, summary],
+    )?;
+    let history_id = conn.last_insert_rowid();
 
-    for (i, task) in tasks.iter().enumerate() {
-        conn.execute("UPDATE tasks SET idx = ?1 WHERE id = ?2", params![i as i32 + 1, task.id])?;
+    for task in affected {
+        conn.execute(
+            "INSERT INTO history_tasks (history_id, id, idx, name, done, completed_at, due_at, note, parent_id, recurrence, snoozed_until, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![history_id, task.id, task.idx, task.name, task.done, task.completed_at, task.due_at, task.note, task.parent_id, task.recurrence, task.snoozed_until, task.created_at],
+        )?;
+        conn.execute(
+            "INSERT INTO history_task_fields (history_id, task_id, key, value) SELECT ?1, task_id, key, value FROM task_fields WHERE task_id = ?2",
+            params![history_id, task.id],
+        )?;
+        conn.execute(
+            "INSERT INTO history_dependencies (history_id, task_id, blocker_id)
+            SELECT ?1, task_id, blocker_id FROM dependencies WHERE task_id = ?2 OR blocker_id = ?2",
+            params![history_id, task.id],
+        )?;
     }
 
+    // Entries are only ever added or undone at the top, so the newest ones have consecutive ids
+    for table in ["history_tasks", "history_task_fields", "history_dependencies"] {
+        conn.execute(&format!("DELETE FROM {} WHERE history_id <= ?1", table), [history_id - HISTORY_LIMIT])?;
+    }
+    conn.execute("DELETE FROM history WHERE id <= ?1", [history_id - HISTORY_LIMIT])?;
+
+    Ok(history_id)
+}
+
+/// Adds the order of every task to a history entry, for the operations that move tasks around
+/// without recording them as affected
+fn record_task_order(conn: &Connection, history_id: i64) -> Result<(), Error> {
+    conn.execute("UPDATE history SET task_order = ?1 WHERE id = ?2", params![format_order(&get_task_order(conn)?), history_id])?;
     Ok(())
 }
 
-pub fn shift_task_indices(conn: &mut Connection, index: &i32, size: &i32) -> Result<(), Error>  {
-    let mut stmt = conn.prepare("SELECT idx FROM tasks WHERE idx >= ?1")?;
-    let rows = stmt.query_map([index], |row| row.get(0))?;
-    
-    let mut indices: Vec<i32> = rows.map(|row| row.unwrap()).collect();
-    indices.sort_by(|a, b| b.cmp(a));
-    
+/// Adds the ids of tasks an operation created to its history entry, so `undo` deletes them again
+fn record_created_tasks(conn: &Connection, history_id: i64, task_ids: &[i32]) -> Result<(), Error> {
+    conn.execute("UPDATE history SET created_ids = ?1 WHERE id = ?2", params![format_order(task_ids), history_id])?;
+    Ok(())
+}
+
+pub struct HistoryEntry {
+    /// When the operation ran, in local time
+    pub created_at: String,
+    pub operation: String,
+    pub summary: String,
+}
+
+/// Recent operations that can be undone, newest first
+pub fn get_history_from_db(conn: &Connection) -> Result<Vec<HistoryEntry>, Error> {
+    let mut stmt = conn.prepare("SELECT datetime(created_at, 'localtime'), operation, summary FROM history ORDER BY id DESC")?;
+    let rows = stmt.query_map([], |row| Ok(HistoryEntry { created_at: row.get(0)?, operation: row.get(1)?, summary: row.get(2)? }))?;
+
+    let history: Vec<HistoryEntry> = rows.map(|row| row.unwrap()).collect();
+    Ok(history)
+}
+
+/// Reverses the most recent operation in the history and drops it from there. Returns what was
+/// undone, or `None` if the history is empty.
+pub fn undo_last_in_db(conn: &mut Connection) -> Result<Option<HistoryEntry>, Error> {
+    let transaction = write_transaction(conn)?;
+
+    let mut stmt = transaction.prepare(
+        "SELECT id, datetime(created_at, 'localtime'), operation, summary, task_order, created_ids, created_at, with_links FROM history ORDER BY id DESC LIMIT 1",
+    )?;
+    let mut rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            HistoryEntry { created_at: row.get(1)?, operation: row.get(2)?, summary: row.get(3)? },
+            row.get::<_, String>(4)?,
+            row.get::<_, String>(5)?,
+            row.get::<_, String>(6)?,
+            row.get::<_, bool>(7)?,
+        ))
+    })?;
+
+    let Some((history_id, entry, task_order, created_ids, created_at, with_links)) = rows.next().transpose()? else {
+        return Ok(None);
+    };
+    drop(rows);
     drop(stmt);
 
-    let transaction = conn.transaction()?;
-    for index_to_shift in indices.iter() {
+    for id in parse_order(&created_ids) {
+        move_tasks_to_trash(&transaction, &format!("id = {}", id))?;
+    }
+
+    transaction.execute(
+        "INSERT OR REPLACE INTO tasks (id, idx, name, done, completed_at, due_at, note, parent_id, recurrence, snoozed_until, created_at)
+        SELECT id, NULL, name, done, completed_at, due_at, note, parent_id, recurrence, snoozed_until, created_at FROM history_tasks WHERE history_id = ?1",
+        [history_id],
+    )?;
+
+    if with_links {
+        let affected = "SELECT id FROM history_tasks WHERE history_id = ?1";
+        transaction.execute(&format!("DELETE FROM task_fields WHERE task_id IN ({})", affected), [history_id])?;
+        transaction.execute(
+            "INSERT INTO task_fields (task_id, key, value) SELECT task_id, key, value FROM history_task_fields WHERE history_id = ?1",
+            [history_id],
+        )?;
+
+        transaction.execute(&format!("DELETE FROM dependencies WHERE task_id IN ({0}) OR blocker_id IN ({0})", affected), [history_id])?;
+        // Both ends of a dependency may have been recorded, and a task at the other end may be gone since
         transaction.execute(
-            "UPDATE tasks SET idx = idx + ?1 WHERE idx = ?2",
-            params![&size, &index_to_shift],
+            "INSERT OR IGNORE INTO dependencies (task_id, blocker_id)
+            SELECT task_id, blocker_id FROM history_dependencies
+            WHERE history_id = ?1 AND task_id IN (SELECT id FROM tasks) AND blocker_id IN (SELECT id FROM tasks)",
+            [history_id],
         )?;
     }
 
-    transaction.commit()?;
+    // Tasks the operation removed are back, so take them out of the trash again
+    transaction.execute(
+        "DELETE FROM trash WHERE deleted_at >= ?1 AND task_id IN (SELECT id FROM history_tasks WHERE history_id = ?2)",
+        params![created_at, history_id],
+    )?;
 
-    Ok(())
-}
+    let current_order = get_task_order(&transaction)?;
+    let order: Vec<i32> = if !task_order.is_empty() {
+        // Put the tasks back in their old order, with any that came from elsewhere since at the end
+        let previous_order = parse_order(&task_order);
+        previous_order.iter()
+            .filter(|id| current_order.contains(id))
+            .chain(current_order.iter().filter(|id| !previous_order.contains(id)))
+            .copied()
+            .collect()
+    } else {
+        // The other tasks kept their order, so put each recorded one back at its old index among them
+        let mut stmt = transaction.prepare("SELECT id, idx FROM history_tasks WHERE history_id = ?1 ORDER BY idx IS NULL, idx ASC")?;
+        let recorded: Vec<(i32, Option<i64>)> = stmt.query_map([history_id], |row| Ok((row.get(0)?, row.get(1)?)))?.collect::<Result<_, _>>()?;
 
-pub fn get_tasks_length(conn: &Connection) -> i32 {
-    match conn.query_row("SELECT COUNT(*) FROM tasks", (), |row| row.get::<_, i64>(0)) {
-        Ok(count) => {
-            if count > i32::MAX as i64 {
-                eprintln!("Warning: Count exceeds i32 range. Truncating to i32::MAX.");
-                i32::MAX
-            } else {
-                count as i32
-            }
-        },
-        Err(e) => {
-            panic!("Failed to get the length of tasks: {}", e);
+        let mut order: Vec<i32> = current_order.iter().copied().filter(|id| !recorded.iter().any(|(recorded_id, _)| recorded_id == id)).collect();
+        for (id, idx) in recorded {
+            let position = idx.map_or(order.len(), |idx| (idx.max(1) as usize - 1).min(order.len()));
+            order.insert(position, id);
         }
+        order
+    };
+
+    transaction.execute("UPDATE tasks SET idx = NULL", [])?;
+    for (i, id) in order.iter().enumerate() {
+        transaction.execute("UPDATE tasks SET idx = ?1 WHERE id = ?2", params![i as i64 + 1, id])?;
     }
-}
 
-fn add_task_to_db(conn: &mut Connection, task: &Task) {
-    match conn.execute(
-        "INSERT INTO tasks (name) VALUES (?1)",
-        params![&task.name],
-    ) {
-        Ok(_) => {},
-        Err(e) => { 
-            panic!("Failed to add task {}: {}", &task.name, e);
-        },
+    for table in ["history_tasks", "history_task_fields", "history_dependencies"] {
+        transaction.execute(&format!("DELETE FROM {} WHERE history_id = ?1", table), [history_id])?;
     }
+    transaction.execute("DELETE FROM history WHERE id = ?1", [history_id])?;
+
+    transaction.commit()?;
+
+    Ok(Some(entry))
 }
 
-pub fn add_tasks_to_db(conn: &mut Connection, tasks: &[Task]) {
-    for task in tasks {
-        add_task_to_db(conn, task);
+/// Copies the database open as `from` over the one open as `to` through SQLite, so changes still
+/// in the write-ahead log of either are taken into account and `to`'s other connections see the copy
+fn copy_database(from: &Connection, to: &mut Connection) -> Result<(), Error> {
+    to.busy_timeout(Duration::from_millis(config().busy_timeout.unwrap_or(5000)))?;
+
+    // All pages in one step, so a write to `from` halfway through can't make the copy start over
+    match Backup::new(from, to)?.step(-1)? {
+        StepResult::Done => Ok(()),
+        _ => Err(Error::SqliteFailure(ffi::Error::new(ffi::SQLITE_BUSY), None)),
     }
+}
 
-    match get_tasks_from_db_and_update_indices(conn) {
+pub fn backup_db(destination_path: &str) -> Result<(), FileError> {
+    let source_path = validate_file_path(&db_path(), false)?;
+    let destination_path = validate_file_path(Path::new(destination_path), true)?;
+
+    // Written next to the destination first, so a failed backup leaves an older one as it was
+    let copy_path = sidecar_path(&destination_path, ".partial");
+    match fs::remove_file(&copy_path) {
         Ok(_) => {},
-        Err(e) => {
-            panic!("Failed to update indices after adding tasks: {}", e);
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+        Err(source) => return Err(FileError::Remove { path: copy_path, source }),
+    }
+
+    let source = open_with_flags(&source_path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+        .map_err(|source| FileError::Database { path: source_path.clone(), source })?;
+    let copy = || -> Result<(), Error> {
+        let mut copy = Connection::open(&copy_path)?;
+        // The copy is encrypted with the same passphrase as the database
+        if is_encrypted(&source_path) {
+            copy.pragma_update(None, "key", passphrase())?;
         }
+        copy_database(&source, &mut copy)
     };
+    if let Err(source) = copy() {
+        fs::remove_file(&copy_path).ok();
+        return Err(FileError::Database { path: destination_path, source });
+    }
+
+    fs::rename(&copy_path, &destination_path).map_err(|source| FileError::Write { path: destination_path, source })
 }
 
-fn insert_task_to_db(conn: &mut Connection, task: &Task) {
-    match conn.execute(
-        "INSERT INTO tasks (idx, name) VALUES (?1, ?2)",
-        params![&task.idx, &task.name],
-    ) {
-        Ok(_) => {},
-        Err(e) => panic!("Failed to insert task {}: {}", &task.name, e),
-    }
+pub fn restore_db(backup_path: &str) -> Result<(), FileError> {
+    // Checked before the copy, so a file that isn't a task database never replaces the tasks
+    get_backup_task_count(backup_path)?;
+
+    let (backup, _) = open_backup(backup_path)?;
+    let source_path = validate_file_path(&db_path(), true)?;
+
+    // Written into the open database rather than over its file, which would leave the
+    // write-ahead log of the tasks being replaced to be played back over the restored ones
+    let mut conn = open_with_flags(&source_path, OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE)
+        .map_err(|source| FileError::Database { path: source_path.clone(), source })?;
+    copy_database(&backup, &mut conn).map_err(|source| FileError::Database { path: source_path, source })
 }
 
-pub fn insert_tasks_to_db(conn: &mut Connection, idx: &i32, tasks: &[Task]) {
-    match shift_task_indices(conn, idx, &(tasks.len() as i32)) {
-        Ok(()) => {},
-        Err(e) => panic!("Failed to shift indices when inserting tasks: {}", e)
-    }
+/// Opens the database backup at `backup_path` read-only, so it's left as it is, once it has been
+/// checked to hold a task list. Returns an error for any other kind of file.
+fn open_backup(backup_path: &str) -> Result<(Connection, PathBuf), FileError> {
+    let backup_path = validate_file_path(Path::new(backup_path), false)?;
+    let database_error = |source| FileError::Database { path: backup_path.clone(), source };
 
-    for task in tasks {
-        insert_task_to_db(conn, task);
-    }
+    let conn = open_with_flags(&backup_path, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(database_error)?;
+    // Every version of the schema has these columns
+    conn.prepare("SELECT id, idx, name, done FROM tasks LIMIT 0").map_err(database_error)?;
+
+    Ok((conn, backup_path))
 }
 
-pub fn get_tasks_from_db_and_update_indices(conn: &mut Connection) -> Result<Vec<Task>> {
-    let mut stmt = conn.prepare("SELECT id, name, done FROM tasks ORDER BY idx ASC")?;
+/// How many tasks the database backup at `backup_path` holds
+pub fn get_backup_task_count(backup_path: &str) -> Result<usize, FileError> {
+    let (conn, backup_path) = open_backup(backup_path)?;
+    conn.query_row("SELECT COUNT(*) FROM tasks", [], |row| row.get(0)).map_err(|source| FileError::Database { path: backup_path, source })
+}
+
+/// The name and done state of every task in the database backup at `backup_path`, in list order
+pub fn get_backup_tasks(backup_path: &str) -> Result<Vec<(String, bool)>, FileError> {
+    let (conn, backup_path) = open_backup(backup_path)?;
+
+    let read = || -> Result<Vec<(String, bool)>, Error> {
+        let mut stmt = conn.prepare(&format!("SELECT name, done FROM tasks ORDER BY {}", TASK_ORDER))?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    };
+    read().map_err(|source| FileError::Database { path: backup_path, source })
+}
+
+/// Drops what refers to the tasks matching the SQL `condition` before they are deleted: their
+/// custom fields, their dependencies either way round, and the parent of their subtasks, which
+/// become top-level tasks
+fn detach_tasks(conn: &Connection, condition: &str) -> Result<(), Error> {
+    conn.execute(&format!("DELETE FROM task_fields WHERE task_id IN (SELECT id FROM tasks WHERE {})", condition), [])?;
+    conn.execute(&format!("DELETE FROM time_entries WHERE task_id IN (SELECT id FROM tasks WHERE {})", condition), [])?;
+    conn.execute(
+        &format!(
+            "DELETE FROM dependencies WHERE task_id IN (SELECT id FROM tasks WHERE {0}) OR blocker_id IN (SELECT id FROM tasks WHERE {0})",
+            condition,
+        ),
+        [],
+    )?;
+    conn.execute(&format!("UPDATE tasks SET parent_id = NULL WHERE parent_id IN (SELECT id FROM tasks WHERE {})", condition), [])?;
+    Ok(())
+}
+
+/// Moves the tasks matching the SQL `condition` into the trash. Runs in the caller's transaction,
+/// so a task is never missing from both tables. Returns how many tasks were moved.
+fn move_tasks_to_trash(conn: &Connection, condition: &str) -> Result<usize, Error> {
+    conn.execute(
+        &format!(
+            "INSERT INTO trash (task_id, name, done, completed_at, due_at, note, parent_id, recurrence, snoozed_until, created_at, deleted_at)
+            SELECT id, name, done, completed_at, due_at, note, parent_id, recurrence, snoozed_until, created_at, datetime('now') FROM tasks WHERE {} ORDER BY idx ASC",
+            condition,
+        ),
+        [],
+    )?;
+    detach_tasks(conn, condition)?;
+    conn.execute(&format!("DELETE FROM tasks WHERE {}", condition), [])
+}
+
+/// Trashed tasks, most recently removed first, with when they were removed
+pub fn get_trash_from_db(conn: &Connection) -> Result<Vec<(Task, String)>, Error> {
+    let mut stmt = conn.prepare("SELECT task_id, name, done, completed_at, due_at, note, deleted_at FROM trash ORDER BY deleted_at DESC, id DESC")?;
     let rows = stmt.query_map([], |row| {
-        Ok(Task {
+        let task = Task {
             id: row.get(0)?,
-            idx: None,
             name: row.get(1)?,
             done: row.get(2)?,
-        })
+            completed_at: row.get(3)?,
+            due_at: row.get(4)?,
+            note: row.get(5)?,
+            ..Default::default()
+        };
+        Ok((task, row.get(6)?))
     })?;
 
-    let tasks: Vec<Task> = rows.map(|row| row.unwrap()).collect();
+    let trash: Vec<(Task, String)> = rows.map(|row| row.unwrap()).collect();
+    Ok(trash)
+}
 
-    match update_task_indices(conn, &tasks) {
-        Ok(_) => {},
-        Err(e) =>  panic!("Failed to update task indices: {}", e)
+/// Puts the `position`th task of `get_trash_from_db` back at the end of the task list, under its
+/// old id if that's still free. Returns its name, or `None` if there's no such task in the trash.
+pub fn restore_task_from_trash_in_db(conn: &mut Connection, position: usize) -> Result<Option<String>, Error> {
+    if position == 0 {
+        return Ok(None);
     }
-    
-    let mut stmt = conn.prepare("SELECT id, idx, name, done FROM tasks ORDER BY idx ASC")?;
-    let rows = stmt.query_map([], |row| {
-        Ok(Task {
-            id: row.get(0)?,
-            idx: row.get(1)?,
-            name: row.get(2)?,
-            done: row.get(3)?,
-        })
-    })?;
 
-    let tasks: Vec<Task> = rows.map(|row| row.unwrap()).collect();
-    Ok(tasks)
+    let transaction = write_transaction(conn)?;
+
+    let mut stmt = transaction.prepare("SELECT id, task_id, name FROM trash ORDER BY deleted_at DESC, id DESC LIMIT 1 OFFSET ?1")?;
+    let mut rows = stmt.query_map([position as i64 - 1], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i32>(1)?, row.get::<_, String>(2)?)))?;
+
+    let Some((trash_id, task_id, name)) = rows.next().transpose()? else {
+        return Ok(None);
+    };
+    drop(rows);
+    drop(stmt);
+
+    let history_id = record_history(&transaction, "restore", &name, &[])?;
+
+    transaction.execute(
+        "INSERT INTO tasks (id, idx, name, done, completed_at, due_at, note, parent_id, recurrence, snoozed_until, created_at)
+        SELECT CASE WHEN EXISTS (SELECT 1 FROM tasks WHERE id = ?2) THEN NULL ELSE ?2 END,
+            (SELECT COALESCE(MAX(idx), 0) + 1 FROM tasks), name, done, completed_at, due_at, note,
+            (SELECT id FROM tasks WHERE id = trash.parent_id), recurrence, snoozed_until, created_at
+        FROM trash WHERE id = ?1",
+        params![trash_id, task_id],
+    )?;
+    record_created_tasks(&transaction, history_id, &[transaction.last_insert_rowid() as i32])?;
+    transaction.execute("DELETE FROM trash WHERE id = ?1", [trash_id])?;
+
+    transaction.commit()?;
+
+    Ok(Some(name))
 }
 
-pub fn find_tasks_from_db(conn: &mut Connection, query: &str) -> Result<Vec<Task>, Error> {
-    let mut stmt = conn.prepare("SELECT id, name, done FROM tasks WHERE name LIKE ?1 ORDER BY idx ASC")?;
-    let pattern = format!("%{}%", query);
+/// Permanently deletes trashed tasks removed more than `older_than` seconds ago, or all of
+/// them with `None`. Returns how many were deleted.
+pub fn empty_trash_in_db(conn: &Connection, older_than: Option<i64>) -> Result<usize, Error> {
+    conn.execute(
+        "DELETE FROM trash WHERE ?1 IS NULL OR deleted_at <= datetime('now', ?2)",
+        params![older_than, format!("-{} seconds", older_than.unwrap_or(0))],
+    )
+}
 
-    let rows = stmt.query_map([&pattern], |row| {
-        Ok(Task {
-            id: row.get(0)?,
-            idx: None,
-            name: row.get(1)?,
-            done: row.get(2)?,
-        })
-    })?;
+fn auto_empty_trash(conn: &Connection, after: i64) -> Result<usize, Error> {
+    let today: String = conn.query_row("SELECT date('now', 'localtime')", [], |row| row.get(0))?;
 
-    let mut tasks_found: Vec<Task> = rows.map(|row| row.unwrap()).collect();
-    for (i, task) in tasks_found.iter_mut().enumerate() {
-        task.idx = Some(i as i32 + 1);
+    if get_metadata(conn, "last_trash_purge")?.as_deref() == Some(today.as_str()) {
+        return Ok(0);
     }
-    
-    Ok(tasks_found)
-}
 
-pub fn edit_task_in_db(conn: &mut Connection, task_index: &i32, new_name: &String) -> Result<(), Error> {
-    conn.execute("UPDATE tasks SET name = ?1 WHERE idx = ?2", params![new_name, task_index])?;
-    Ok(())
+    let count = empty_trash_in_db(conn, Some(after))?;
+    set_metadata(conn, "last_trash_purge", &today)?;
+
+    Ok(count)
 }
 
-pub fn mark_task_in_db_as_done(conn: &mut Connection, task_index: &i32) -> Result<(), Error> {
-    conn.execute("UPDATE tasks SET done = true WHERE idx = ?1", [task_index])?;
+/// Applies what `sync-md` or `sync` (the `operation`) read from a file, as one change `undo` takes back
+pub fn apply_sync_changes_in_db(conn: &mut Connection, operation: &str, changes: &[SyncChange]) -> Result<(), Error> {
+    let transaction = write_transaction(conn)?;
+
+    let mut affected = Vec::new();
+    for change in changes {
+        if let SyncChange::SetDone { id, .. } | SyncChange::Rename { id, .. } | SyncChange::Remove { id, .. } = change {
+            affected.extend(get_task_by_id(&transaction, id)?);
+        }
+    }
+    let history_id = record_history(&transaction, operation, &format!("{} change(s)", changes.len()), &affected)?;
+
+    let mut created_ids = Vec::new();
+    for change in changes {
+        match change {
+            SyncChange::Add { name, done } => {
+                transaction.execute(
+                    "INSERT INTO tasks (idx, name, done, completed_at, created_at)
+                    VALUES ((SELECT COALESCE(MAX(idx), 0) + 1 FROM tasks), ?1, ?2, CASE WHEN ?2 THEN datetime('now') END, datetime('now'))",
+                    params![name, done],
+                )?;
+                created_ids.push(transaction.last_insert_rowid() as i32);
+            }
+            SyncChange::SetDone { id, done, .. } => {
+                transaction.execute(
+                    "UPDATE tasks SET done = ?1, completed_at = CASE WHEN ?1 THEN datetime('now') END WHERE id = ?2",
+                    params![done, id],
+                )?;
+            }
+            SyncChange::Rename { id, name, .. } => {
+                transaction.execute("UPDATE tasks SET name = ?1 WHERE id = ?2", params![name, id])?;
+            }
+            SyncChange::Remove { id, .. } => {
+                move_tasks_to_trash(&transaction, &format!("id = {}", id))?;
+            }
+        }
+    }
+
+    created_ids.extend(respawn_recurring_tasks(&transaction)?);
+    record_created_tasks(&transaction, history_id, &created_ids)?;
+    transaction.commit()?;
+
+    get_tasks_from_db_and_update_indices(conn)?;
+
     Ok(())
 }
 
-pub fn sort_tasks_in_db(conn: &mut Connection) -> Result<(), Error> {
-    let mut stmt = conn.prepare("SELECT id, idx, name, done FROM tasks ORDER BY done ASC, idx ASC")?;
-    let rows = stmt.query_map([], |row| {
-        Ok(Task {
-            id: row.get(0)?,
-            idx: row.get(1)?,
-            name: row.get(2)?,
-            done: row.get(3)?,
-        })
-    })?;
+/// Makes the task list match `edited`, the whole list as saved from `edit-all`: tasks with an id
+/// take its name, done state and position, ones without are added and tasks left out are moved to
+/// the trash. Runs as one operation that `undo` can take back.
+pub fn apply_edit_all_in_db(conn: &mut Connection, edited: &[EditedTask], summary: &str) -> Result<(), ReorderError> {
+    let transaction = write_transaction(conn)?;
 
-    let tasks: Vec<Task> = rows.map(|row| row.unwrap()).collect();
+    let current = get_tasks_from_db(&transaction)?;
+    let history_id = record_history(&transaction, "edit-all", summary, &current)?;
 
-    conn.execute("UPDATE tasks SET idx = NULL", [])?;
+    let kept: HashSet<i32> = edited.iter().filter_map(|t| t.id).collect();
+    for task in current.iter().filter(|t| !kept.contains(&t.id.unwrap())) {
+        move_tasks_to_trash(&transaction, &format!("id = {}", task.id.unwrap()))?;
+    }
+
+    let mut order = Vec::new();
+    let mut created_ids = Vec::new();
+    for task in edited {
+        let id = match task.id {
+            Some(id) => {
+                transaction.execute(
+                    "UPDATE tasks SET name = ?1, done = ?2, completed_at = CASE WHEN ?2 THEN COALESCE(completed_at, datetime('now')) END WHERE id = ?3",
+                    params![task.name, task.done, id],
+                )?;
+                id
+            }
+            None => {
+                transaction.execute(
+                    "INSERT INTO tasks (idx, name, done, completed_at, created_at)
+                    VALUES ((SELECT COALESCE(MAX(idx), 0) + 1 FROM tasks), ?1, ?2, CASE WHEN ?2 THEN datetime('now') END, datetime('now'))",
+                    params![task.name, task.done],
+                )?;
+                let id = transaction.last_insert_rowid() as i32;
+                created_ids.push(id);
+                id
+            }
+        };
+        order.push(id);
+    }
+
+    apply_task_order(&transaction, &order)?;
+
+    created_ids.extend(respawn_recurring_tasks(&transaction)?);
+    record_created_tasks(&transaction, history_id, &created_ids)?;
+    transaction.commit()?;
+
+    Ok(())
+}
 
-    let (done_tasks, not_done_tasks): (Vec<_>, Vec<_>) = tasks.into_iter().partition(|task| task.done);
+/// Applies the decisions of a `review` in one transaction, recorded as a single history entry
+pub fn apply_review_in_db(conn: &mut Connection, decisions: &[ReviewDecision]) -> Result<(), Error> {
+    let transaction = write_transaction(conn)?;
 
-    for (i, task) in not_done_tasks.iter().enumerate() {
-        conn.execute("UPDATE tasks SET idx = ?1 WHERE id = ?2", params![i as i32 + 1, task.id])?;
+    let mut affected = Vec::new();
+    for decision in decisions {
+        let (ReviewDecision::Done { id, .. } | ReviewDecision::Remove { id, .. } | ReviewDecision::Snooze { id, .. } | ReviewDecision::Rename { id, .. }) = decision;
+        affected.extend(get_task_by_id(&transaction, id)?);
+
+        // Subtasks of removed tasks become top-level tasks, as with `remove`
+        if let ReviewDecision::Remove { id, .. } = decision {
+            affected.extend(get_tasks_from_db(&transaction)?.into_iter().filter(|t| t.parent_id == Some(*id)));
+        }
     }
+    let history_id = record_history(&transaction, "review", &format!("{} change(s)", decisions.len()), &affected)?;
 
-    for (i, task) in done_tasks.iter().enumerate() {
-        conn.execute("UPDATE tasks SET idx = ?1 WHERE id = ?2", params![i as i32 + not_done_tasks.len() as i32 + 1, task.id])?;
+    for decision in decisions {
+        match decision {
+            ReviewDecision::Done { id, .. } => {
+                transaction.execute("UPDATE tasks SET done = 1, completed_at = datetime('now') WHERE id = ?1", [id])?;
+            }
+            ReviewDecision::Remove { id, .. } => {
+                transaction.execute("UPDATE tasks SET parent_id = NULL WHERE parent_id = ?1", [id])?;
+                move_tasks_to_trash(&transaction, &format!("id = {}", id))?;
+            }
+            ReviewDecision::Snooze { id, due_at, .. } => {
+                transaction.execute("UPDATE tasks SET due_at = ?1 WHERE id = ?2", params![due_at, id])?;
+            }
+            ReviewDecision::Rename { id, new_name, .. } => {
+                transaction.execute("UPDATE tasks SET name = ?1 WHERE id = ?2", params![new_name, id])?;
+            }
+        }
     }
 
+    let created_ids = respawn_recurring_tasks(&transaction)?;
+    record_created_tasks(&transaction, history_id, &created_ids)?;
+
+    transaction.commit()?;
+
+    get_tasks_from_db_and_update_indices(conn)?;
+
     Ok(())
 }
 
-pub fn remove_task_from_db(conn: &mut Connection, task_index: &i32) -> Result<(), Error> {
-    conn.execute("DELETE FROM tasks WHERE idx = ?1", params![task_index])?;
+pub fn record_usage_in_db(conn: &Connection, command: &str) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO usage (command, day, count) VALUES (?1, date('now', 'localtime'), 1)
+        ON CONFLICT (command, day) DO UPDATE SET count = count + 1",
+        [command],
+    )?;
     Ok(())
 }
 
-pub fn delete_tasks_from_db(conn: &mut Connection) -> Result<(), Error> {
-    conn.execute("DELETE FROM tasks", ())?;
+pub fn get_usage_from_db(conn: &Connection) -> Result<Vec<(String, i64, i64)>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT command, SUM(count), SUM(CASE WHEN day = date('now', 'localtime') THEN count ELSE 0 END)
+        FROM usage GROUP BY command ORDER BY SUM(count) DESC, command ASC",
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+
+    let usage: Vec<(String, i64, i64)> = rows.map(|row| row.unwrap()).collect();
+    Ok(usage)
+}
+
+/// Records a finished focus session that lasted `duration` seconds and the tasks completed in it
+pub fn log_session_in_db(conn: &mut Connection, duration: i64, completed: &[Task]) -> Result<(), Error> {
+    let transaction = write_transaction(conn)?;
+
+    transaction.execute(
+        "INSERT INTO sessions (started_at, duration) VALUES (datetime('now', ?1), ?2)",
+        params![format!("-{} seconds", duration), duration],
+    )?;
+    let session_id = transaction.last_insert_rowid();
+
+    for task in completed {
+        transaction.execute("INSERT INTO session_tasks (session_id, name) VALUES (?1, ?2)", params![session_id, task.name])?;
+    }
+
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Past focus sessions, newest first, as (started at in local time, duration in seconds, completed task names)
+pub fn get_sessions_from_db(conn: &Connection) -> Result<Vec<(String, i64, Vec<String>)>, Error> {
+    let mut stmt = conn.prepare("SELECT id, datetime(started_at, 'localtime'), duration FROM sessions ORDER BY started_at DESC, id DESC")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?)))?;
+    let sessions: Vec<(i64, String, i64)> = rows.map(|row| row.unwrap()).collect();
+
+    let mut stmt = conn.prepare("SELECT name FROM session_tasks WHERE session_id = ?1 ORDER BY rowid ASC")?;
+    let mut history = Vec::new();
+
+    for (id, started_at, duration) in sessions {
+        let names = stmt.query_map([id], |row| row.get(0))?.map(|row| row.unwrap()).collect();
+        history.push((started_at, duration, names));
+    }
+
+    Ok(history)
+}
+
+pub fn clear_usage_in_db(conn: &Connection) -> Result<(), Error> {
+    conn.execute("DELETE FROM usage", ())?;
     Ok(())
 }
 
-pub fn backup_db(destination_path: &str) -> io::Result<()> {
-    let source_path = data_local_dir().unwrap_or_default().join("TodoLn").join("todoln.db");
+/// The file contents `sync` last wrote to `file` (an absolute path), or `None` before the first sync
+pub fn get_sync_state_from_db(conn: &Connection, file: &str) -> Result<Option<String>, Error> {
+    get_metadata(conn, &format!("sync:{}", file))
+}
+
+pub fn set_sync_state_in_db(conn: &Connection, file: &str, contents: &str) -> Result<(), Error> {
+    set_metadata(conn, &format!("sync:{}", file), contents)
+}
+
+fn get_metadata(conn: &Connection, key: &str) -> Result<Option<String>, Error> {
+    let mut stmt = conn.prepare("SELECT value FROM metadata WHERE key = ?1")?;
+    let mut rows = stmt.query_map([key], |row| row.get(0))?;
 
-    fs::copy(source_path, destination_path)?;
+    rows.next().transpose()
+}
 
+fn set_metadata(conn: &Connection, key: &str, value: &str) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO metadata (key, value) VALUES (?1, ?2) ON CONFLICT (key) DO UPDATE SET value = ?2",
+        params![key, value],
+    )?;
     Ok(())
 }
 
-pub fn restore_db(backup_path: &str) -> io::Result<()> {
-    let source_path = data_local_dir().unwrap_or_default().join("TodoLn").join("todoln.db");
+fn archive_done_tasks(conn: &Connection, older_than: Option<i64>) -> Result<usize, Error> {
+    let condition = match older_than {
+        Some(seconds) => format!("done AND completed_at <= datetime('now', '-{} seconds')", seconds),
+        None => String::from("done"),
+    };
+
+    conn.execute(
+        &format!(
+            "INSERT INTO archive (name, completed_at, archived_at)
+            SELECT name, completed_at, datetime('now') FROM tasks WHERE {} ORDER BY idx ASC",
+            condition,
+        ),
+        [],
+    )?;
+    detach_tasks(conn, &condition)?;
+    conn.execute(&format!("DELETE FROM tasks WHERE {}", condition), [])
+}
+
+/// Moves done tasks into the archive, optionally only those completed more than `older_than` seconds ago
+pub fn archive_done_tasks_in_db(conn: &mut Connection, older_than: Option<i64>) -> Result<usize, Error> {
+    let transaction = write_transaction(conn)?;
+    let count = archive_done_tasks(&transaction, older_than)?;
+    transaction.commit()?;
+
+    renormalize_task_indices(conn)?;
+
+    Ok(count)
+}
+
+fn auto_archive_done_tasks(conn: &mut Connection, after: i64) -> Result<usize, Error> {
+    let today: String = conn.query_row("SELECT date('now', 'localtime')", [], |row| row.get(0))?;
 
-    fs::copy(backup_path, &source_path)?;
+    if get_metadata(conn, "last_auto_archive")?.as_deref() == Some(today.as_str()) {
+        return Ok(0);
+    }
+
+    let count = archive_done_tasks_in_db(conn, Some(after))?;
+    set_metadata(conn, "last_auto_archive", &today)?;
+
+    Ok(count)
+}
+
+pub fn get_tasks_completed_today_from_db(conn: &Connection) -> Result<Vec<Task>, Error> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM tasks WHERE done AND date(completed_at, 'localtime') = date('now', 'localtime') ORDER BY idx ASC",
+        TASK_COLUMNS,
+    ))?;
+    let rows = stmt.query_map([], task_from_row)?;
+
+    let tasks: Vec<Task> = rows.map(|row| row.unwrap()).collect();
+    Ok(tasks)
+}
+
+/// Archives or clears every done task and/or sorts what is left, all in one transaction so a
+/// failure part way leaves the list untouched. Returns how many done tasks were moved away.
+pub fn wrapup_tasks_in_db(conn: &mut Connection, done_action: Option<WrapupDone>, sort: bool) -> Result<usize, ReorderError> {
+    let transaction = write_transaction(conn)?;
+
+    let count = match done_action {
+        Some(WrapupDone::Archive) => archive_done_tasks(&transaction, None)?,
+        Some(WrapupDone::Clear) => move_tasks_to_trash(&transaction, "done")?,
+        None => 0,
+    };
+
+    if sort {
+        sort_tasks(&transaction, SortKey::Done, false)?;
+    } else if count > 0 {
+        let tasks = group_subtasks(get_tasks_from_db(&transaction)?);
+        update_task_indices(&transaction, &tasks)?;
+    }
+
+    transaction.commit()?;
 
-    match Connection::open(source_path.clone()) {
+    Ok(count)
+}
+
+/// Writes a consistent copy of the open database to `destination`, including changes still in the WAL
+pub fn snapshot_db(conn: &Connection, destination: &Path) -> Result<(), FileError> {
+    match conn.execute("VACUUM INTO ?1", [destination.to_string_lossy()]) {
         Ok(_) => Ok(()),
-        Err(e) => {
-            fs::remove_file(&source_path).ok();
+        Err(e) => Err(FileError::Database { path: destination.to_path_buf(), source: e }),
+    }
+}
+
+/// Archived tasks, most recently archived first, optionally only those completed in `completed_in`
+pub fn get_archived_tasks_from_db(conn: &Connection, completed_in: Option<Period>) -> Result<Vec<(String, Option<String>)>, Error> {
+    let format = |t: DateTime<Utc>| t.format("%Y-%m-%d %H:%M:%S").to_string();
+    let (start, end) = completed_in.map(|(start, end)| (format(start), format(end))).unzip();
+
+    let mut stmt = conn.prepare(
+        "SELECT name, datetime(completed_at, 'localtime') FROM archive
+        WHERE ?1 IS NULL OR (completed_at >= ?1 AND completed_at < ?2)
+        ORDER BY archived_at DESC, id DESC"
+    )?;
+    let rows = stmt.query_map(params![start, end], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+    let archived: Vec<(String, Option<String>)> = rows.map(|row| row.unwrap()).collect();
+    Ok(archived)
+}
+
+/// Every completed task's name and completion time (UTC): the ones still in the list, the
+/// archived ones and the ones cleared into the trash
+const COMPLETED_TASKS: &str = "(
+    SELECT name, completed_at FROM tasks WHERE done AND completed_at IS NOT NULL
+    UNION ALL SELECT name, completed_at FROM archive WHERE completed_at IS NOT NULL
+    UNION ALL SELECT name, completed_at FROM trash WHERE done AND completed_at IS NOT NULL
+)";
+
+/// Every completed task with when it was completed in local time, newest first: the ones still in
+/// the list, the archived ones and the ones cleared into the trash. With `since`, only those
+/// completed from then on.
+pub fn get_completion_log_from_db(conn: &Connection, since: Option<DateTime<Utc>>) -> Result<Vec<(String, NaiveDateTime)>, Error> {
+    let since = since.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string());
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT name, datetime(completed_at, 'localtime') FROM {}
+        WHERE ?1 IS NULL OR completed_at >= ?1
+        ORDER BY completed_at DESC",
+        COMPLETED_TASKS,
+    ))?;
+    let rows = stmt.query_map([since], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+
+    let mut completed = Vec::new();
+    for row in rows {
+        let (name, completed_at) = row?;
+        if let Ok(completed_at) = NaiveDateTime::parse_from_str(&completed_at, "%Y-%m-%d %H:%M:%S") {
+            completed.push((name, completed_at));
+        }
+    }
+
+    Ok(completed)
+}
+
+/// How many tasks were completed on each local day that had any, newest day first
+pub fn get_completions_per_day_from_db(conn: &Connection) -> Result<Vec<(NaiveDate, i64)>, Error> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT date(completed_at, 'localtime') AS day, COUNT(*) FROM {} GROUP BY day ORDER BY day DESC",
+        COMPLETED_TASKS,
+    ))?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
 
-            Err(io::Error::new(io::ErrorKind::Other, format!("Failed to open the database: {}", e)))
+    let mut days = Vec::new();
+    for row in rows {
+        let (day, count) = row?;
+        if let Ok(day) = NaiveDate::parse_from_str(&day, "%Y-%m-%d") {
+            days.push((day, count));
         }
     }
+
+    Ok(days)
+}
+
+#[derive(Default)]
+pub struct DbProblems {
+    pub integrity_errors: Vec<String>,
+    pub foreign_key_violations: i64,
+    pub null_indices: Vec<Task>,
+    /// Tasks whose index is below 1 or too large to address, with that index
+    pub out_of_range_indices: Vec<(Task, i64)>,
+    pub duplicate_indices: Vec<i64>,
+    pub missing_indices: i64,
+    pub exact_duplicates: Vec<Task>,
+}
+
+impl DbProblems {
+    pub fn is_empty(&self) -> bool {
+        self.integrity_errors.is_empty()
+            && self.foreign_key_violations == 0
+            && self.null_indices.is_empty()
+            && self.out_of_range_indices.is_empty()
+            && self.duplicate_indices.is_empty()
+            && self.missing_indices == 0
+            && self.exact_duplicates.is_empty()
+    }
+}
+
+/// SQL for all of the fields of the task `alias` as one string, to compare them in a single test
+fn task_fields_signature(alias: &str) -> String {
+    format!(
+        "SELECT group_concat(key || char(31) || value, char(30)) FROM (SELECT key, value FROM task_fields WHERE task_id = {}.id ORDER BY key)",
+        alias,
+    )
+}
+
+/// SQL for the ids of the tasks blocking the task `alias` as one string
+fn blockers_signature(alias: &str) -> String {
+    format!(
+        "SELECT group_concat(blocker_id) FROM (SELECT blocker_id FROM dependencies WHERE task_id = {}.id ORDER BY blocker_id)",
+        alias,
+    )
+}
+
+pub fn check_db(conn: &Connection) -> Result<DbProblems, Error> {
+    let mut problems = DbProblems::default();
+
+    let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    problems.integrity_errors = rows.collect::<Result<Vec<_>, _>>()?.into_iter().filter(|message| message != "ok").collect();
+
+    problems.foreign_key_violations = conn.query_row("SELECT COUNT(*) FROM pragma_foreign_key_check", [], |row| row.get(0))?;
+
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM tasks WHERE idx IS NULL ORDER BY id ASC", TASK_COLUMNS))?;
+    let rows = stmt.query_map([], task_from_row)?;
+    problems.null_indices = rows.collect::<Result<_, _>>()?;
+
+    let mut stmt = conn.prepare(&format!("SELECT {}, idx FROM tasks WHERE idx NOT BETWEEN 1 AND 2147483647 ORDER BY id ASC", TASK_COLUMNS))?;
+    let rows = stmt.query_map([], |row| Ok((task_from_row(row)?, row.get(11)?)))?;
+    problems.out_of_range_indices = rows.collect::<Result<_, _>>()?;
+
+    let mut stmt = conn.prepare("SELECT idx FROM tasks WHERE idx IS NOT NULL GROUP BY idx HAVING COUNT(*) > 1 ORDER BY idx ASC")?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    problems.duplicate_indices = rows.collect::<Result<_, _>>()?;
+
+    // Every position in 1..=MAX(idx) that no task occupies
+    problems.missing_indices = conn.query_row(
+        "SELECT COALESCE(MAX(idx), 0) - COUNT(DISTINCT idx) FROM tasks WHERE idx BETWEEN 1 AND 2147483647",
+        [],
+        |row| row.get(0),
+    )?;
+
+    // A row is only an exact duplicate, left behind by an interrupted insert, if an earlier one
+    // matches it in every column but the id and index, has the same fields and blockers, and
+    // nothing refers to it. Tasks that merely share a name are the user's and left to `dedupe`.
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM tasks AS t
+        WHERE EXISTS (
+            SELECT 1 FROM tasks AS o
+            WHERE o.id < t.id
+                AND o.name IS t.name AND o.done IS t.done AND o.completed_at IS t.completed_at
+                AND o.due_at IS t.due_at AND o.note IS t.note AND o.parent_id IS t.parent_id
+                AND o.recurrence IS t.recurrence AND o.snoozed_until IS t.snoozed_until AND o.created_at IS t.created_at
+                AND ({fields}) IS ({fields_t})
+                AND ({blockers}) IS ({blockers_t})
+        )
+            AND NOT EXISTS (SELECT 1 FROM tasks WHERE parent_id = t.id)
+            AND NOT EXISTS (SELECT 1 FROM dependencies WHERE blocker_id = t.id)
+            AND NOT EXISTS (SELECT 1 FROM time_entries WHERE task_id = t.id)
+        ORDER BY idx ASC",
+        TASK_COLUMNS.split(", ").map(|column| format!("t.{}", column)).collect::<Vec<_>>().join(", "),
+        fields = task_fields_signature("o"),
+        fields_t = task_fields_signature("t"),
+        blockers = blockers_signature("o"),
+        blockers_t = blockers_signature("t"),
+    ))?;
+    let rows = stmt.query_map([], task_from_row)?;
+    problems.exact_duplicates = rows.collect::<Result<_, _>>()?;
+
+    Ok(problems)
+}
+
+pub fn repair_db(conn: &mut Connection, problems: &DbProblems) -> Result<(), Error> {
+    let transaction = write_transaction(conn)?;
+
+    for task in &problems.exact_duplicates {
+        let condition = format!("id = {}", task.id.unwrap());
+        detach_tasks(&transaction, &condition)?;
+        transaction.execute(&format!("DELETE FROM tasks WHERE {}", condition), [])?;
+    }
+
+    let tasks = get_tasks_from_db(&transaction)?;
+    update_task_indices(&transaction, &tasks)?;
+
+    transaction.commit()?;
+
+    conn.execute_batch("VACUUM")?;
+
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,72 @@
+use clap::Parser;
+
+use crate::commands::Cli;
+use crate::database::start_demo;
+use crate::run_command;
+use crate::utils::{print_error, styled, Style};
+
+/// The example list `demo` starts from. Dates are relative to now so the output reads the same on
+/// any day.
+const SEED: &str = "
+    INSERT INTO tasks (id, idx, name, done, completed_at, due_at, note, parent_id, created_at) VALUES
+        (1, 1, 'Write the quarterly report for the finance team, with the updated forecast charts', 0, NULL, datetime('now', '+3 days'), 'The sales figures are in the shared drive', NULL, datetime('now', '-6 days')),
+        (2, 2, 'Draft the outline', 1, datetime('now', '-2 days'), NULL, NULL, 1, datetime('now', '-6 days')),
+        (3, 3, 'Collect the figures from sales', 0, NULL, NULL, NULL, 1, datetime('now', '-6 days')),
+        (4, 4, 'Make the charts', 0, NULL, NULL, NULL, 1, datetime('now', '-6 days')),
+        (5, 5, 'Renew passport', 0, NULL, datetime('now', '+14 days'), NULL, NULL, datetime('now', '-20 days')),
+        (6, 6, 'Call the plumber about the kitchen sink', 1, datetime('now', '-1 days'), NULL, NULL, NULL, datetime('now', '-3 days')),
+        (7, 7, 'Buy groceries', 0, NULL, NULL, NULL, NULL, datetime('now', '-1 days')),
+        (8, 8, 'Read \"The Pragmatic Programmer\"', 0, NULL, NULL, NULL, NULL, datetime('now', '-40 days'));
+    UPDATE tasks SET recurrence = 'sat' WHERE id = 7;
+    INSERT INTO task_fields (task_id, key, value) VALUES
+        (1, 'priority', 'high'),
+        (5, 'priority', 'medium'),
+        (7, 'list', 'home');
+";
+
+/// The commands `demo` runs, in order, each as it would be typed after `todoln`
+const SCRIPT: &[&[&str]] = &[
+    &["list", "all"],
+    &["add", "Book a dentist appointment"],
+    &["done", "3"],
+    &["find", "report"],
+    &["set", "8", "priority=low"],
+    &["sort", "priority"],
+    &["list", "todo"],
+    &["due"],
+    &["undo"],
+    &["list", "all"],
+];
+
+/// How `args` would be typed in a shell, quoting the ones with spaces
+fn command_line(args: &[&str]) -> String {
+    let quoted: Vec<String> = args.iter()
+        .map(|arg| if arg.contains(' ') { format!("\"{}\"", arg) } else { arg.to_string() })
+        .collect();
+
+    format!("$ todoln {}", quoted.join(" "))
+}
+
+/// Seeds an in-memory database with the example list and runs the script against it, printing
+/// each command line before its output. The task database is never opened.
+pub fn run() {
+    let conn = start_demo();
+
+    if let Err(e) = conn.execute_batch(SEED) {
+        print_error(&format!("Failed to set up the example tasks: {}", e));
+        return;
+    }
+
+    for (i, args) in SCRIPT.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("{}", styled(Style::Bold, &command_line(args)));
+        println!();
+
+        match Cli::try_parse_from(["todoln"].iter().chain(args.iter())) {
+            Ok(cli) => run_command(&cli.command),
+            Err(e) => print_error(&format!("Failed to run `{}`: {}", command_line(args), e)),
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use std::io::{self, IsTerminal, Write};
+
+use crossterm::cursor::{MoveTo, MoveToColumn};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::terminal::{self, Clear, ClearType, DisableLineWrap, EnableLineWrap, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::utils::{styled, Style};
+
+/// Text being edited plus a cursor, kept as a byte offset that always sits on a grapheme boundary
+pub struct LineBuffer {
+    text: String,
+    cursor: usize,
+}
+
+impl LineBuffer {
+    pub fn new(text: &str) -> LineBuffer {
+        LineBuffer { text: text.to_string(), cursor: text.len() }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Display width of the text before the cursor, in terminal cells
+    pub fn cursor_width(&self) -> usize {
+        self.text[..self.cursor].width()
+    }
+
+    fn previous_boundary(&self) -> Option<usize> {
+        self.text[..self.cursor].grapheme_indices(true).next_back().map(|(i, _)| i)
+    }
+
+    fn next_boundary(&self) -> Option<usize> {
+        self.text[self.cursor..].graphemes(true).next().map(|g| self.cursor + g.len())
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    pub fn backspace(&mut self) {
+        if let Some(start) = self.previous_boundary() {
+            self.text.replace_range(start..self.cursor, "");
+            self.cursor = start;
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if let Some(end) = self.next_boundary() {
+            self.text.replace_range(self.cursor..end, "");
+        }
+    }
+
+    pub fn left(&mut self) {
+        if let Some(start) = self.previous_boundary() {
+            self.cursor = start;
+        }
+    }
+
+    pub fn right(&mut self) {
+        if let Some(end) = self.next_boundary() {
+            self.cursor = end;
+        }
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// Applies a cursor movement or editing key, returning `false` for keys it doesn't handle
+    pub fn apply_key(&mut self, code: KeyCode) -> bool {
+        match code {
+            KeyCode::Left => self.left(),
+            KeyCode::Right => self.right(),
+            KeyCode::Home => self.home(),
+            KeyCode::End => self.end(),
+            KeyCode::Backspace => self.backspace(),
+            KeyCode::Delete => self.delete(),
+            KeyCode::Char(c) => self.insert(c),
+            _ => return false,
+        }
+        true
+    }
+}
+
+pub struct RawModeGuard;
+
+impl RawModeGuard {
+    pub fn enable() -> io::Result<RawModeGuard> {
+        terminal::enable_raw_mode()?;
+        Ok(RawModeGuard)
+    }
+}
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+fn render(stdout: &mut io::Stdout, prompt: &str, buffer: &LineBuffer) -> io::Result<()> {
+    execute!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine))?;
+    write!(stdout, "{}{}", prompt, buffer.text())?;
+    execute!(stdout, MoveToColumn((prompt.width() + buffer.cursor_width()) as u16))?;
+    stdout.flush()
+}
+
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+/// Lets the user edit `initial` in place. Returns `None` if the edit was cancelled with Esc.
+pub fn edit_line(prompt: &str, initial: &str) -> io::Result<Option<String>> {
+    let mut stdout = io::stdout();
+    let mut buffer = LineBuffer::new(initial);
+
+    let result = {
+        let _guard = RawModeGuard::enable()?;
+        render(&mut stdout, prompt, &buffer)?;
+
+        loop {
+            let Event::Key(KeyEvent { code, modifiers, kind, .. }) = event::read()? else {
+                continue;
+            };
+
+            if kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match code {
+                KeyCode::Enter => break Some(buffer.text().to_string()),
+                KeyCode::Esc => break None,
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => break None,
+                _ => {
+                    buffer.apply_key(code);
+                }
+            }
+
+            render(&mut stdout, prompt, &buffer)?;
+        }
+    };
+
+    println!();
+
+    Ok(result)
+}
+
+/// Switches to the alternate screen for a full-screen prompt and back again on drop
+pub struct AlternateScreenGuard;
+
+impl AlternateScreenGuard {
+    pub fn enter(stdout: &mut io::Stdout) -> io::Result<AlternateScreenGuard> {
+        execute!(stdout, EnterAlternateScreen, DisableLineWrap)?;
+        Ok(AlternateScreenGuard)
+    }
+}
+
+impl Drop for AlternateScreenGuard {
+    fn drop(&mut self) {
+        execute!(io::stdout(), EnableLineWrap, LeaveAlternateScreen).ok();
+    }
+}
+
+pub struct PickerItem {
+    /// What is shown for the item, may contain styling
+    pub label: String,
+    /// What the filter is matched against
+    pub text: String,
+}
+
+/// Scores `query` as a case-insensitive subsequence of `text`, favouring runs of consecutive
+/// characters and matches at the start of words. Returns `None` if it doesn't match at all.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let text: Vec<char> = text.chars().flat_map(char::to_lowercase).collect();
+    let mut score = 0;
+    let mut start = 0;
+    let mut previous: Option<usize> = None;
+
+    for c in query.chars().flat_map(char::to_lowercase) {
+        let found = start + text[start..].iter().position(|&t| t == c)?;
+
+        score += 1;
+        if previous == Some(found.wrapping_sub(1)) {
+            score += 4;
+        }
+        if found == 0 || !text[found - 1].is_alphanumeric() {
+            score += 2;
+        }
+
+        previous = Some(found);
+        start = found + 1;
+    }
+
+    Some(score)
+}
+
+/// Items matching `query`, best match first, keeping the original order for equal scores
+fn filter_items(items: &[PickerItem], query: &str) -> Vec<usize> {
+    let mut scored: Vec<(i64, usize)> = items.iter()
+        .enumerate()
+        .filter_map(|(i, item)| fuzzy_score(query, &item.text).map(|score| (score, i)))
+        .collect();
+
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    scored.into_iter().map(|(_, i)| i).collect()
+}
+
+struct Picker<'a> {
+    title: &'a str,
+    items: &'a [PickerItem],
+    multi: bool,
+    query: LineBuffer,
+    selected: Vec<bool>,
+    cursor: usize,
+    offset: usize,
+}
+
+impl Picker<'_> {
+    fn render(&self, stdout: &mut io::Stdout, visible: &[usize], height: usize) -> io::Result<()> {
+        let prompt = format!("{} ", self.title);
+        let help = if self.multi {
+            "up/down move, space select, enter confirm, esc cancel"
+        } else {
+            "up/down move, enter confirm, esc cancel"
+        };
+
+        queue!(stdout, Clear(ClearType::All), MoveTo(0, 0), Print(&prompt), Print(self.query.text()))?;
+        queue!(stdout, MoveTo(0, 1), Style::Dim.print(help))?;
+
+        if visible.is_empty() {
+            queue!(stdout, MoveTo(0, 2), Print("  No matching tasks"))?;
+        }
+
+        for (row, &item) in visible.iter().enumerate().skip(self.offset).take(height) {
+            let pointer = if row == self.cursor { styled(Style::Bold, ">") } else { String::from(" ") };
+            let mark = match (self.multi, self.selected[item]) {
+                (false, _) => "",
+                (true, true) => "[x]",
+                (true, false) => "[ ]",
+            };
+
+            queue!(stdout, MoveTo(0, (row - self.offset + 2) as u16), Print(format!("{} {}{}", pointer, mark, self.items[item].label)))?;
+        }
+
+        queue!(stdout, MoveTo((prompt.width() + self.query.cursor_width()) as u16, 0))?;
+        stdout.flush()
+    }
+}
+
+/// Lets the user fuzzy-filter `items` and pick one, or with `multi` several, of them. Returns the
+/// positions of the chosen items in `items`, or `None` if the picker was cancelled with Esc.
+pub fn pick(title: &str, items: &[PickerItem], multi: bool) -> io::Result<Option<Vec<usize>>> {
+    let mut stdout = io::stdout();
+    let mut picker = Picker {
+        title,
+        items,
+        multi,
+        query: LineBuffer::new(""),
+        selected: vec![false; items.len()],
+        cursor: 0,
+        offset: 0,
+    };
+
+    let _raw_mode = RawModeGuard::enable()?;
+    let _screen = AlternateScreenGuard::enter(&mut stdout)?;
+
+    loop {
+        let visible = filter_items(items, picker.query.text());
+        picker.cursor = picker.cursor.min(visible.len().saturating_sub(1));
+
+        let height = (terminal::size()?.1 as usize).saturating_sub(2).max(1);
+        if picker.cursor < picker.offset {
+            picker.offset = picker.cursor;
+        } else if picker.cursor >= picker.offset + height {
+            picker.offset = picker.cursor + 1 - height;
+        }
+
+        picker.render(&mut stdout, &visible, height)?;
+
+        let Event::Key(KeyEvent { code, modifiers, kind, .. }) = event::read()? else {
+            continue;
+        };
+
+        if kind != KeyEventKind::Press {
+            continue;
+        }
+
+        match code {
+            KeyCode::Enter => {
+                let chosen: Vec<usize> = (0..items.len()).filter(|&i| picker.selected[i]).collect();
+
+                if !chosen.is_empty() {
+                    return Ok(Some(chosen));
+                }
+                if let Some(&item) = visible.get(picker.cursor) {
+                    return Ok(Some(vec![item]));
+                }
+            }
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(None),
+            KeyCode::Up => picker.cursor = picker.cursor.saturating_sub(1),
+            KeyCode::Down => picker.cursor = (picker.cursor + 1).min(visible.len().saturating_sub(1)),
+            KeyCode::Char(' ') if multi => {
+                if let Some(&item) = visible.get(picker.cursor) {
+                    picker.selected[item] = !picker.selected[item];
+                }
+            }
+            _ => {
+                picker.query.apply_key(code);
+            }
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use std::env;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+use crate::config::config;
+
+type Catalog = &'static [(&'static str, &'static str)];
+
+/// The messages todoln prints, by key. `{0}`, `{1}` and so on are replaced by the arguments in
+/// that order, wherever a translation puts them.
+const EN: Catalog = &[
+    ("invalid-index", "Error: Invalid index '{0}'."),
+    ("no-task-at-index", "No task at index {0}"),
+    ("invalid-id", "Error: Invalid task id '{0}', ids have no subtask part."),
+    ("no-task-with-id", "Error: No task with id {0}."),
+    ("no-valid-tasks", "Error: No valid tasks provided."),
+    ("insert-index-without-names", "Error: No tasks given to insert at index {0}; to add a task named '{0}' at the end, use `todoln insert -- {0}`."),
+    ("new-name-empty", "Error: New task cannot be empty or whitespace-only."),
+    ("failed-to-retrieve-tasks", "Failed to retrieve tasks: {0}"),
+    ("tasks-added", "Task(s) added successfully: {0}"),
+    ("no-tasks-added", "No tasks added."),
+    ("tasks-inserted", "Task(s) inserted successfully: {0}"),
+    ("no-tasks-inserted", "No tasks inserted."),
+    ("task-modified", "Task modifed successfully: '{0}'"),
+    ("tasks-modified", "{0} task(s) modified successfully:"),
+    ("tasks-completed", "Task(s) completed successfully: {0}"),
+    ("tasks-marked-todo", "Task(s) marked as todo successfully: {0}"),
+    ("task-already-done", "Warning: Task {0} was already done."),
+    ("tasks-removed", "Task(s) removed successfully: {0}"),
+    ("completed-tasks-cleared", "{0} completed task(s) cleared successfully: {1}"),
+    ("no-completed-tasks-to-clear", "No completed tasks to clear."),
+    ("tasks-reset", "Tasks reset successfully"),
+    ("tasks-reset-snapshot", "Tasks reset successfully, snapshot saved to {0}"),
+    ("tasks-sorted", "Tasks sorted successfully"),
+    ("no-tasks-found", "No tasks found."),
+    ("no-matches", "No matches for '{0}'."),
+    ("title-tasks", "Tasks:"),
+    ("title-tasks-todo", "Tasks todo:"),
+    ("title-tasks-done", "Tasks done:"),
+    ("title-tasks-snoozed", "Tasks snoozed:"),
+    ("list-summary", "{0}/{1} done ({2}%)"),
+    ("group-todo", "Todo:"),
+    ("group-done", "Done:"),
+    ("group-priority", "Priority {0}:"),
+    ("group-tag", "{0}:"),
+    ("group-other", "Other:"),
+    ("nothing-changed", "Nothing was changed."),
+    ("modification-cancelled", "Modification cancelled."),
+    ("no-changes-made", "No changes made."),
+    ("task-has-no-note", "Task {0} has no note."),
+    ("no-names-contain", "No task names contain '{0}'."),
+    ("nothing-renamed", "Nothing was renamed."),
+    ("no-tasks-to-pick-from", "No tasks to pick from."),
+    ("cancelled", "Cancelled."),
+    ("no-blocked-tasks", "No tasks are blocked."),
+    ("title-blocked", "Blocked tasks:"),
+    ("no-due-dates", "No tasks have a due date."),
+    ("title-due", "Due tasks:"),
+    ("nothing-due-today", "Nothing is due today."),
+    ("title-due-today", "Due today:"),
+    ("nothing-due-this-week", "Nothing is due in the next seven days."),
+    ("title-someday", "Someday:"),
+    ("already-in-that-order", "The tasks are already in that order."),
+    ("no-tasks-to-pick", "No tasks to pick."),
+    ("keeping-all-completed", "Keeping all {0} completed task(s), nothing to clear."),
+    ("title-clearing-keeping", "Clearing {0} completed task(s), keeping the {1} most recent:"),
+    ("nothing-to-undo", "Nothing to undo."),
+    ("title-history", "History (newest first):"),
+    ("trash-empty", "The trash is empty."),
+    ("title-trash", "Trash:"),
+    ("trash-already-empty", "The trash is already empty."),
+    ("nothing-old-in-trash", "No tasks have been in the trash for over {0} days."),
+    ("no-tasks-imported", "No tasks imported."),
+    ("import-skipped", "Skipped {0} task(s) already in the list: {1}"),
+    ("title-changes", "Changes:"),
+    ("indices-in-order", "Task indices are already in order."),
+    ("no-session", "No session is active."),
+    ("title-info", "Info:"),
+    ("no-aliases", "No aliases defined, add them under [aliases] in '{0}'."),
+    ("title-aliases", "Aliases:"),
+    ("no-usage", "No usage recorded."),
+    ("title-usage", "Usage:"),
+    ("no-archived-tasks", "No archived tasks."),
+    ("title-archived", "Archived tasks:"),
+    ("nothing-to-archive", "No completed tasks to archive."),
+    ("no-completed-tasks", "No completed tasks."),
+    ("title-today", "Today:"),
+    ("title-yesterday", "Yesterday:"),
+    ("title-tomorrow", "Tomorrow:"),
+    ("title-completed-last-days", "Completed in the last {0} days:"),
+    ("none-completed-today", "No tasks completed today."),
+    ("title-completed-today", "Completed today:"),
+    ("nothing-to-do", "Nothing to do."),
+    ("wrapup-cancelled", "Wrap-up cancelled."),
+    ("already-tracking", "Already tracking time on task {0} ({1}) for {2}."),
+    ("stopped-tracking", "Stopped tracking '{0}' after {1}."),
+    ("no-timer", "No timer is running."),
+    ("no-time-tracked", "No time tracked."),
+    ("title-time-on-task", "Time tracked on {0}:"),
+    ("title-time-per-task", "Time tracked per task:"),
+    ("title-time-per-day", "Time tracked per day:"),
+    ("no-focus-sessions", "No focus sessions yet."),
+    ("title-focus-sessions", "Focus sessions:"),
+    ("nothing-to-focus-on", "No todo tasks to focus on."),
+    ("title-focus-session", "Focus session of {0}:"),
+    ("title-focus-finished", "Session finished after {0}, {1} task(s) completed:"),
+    ("nothing-to-review", "No todo tasks to review."),
+    ("no-duplicates", "No duplicate tasks found."),
+    ("title-duplicates", "Duplicate tasks:"),
+    ("title-found-problems", "Found problems:"),
+    ("title-fixed-problems", "Fixed problems:"),
+    ("title-remaining-problems", "Remaining problems:"),
+    ("title-database", "Database:"),
+    ("no-maintenance-needed", "No maintenance needed."),
+    ("would-remove", "Would remove: {0}"),
+    ("nothing-would-remove", "Nothing would be removed."),
+    ("would-clear", "Would clear: {0}"),
+    ("nothing-would-clear", "Nothing would be cleared."),
+    ("would-reset", "Would delete: {0}"),
+    ("nothing-would-reset", "Nothing would be deleted."),
+    ("recovered-journal", "Recovered the journal of '{0}' left behind by an interrupted todoln run."),
+    ("removed-stale-lock", "Removed the stale lock file '{0}' left behind by an interrupted todoln run."),
+    ("session-timed-out", "Ended the scripting session of process {0}, which timed out."),
+    ("auto-archived", "Auto-archived {0} old task(s)."),
+    ("trash-purged", "Deleted {0} task(s) that were in the trash for over {1} days."),
+    ("serving", "Serving the task API on http://{0}:{1}/tasks, press Ctrl+C to stop."),
+];
+
+const DE: Catalog = &[
+    ("invalid-index", "Fehler: Ungültiger Index '{0}'."),
+    ("no-task-at-index", "Keine Aufgabe mit Index {0}"),
+    ("invalid-id", "Fehler: Ungültige Aufgaben-ID '{0}', IDs haben keinen Teil für Unteraufgaben."),
+    ("no-task-with-id", "Fehler: Keine Aufgabe mit ID {0}."),
+    ("no-valid-tasks", "Fehler: Keine gültigen Aufgaben angegeben."),
+    ("insert-index-without-names", "Fehler: Keine Aufgaben zum Einfügen bei Index {0} angegeben; um eine Aufgabe namens '{0}' am Ende hinzuzufügen, `todoln insert -- {0}` verwenden."),
+    ("new-name-empty", "Fehler: Der neue Name darf nicht leer sein oder nur aus Leerzeichen bestehen."),
+    ("failed-to-retrieve-tasks", "Aufgaben konnten nicht gelesen werden: {0}"),
+    ("tasks-added", "Aufgabe(n) hinzugefügt: {0}"),
+    ("no-tasks-added", "Keine Aufgaben hinzugefügt."),
+    ("tasks-inserted", "Aufgabe(n) eingefügt: {0}"),
+    ("no-tasks-inserted", "Keine Aufgaben eingefügt."),
+    ("task-modified", "Aufgabe geändert: '{0}'"),
+    ("tasks-modified", "{0} Aufgabe(n) geändert:"),
+    ("tasks-completed", "Aufgabe(n) erledigt: {0}"),
+    ("tasks-marked-todo", "Aufgabe(n) wieder offen: {0}"),
+    ("task-already-done", "Warnung: Aufgabe {0} war schon erledigt."),
+    ("tasks-removed", "Aufgabe(n) entfernt: {0}"),
+    ("completed-tasks-cleared", "Erledigte Aufgaben entfernt: {1} ({0} insgesamt)"),
+    ("no-completed-tasks-to-clear", "Keine erledigten Aufgaben zum Entfernen."),
+    ("tasks-reset", "Alle Aufgaben gelöscht"),
+    ("tasks-reset-snapshot", "Alle Aufgaben gelöscht, Sicherung in {0} gespeichert"),
+    ("tasks-sorted", "Aufgaben sortiert"),
+    ("no-tasks-found", "Keine Aufgaben gefunden."),
+    ("no-matches", "Keine Treffer für '{0}'."),
+    ("title-tasks", "Aufgaben:"),
+    ("title-tasks-todo", "Offene Aufgaben:"),
+    ("title-tasks-done", "Erledigte Aufgaben:"),
+    ("title-tasks-snoozed", "Zurückgestellte Aufgaben:"),
+    ("list-summary", "{0}/{1} erledigt ({2} %)"),
+    ("group-todo", "Offen:"),
+    ("group-done", "Erledigt:"),
+    ("group-priority", "Priorität {0}:"),
+    ("group-tag", "{0}:"),
+    ("group-other", "Sonstige:"),
+    ("nothing-changed", "Nichts wurde geändert."),
+    ("modification-cancelled", "Änderung abgebrochen."),
+    ("no-changes-made", "Keine Änderungen vorgenommen."),
+    ("task-has-no-note", "Aufgabe {0} hat keine Notiz."),
+    ("no-names-contain", "Kein Aufgabenname enthält '{0}'."),
+    ("nothing-renamed", "Nichts wurde umbenannt."),
+    ("no-tasks-to-pick-from", "Keine Aufgaben zur Auswahl."),
+    ("cancelled", "Abgebrochen."),
+    ("no-blocked-tasks", "Keine Aufgaben sind blockiert."),
+    ("title-blocked", "Blockierte Aufgaben:"),
+    ("no-due-dates", "Keine Aufgaben haben ein Fälligkeitsdatum."),
+    ("title-due", "Fällige Aufgaben:"),
+    ("nothing-due-today", "Heute ist nichts fällig."),
+    ("title-due-today", "Heute fällig:"),
+    ("nothing-due-this-week", "In den nächsten sieben Tagen ist nichts fällig."),
+    ("title-someday", "Irgendwann:"),
+    ("already-in-that-order", "Die Aufgaben sind schon in dieser Reihenfolge."),
+    ("no-tasks-to-pick", "Keine Aufgaben zum Auswählen."),
+    ("keeping-all-completed", "Alle {0} erledigten Aufgaben bleiben, nichts zu entfernen."),
+    ("title-clearing-keeping", "{0} erledigte Aufgabe(n) werden entfernt, die {1} neuesten bleiben:"),
+    ("nothing-to-undo", "Nichts rückgängig zu machen."),
+    ("title-history", "Verlauf (neueste zuerst):"),
+    ("trash-empty", "Der Papierkorb ist leer."),
+    ("title-trash", "Papierkorb:"),
+    ("trash-already-empty", "Der Papierkorb ist schon leer."),
+    ("nothing-old-in-trash", "Keine Aufgaben liegen seit über {0} Tagen im Papierkorb."),
+    ("no-tasks-imported", "Keine Aufgaben importiert."),
+    ("import-skipped", "{0} Aufgabe(n) übersprungen, die schon in der Liste sind: {1}"),
+    ("title-changes", "Änderungen:"),
+    ("indices-in-order", "Die Indizes der Aufgaben sind schon in Ordnung."),
+    ("no-session", "Keine Sitzung aktiv."),
+    ("title-info", "Info:"),
+    ("no-aliases", "Keine Aliase definiert, sie gehören unter [aliases] in '{0}'."),
+    ("title-aliases", "Aliase:"),
+    ("no-usage", "Keine Nutzung aufgezeichnet."),
+    ("title-usage", "Nutzung:"),
+    ("no-archived-tasks", "Keine archivierten Aufgaben."),
+    ("title-archived", "Archivierte Aufgaben:"),
+    ("nothing-to-archive", "Keine erledigten Aufgaben zum Archivieren."),
+    ("no-completed-tasks", "Keine erledigten Aufgaben."),
+    ("title-today", "Heute:"),
+    ("title-yesterday", "Gestern:"),
+    ("title-tomorrow", "Morgen:"),
+    ("title-completed-last-days", "In den letzten {0} Tagen erledigt:"),
+    ("none-completed-today", "Heute keine Aufgaben erledigt."),
+    ("title-completed-today", "Heute erledigt:"),
+    ("nothing-to-do", "Nichts zu tun."),
+    ("wrapup-cancelled", "Abschluss abgebrochen."),
+    ("already-tracking", "Die Zeit für Aufgabe {0} ({1}) läuft schon seit {2}."),
+    ("stopped-tracking", "Zeiterfassung für '{0}' nach {1} beendet."),
+    ("no-timer", "Keine Zeiterfassung läuft."),
+    ("no-time-tracked", "Keine Zeit erfasst."),
+    ("title-time-on-task", "Erfasste Zeit für {0}:"),
+    ("title-time-per-task", "Erfasste Zeit pro Aufgabe:"),
+    ("title-time-per-day", "Erfasste Zeit pro Tag:"),
+    ("no-focus-sessions", "Noch keine Fokus-Sitzungen."),
+    ("title-focus-sessions", "Fokus-Sitzungen:"),
+    ("nothing-to-focus-on", "Keine offenen Aufgaben für eine Fokus-Sitzung."),
+    ("title-focus-session", "Fokus-Sitzung von {0}:"),
+    ("title-focus-finished", "Sitzung nach {0} beendet, {1} Aufgabe(n) erledigt:"),
+    ("nothing-to-review", "Keine offenen Aufgaben zum Durchsehen."),
+    ("no-duplicates", "Keine doppelten Aufgaben gefunden."),
+    ("title-duplicates", "Doppelte Aufgaben:"),
+    ("title-found-problems", "Gefundene Probleme:"),
+    ("title-fixed-problems", "Behobene Probleme:"),
+    ("title-remaining-problems", "Verbleibende Probleme:"),
+    ("title-database", "Datenbank:"),
+    ("no-maintenance-needed", "Keine Wartung nötig."),
+    ("would-remove", "Würde entfernen: {0}"),
+    ("nothing-would-remove", "Nichts würde entfernt."),
+    ("would-clear", "Würde entfernen: {0}"),
+    ("nothing-would-clear", "Nichts würde entfernt."),
+    ("would-reset", "Würde löschen: {0}"),
+    ("nothing-would-reset", "Nichts würde gelöscht."),
+    ("recovered-journal", "Das Journal von '{0}', das ein unterbrochener todoln-Lauf hinterlassen hat, wurde wiederhergestellt."),
+    ("removed-stale-lock", "Die veraltete Sperrdatei '{0}', die ein unterbrochener todoln-Lauf hinterlassen hat, wurde entfernt."),
+    ("session-timed-out", "Die abgelaufene Skript-Sitzung von Prozess {0} wurde beendet."),
+    ("auto-archived", "{0} alte Aufgabe(n) automatisch archiviert."),
+    ("trash-purged", "{0} Aufgabe(n) gelöscht, die seit über {1} Tagen im Papierkorb lagen."),
+    ("serving", "Die Aufgaben-API läuft unter http://{0}:{1}/tasks, mit Strg+C beenden."),
+];
+
+/// Every language with a catalog, English first as the one the others fall back to
+pub const CATALOGS: &[(&str, Catalog)] = &[("en", EN), ("de", DE)];
+
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+/// The language code in `language` from the config file, or else in the first of `LC_ALL`,
+/// `LC_MESSAGES` and `LANG` that's set (`de_DE.UTF-8` gives `de`)
+fn language() -> Option<String> {
+    let locale = config().language.clone()
+        .or_else(|| ["LC_ALL", "LC_MESSAGES", "LANG"].iter().find_map(|var| env::var(var).ok().filter(|value| !value.is_empty())))?;
+
+    let code = locale.split(['_', '-', '.', '@']).next().unwrap_or_default().to_lowercase();
+    Some(code)
+}
+
+fn catalog() -> Catalog {
+    CATALOG.get_or_init(|| {
+        language()
+            .and_then(|code| CATALOGS.iter().find(|(language, _)| *language == code))
+            .map_or(EN, |(_, catalog)| *catalog)
+    })
+}
+
+fn lookup(catalog: Catalog, key: &str) -> Option<&'static str> {
+    catalog.iter().find(|(k, _)| *k == key).map(|(_, message)| *message)
+}
+
+/// Fills in `{0}`, `{1}` and so on in `template` with `args`
+pub fn format_message(template: &str, args: &[&dyn Display]) -> String {
+    let mut message = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        message.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = rest.find('}').unwrap_or(0);
+        match rest[1..end.max(1)].parse::<usize>().ok().and_then(|i| args.get(i)) {
+            Some(arg) => {
+                message.push_str(&arg.to_string());
+                rest = &rest[end + 1..];
+            }
+            None => {
+                message.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    message.push_str(rest);
+    message
+}
+
+/// The message `key` in the user's language, or in English when it hasn't been translated
+pub fn tr(key: &str, args: &[&dyn Display]) -> String {
+    let template = lookup(catalog(), key).or_else(|| lookup(EN, key)).unwrap_or(key);
+    format_message(template, args)
+}
@@ -0,0 +1,96 @@
+//! The task database and commands behind the `todoln` binary. The `database` module works on an
+//! explicit `Connection`, so it can be driven against an in-memory or temporary database.
+
+pub mod clipboard;
+pub mod commands;
+pub mod config;
+pub mod database;
+pub mod demo;
+pub mod editor;
+pub mod i18n;
+pub mod server;
+pub mod tui;
+pub mod utils;
+
+use commands::{Commands, FindOptions, ListOptions, Page, SearchField, TaskIndex};
+
+/// Runs a parsed command, the way `main` does for the command line and `demo` for its script
+pub fn run_command(command: &Option<Commands>) {
+    match command {
+        Some(Commands::Add {task_names, force, from_clipboard, under, every}) => commands::add(task_names, *force, *from_clipboard, under, every),
+        Some(Commands::Insert {index, task_names, literal_names, force}) => commands::insert(index, task_names, literal_names, *force),
+        Some(Commands::Modify {task_index, new_name, renames, force, by_id, ..}) => commands::modify(&task_index.map(|task_index| task_index.as_id(*by_id)), new_name, renames, *force),
+        Some(Commands::Duplicate {task_index, times}) => commands::duplicate(task_index, *times as usize),
+        Some(Commands::EditAll) => commands::edit_all(),
+        Some(Commands::Append {task_index, text, force}) => commands::extend_name(task_index, text, false, *force),
+        Some(Commands::Prepend {task_index, text, force}) => commands::extend_name(task_index, text, true, *force),
+        Some(Commands::List {display_type, watch, comments, snoozed, field_filters, completed_in, limit, offset, no_pager, wrap, long, group_by}) => {
+            let options = ListOptions { comments: *comments, snoozed: *snoozed, page: Page { offset: *offset, limit: *limit }, wrap: *wrap, width: None, long: *long, group_by: *group_by };
+            commands::list(display_type, *watch, field_filters, *completed_in, options, *no_pager)
+        },
+        Some(Commands::Show {task_index, json, escaped, by_id}) => commands::show(&task_index.as_id(*by_id), *json, *escaped),
+        Some(Commands::Find {search_term, porcelain, regex, case_sensitive, copy, subset, limit, offset, no_pager, include, only}) => {
+            let options = FindOptions { porcelain: *porcelain, copy: *copy, page: Page { offset: *offset, limit: *limit }, no_pager: *no_pager, fields: SearchField::chosen(include, only) };
+            commands::find(search_term, *regex, *case_sensitive, subset, options)
+        },
+        Some(Commands::RenameAll {pattern, replacement, regex, case_sensitive, yes}) => commands::rename_all(pattern, replacement, *regex, *case_sensitive, *yes),
+        Some(Commands::Raw {display_type, copy, template}) => commands::raw(display_type, *copy, template),
+        Some(Commands::Done {all_todo: true, yes, ..}) => commands::done_all_todo(*yes),
+        Some(Commands::Done {task_indices, name, all, cascade, force, toggle, by_id, ..}) => commands::done(&as_ids(task_indices, *by_id), name, *all, *cascade, *force, *toggle),
+        Some(Commands::Block {task_index, on, remove}) => commands::block(task_index, on, *remove),
+        Some(Commands::Blocked) => commands::blocked(),
+        Some(Commands::Set {task_index, fields}) => commands::set(task_index, fields),
+        Some(Commands::Today) => commands::today(),
+        Some(Commands::Week {someday}) => commands::week(*someday),
+        Some(Commands::Notify {window, summary}) => commands::notify(window, *summary),
+        Some(Commands::Snooze {task_index, until, duration, clear}) => commands::snooze(task_index, until, duration, *clear),
+        Some(Commands::Due {task_index, when, clear, check, within}) => commands::due(task_index, when, *clear, *check, within),
+        Some(Commands::Note {task_index, note, clear}) => commands::note(task_index, note, *clear),
+        Some(Commands::Sort {key, reverse, undo, dry_run, json}) => commands::sort(*key, *reverse, *undo, *dry_run, *json),
+        Some(Commands::Reverse) => commands::reverse(),
+        Some(Commands::Shuffle {all}) => commands::shuffle(*all),
+        Some(Commands::Pick {count, any, weighted}) => commands::pick_random(*count, *any, *weighted),
+        Some(Commands::Remove {done, matching, cascade, dry_run, json, yes, ..}) if *done || matching.is_some() => commands::remove_matching(*done, matching, *cascade, *dry_run, *json, *yes),
+        Some(Commands::Remove {task_indices, name, all, cascade, dry_run, json, by_id, ..}) => commands::remove(&as_ids(task_indices, *by_id), name, *all, *cascade, *dry_run, *json),
+        Some(Commands::Clear {keep, dry_run, json}) => commands::clear(*keep, *dry_run, *json),
+        Some(Commands::Reset {dry_run, json, no_snapshot}) => commands::reset(*dry_run, *json, *no_snapshot),
+        Some(Commands::Undo {list}) => commands::undo(*list),
+        Some(Commands::Trash {action}) => commands::trash(action),
+        Some(Commands::Encrypt) => commands::encrypt(),
+        Some(Commands::Decrypt) => commands::decrypt(),
+        Some(Commands::Backup {json}) => commands::backup(*json),
+        Some(Commands::Restore {backup_path, last_reset, dry_run, json, report, force}) => commands::restore(backup_path, *last_reset, *dry_run, *json, *force, report),
+        Some(Commands::Import {source}) => commands::import(source),
+        Some(Commands::SyncMd {file, prune, dry_run, report}) => commands::sync_md(file, *prune, *dry_run, report),
+        Some(Commands::Sync {file, status}) => commands::sync(file, *status),
+        Some(Commands::Archive {list, completed_in}) => commands::archive(*list, *completed_in),
+        Some(Commands::Log {since}) => commands::log(*since),
+        Some(Commands::Stats {weekly}) => commands::stats(*weekly),
+        Some(Commands::Wrapup {no_archive, no_sort, no_backup, yes}) => commands::wrapup(*no_archive, *no_sort, *no_backup, *yes),
+        Some(Commands::Start {task_index}) => commands::start(task_index),
+        Some(Commands::Stop) => commands::stop(),
+        Some(Commands::Time {task_index, ..}) => commands::time(task_index),
+        Some(Commands::Session {duration, count, history}) => commands::session(duration, *count, *history),
+        Some(Commands::Ui) => commands::ui(),
+        Some(Commands::Serve {port, host, token}) => server::serve(host, *port, token),
+        Some(Commands::Review {apply_partial}) => commands::review(*apply_partial),
+        Some(Commands::Dedupe {apply}) => commands::dedupe(*apply),
+        Some(Commands::Doctor {fix, unlock}) => commands::doctor(*fix, *unlock),
+        Some(Commands::Maintenance {auto}) => commands::maintenance(*auto),
+        Some(Commands::Reindex) => commands::reindex(),
+        Some(Commands::BeginSession {timeout}) => commands::begin_session(timeout),
+        Some(Commands::EndSession) => commands::end_session(),
+        Some(Commands::Info) => commands::info(),
+        Some(Commands::Aliases) => commands::aliases(),
+        Some(Commands::Man) => commands::man(),
+        Some(Commands::Usage {clear}) => commands::usage(*clear),
+        Some(Commands::Demo) => demo::run(),
+        Some(Commands::Introspect {json}) => commands::introspect(*json),
+        None => commands::list(&String::from("all"), false, &[], None, ListOptions::default(), false)
+    }
+}
+
+/// The task indices of a command, read as task ids when it was given `--id`
+fn as_ids(task_indices: &[TaskIndex], by_id: bool) -> Vec<TaskIndex> {
+    task_indices.iter().map(|task_index| task_index.as_id(by_id)).collect()
+}
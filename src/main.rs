@@ -1,27 +1,109 @@
-mod commands;
-mod database;
-mod utils;
+use std::env;
+use std::ffi::OsString;
+use std::process;
+use std::time::Instant;
 
-use clap::Parser;
-use commands::{Cli, Commands};
+use clap::error::ErrorKind;
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use todoln::commands::{self, Cli, Commands, OutputFormat};
+use todoln::config::{config, default_command, expand_alias};
+use todoln::utils::{self, Verbosity};
+use todoln::{database, editor, run_command};
 
 fn main() {
-    let cli = Cli::parse();
-
-    match &cli.command {
-        Some(Commands::Add {task_names}) => commands::add(task_names),
-        Some(Commands::Insert {index, task_names}) => commands::insert(index, task_names),
-        Some(Commands::Modify {task_index, new_name}) => commands::modify(task_index, new_name),
-        Some(Commands::List {display_type}) => commands::list(display_type),
-        Some(Commands::Find {search_term}) => commands::find(search_term),
-        Some(Commands::Raw {display_type}) => commands::raw(display_type),
-        Some(Commands::Done {task_indices}) => commands::done(task_indices),
-        Some(Commands::Sort) => commands::sort(),
-        Some(Commands::Remove {task_indices}) => commands::remove(task_indices),
-        Some(Commands::Clear) => commands::clear(),
-        Some(Commands::Reset) => commands::reset(),
-        Some(Commands::Backup) => commands::backup(),
-        Some(Commands::Restore {backup_path}) => commands::restore(backup_path.to_string()),
-        None => commands::list(&String::from("all"))
-    }
-}
\ No newline at end of file
+    let command = if editor::is_interactive() { commands::allow_task_picker(Cli::command()) } else { Cli::command() };
+    let command = commands::describe_default_command(command, default_command().unwrap_or("list all"));
+    let mut args: Vec<OsString> = env::args_os().collect();
+
+    // Aliases from the config file are expanded before clap sees the arguments, so its help and
+    // errors stay those of the real commands
+    if let Some(name) = args.get(1).and_then(|arg| arg.to_str()).map(str::to_string) {
+        match expand_alias(&config().aliases, &name, commands::is_builtin_command) {
+            Ok(Some(expansion)) => {
+                args.splice(1..2, expansion.into_iter().map(OsString::from));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                utils::print_error(&format!("Error: Alias '{}' {}.", name, e));
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut matches = command.clone().get_matches_from(&args);
+
+    if let Some(default) = default_command().filter(|_| matches.subcommand().is_none()) {
+        matches = default_command_matches(&command, &args, default).unwrap_or(matches);
+    }
+
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    if let Some(db) = &cli.db {
+        database::set_db_path(db.clone());
+    }
+
+    if cli.stable_read {
+        database::set_stable_read();
+    }
+
+    utils::set_verbosity(match (cli.quiet, cli.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, true) => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    });
+    utils::set_json_output(cli.format == OutputFormat::Json);
+    utils::set_plain_output(cli.no_color);
+    let started = Instant::now();
+
+    if let Some(command) = &cli.command {
+        if !commands::guard_context(command, matches.subcommand_name().unwrap_or_default(), cli.force_context) {
+            process::exit(1);
+        }
+    }
+
+    // `due --check` runs on every new shell and must not wait on the database, and `demo` must
+    // leave it alone
+    let untracked = matches!(cli.command, Some(Commands::Due { check: true, .. }) | Some(Commands::Demo));
+
+    let read_only = cli.command.as_ref().map_or(true, Commands::is_read_only);
+
+    if config().usage_stats && !untracked && !(read_only && database::stable_read()) {
+        commands::record_usage(matches.subcommand_name().unwrap_or("list"));
+    }
+
+    run_command(&cli.command);
+
+    utils::print_verbose(&format!("Finished in {:.1?}", started.elapsed()));
+
+    if utils::failed() {
+        process::exit(1);
+    }
+}
+
+/// The arguments of a bare `todoln` run with `default_command` added, or `None` after a warning
+/// when it isn't something todoln can run, so the run lists all tasks as it would without it
+fn default_command_matches(command: &clap::Command, args: &[OsString], default: &str) -> Option<ArgMatches> {
+    let mut words: Vec<String> = default.split_whitespace().map(str::to_string).collect();
+
+    match expand_alias(&config().aliases, &words[0], commands::is_builtin_command) {
+        Ok(Some(expansion)) => {
+            words.splice(0..1, expansion);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            utils::print_warning(&format!("Warning: default_command '{}' is ignored as alias '{}' {}.", default, words[0], e));
+            return None;
+        }
+    }
+
+    match command.clone().try_get_matches_from(args.iter().cloned().chain(words.into_iter().map(OsString::from))) {
+        Ok(matches) => Some(matches),
+        Err(e) if matches!(e.kind(), ErrorKind::DisplayHelp | ErrorKind::DisplayVersion | ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand) => e.exit(),
+        Err(e) => {
+            let reason = e.to_string();
+            let reason = reason.lines().next().unwrap_or_default().trim_start_matches("error: ");
+            utils::print_warning(&format!("Warning: default_command '{}' is ignored as it isn't a command todoln can run: {}", default, reason));
+            None
+        }
+    }
+}
@@ -0,0 +1,410 @@
+//! `todoln serve`: a small JSON API over HTTP/1.1 for scripts and launchers, hand-rolled on
+//! `std::net` and handling one request at a time over a connection to the database opened once.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use rusqlite::Connection;
+use serde_json::{json, Value};
+
+use crate::commands::{find_duplicate_names, SortKey, Task};
+use crate::i18n::tr;
+use crate::database::{
+    add_tasks_to_db, edit_task_in_db, establish_connection, find_tasks_from_db, get_task_by_id, get_task_from_db, get_tasks_from_db,
+    mark_task_in_db_as_todo, mark_tasks_in_db_as_done, remove_tasks_from_db, sort_tasks_in_db, SearchMode, TaskSubset,
+};
+use crate::utils::{parse_timestamp, print_error, print_notice};
+
+/// Request bodies larger than this are turned away
+const MAX_BODY: usize = 1024 * 1024;
+
+/// Request lines and headers together longer than this are turned away
+const MAX_HEAD: u64 = 16 * 1024;
+
+/// How long a client gets to send its whole request before it's turned away, so one stalled
+/// client can't hold up the others
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reads from a client until `deadline`, however the request trickles in
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    host: Option<String>,
+    content_type: Option<String>,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Response {
+        Response { status: 200, body }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response { status, body: json!({ "error": message }) }
+    }
+}
+
+/// Serves the API on `host:port` until the process is stopped. With a `token`, requests that
+/// change tasks need an `Authorization: Bearer <token>` header; reading never does. Requests
+/// naming another host, and `POST` or `PATCH` requests without a JSON body, are turned away, so a
+/// web page open in a browser can't reach the API.
+pub fn serve(host: &str, port: u16, token: &Option<String>) {
+    let listener = match TcpListener::bind((host, port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            print_error(&format!("Failed to listen on {}:{}: {}", host, port, e));
+            return;
+        }
+    };
+
+    // Opened once, so repairs, auto-archiving and trash purging run at startup rather than on
+    // every request, and a failure later on is a 500 instead of the end of the server
+    let mut conn = establish_connection();
+
+    print_notice(&tr("serving", &[&host, &port]));
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => handle_connection(&mut conn, stream, (host, port), token),
+            Err(e) => eprintln!("Warning: Failed to accept a connection: {}", e),
+        }
+    }
+}
+
+fn handle_connection(conn: &mut Connection, mut stream: TcpStream, bound: (&str, u16), token: &Option<String>) {
+    let response = match read_request(&stream) {
+        Ok(request) => respond(conn, &request, bound, token),
+        Err(response) => response,
+    };
+
+    let body = response.body.to_string();
+    let reply = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status, reason(response.status), body.len(), body,
+    );
+
+    if let Err(e) = stream.write_all(reply.as_bytes()) {
+        eprintln!("Warning: Failed to send a response: {}", e);
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        421 => "Misdirected Request",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+fn read_request(stream: &TcpStream) -> Result<Request, Response> {
+    let bad_request = || Response::error(400, "malformed HTTP request");
+    let read_error = |e: io::Error| match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Response::error(408, "the request took too long to arrive"),
+        _ => bad_request(),
+    };
+    let mut reader = BufReader::new(DeadlineReader { stream, deadline: Instant::now() + READ_TIMEOUT });
+
+    // Each line is read with what is left of the room for the head, so an endless line stops there
+    let mut head_left = MAX_HEAD;
+    let mut read_head_line = |reader: &mut BufReader<DeadlineReader>| -> Result<String, Response> {
+        let mut line = String::new();
+        let read = reader.take(head_left).read_line(&mut line).map_err(read_error)?;
+        head_left -= read as u64;
+        if !line.ends_with('\n') && head_left == 0 {
+            return Err(Response::error(431, "the request headers are too large"));
+        }
+        Ok(line)
+    };
+
+    let request_line = read_head_line(&mut reader)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(bad_request());
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut content_length = 0;
+    let mut host = None;
+    let mut content_type = None;
+    let mut authorization = None;
+    loop {
+        let line = read_head_line(&mut reader)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(bad_request());
+        };
+        match name.trim().to_lowercase().as_str() {
+            "content-length" => content_length = value.trim().parse().map_err(|_| bad_request())?,
+            "host" => host = Some(value.trim().to_string()),
+            "content-type" => content_type = Some(value.trim().to_string()),
+            "authorization" => authorization = Some(value.trim().to_string()),
+            _ => {},
+        }
+    }
+
+    if content_length > MAX_BODY {
+        return Err(Response::error(413, "the request body is too large"));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).map_err(read_error)?;
+
+    Ok(Request { method: method.to_uppercase(), path: path.trim_end_matches('/').to_string(), query: query.to_string(), host, content_type, authorization, body })
+}
+
+/// Whether a `Host` header names this server: the host it's bound to, `localhost` or an IP address,
+/// with the port it listens on if any. A domain name a web page has pointed at this machine isn't.
+fn is_own_host(header: &str, (host, port): (&str, u16)) -> bool {
+    let (name, header_port) = match header.strip_prefix('[').and_then(|header| header.split_once(']')) {
+        Some((name, rest)) => (name, rest.strip_prefix(':')),
+        None => match header.split_once(':') {
+            Some((name, header_port)) => (name, Some(header_port)),
+            None => (header, None),
+        },
+    };
+
+    let own_name = name.eq_ignore_ascii_case(host.trim_start_matches('[').trim_end_matches(']'))
+        || name.eq_ignore_ascii_case("localhost")
+        || name.parse::<IpAddr>().is_ok();
+    own_name && header_port.map_or(true, |header_port| header_port == port.to_string())
+}
+
+/// Whether a `Content-Type` header is JSON, which a browser only sends to another site after a
+/// preflight request this server never allows
+fn is_json(content_type: &str) -> bool {
+    content_type.split(';').next().is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"))
+}
+
+fn respond(conn: &mut Connection, request: &Request, bound: (&str, u16), token: &Option<String>) -> Response {
+    let segments: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+    let method = request.method.as_str();
+
+    if let Some(host) = request.host.as_deref().filter(|host| !is_own_host(host, bound)) {
+        return Response::error(421, &format!("this server doesn't answer for the host '{}'", host));
+    }
+
+    let authorized = token.as_ref().map_or(true, |token| request.authorization.as_deref() == Some(&format!("Bearer {}", token)));
+    if method != "GET" && !authorized {
+        return Response::error(401, "this server needs `Authorization: Bearer <token>` to change tasks");
+    }
+
+    if matches!(method, "POST" | "PATCH") && !request.content_type.as_deref().is_some_and(is_json) {
+        return Response::error(415, "requests that change tasks need `Content-Type: application/json`");
+    }
+
+    let body = if request.body.is_empty() {
+        Value::Null
+    } else {
+        match serde_json::from_slice(&request.body) {
+            Ok(body) => body,
+            Err(e) => return Response::error(400, &format!("the request body isn't valid JSON: {}", e)),
+        }
+    };
+
+    let result = match (method, segments.as_slice()) {
+        ("GET", ["tasks"]) => list_tasks(conn, &request.query),
+        ("POST", ["tasks"]) => add_tasks(conn, &body),
+        ("PATCH", ["tasks", idx]) => update_task(conn, idx, &body),
+        ("DELETE", ["tasks", idx]) => remove_task(conn, idx),
+        ("POST", ["sort"]) => sort_tasks(conn, &body),
+        (_, ["tasks"] | ["tasks", _] | ["sort"]) => Err(Response::error(405, &format!("{} isn't supported on {}", method, request.path))),
+        _ => Err(Response::error(404, &format!("no such endpoint {}", request.path))),
+    };
+
+    result.unwrap_or_else(|response| response)
+}
+
+/// A task as the API returns it, with the same fields and RFC 3339 times as `show --json`
+fn task_object(task: &Task) -> Value {
+    let timestamp = |t: &Option<String>| t.as_deref().and_then(parse_timestamp).map(|t| t.to_rfc3339());
+
+    json!({
+        "id": task.id,
+        "idx": task.idx,
+        "name": task.name,
+        "done": task.done,
+        "created_at": timestamp(&task.created_at),
+        "completed_at": timestamp(&task.completed_at),
+        "due_at": timestamp(&task.due_at),
+        "note": task.note,
+        "recurrence": task.recurrence,
+    })
+}
+
+fn database_error(e: impl std::fmt::Display) -> Response {
+    Response::error(500, &e.to_string())
+}
+
+fn find_task(conn: &Connection, idx: &str) -> Result<Task, Response> {
+    let index: i32 = idx.parse().map_err(|_| Response::error(400, &format!("invalid task index '{}'", idx)))?;
+
+    match get_task_from_db(conn, &index) {
+        Ok(Some(task)) => Ok(task),
+        Ok(None) => Err(Response::error(404, &format!("no task at index {}", index))),
+        Err(e) => Err(database_error(e)),
+    }
+}
+
+/// The task with `id` as it is after a change, which may have moved it
+fn reread_task(conn: &Connection, id: i32) -> Result<Value, Response> {
+    match get_task_by_id(conn, &id) {
+        Ok(task) => Ok(task.as_ref().map_or(Value::Null, task_object)),
+        Err(e) => Err(database_error(e)),
+    }
+}
+
+/// `GET /tasks`, or `GET /tasks?filter=todo` or `done`
+fn list_tasks(conn: &Connection, query: &str) -> Result<Response, Response> {
+    let filter = query.split('&').find_map(|pair| pair.strip_prefix("filter=")).unwrap_or("all");
+    let subset = TaskSubset::parse(filter).ok_or_else(|| Response::error(400, &format!("invalid filter '{}', use all, todo or done", filter)))?;
+
+    let tasks = find_tasks_from_db(conn, "", &SearchMode::Substring { case_sensitive: false }, subset).map_err(database_error)?;
+    Ok(Response::ok(json!({ "tasks": tasks.iter().map(task_object).collect::<Vec<_>>() })))
+}
+
+/// `POST /tasks` with `{"name": "..."}` or `{"names": ["...", ...]}`, turning names away the way
+/// `add` does unless `"force": true` is given
+fn add_tasks(conn: &mut Connection, body: &Value) -> Result<Response, Response> {
+    let usage = || Response::error(400, "expected {\"name\": \"...\"} or {\"names\": [\"...\"]}");
+    let names: Vec<String> = match (body.get("name"), body.get("names")) {
+        (Some(Value::String(name)), None) => vec![name.clone()],
+        (None, Some(Value::Array(names))) => names.iter().map(|name| name.as_str().map(String::from)).collect::<Option<_>>().ok_or_else(usage)?,
+        _ => return Err(usage()),
+    };
+    let force = force_flag(body)?;
+
+    let names: Vec<String> = names.into_iter().filter(|name| !name.trim().is_empty()).collect();
+    if names.is_empty() {
+        return Err(Response::error(400, "task names can't be empty"));
+    }
+
+    let existing = get_tasks_from_db(conn).map_err(database_error)?;
+    let checked = find_duplicate_names(&existing, &names);
+    let duplicates: Vec<String> = checked.iter()
+        .filter_map(|(name, duplicate_of)| duplicate_of.as_ref().map(|duplicate_of| format!("'{}' not saved, {}", name, duplicate_of)))
+        .collect();
+    if !force && !duplicates.is_empty() {
+        return Err(Response::error(409, &format!("{}; pass \"force\": true to keep similar names anyway", duplicates.join("; "))));
+    }
+
+    let tasks: Vec<Task> = checked.into_iter().map(|(name, _)| Task { name, ..Task::default() }).collect();
+    let ids = add_tasks_to_db(conn, &tasks).map_err(database_error)?;
+    let added = ids.iter().map(|id| reread_task(conn, *id)).collect::<Result<Vec<_>, _>>()?;
+    Ok(Response { status: 201, body: json!({ "tasks": added }) })
+}
+
+/// The optional `"force"` of a request body, which keeps names similar to existing ones like `--force`
+fn force_flag(body: &Value) -> Result<bool, Response> {
+    match body.get("force") {
+        None => Ok(false),
+        Some(Value::Bool(force)) => Ok(*force),
+        Some(_) => Err(Response::error(400, "`force` must be true or false")),
+    }
+}
+
+/// `PATCH /tasks/:idx` with a new `name`, `done` or both
+fn update_task(conn: &mut Connection, idx: &str, body: &Value) -> Result<Response, Response> {
+    let task = find_task(conn, idx)?;
+    let (index, id) = (task.idx.unwrap(), task.id.unwrap());
+
+    let name = match body.get("name") {
+        None => None,
+        Some(Value::String(name)) if !name.trim().is_empty() => Some(name.clone()),
+        Some(_) => return Err(Response::error(400, "`name` must be a non-empty string")),
+    };
+    let done = match body.get("done") {
+        None => None,
+        Some(Value::Bool(done)) => Some(*done),
+        Some(_) => return Err(Response::error(400, "`done` must be true or false")),
+    };
+    if name.is_none() && done.is_none() {
+        return Err(Response::error(400, "expected `name`, `done` or both"));
+    }
+    let force = force_flag(body)?;
+
+    let name = match name {
+        Some(name) => {
+            let others: Vec<Task> = get_tasks_from_db(conn).map_err(database_error)?.into_iter().filter(|t| t.id != Some(id)).collect();
+            let (name, duplicate_of) = find_duplicate_names(&others, &[name]).remove(0);
+            if let (Some(duplicate_of), false) = (duplicate_of, force) {
+                return Err(Response::error(409, &format!("'{}' not saved, {}; pass \"force\": true to keep a similar name anyway", name, duplicate_of)));
+            }
+            Some(name)
+        }
+        None => None,
+    };
+
+    if let Some(name) = &name {
+        edit_task_in_db(conn, &index, name).map_err(database_error)?;
+    }
+    match done {
+        Some(true) if !task.done => { mark_tasks_in_db_as_done(conn, &[index], false).map_err(database_error)?; },
+        Some(false) if task.done => mark_task_in_db_as_todo(conn, &index).map_err(database_error)?,
+        _ => {},
+    }
+
+    Ok(Response::ok(json!({ "task": reread_task(conn, id)? })))
+}
+
+/// `DELETE /tasks/:idx`, which moves the task to the trash like `remove`
+fn remove_task(conn: &mut Connection, idx: &str) -> Result<Response, Response> {
+    let task = find_task(conn, idx)?;
+    let removed = remove_tasks_from_db(conn, &[task.idx.unwrap()], false).map_err(database_error)?;
+
+    match removed.first() {
+        Some(task) => Ok(Response::ok(json!({ "task": task_object(task) }))),
+        None => Err(Response::error(404, &format!("no task at index {}", idx))),
+    }
+}
+
+/// `POST /sort`, optionally with `{"key": "name", "reverse": true}`; the key defaults to `done`
+fn sort_tasks(conn: &mut Connection, body: &Value) -> Result<Response, Response> {
+    let key = match body.get("key") {
+        None => SortKey::Done,
+        Some(Value::String(key)) => SortKey::from_str(key, true).map_err(|_| Response::error(400, &format!("invalid sort key '{}', use done, name, created or priority", key)))?,
+        Some(_) => return Err(Response::error(400, "`key` must be a string")),
+    };
+    let reverse = body.get("reverse").and_then(Value::as_bool).unwrap_or(false);
+
+    sort_tasks_in_db(conn, key, reverse).map_err(database_error)?;
+    list_tasks(conn, "")
+}
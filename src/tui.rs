@@ -0,0 +1,486 @@
+use std::io::{self, Write};
+use std::panic;
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::terminal::{self, Clear, ClearType, EnableLineWrap, LeaveAlternateScreen, SetTitle};
+use crossterm::{execute, queue};
+use rusqlite::Connection;
+use unicode_width::UnicodeWidthStr;
+
+use crate::commands::{format_task_row, idx_width, ReviewDecision, SortKey, Task};
+use crate::database::{
+    add_tasks_to_db,
+    edit_task_in_db,
+    get_tasks_from_db_and_update_indices,
+    mark_tasks_in_db_as_done,
+    mark_task_in_db_as_done_by_id,
+    mark_task_in_db_as_todo,
+    remove_tasks_from_db,
+    sort_tasks_in_db,
+};
+use crate::editor::{AlternateScreenGuard, LineBuffer, RawModeGuard};
+use crate::utils::{done_text, format_clock, format_local_timestamp, parse_due, sanitize, styled, todo_text, wrap_text, Style};
+
+const HELP_TEXT: &str = "j/k move  space toggle  a add  e edit  d delete  s sort  q quit";
+
+enum Mode {
+    Browse,
+    Add(LineBuffer),
+    Edit(i32, LineBuffer),
+    ConfirmDelete(i32),
+}
+
+struct App {
+    conn: Connection,
+    tasks: Vec<Task>,
+    cursor: usize,
+    offset: usize,
+    mode: Mode,
+    message: String,
+}
+
+impl App {
+    fn reload(&mut self) {
+        match get_tasks_from_db_and_update_indices(&mut self.conn) {
+            Ok(tasks) => self.tasks = tasks,
+            Err(e) => self.message = format!("Failed to retrieve tasks: {}", e),
+        }
+        self.cursor = self.cursor.min(self.tasks.len().saturating_sub(1));
+    }
+
+    fn selected(&self) -> Option<&Task> {
+        self.tasks.get(self.cursor)
+    }
+
+    /// Shows the outcome of a database call and re-reads the list so it reflects what was stored
+    fn finish(&mut self, result: Result<(), String>, success: &str) {
+        self.message = match result {
+            Ok(_) => success.to_string(),
+            Err(e) => e,
+        };
+        self.reload();
+    }
+
+    fn toggle(&mut self) {
+        let Some(task) = self.selected() else { return };
+        let (idx, done, name) = (task.idx.unwrap(), task.done, task.name.clone());
+
+        if done {
+            let result = mark_task_in_db_as_todo(&mut self.conn, &idx).map_err(|e| format!("Failed to mark task {} as todo: {}", idx, e));
+            self.finish(result, &format!("Marked '{}' as todo", name));
+        } else {
+            let result = mark_tasks_in_db_as_done(&mut self.conn, &[idx], false).map(|_| ()).map_err(|e| format!("Failed to mark task {} as done: {}", idx, e));
+            self.finish(result, &format!("Marked '{}' as done", name));
+        }
+    }
+
+    fn add(&mut self, name: String) {
+        if name.trim().is_empty() {
+            self.message = String::from("Tasks cannot be empty or whitespace-only");
+            return;
+        }
+
+        let result = add_tasks_to_db(&mut self.conn, &[Task { name: name.clone(), ..Default::default() }]).map(|_| ()).map_err(|e| format!("Failed to add task: {}", e));
+        self.finish(result, &format!("Added '{}'", name));
+        self.cursor = self.tasks.len().saturating_sub(1);
+    }
+
+    fn edit(&mut self, idx: i32, name: String) {
+        if name.trim().is_empty() {
+            self.message = String::from("Tasks cannot be empty or whitespace-only");
+            return;
+        }
+
+        let result = edit_task_in_db(&mut self.conn, &idx, &name).map_err(|e| format!("Failed to modify task {}: {}", idx, e));
+        self.finish(result, &format!("Renamed task {} to '{}'", idx, name));
+    }
+
+    fn delete(&mut self, idx: i32) {
+        let result = remove_tasks_from_db(&mut self.conn, &[idx], false).map(|_| ()).map_err(|e| format!("Failed to remove task {}: {}", idx, e));
+        self.finish(result, &format!("Removed task {}", idx));
+    }
+
+    fn sort(&mut self) {
+        let result = sort_tasks_in_db(&mut self.conn, SortKey::Done, false).map_err(|e| format!("Failed to sort tasks: {}", e));
+        self.finish(result, "Tasks sorted");
+    }
+
+    fn render(&mut self, stdout: &mut io::Stdout) -> io::Result<()> {
+        let (_, rows) = terminal::size()?;
+        let rows = rows as usize;
+        let list_height = rows.saturating_sub(3).max(1);
+
+        if self.cursor < self.offset {
+            self.offset = self.cursor;
+        } else if self.cursor >= self.offset + list_height {
+            self.offset = self.cursor + 1 - list_height;
+        }
+
+        let done_count = self.tasks.iter().filter(|t| t.done).count();
+
+        queue!(stdout, Clear(ClearType::All), MoveTo(0, 0))?;
+        queue!(stdout, Style::Bold.print("TodoLn"), Style::Dim.print(&format!("  {} task(s), {} done", self.tasks.len(), done_count)))?;
+
+        if self.tasks.is_empty() {
+            queue!(stdout, MoveTo(0, 1), Print("  No tasks yet, press a to add one"))?;
+        }
+
+        let idx_width = idx_width(&self.tasks.iter().collect::<Vec<_>>());
+        for (row, task) in self.tasks.iter().enumerate().skip(self.offset).take(list_height) {
+            let pointer = if row == self.cursor { styled(Style::Bold, ">") } else { String::from(" ") };
+            queue!(stdout, MoveTo(0, (row - self.offset + 1) as u16), Print(pointer), Print(format_task_row(task, idx_width)))?;
+        }
+
+        let message_row = rows.saturating_sub(2) as u16;
+        let prompt_row = rows.saturating_sub(1) as u16;
+        queue!(stdout, MoveTo(0, message_row), Style::Warning.print(&self.message))?;
+
+        let (prompt, buffer) = match &self.mode {
+            Mode::Browse => {
+                queue!(stdout, MoveTo(0, prompt_row), Style::Dim.print(HELP_TEXT), Hide)?;
+                return stdout.flush();
+            }
+            Mode::ConfirmDelete(idx) => {
+                queue!(stdout, MoveTo(0, prompt_row), Print(format!("Delete task {}? (y/n)", idx)), Hide)?;
+                return stdout.flush();
+            }
+            Mode::Add(buffer) => (String::from("Add: "), buffer),
+            Mode::Edit(idx, buffer) => (format!("Edit [{}]: ", idx), buffer),
+        };
+
+        queue!(stdout, MoveTo(0, prompt_row), Print(&prompt), Print(buffer.text()))?;
+        queue!(stdout, MoveTo((prompt.width() + buffer.cursor_width()) as u16, prompt_row), Show)?;
+        stdout.flush()
+    }
+
+    /// Handles one key press, returning `false` once the user asks to quit
+    fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        if code == KeyCode::Char('c') && modifiers.contains(KeyModifiers::CONTROL) {
+            return false;
+        }
+
+        match std::mem::replace(&mut self.mode, Mode::Browse) {
+            Mode::Browse => match code {
+                KeyCode::Char('q') | KeyCode::Esc => return false,
+                KeyCode::Char('j') | KeyCode::Down => self.cursor = (self.cursor + 1).min(self.tasks.len().saturating_sub(1)),
+                KeyCode::Char('k') | KeyCode::Up => self.cursor = self.cursor.saturating_sub(1),
+                KeyCode::Char(' ') => self.toggle(),
+                KeyCode::Char('a') => self.mode = Mode::Add(LineBuffer::new("")),
+                KeyCode::Char('e') => {
+                    if let Some(task) = self.selected() {
+                        self.mode = Mode::Edit(task.idx.unwrap(), LineBuffer::new(&task.name));
+                    }
+                }
+                KeyCode::Char('d') => {
+                    if let Some(task) = self.selected() {
+                        self.mode = Mode::ConfirmDelete(task.idx.unwrap());
+                    }
+                }
+                KeyCode::Char('s') => self.sort(),
+                _ => {},
+            },
+            Mode::ConfirmDelete(idx) => match code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => self.delete(idx),
+                _ => self.message = String::from("Delete cancelled"),
+            },
+            Mode::Add(mut buffer) => match code {
+                KeyCode::Enter => self.add(buffer.text().to_string()),
+                KeyCode::Esc => {},
+                _ => {
+                    buffer.apply_key(code);
+                    self.mode = Mode::Add(buffer);
+                }
+            },
+            Mode::Edit(idx, mut buffer) => match code {
+                KeyCode::Enter => self.edit(idx, buffer.text().to_string()),
+                KeyCode::Esc => {},
+                _ => {
+                    buffer.apply_key(code);
+                    self.mode = Mode::Edit(idx, buffer);
+                }
+            },
+        }
+
+        true
+    }
+}
+
+/// Puts the terminal back to normal before the panic message is printed, so it isn't lost on the
+/// alternate screen or mangled by raw mode
+fn install_panic_hook() {
+    let previous_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        execute!(io::stdout(), Show, EnableLineWrap, LeaveAlternateScreen).ok();
+        terminal::disable_raw_mode().ok();
+        previous_hook(info);
+    }));
+}
+
+/// Runs the full-screen task list until the user quits
+pub fn run(conn: Connection) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    let mut app = App {
+        conn,
+        tasks: Vec::new(),
+        cursor: 0,
+        offset: 0,
+        mode: Mode::Browse,
+        message: String::new(),
+    };
+
+    app.reload();
+    install_panic_hook();
+
+    let _raw_mode = RawModeGuard::enable()?;
+    let _screen = AlternateScreenGuard::enter(&mut stdout)?;
+
+    loop {
+        app.render(&mut stdout)?;
+
+        let Event::Key(KeyEvent { code, modifiers, kind, .. }) = event::read()? else {
+            continue;
+        };
+
+        if kind != KeyEventKind::Press {
+            continue;
+        }
+
+        if !app.handle_key(code, modifiers) {
+            break;
+        }
+    }
+
+    execute!(stdout, Show)?;
+
+    Ok(())
+}
+
+fn render_session(stdout: &mut io::Stdout, tasks: &[Task], completed: &[bool], remaining: Duration, message: &str) -> io::Result<()> {
+    let clock = format_clock(remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0));
+
+    queue!(stdout, SetTitle(format!("todoln {}", clock)), Clear(ClearType::All), MoveTo(0, 0))?;
+    queue!(stdout, Style::Bold.print("Focus session"), Print(format!("  {} left", clock)))?;
+
+    for (i, task) in tasks.iter().enumerate() {
+        let name = if completed[i] { done_text(&task.name) } else { todo_text(&task.name) };
+        queue!(stdout, MoveTo(0, i as u16 + 2), Print(format!("  {}  {}", styled(Style::Bold, &(i + 1).to_string()), name)))?;
+    }
+
+    let footer_row = tasks.len() as u16 + 3;
+    queue!(stdout, MoveTo(0, footer_row), Style::Warning.print(message))?;
+    queue!(stdout, MoveTo(0, footer_row + 1), Style::Dim.print(&format!("1-{} mark a task done  q finish early", tasks.len())))?;
+    stdout.flush()
+}
+
+/// Counts down `duration` over `tasks`, marking them done as their number keys are pressed.
+/// Returns how long the session actually ran and which of `tasks` were completed.
+pub fn run_session(conn: &Connection, tasks: &[Task], duration: Duration) -> io::Result<(Duration, Vec<bool>)> {
+    let mut stdout = io::stdout();
+    let mut completed = vec![false; tasks.len()];
+    let mut message = String::new();
+
+    install_panic_hook();
+
+    let _raw_mode = RawModeGuard::enable()?;
+    let _screen = AlternateScreenGuard::enter(&mut stdout)?;
+    queue!(stdout, Hide)?;
+
+    let start = Instant::now();
+
+    loop {
+        let elapsed = start.elapsed();
+        if elapsed >= duration {
+            break;
+        }
+
+        let remaining = duration - elapsed;
+        render_session(&mut stdout, tasks, &completed, remaining, &message)?;
+
+        // Wake up on the next whole second so the countdown ticks evenly
+        let until_tick = Duration::from_nanos(u64::from(remaining.subsec_nanos())).max(Duration::from_millis(1));
+        if !event::poll(until_tick.min(remaining))? {
+            continue;
+        }
+
+        let Event::Key(KeyEvent { code, modifiers, kind, .. }) = event::read()? else {
+            continue;
+        };
+
+        if kind != KeyEventKind::Press {
+            continue;
+        }
+
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => break,
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => break,
+            KeyCode::Char(c @ '1'..='9') => {
+                let i = c as usize - '1' as usize;
+
+                if i < tasks.len() && !completed[i] {
+                    message = match mark_task_in_db_as_done_by_id(conn, &tasks[i].id.unwrap()) {
+                        Ok(_) => {
+                            completed[i] = true;
+                            format!("Completed '{}'", tasks[i].name)
+                        }
+                        Err(e) => format!("Failed to mark '{}' as done: {}", tasks[i].name, e),
+                    };
+                }
+            }
+            _ => {},
+        }
+    }
+
+    execute!(stdout, SetTitle(""), Show)?;
+
+    Ok((start.elapsed().min(duration), completed))
+}
+
+const REVIEW_HELP_TEXT: &str = "k keep  d done  x delete  s snooze  e edit  q quit";
+
+enum ReviewMode {
+    Choose,
+    Snooze(LineBuffer),
+    Edit(LineBuffer),
+}
+
+fn render_review(stdout: &mut io::Stdout, tasks: &[Task], position: usize, mode: &ReviewMode, message: &str) -> io::Result<()> {
+    let task = &tasks[position];
+    let (columns, rows) = terminal::size()?;
+
+    queue!(stdout, Clear(ClearType::All), MoveTo(0, 0))?;
+    queue!(stdout, Style::Bold.print("Weekly review"), Style::Dim.print(&format!("  task {} of {}", position + 1, tasks.len())))?;
+    queue!(stdout, MoveTo(0, 2), Print(format!("  {}", styled(Style::Bold, &task.name))))?;
+
+    let mut row = 4;
+    queue!(stdout, MoveTo(0, row), Print(format!("  {} {}", styled(Style::Bold, "Index:"), task.idx.unwrap())))?;
+
+    if let Some(due_at) = &task.due_at {
+        row += 1;
+        queue!(stdout, MoveTo(0, row), Print(format!("  {} {}", styled(Style::Bold, "Due:"), format_local_timestamp(due_at))))?;
+    }
+
+    if let Some(note) = &task.note {
+        row += 1;
+        for line in wrap_text(note, (columns as usize).saturating_sub(4)) {
+            row += 1;
+            queue!(stdout, MoveTo(0, row), Print(format!("  {}", sanitize(&line))))?;
+        }
+    }
+
+    let message_row = rows.saturating_sub(2);
+    let prompt_row = rows.saturating_sub(1);
+    queue!(stdout, MoveTo(0, message_row), Style::Warning.print(message))?;
+
+    let (prompt, buffer) = match mode {
+        ReviewMode::Choose => {
+            queue!(stdout, MoveTo(0, prompt_row), Style::Dim.print(REVIEW_HELP_TEXT), Hide)?;
+            return stdout.flush();
+        }
+        ReviewMode::Snooze(buffer) => ("Snooze for (e.g. 3d, 2w): ", buffer),
+        ReviewMode::Edit(buffer) => ("Edit: ", buffer),
+    };
+
+    queue!(stdout, MoveTo(0, prompt_row), Print(prompt), Print(buffer.text()))?;
+    queue!(stdout, MoveTo((prompt.width() + buffer.cursor_width()) as u16, prompt_row), Show)?;
+    stdout.flush()
+}
+
+/// Steps through `tasks` one at a time, asking what to do with each. Nothing is written here;
+/// returns the decisions made, leaving out kept tasks, and how many tasks were reviewed before
+/// the user quit.
+pub fn run_review(tasks: &[Task]) -> io::Result<(Vec<ReviewDecision>, usize)> {
+    let mut stdout = io::stdout();
+    let mut decisions = Vec::new();
+    let mut position = 0;
+    let mut mode = ReviewMode::Choose;
+    let mut message = String::new();
+
+    install_panic_hook();
+
+    let _raw_mode = RawModeGuard::enable()?;
+    let _screen = AlternateScreenGuard::enter(&mut stdout)?;
+
+    while position < tasks.len() {
+        render_review(&mut stdout, tasks, position, &mode, &message)?;
+
+        let Event::Key(KeyEvent { code, modifiers, kind, .. }) = event::read()? else {
+            continue;
+        };
+
+        if kind != KeyEventKind::Press {
+            continue;
+        }
+
+        if code == KeyCode::Char('c') && modifiers.contains(KeyModifiers::CONTROL) {
+            break;
+        }
+
+        let task = &tasks[position];
+        let (id, name) = (task.id.unwrap(), task.name.clone());
+        let mut decided = None;
+
+        match std::mem::replace(&mut mode, ReviewMode::Choose) {
+            ReviewMode::Choose => match code {
+                KeyCode::Char('q') | KeyCode::Esc => break,
+                KeyCode::Char('k') => {
+                    message = format!("Kept '{}'", name);
+                    position += 1;
+                }
+                KeyCode::Char('d') => decided = Some(ReviewDecision::Done { id, name }),
+                KeyCode::Char('x') => decided = Some(ReviewDecision::Remove { id, name }),
+                KeyCode::Char('s') => mode = ReviewMode::Snooze(LineBuffer::new("")),
+                KeyCode::Char('e') => mode = ReviewMode::Edit(LineBuffer::new(&task.name)),
+                _ => {},
+            },
+            ReviewMode::Snooze(mut buffer) => match code {
+                KeyCode::Enter => match parse_due(buffer.text()) {
+                    Some(due_at) => decided = Some(ReviewDecision::Snooze { id, name, due_at }),
+                    None => {
+                        message = format!("Invalid duration '{}'", buffer.text());
+                        mode = ReviewMode::Snooze(buffer);
+                    }
+                },
+                KeyCode::Esc => {},
+                _ => {
+                    buffer.apply_key(code);
+                    mode = ReviewMode::Snooze(buffer);
+                }
+            },
+            ReviewMode::Edit(mut buffer) => match code {
+                KeyCode::Enter if buffer.text().trim().is_empty() => {
+                    message = String::from("Tasks cannot be empty or whitespace-only");
+                    mode = ReviewMode::Edit(buffer);
+                }
+                KeyCode::Enter if buffer.text() == name => {
+                    message = format!("Kept '{}'", name);
+                    position += 1;
+                }
+                KeyCode::Enter => decided = Some(ReviewDecision::Rename { id, name, new_name: buffer.text().to_string() }),
+                KeyCode::Esc => {},
+                _ => {
+                    buffer.apply_key(code);
+                    mode = ReviewMode::Edit(buffer);
+                }
+            },
+        }
+
+        if let Some(decision) = decided {
+            message = match &decision {
+                ReviewDecision::Done { name, .. } => format!("Will mark '{}' as done", name),
+                ReviewDecision::Remove { name, .. } => format!("Will remove '{}'", name),
+                ReviewDecision::Snooze { name, due_at, .. } => format!("Will snooze '{}' until {}", name, format_local_timestamp(due_at)),
+                ReviewDecision::Rename { name, new_name, .. } => format!("Will rename '{}' to '{}'", name, new_name),
+            };
+            decisions.push(decision);
+            position += 1;
+        }
+    }
+
+    execute!(stdout, Show)?;
+
+    Ok((decisions, position))
+}
@@ -1,26 +1,598 @@
-use crossterm::style::Stylize;
+use std::io::{self, IsTerminal, Write};
+use std::mem;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{DateTime, Datelike, Local, Months, NaiveDate, NaiveDateTime, TimeDelta, Utc, Weekday};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{ContentStyle, PrintStyledContent, Stylize};
+use crossterm::terminal;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Replaces control characters with visible stand-ins so task names can't emit escape
+/// sequences or fake extra rows. C0 controls and DEL become their Unicode control pictures
+/// (ESC shows as ␛), C1 controls become the replacement character.
+pub fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '\u{0}'..='\u{1f}' => char::from_u32(0x2400 + c as u32).unwrap_or('\u{fffd}'),
+            '\u{7f}' => '\u{2421}',
+            '\u{80}'..='\u{9f}' => '\u{fffd}',
+            _ => c,
+        })
+        .collect()
+}
+
+/// Shows the exact stored content, with control characters written as escapes like `\u{1b}`
+pub fn escape_text(s: &str) -> String {
+    s.escape_debug().to_string()
+}
+
+/// The kinds of styled output, kept apart from any text so that plain output (`styled`) and the
+/// interactive views (`Style::print`) draw them the same way, with crossterm leaving out the
+/// colours when NO_COLOR is set
+#[derive(Clone, Copy)]
+pub enum Style {
+    Success,
+    Error,
+    Warning,
+    Title,
+    Bold,
+    Todo,
+    Done,
+    Dim,
+    /// Part of a task name that matched a search, in a todo or (with `true`) done task
+    Match(bool),
+}
+
+impl Style {
+    /// The colour and attributes the style stands for, or none at all with `--no-color`
+    pub fn content_style(self) -> ContentStyle {
+        let style = ContentStyle::new();
+
+        if plain_output() {
+            return style;
+        }
+
+        match self {
+            Style::Success => style.green(),
+            Style::Error => style.red(),
+            Style::Warning => style.yellow(),
+            Style::Title => style.bold().underlined(),
+            Style::Bold | Style::Match(false) => style.bold(),
+            Style::Todo => style,
+            Style::Done => style.dark_grey().crossed_out(),
+            Style::Dim => style.dark_grey(),
+            Style::Match(true) => style.bold().dark_grey().crossed_out(),
+        }
+    }
+
+    /// Draws `s` in this style inside an interactive view, e.g. with `queue!`
+    pub fn print(self, s: &str) -> PrintStyledContent<String> {
+        PrintStyledContent(self.content_style().apply(sanitize(s)))
+    }
+}
+
+/// Renders `s` in `style` for plain terminal output, with control characters made visible
+pub fn styled(style: Style, s: &str) -> String {
+    style.content_style().apply(sanitize(s)).to_string()
+}
+
+/// How much todoln prints besides what a command was asked for, from `--quiet` and `--verbose`
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum Verbosity {
+    /// Only output and errors: no success messages, titles or "No tasks found." notices
+    Quiet,
+    #[default]
+    Normal,
+    /// Also the database used, the SQL run and how long the command took, on stderr
+    Verbose,
+}
+
+static VERBOSITY: OnceLock<Verbosity> = OnceLock::new();
+
+/// Sets the verbosity for the rest of the run; only the first call counts
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.set(verbosity).ok();
+}
+
+pub fn verbosity() -> Verbosity {
+    VERBOSITY.get().copied().unwrap_or_default()
+}
+
+static PLAIN_OUTPUT: OnceLock<bool> = OnceLock::new();
+
+/// Leaves out all colours and styling for the rest of the run
+pub fn set_plain_output(plain: bool) {
+    PLAIN_OUTPUT.set(plain).ok();
+}
+
+pub fn plain_output() -> bool {
+    PLAIN_OUTPUT.get().copied().unwrap_or_default()
+}
+
+static JSON_OUTPUT: OnceLock<bool> = OnceLock::new();
+
+/// Makes commands that change tasks print what they did as JSON, for the rest of the run
+pub fn set_json_output(json: bool) {
+    JSON_OUTPUT.set(json).ok();
+}
+
+pub fn json_output() -> bool {
+    JSON_OUTPUT.get().copied().unwrap_or_default()
+}
+
+/// Whether messages meant for people are left out, with `--quiet` or because stdout carries JSON
+pub fn is_quiet() -> bool {
+    verbosity() == Verbosity::Quiet || json_output()
+}
+
+/// The dimmed line saying there is nothing to show, or nothing with `--quiet`
+pub fn empty_text(s: &str) -> String {
+    if is_quiet() { String::new() } else { format!("{}\n", dim_text(s)) }
+}
+
+/// Prints a notice such as "No tasks to review.", unless `--quiet`. It is sanitized like `styled`
+/// text, since notices can name tasks.
+pub fn print_notice(s: &str) {
+    if !is_quiet() {
+        println!("{}", sanitize(s));
+    }
+}
+
+/// Prints a dimmed debugging line to stderr with `--verbose`
+pub fn print_verbose(s: &str) {
+    if verbosity() == Verbosity::Verbose {
+        eprintln!("{}", dim_text(s));
+    }
+}
 
 pub fn print_success(s: &str) { 
-    println!("{}", s.green());
+    if !is_quiet() {
+        println!("{}", styled(Style::Success, s));
+    }
 }
 
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Errors and warnings go to stderr, so they show even when the output is piped and `--quiet`
+/// never hides them. Printing an error also makes todoln exit with status 1 once it's done.
 pub fn print_error(s: &str) {
-    println!("{}", s.red());
+    FAILED.store(true, Ordering::Relaxed);
+    eprintln!("{}", styled(Style::Error, s));
+}
+
+/// Whether an error has been printed during this run
+pub fn failed() -> bool {
+    FAILED.load(Ordering::Relaxed)
+}
+
+pub fn print_warning(s: &str) {
+    eprintln!("{}", styled(Style::Warning, s));
 }
 
 pub fn print_title(s: &str) { 
-    println!("{}", s.bold().underlined());
-    println!();
+    if !is_quiet() {
+        println!("{}", title_text(s));
+        println!();
+    }
+}
+
+pub fn title_text(s: &str) -> String {
+    styled(Style::Title, s)
 }
 
 pub fn bold_text(s: &str) -> String {
-    s.bold().to_string()
+    styled(Style::Bold, s)
 }
 
 pub fn todo_text(s: &str) -> String {
-    s.to_string()
+    styled(Style::Todo, s)
 }
 
 pub fn done_text(s: &str) -> String {
-    s.dark_grey().crossed_out().to_string()
+    styled(Style::Done, s)
+}
+
+pub fn dim_text(s: &str) -> String {
+    styled(Style::Dim, s)
+}
+
+/// Breaks `s` into lines at most `width` columns wide, at spaces where possible. Line breaks
+/// already in `s` are kept.
+pub fn wrap_text(s: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+
+    for paragraph in s.lines() {
+        let mut line = String::new();
+
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.width() + 1 + word.width() > width {
+                lines.push(mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+
+            // A single word longer than the line is split wherever it has to be
+            while line.width() > width {
+                let (mut end, mut used) = (0, 0);
+                for (i, c) in line.char_indices() {
+                    let w = c.width().unwrap_or(0);
+                    if used + w > width && i > 0 {
+                        break;
+                    }
+                    used += w;
+                    end = i + c.len_utf8();
+                }
+
+                let rest = line.split_off(end);
+                lines.push(mem::replace(&mut line, rest));
+            }
+        }
+
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Cuts `s` down to at most `width` columns, ending it with "…" if anything was cut
+pub fn truncate_text(s: &str, width: usize) -> String {
+    if s.width() <= width {
+        return s.to_string();
+    }
+
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in s.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w + 1 > width {
+            break;
+        }
+        used += w;
+        truncated.push(c);
+    }
+
+    if width > 0 {
+        truncated.push('…');
+    }
+    truncated
+}
+
+/// Bold version of `todo_text` or `done_text`, for highlighting part of a task name
+pub fn match_text(s: &str, done: bool) -> String {
+    styled(Style::Match(done), s)
+}
+
+/// Styles a task name like `list` does, with every byte range in `ranges` that a search matched
+/// in bold. Without `color` (for `--no-color`, or output that isn't a terminal) the matches are
+/// left unmarked, so piped names come out as they are.
+pub fn highlight_matches(name: &str, done: bool, ranges: &[(usize, usize)], color: bool) -> String {
+    let plain = |s: &str| if done { done_text(s) } else { todo_text(s) };
+    if !color {
+        return plain(name);
+    }
+
+    let mut styled = String::new();
+    let mut last = 0;
+
+    for &(start, end) in ranges {
+        styled.push_str(&plain(&name[last..start]));
+        styled.push_str(&match_text(&name[start..end], done));
+        last = end;
+    }
+
+    styled.push_str(&plain(&name[last..]));
+    styled
+}
+
+/// Splits a task name at the first `delimiter` outside double quotes into the name itself and a
+/// trailing comment, both trimmed. The comment is `None` if the name has no delimiter.
+pub fn split_comment<'a>(name: &'a str, delimiter: &str) -> (&'a str, Option<&'a str>) {
+    if delimiter.is_empty() {
+        return (name, None);
+    }
+
+    let mut in_quotes = false;
+
+    for (i, c) in name.char_indices() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        } else if !in_quotes && name[i..].starts_with(delimiter) {
+            return (name[..i].trim_end(), Some(name[i + delimiter.len()..].trim()));
+        }
+    }
+
+    (name, None)
+}
+
+/// Puts a name and comment split by `split_comment` back together. An empty comment is dropped.
+pub fn join_comment(name: &str, comment: Option<&str>, delimiter: &str) -> String {
+    match comment.filter(|comment| !comment.is_empty()) {
+        Some(comment) => format!("{} {} {}", name, delimiter, comment),
+        None => name.to_string(),
+    }
+}
+
+/// Prints `question` and reads one line of answer, which is empty at the end of input
+pub fn ask(question: &str) -> String {
+    print!("{} ", sanitize(question));
+    io::stdout().flush().ok();
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return String::new();
+    }
+
+    answer
+}
+
+/// Asks for a passphrase on stderr and reads it without showing what's typed. Without a terminal
+/// it reads a line from stdin instead. Ctrl+C exits.
+pub fn ask_secret(question: &str) -> String {
+    eprint!("{} ", sanitize(question));
+    io::stderr().flush().ok();
+
+    if !io::stdin().is_terminal() || terminal::enable_raw_mode().is_err() {
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).ok();
+        return answer.trim_end_matches(['\r', '\n']).to_string();
+    }
+
+    let mut answer = String::new();
+    let cancelled = loop {
+        let Ok(Event::Key(key)) = event::read() else { continue };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Enter => break false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break true,
+            KeyCode::Char(c) => answer.push(c),
+            KeyCode::Backspace => { answer.pop(); },
+            _ => {},
+        }
+    };
+
+    terminal::disable_raw_mode().ok();
+    eprintln!();
+    if cancelled {
+        std::process::exit(130);
+    }
+
+    answer
+}
+
+/// Asks a yes/no question, treating anything but "y" or "yes" (including end of input) as no
+pub fn confirm(question: &str) -> bool {
+    let answer = ask(&format!("{} [y/N]", question));
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Parses durations such as "90s", "30m", "12h", "14d" or "2w" into seconds
+pub fn parse_duration(s: &str) -> Option<i64> {
+    let s = s.trim();
+    let unit = s.chars().last()?;
+    let amount: i64 = s[..s.len() - unit.len_utf8()].trim().parse().ok()?;
+
+    let multiplier = match unit.to_ascii_lowercase() {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 60 * 60 * 24,
+        'w' => 60 * 60 * 24 * 7,
+        _ => return None,
+    };
+
+    if amount < 0 {
+        return None;
+    }
+
+    amount.checked_mul(multiplier)
+}
+
+/// A task name as it's stored: surrounding whitespace trimmed and inner runs collapsed to one
+/// space, keeping the casing it was given
+pub fn clean_name(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Key used to spot logical duplicates: surrounding whitespace trimmed, inner runs collapsed
+/// and Unicode case folded, so "Buy  Milk " and "buy milk" compare equal
+pub fn normalize_name(s: &str) -> String {
+    caseless::default_case_fold_str(&clean_name(s))
+}
+
+/// Timestamps are stored in UTC as SQLite's `datetime('now')` writes them
+pub fn parse_timestamp(s: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").ok().map(|t| t.and_utc())
+}
+
+/// Parses a due date given as a local "YYYY-MM-DD" (the end of that day) or "YYYY-MM-DD HH:MM",
+/// or as a duration from now such as "3d", into the UTC format the database stores
+pub fn parse_due(s: &str) -> Option<String> {
+    let s = s.trim();
+
+    let due = if let Some(seconds) = parse_duration(s) {
+        Utc::now() + TimeDelta::try_seconds(seconds)?
+    } else {
+        let local = match NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M") {
+            Ok(local) => local,
+            Err(_) => NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?.and_hms_opt(23, 59, 59)?,
+        };
+        local.and_local_timezone(Local).earliest()?.with_timezone(&Utc)
+    };
+
+    Some(due.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+/// Parses when a snoozed task wakes up: a time from now ("3d"), "tomorrow", a weekday ("monday",
+/// the next one after today), a date ("2024-05-01") or a date and time ("2024-05-01 14:00"). Days
+/// without a time wake at midnight, so the task is back for all of that day.
+pub fn parse_wake(s: &str) -> Option<String> {
+    let s = s.trim();
+
+    if parse_duration(s).is_some() {
+        return parse_due(s);
+    }
+
+    let today = Local::now().date_naive();
+    let local = if s.eq_ignore_ascii_case("tomorrow") {
+        (today + TimeDelta::days(1)).and_hms_opt(0, 0, 0)?
+    } else if let Ok(weekday) = s.parse::<Weekday>() {
+        (1..=7).map(|days| today + TimeDelta::days(days)).find(|day| day.weekday() == weekday)?.and_hms_opt(0, 0, 0)?
+    } else {
+        match NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M") {
+            Ok(local) => local,
+            Err(_) => NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0)?,
+        }
+    };
+
+    let wake = local.and_local_timezone(Local).earliest()?.with_timezone(&Utc);
+    Some(wake.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+/// The local days a period written as a year-month ("2024-06"), ISO week ("2024-W26") or date
+/// ("2024-06-03") covers, as its first day and the day after its last
+fn parse_period_days(s: &str) -> Option<(NaiveDate, NaiveDate)> {
+    let (year, rest) = s.split_once('-')?;
+    let year: i32 = year.parse().ok().filter(|_| year.len() == 4)?;
+
+    if let Some(week) = rest.strip_prefix(['W', 'w']) {
+        let week: u32 = week.parse().ok().filter(|_| week.len() == 2)?;
+        let monday = NaiveDate::from_isoywd_opt(year, week, Weekday::Mon)?;
+        return Some((monday, monday + TimeDelta::days(7)));
+    }
+
+    if rest.len() == 2 {
+        let first = NaiveDate::from_ymd_opt(year, rest.parse().ok()?, 1)?;
+        return Some((first, first.checked_add_months(Months::new(1))?));
+    }
+
+    let day = NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?;
+    Some((day, day + TimeDelta::days(1)))
+}
+
+/// A span of time as the UTC times it starts at and ends before
+pub type Period = (DateTime<Utc>, DateTime<Utc>);
+
+/// Parses a period of local days into the UTC times it starts at and ends before: a year-month
+/// ("2024-06"), an ISO week ("2024-W26"), a date ("2024-06-03"), or a range of any two of them
+/// ("2024-06-01..2024-06-15", both ends included)
+pub fn parse_period(s: &str) -> Option<Period> {
+    let s = s.trim();
+
+    let (start, end) = match s.split_once("..") {
+        Some((first, last)) => (parse_period_days(first.trim())?.0, parse_period_days(last.trim())?.1),
+        None => parse_period_days(s)?,
+    };
+
+    if start >= end {
+        return None;
+    }
+
+    Some((local_midnight(start)?, local_midnight(end)?))
+}
+
+/// How long ago something happened, in its largest whole unit: 5m, 3h, 2d, 6w or 1y
+pub fn format_age(seconds: i64) -> String {
+    let minutes = seconds.max(0) / 60;
+    let (hours, days) = (minutes / 60, minutes / (60 * 24));
+
+    match days {
+        0 if hours == 0 => format!("{}m", minutes),
+        0 => format!("{}h", hours),
+        1..=13 => format!("{}d", days),
+        14..=364 => format!("{}w", days / 7),
+        _ => format!("{}y", days / 365),
+    }
+}
+
+/// A number of seconds as hours and minutes, like "1h 23m", or just minutes under an hour
+pub fn format_duration(seconds: i64) -> String {
+    let minutes = seconds.max(0) / 60;
+
+    match minutes / 60 {
+        0 => format!("{}m", minutes),
+        hours => format!("{}h {}m", hours, minutes % 60),
+    }
+}
+
+/// When a local day starts, in UTC
+pub fn local_midnight(day: NaiveDate) -> Option<DateTime<Utc>> {
+    day.and_hms_opt(0, 0, 0)?.and_local_timezone(Local).earliest().map(|t| t.with_timezone(&Utc))
+}
+
+/// Whether a timestamp as the database stores it falls within a period from `parse_period`
+pub fn in_period(timestamp: Option<&str>, (start, end): Period) -> bool {
+    timestamp.and_then(parse_timestamp).is_some_and(|t| t >= start && t < end)
+}
+
+/// Whether a task snoozed until `snoozed_until` is still hidden
+pub fn is_snoozed(snoozed_until: Option<&str>) -> bool {
+    snoozed_until.and_then(parse_timestamp).is_some_and(|wake| wake > Utc::now())
+}
+
+/// Parses a recurrence rule: a number of days or weeks ("3d", "2w") or weekday names ("mon",
+/// "monday,thursday"). Returns it normalized, e.g. "mon,thu", so it can be stored as is.
+pub fn parse_recurrence(s: &str) -> Option<String> {
+    let s = s.trim().to_lowercase();
+
+    if let Some(unit) = s.chars().last().filter(|unit| matches!(unit, 'd' | 'w')) {
+        if let Ok(amount) = s[..s.len() - 1].trim().parse::<u32>() {
+            return (amount > 0).then(|| format!("{}{}", amount, unit));
+        }
+    }
+
+    let mut weekdays: Vec<Weekday> = Vec::new();
+    for name in s.split(',') {
+        let weekday: Weekday = name.trim().parse().ok()?;
+        if !weekdays.contains(&weekday) {
+            weekdays.push(weekday);
+        }
+    }
+    weekdays.sort_by_key(|weekday| weekday.num_days_from_monday());
+
+    Some(weekdays.iter().map(|weekday| weekday.to_string().to_lowercase()).collect::<Vec<_>>().join(","))
+}
+
+/// When a task repeating by a rule from `parse_recurrence` is next due, counting from now: that
+/// many days or weeks later, or the end of the next matching weekday after today
+pub fn next_occurrence(rule: &str) -> Option<String> {
+    if parse_duration(rule).is_some() {
+        return parse_due(rule);
+    }
+
+    let weekdays: Vec<Weekday> = rule.split(',').map(|name| name.parse().ok()).collect::<Option<_>>()?;
+    let today = Local::now().date_naive();
+    let next = (1..=7).map(|days| today + TimeDelta::days(days)).find(|day| weekdays.contains(&day.weekday()))?;
+
+    parse_due(&next.format("%Y-%m-%d").to_string())
+}
+
+/// Draws `done` out of `total` as a bar of `width` cells, like "[####------]"
+pub fn progress_bar(done: usize, total: usize, width: usize) -> String {
+    let filled = (done.min(total) * width).checked_div(total).unwrap_or(0);
+    format!("[{}{}]", "#".repeat(filled), "-".repeat(width - filled))
+}
+
+/// Formats a number of seconds as a countdown clock, "mm:ss" or "h:mm:ss"
+pub fn format_clock(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{:02}:{:02}", minutes, seconds)
+    }
+}
+
+pub fn format_local_timestamp(s: &str) -> String {
+    match parse_timestamp(s) {
+        Some(t) => t.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string(),
+        None => s.to_string(),
+    }
 }
\ No newline at end of file
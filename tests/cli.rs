@@ -0,0 +1,1312 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::thread;
+
+use assert_cmd::Command;
+use clap::CommandFactory;
+use predicates::prelude::*;
+use regex::Regex;
+use tempfile::TempDir;
+use todoln::commands::{Cli, INTROSPECT_VERSION};
+
+/// A temporary directory holding the task database, the config and any files a test writes.
+/// `todoln` runs in it with `TODOLN_DB` pointing at its database, so the real task list and config
+/// are never touched.
+struct Todoln {
+    dir: TempDir,
+}
+
+impl Todoln {
+    fn new() -> Self {
+        Todoln { dir: TempDir::new().unwrap() }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    fn cmd(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::cargo_bin("todoln").unwrap();
+        cmd.args(args)
+            .current_dir(self.dir.path())
+            .env("TODOLN_DB", self.path("todoln.db"))
+            .env("XDG_DATA_HOME", self.dir.path())
+            .env("XDG_CONFIG_HOME", self.dir.path())
+            .env_remove("PAGER")
+            .env_remove("LC_ALL")
+            .env_remove("LC_MESSAGES")
+            .env_remove("LANG");
+        cmd
+    }
+
+    /// Runs a command that has to succeed, returning what it printed
+    fn run(&self, args: &[&str]) -> String {
+        let output = self.cmd(args).assert().success().get_output().stdout.clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    /// The task names as `raw all` prints them
+    fn names(&self) -> String {
+        self.run(&["raw", "all"])
+    }
+}
+
+fn strip_ansi(s: &str) -> String {
+    Regex::new("\x1b\\[[0-9;]*m").unwrap().replace_all(s, "").into_owned()
+}
+
+#[test]
+fn add_then_list() {
+    let todoln = Todoln::new();
+    todoln.cmd(&["add", "buy milk", "walk dog"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Task(s) added successfully: buy milk, walk dog"));
+
+    assert_eq!(strip_ansi(&todoln.run(&["list", "all"])), "Tasks:\n\n  [1] · buy milk\n  [2] · walk dog\n\n0/2 done (0%)\n");
+}
+
+#[test]
+fn list_styles() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "buy milk", "walk dog"]);
+    todoln.run(&["done", "1"]);
+    todoln.run(&["note", "2", "the long way round"]);
+
+    assert_eq!(
+        todoln.run(&["list", "all"]),
+        "\x1b[1m\x1b[4mTasks:\x1b[0m\n\n  [\x1b[1m1\x1b[0m] \x1b[38;5;10m✔\x1b[39m \x1b[38;5;8m\x1b[9mbuy milk\x1b[0m\n  [\x1b[1m2\x1b[0m] \x1b[38;5;8m·\x1b[39m walk dog \x1b[38;5;8m*\x1b[39m\n\n\x1b[38;5;8m1/2 done (50%)\x1b[39m\n",
+    );
+    assert_eq!(
+        todoln.run(&["find", "dog"]),
+        "  [\x1b[1m2\x1b[0m] walk dog \x1b[38;5;8m*\x1b[39m\n\n\x1b[38;5;8m1 match\x1b[39m\n",
+    );
+}
+
+#[test]
+fn control_characters_in_names_cannot_reach_the_terminal() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "ok"]);
+    // Stored as is: ESC with a CSI sequence, a newline and indent faking a row, and C1 CSI and NEL
+    let conn = rusqlite::Connection::open(todoln.path("todoln.db")).unwrap();
+    conn.execute("INSERT INTO tasks (idx, name, done) VALUES (2, ?1, 0)", ["evil\u{1b}[2J\n  [3] · fake\u{9b}31m\u{85}"]).unwrap();
+    let shown = "evil␛[2J␊  [3] · fake\u{fffd}31m\u{fffd}";
+
+    assert_eq!(strip_ansi(&todoln.run(&["list", "all"])), format!("Tasks:\n\n  [1] · ok\n  [2] · {}\n\n0/2 done (0%)\n", shown));
+    assert_eq!(strip_ansi(&todoln.run(&["find", "evil"])), format!("  [2] {}\n\n1 match\n", shown));
+    assert_eq!(todoln.run(&["raw", "all"]), format!("ok\n{}\n", shown));
+
+    let output = todoln.run(&["add", "\u{1b}]0;pwned\u{7}title"]);
+    assert_eq!(strip_ansi(&output), "Task(s) added successfully: ␛]0;pwned␇title\n");
+    let output = todoln.run(&["rename-all", "nothing\u{1b}[2J", "x", "--yes"]);
+    assert_eq!(output, "No task names contain 'nothing␛[2J'.\n");
+
+    todoln.run(&["due", "2", "2020-01-01"]);
+    let output = todoln.run(&["notify"]);
+    assert!(output.starts_with(&format!("{} (overdue since ", shown)), "{:?}", output);
+    assert!(!output.contains(['\u{1b}', '\u{9b}', '\u{85}']), "{:?}", output);
+
+    // Only the styling todoln adds itself is left, never the sequences from the names
+    for args in [&["list", "all"][..], &["find", "evil"], &["raw", "all"]] {
+        let output = strip_ansi(&todoln.run(args));
+        assert!(!output.contains(['\u{1b}', '\u{9b}', '\u{85}', '\u{7}']), "{:?}", output);
+    }
+}
+
+#[test]
+fn find_in_a_subset_with_a_limit() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "buy milk", "buy eggs", "buy bread", "walk dog"]);
+    todoln.run(&["done", "2"]);
+
+    assert_eq!(strip_ansi(&todoln.run(&["find", "buy", "--in", "todo"])), "  [1] buy milk\n  [3] buy bread\n\n2 matches\n");
+    assert_eq!(strip_ansi(&todoln.run(&["find", "b.y", "--regex", "--in", "done"])), "  [2] buy eggs (done)\n\n1 match\n");
+    assert_eq!(strip_ansi(&todoln.run(&["find", "buy", "--limit", "1"])), "  [1] buy milk\n\n3 matches, 2 more not shown\n");
+    assert_eq!(strip_ansi(&todoln.run(&["find", "buy", "--in", "todo", "--limit", "1"])), "  [1] buy milk\n\n2 matches, 1 more not shown\n");
+}
+
+#[test]
+fn find_searches_notes_and_tags_when_asked() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "Prepare meeting slides", "Call the bank", "Buy milk"]);
+    todoln.run(&["note", "2", "Ask about the mortgage before the team meeting on Friday,\nand bring the papers"]);
+    todoln.run(&["set", "3", "tags=errands,meeting-prep"]);
+
+    assert_eq!(strip_ansi(&todoln.run(&["find", "meeting"])), "  [1] Prepare meeting slides\n\n1 match\n");
+    assert_eq!(
+        strip_ansi(&todoln.run(&["find", "meeting", "--include", "notes,tags"])),
+        "  [1] Prepare meeting slides [name]\n  [2] Call the bank * [note]\n      note: …age before the team meeting on Friday, and brin…\n  [3] Buy milk [tags]\n      tags: errands,meeting-prep\n\n3 matches\n",
+    );
+    assert_eq!(strip_ansi(&todoln.run(&["find", "MEETING", "--only", "notes"])), "  [2] Call the bank * [note]\n      note: …age before the team meeting on Friday, and brin…\n\n1 match\n");
+    assert_eq!(todoln.run(&["find", "meet.ng-", "--regex", "--only", "tags", "--porcelain"]), "3\t0\tBuy milk\t3\n");
+}
+
+#[test]
+fn list_long_without_colour_is_an_aligned_table() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "write the report", "buy milk"]);
+    todoln.run(&["add", "--under", "1", "draft"]);
+    todoln.run(&["done", "2"]);
+    todoln.run(&["due", "1", "2020-01-01"]);
+    todoln.run(&["set", "3", "priority=high"]);
+
+    assert_eq!(
+        todoln.run(&["--no-color", "list", "all", "--long"]),
+        "Tasks:\n\n\
+        \x20   #       Priority  Name              Due               Age\n\
+        \x20   1  [ ]            write the report  2020-01-01 23:59  0m\n\
+        \x20 1.1  [x]              draft                             0m\n\
+        \x20   3  [ ]  high      buy milk                            0m\n\n\
+        1/3 done (33%)\n",
+    );
+    assert_eq!(
+        todoln.run(&["--no-color", "list", "todo", "--long", "--where", "priority=high"]),
+        "Tasks todo:\n\n  #       Priority  Name      Age\n  3  [ ]  high      buy milk  0m\n",
+    );
+}
+
+#[test]
+fn list_status_glyphs_can_be_ascii_or_left_out() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b", "c"]);
+    todoln.run(&["done", "1"]);
+    fs::create_dir_all(todoln.path("Todoln")).unwrap();
+
+    fs::write(todoln.path("Todoln/config.toml"), "status_glyphs = \"ascii\"\n").unwrap();
+    assert_eq!(strip_ansi(&todoln.run(&["list", "all"])), "Tasks:\n\n  [1] x a\n  [2] - b\n  [3] - c\n\n1/3 done (33%)\n");
+
+    fs::write(todoln.path("Todoln/config.toml"), "status_glyphs = \"none\"\n").unwrap();
+    assert_eq!(strip_ansi(&todoln.run(&["list", "todo"])), "Tasks todo:\n\n  [2] b\n  [3] c\n");
+    assert_eq!(todoln.run(&["raw", "all"]), "a\nb\nc\n");
+}
+
+#[test]
+fn list_group_by_puts_tasks_under_each_of_their_tags() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b", "c"]);
+    todoln.run(&["set", "1", "tags=work, home"]);
+    todoln.run(&["set", "2", "tags=home", "priority=high"]);
+    todoln.run(&["done", "2"]);
+
+    assert_eq!(
+        strip_ansi(&todoln.run(&["list", "all", "--group-by", "tag"])),
+        "home:\n\n  [1] · a\n  [2] ✔ b\n\nwork:\n\n  [1] · a\n\nOther:\n\n  [3] · c\n\n1/3 done (33%)\n",
+    );
+    assert_eq!(strip_ansi(&todoln.run(&["list", "todo", "--group-by", "status"])), "Todo:\n\n  [1] · a\n  [3] · c\n");
+    assert_eq!(strip_ansi(&todoln.run(&["list", "all", "--group-by", "priority"])).lines().filter(|line| line.ends_with(':')).collect::<Vec<_>>(), ["Priority high:", "Other:"]);
+}
+
+#[test]
+fn list_pads_indices_to_the_same_width() {
+    let todoln = Todoln::new();
+    let names: Vec<String> = (1..=12).map(|i| format!("task {}", i)).collect();
+    todoln.run(&[&["add"], names.iter().map(String::as_str).collect::<Vec<_>>().as_slice()].concat());
+
+    let listing = strip_ansi(&todoln.run(&["list", "all"]));
+    let rows: Vec<&str> = listing.lines().skip(2).take(12).collect();
+    assert_eq!(rows.first(), Some(&"  [ 1] · task 1"));
+    assert_eq!(rows.get(8), Some(&"  [ 9] · task 9"));
+    assert_eq!(rows.last(), Some(&"  [12] · task 12"));
+}
+
+#[test]
+fn list_and_find_pad_a_12_item_list_with_subtasks() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "trip", "errand"]);
+    let steps: Vec<String> = (1..=10).map(|i| format!("step {}", i)).collect();
+    todoln.run(&[&["add", "--under", "1"], steps.iter().map(String::as_str).collect::<Vec<_>>().as_slice()].concat());
+
+    assert_eq!(strip_ansi(&todoln.run(&["list", "all"])), "\
+Tasks:
+
+  [ 1] · trip [0/10]
+    [ 1.1] · step 1
+    [ 1.2] · step 2
+    [ 1.3] · step 3
+    [ 1.4] · step 4
+    [ 1.5] · step 5
+    [ 1.6] · step 6
+    [ 1.7] · step 7
+    [ 1.8] · step 8
+    [ 1.9] · step 9
+    [1.10] · step 10
+  [12] · errand
+
+0/12 done (0%)
+");
+
+    assert_eq!(strip_ansi(&todoln.run(&["find", "step 1"])), "  [ 2] step 1\n  [11] step 10\n\n2 matches\n");
+    assert!(todoln.run(&["raw", "all"]).starts_with("trip\n  step 1\n"));
+}
+
+#[test]
+fn insert_at_an_index() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b", "c"]);
+    todoln.cmd(&["insert", "1", "top"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Task(s) inserted successfully: top"));
+    assert_eq!(todoln.names(), "top\na\nb\nc\n");
+
+    todoln.run(&["insert", "4", "before c"]);
+    assert_eq!(todoln.names(), "top\na\nb\nbefore c\nc\n");
+
+    todoln.run(&["insert", "6", "end"]);
+    assert_eq!(todoln.names(), "top\na\nb\nbefore c\nc\nend\n");
+}
+
+#[test]
+fn insert_counts_negative_indices_from_the_end() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b", "c"]);
+
+    todoln.run(&["insert", "-1", "before c"]);
+    assert_eq!(todoln.names(), "a\nb\nbefore c\nc\n");
+
+    todoln.run(&["insert", "-4", "top", "second"]);
+    assert_eq!(todoln.names(), "top\nsecond\na\nb\nbefore c\nc\n");
+
+    todoln.run(&["insert", "0", "very top"]);
+    assert_eq!(todoln.names(), "very top\ntop\nsecond\na\nb\nbefore c\nc\n");
+}
+
+#[test]
+fn insert_without_an_index_adds_at_the_end() {
+    let todoln = Todoln::new();
+    todoln.run(&["insert", "a"]);
+    todoln.run(&["insert", "b", "c"]);
+
+    assert_eq!(todoln.names(), "a\nb\nc\n");
+}
+
+#[test]
+fn insert_takes_names_that_are_numbers_after_a_double_dash() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b"]);
+
+    todoln.cmd(&["insert", "42"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("to add a task named '42' at the end, use `todoln insert -- 42`"));
+    assert_eq!(todoln.names(), "a\nb\n");
+
+    todoln.run(&["insert", "--", "42"]);
+    todoln.run(&["insert", "1", "--", "7", "-1"]);
+    todoln.run(&["insert", "-1", "c", "--", "2"]);
+    assert_eq!(todoln.names(), "7\n-1\na\nb\nc\n2\n42\n");
+}
+
+#[test]
+fn insert_beyond_either_end_fails() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b"]);
+
+    for index in ["4", "-3"] {
+        todoln.cmd(&["insert", index, "nope"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(format!("Error: Cannot insert at index {} as the total number of tasks is: 2", index)));
+    }
+    assert_eq!(todoln.names(), "a\nb\n");
+}
+
+#[test]
+fn modify_renames_several_tasks_or_none() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b", "c"]);
+
+    todoln.cmd(&["modify", "--set", "1=x", "--set", "9=y"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Error: Invalid index '9'."));
+    assert_eq!(todoln.names(), "a\nb\nc\n");
+
+    let output = strip_ansi(&todoln.run(&["modify", "--set", "1=new a", "--set", "3=new c"]));
+    assert_eq!(output, "2 task(s) modified successfully:\n  [1] a -> new a\n  [3] c -> new c\n");
+    assert_eq!(todoln.names(), "new a\nb\nnew c\n");
+}
+
+#[test]
+fn done_then_clear() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b", "c"]);
+    todoln.run(&["done", "1", "3"]);
+    assert_eq!(todoln.run(&["raw", "done"]), "a\nc\n");
+
+    todoln.cmd(&["clear"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 completed task(s) cleared successfully: a, c"));
+    assert_eq!(todoln.names(), "b\n");
+
+    // The remaining tasks are renumbered straight away
+    todoln.run(&["add", "d"]);
+    todoln.run(&["done", "2"]);
+    assert_eq!(todoln.run(&["raw", "done"]), "d\n");
+
+    todoln.run(&["clear"]);
+    todoln.cmd(&["clear"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No completed tasks to clear."));
+    assert_eq!(todoln.names(), "b\n");
+}
+
+#[test]
+fn done_reports_each_index() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b"]);
+    todoln.run(&["done", "1"]);
+
+    todoln.cmd(&["done", "1", "2", "9"])
+        .assert()
+        .failure()
+        .code(1)
+        .stdout(predicate::str::contains("Task(s) completed successfully: 2"))
+        .stderr(predicate::str::contains("Warning: Task 1 was already done."))
+        .stderr(predicate::str::contains("Error: Invalid index '9'."));
+
+    todoln.cmd(&["done", "--toggle", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Task(s) marked as todo successfully: 1"));
+    assert_eq!(todoln.run(&["raw", "done"]), "b\n");
+}
+
+#[test]
+fn remove_out_of_range_fails_but_removes_the_rest() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b"]);
+
+    todoln.cmd(&["remove", "1", "9"])
+        .assert()
+        .failure()
+        .code(1)
+        .stdout("\x1b[38;5;10mTask(s) removed successfully: 1\x1b[39m\n")
+        .stderr(predicate::str::contains("Error: Invalid index '9'."));
+    assert_eq!(todoln.names(), "b\n");
+
+    todoln.cmd(&["remove", "7"])
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains("Error: Invalid index '7'."));
+    assert_eq!(todoln.names(), "b\n");
+}
+
+#[test]
+fn json_format_reports_each_change() {
+    let todoln = Todoln::new();
+    let json = |args: &[&str]| todoln.run(&[&["--format", "json"], args].concat());
+
+    assert_eq!(
+        json(&["add", "a", "b", "c"]),
+        "{\"action\":\"add\",\"tasks\":[{\"id\":1,\"idx\":1,\"name\":\"a\"},{\"id\":2,\"idx\":2,\"name\":\"b\"},{\"id\":3,\"idx\":3,\"name\":\"c\"}]}\n"
+    );
+    assert_eq!(
+        json(&["insert", "1", "z"]),
+        "{\"action\":\"insert\",\"tasks\":[{\"id\":4,\"idx\":1,\"name\":\"z\"}]}\n"
+    );
+    assert_eq!(
+        json(&["modify", "1", "y"]),
+        "{\"action\":\"modify\",\"tasks\":[{\"id\":4,\"idx\":1,\"name\":\"y\",\"old_name\":\"z\"}]}\n"
+    );
+    assert_eq!(
+        json(&["remove", "1"]),
+        "{\"action\":\"remove\",\"tasks\":[{\"id\":4,\"idx\":1,\"name\":\"y\"}]}\n"
+    );
+    assert_eq!(
+        json(&["done", "1"]),
+        "{\"action\":\"done\",\"already_done\":[],\"not_found\":[],\"tasks\":[{\"id\":1,\"idx\":1,\"name\":\"a\"}]}\n"
+    );
+    assert_eq!(
+        json(&["clear"]),
+        "{\"action\":\"clear\",\"count\":1,\"tasks\":[{\"id\":1,\"idx\":1,\"name\":\"a\"}]}\n"
+    );
+    assert_eq!(json(&["reset", "--no-snapshot"]), "{\"action\":\"reset\",\"count\":2,\"snapshot\":null}\n");
+}
+
+#[test]
+fn json_format_still_fails_on_errors() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a"]);
+
+    todoln.cmd(&["--format", "json", "done", "1", "9"])
+        .assert()
+        .failure()
+        .code(1)
+        .stdout("{\"action\":\"done\",\"already_done\":[],\"not_found\":[9],\"tasks\":[{\"id\":1,\"idx\":1,\"name\":\"a\"}]}\n")
+        .stderr(predicate::str::contains("Error: Invalid index '9'."));
+}
+
+#[test]
+fn id_keeps_pointing_at_the_same_task_when_indices_shift() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b", "c"]);
+    todoln.run(&["remove", "1"]);
+
+    assert_eq!(todoln.run(&["find", "c", "--porcelain"]), "2\t0\tc\t3\n");
+    todoln.run(&["done", "--id", "3"]);
+    todoln.run(&["modify", "--id", "2", "bee"]);
+    assert_eq!(todoln.run(&["raw", "done"]), "c\n");
+    assert_eq!(todoln.names(), "bee\nc\n");
+
+    todoln.cmd(&["remove", "--id", "1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Error: No task with id 1."));
+    todoln.cmd(&["show", "--id", "3.1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Error: Invalid task id '3.1'"));
+    assert_eq!(todoln.names(), "bee\nc\n");
+}
+
+#[test]
+fn bulk_done_and_remove_act_on_the_filtered_tasks() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "standup mon", "a", "Standup tue", "b"]);
+
+    todoln.run(&["remove", "--matching", "standup"]);
+    assert_eq!(todoln.names(), "a\nb\n");
+
+    todoln.run(&["add", "1", "2", "3", "4"]);
+    todoln.cmd(&["done", "--all-todo"]).write_stdin("n\n").assert().success().stdout(predicate::str::contains("Nothing was changed."));
+    assert_eq!(todoln.run(&["raw", "done"]), "");
+
+    todoln.run(&["done", "2"]);
+    todoln.run(&["done", "--all-todo", "--yes"]);
+    assert_eq!(todoln.run(&["raw", "todo"]), "");
+
+    todoln.run(&["add", "c"]);
+    todoln.run(&["remove", "--done", "--yes"]);
+    assert_eq!(todoln.names(), "c\n");
+}
+
+#[test]
+fn sync_writes_the_tasks_and_brings_back_edits_to_the_file() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b", "c"]);
+
+    todoln.run(&["sync", "tasks.md"]);
+    assert_eq!(fs::read_to_string(todoln.path("tasks.md")).unwrap(), "- [ ] a <!-- id:1 -->\n- [ ] b <!-- id:2 -->\n- [ ] c <!-- id:3 -->\n");
+    assert_eq!(todoln.run(&["sync", "--status", "tasks.md"]), "Tasks are in sync with 'tasks.md'.\n");
+
+    fs::write(todoln.path("tasks.md"), "- [x] a <!-- id:1 -->\n- [ ] bee <!-- id:2 -->\n- [ ] d\n").unwrap();
+    assert_eq!(todoln.run(&["sync", "--status", "tasks.md"]), "'tasks.md' has changes to bring into todoln.\n");
+    todoln.run(&["sync", "tasks.md"]);
+    assert_eq!(todoln.names(), "a\nbee\nd\n");
+    assert_eq!(todoln.run(&["raw", "done"]), "a\n");
+    assert!(fs::read_to_string(todoln.path("tasks.md")).unwrap().ends_with("- [ ] d <!-- id:3 -->\n"));
+
+    todoln.run(&["modify", "2", "from todoln"]);
+    fs::write(todoln.path("tasks.md"), "- [x] a <!-- id:1 -->\n- [ ] from the file <!-- id:2 -->\n- [x] d <!-- id:3 -->\n").unwrap();
+    todoln.cmd(&["sync", "tasks.md"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Conflict: 'from todoln' was changed both in the file"));
+    assert_eq!(todoln.names(), "a\nfrom todoln\nd\n");
+    assert_eq!(todoln.run(&["raw", "done"]), "a\nd\n");
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encrypted_database_needs_the_passphrase() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b"]);
+
+    todoln.cmd(&["encrypt"]).env("TODOLN_PASSPHRASE", "open sesame").assert().success();
+    assert!(!fs::read(todoln.path("todoln.db")).unwrap().starts_with(b"SQLite format 3"));
+
+    todoln.cmd(&["raw", "all"]).env("TODOLN_PASSPHRASE", "open sesame").assert().success().stdout("a\nb\n");
+    todoln.cmd(&["add", "c"])
+        .env("TODOLN_PASSPHRASE", "wrong")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Wrong passphrase"));
+
+    todoln.cmd(&["backup"]).env("TODOLN_PASSPHRASE", "open sesame").assert().success();
+    assert!(!fs::read(todoln.path("todoln_backup.db")).unwrap().starts_with(b"SQLite format 3"));
+    todoln.cmd(&["backup", "--json"]).env("TODOLN_PASSPHRASE", "open sesame").assert().failure();
+
+    todoln.cmd(&["decrypt"]).env("TODOLN_PASSPHRASE", "open sesame").assert().success();
+    assert_eq!(todoln.names(), "a\nb\n");
+}
+
+#[cfg(not(feature = "encryption"))]
+#[test]
+fn encrypt_needs_a_build_with_encryption_support() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a"]);
+
+    todoln.cmd(&["encrypt"])
+        .env("TODOLN_PASSPHRASE", "open sesame")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("built without encryption support"));
+    assert_eq!(todoln.names(), "a\n");
+}
+
+#[cfg(not(feature = "encryption"))]
+#[test]
+fn a_corrupt_database_is_not_taken_for_an_encrypted_one() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a"]);
+    // Longer than the 16 bytes of the SQLite header
+    fs::write(todoln.path("junk.db"), "not a database, just some text that is long enough to fill a header ".repeat(2)).unwrap();
+
+    for args in [&["--db", "junk.db", "list", "all"][..], &["restore", "junk.db"]] {
+        todoln.cmd(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("file is not a database"))
+            .stderr(predicate::str::contains("Passphrase").not())
+            .stderr(predicate::str::contains("Wrong passphrase").not());
+    }
+    assert_eq!(todoln.names(), "a\n");
+}
+
+#[test]
+fn maintenance_compacts_the_database_unless_another_todoln_has_it_open() {
+    let todoln = Todoln::new();
+    let names: Vec<String> = (1..=300).map(|i| format!("task {} {}", i, "x".repeat(200))).collect();
+    todoln.cmd(&["add"]).args(&names).assert().success();
+    todoln.run(&["remove", "--matching", "task", "-y"]);
+    todoln.run(&["trash", "empty", "--all"]);
+
+    let size = || fs::metadata(todoln.path("todoln.db")).unwrap().len();
+    let before = size();
+
+    let reader = rusqlite::Connection::open(todoln.path("todoln.db")).unwrap();
+    reader.execute_batch("BEGIN; SELECT * FROM tasks;").unwrap();
+    todoln.cmd(&["maintenance"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Another todoln has the database open"));
+    todoln.cmd(&["maintenance", "--auto"]).assert().success().stdout(predicate::str::contains("skipped compacting it"));
+    drop(reader);
+
+    todoln.cmd(&["maintenance", "--auto"]).assert().success().stdout(predicate::str::contains("Database compacted from"));
+    assert!(size() < before);
+    todoln.cmd(&["maintenance", "--auto"]).assert().success().stdout(predicate::str::contains("No maintenance needed."));
+}
+
+#[test]
+fn reset_saves_a_snapshot_that_restore_last_reset_brings_back() {
+    let todoln = Todoln::new();
+    todoln.cmd(&["restore", "--last-reset"]).assert().failure().stderr(predicate::str::contains("No snapshot from `reset` found"));
+
+    todoln.run(&["add", "a", "b"]);
+    let output = todoln.run(&["reset"]);
+    let snapshot = Regex::new("snapshot saved to (.*reset-[0-9-]+\\.db)").unwrap().captures(&output).unwrap()[1].to_string();
+    assert!(PathBuf::from(&snapshot).starts_with(todoln.path("snapshots")));
+    assert_eq!(todoln.names(), "");
+
+    todoln.run(&["add", "c"]);
+    todoln.run(&["reset", "--no-snapshot"]);
+    assert_eq!(fs::read_dir(todoln.path("snapshots")).unwrap().count(), 1);
+
+    todoln.run(&["restore", "--last-reset"]);
+    assert_eq!(todoln.names(), "a\nb\n");
+}
+
+#[test]
+fn concurrent_changes_keep_indices_contiguous() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "first"]);
+
+    thread::scope(|scope| {
+        for writer in ["a", "b"] {
+            let todoln = &todoln;
+            scope.spawn(move || {
+                for i in 0..15 {
+                    todoln.run(&["add", &format!("{} {}", writer, i), &format!("{} {} again", writer, i)]);
+                    todoln.run(&["remove", "--matching", &format!("{} {} again", writer, i), "-y"]);
+                }
+            });
+        }
+    });
+
+    let conn = rusqlite::Connection::open(todoln.path("todoln.db")).unwrap();
+    let mut stmt = conn.prepare("SELECT idx FROM tasks ORDER BY idx").unwrap();
+    let indices: Vec<i32> = stmt.query_map([], |row| row.get(0)).unwrap().map(Result::unwrap).collect();
+    assert_eq!(indices, (1..=31).collect::<Vec<_>>());
+}
+
+#[test]
+fn several_writers_at_once_all_get_through() {
+    let todoln = Todoln::new();
+    let writers = ["a", "b", "c", "d", "e", "f"];
+
+    thread::scope(|scope| {
+        for writer in writers {
+            let todoln = &todoln;
+            scope.spawn(move || {
+                for i in 0..10 {
+                    // Each one waits out the others' locks instead of failing with "database is locked"
+                    let no_lock_error = || predicate::str::contains("locked").not().and(predicate::str::contains("panicked").not());
+                    todoln.cmd(&["add", &format!("{} {}", writer, i)]).assert().success().stderr(no_lock_error());
+                    todoln.cmd(&["insert", "1", &format!("{} {} top", writer, i)]).assert().success().stderr(no_lock_error());
+                }
+            });
+        }
+    });
+
+    let mut names: Vec<String> = todoln.names().lines().map(str::to_string).collect();
+    names.sort();
+    let mut expected: Vec<String> = writers.iter()
+        .flat_map(|writer| (0..10).flat_map(move |i| [format!("{} {}", writer, i), format!("{} {} top", writer, i)]))
+        .collect();
+    expected.sort();
+    assert_eq!(names, expected);
+
+    let conn = rusqlite::Connection::open(todoln.path("todoln.db")).unwrap();
+    let mut stmt = conn.prepare("SELECT idx FROM tasks ORDER BY idx").unwrap();
+    let indices: Vec<i32> = stmt.query_map([], |row| row.get(0)).unwrap().map(Result::unwrap).collect();
+    assert_eq!(indices, (1..=120).collect::<Vec<_>>());
+}
+
+#[test]
+fn import_db_appends_the_tasks_not_in_the_list_yet() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b", "c"]);
+    todoln.run(&["done", "3"]);
+    todoln.run(&["backup"]);
+    todoln.run(&["reset", "--no-snapshot"]);
+    todoln.run(&["add", "B ", "d"]);
+
+    let output = todoln.run(&["import", "db", "todoln_backup.db"]);
+    assert!(output.contains("Task(s) imported successfully: a, c"), "{}", output);
+    assert!(output.contains("Skipped 1 task(s) already in the list: b"), "{}", output);
+    assert_eq!(todoln.names(), "B\nd\na\nc\n");
+    assert_eq!(todoln.run(&["raw", "done"]), "c\n");
+
+    let output = todoln.run(&["--format", "json", "import", "db", "todoln_backup.db"]);
+    assert!(output.contains(r#"{"action":"import","skipped":["a","b","c"],"tasks":[]}"#), "{}", output);
+
+    todoln.run(&["undo"]);
+    assert_eq!(todoln.names(), "B\nd\n");
+
+    fs::write(todoln.path("broken.db"), "not a database").unwrap();
+    todoln.cmd(&["import", "db", "broken.db"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("file is not a database"));
+    assert_eq!(todoln.names(), "B\nd\n");
+}
+
+#[test]
+fn import_db_sanitizes_the_names_it_prints() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "\u{1b}[31mred", "\u{1b}]0;title\u{7}"]);
+    todoln.run(&["backup"]);
+    todoln.run(&["reset", "--no-snapshot"]);
+    todoln.run(&["add", "\u{1b}[31mRED"]);
+
+    let output = todoln.run(&["import", "db", "todoln_backup.db"]);
+    assert!(output.contains("Task(s) imported successfully: ␛]0;title␇"), "{}", output);
+    assert!(output.contains("Skipped 1 task(s) already in the list: ␛[31mred"), "{}", output);
+    assert!(!output.contains("\u{1b}[31m") && !output.contains("\u{1b}]0;"), "{:?}", output);
+}
+
+#[test]
+fn stable_read_leaves_the_database_file_unchanged() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b", "c"]);
+    // A gap in the indices that any other command would renumber, left in the write-ahead log as
+    // by a todoln that crashed, which any other command would fold back into the database
+    let conn = rusqlite::Connection::open(todoln.path("todoln.db")).unwrap();
+    conn.pragma_update(None, "wal_autocheckpoint", 0).unwrap();
+    conn.execute("UPDATE tasks SET idx = 5 WHERE idx = 3", []).unwrap();
+    let crashed = ["todoln.db", "todoln.db-wal"].map(|name| (name, fs::read(todoln.path(name)).unwrap()));
+    drop(conn);
+    for (name, contents) in crashed {
+        fs::write(todoln.path(name), contents).unwrap();
+    }
+
+    let fingerprint = || {
+        let mut hasher = DefaultHasher::new();
+        for name in ["todoln.db", "todoln.db-wal"] {
+            if let Ok(metadata) = fs::metadata(todoln.path(name)) {
+                (name, fs::read(todoln.path(name)).unwrap(), metadata.modified().unwrap()).hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    };
+
+    let before = fingerprint();
+    for args in [&["list", "all"][..], &["raw", "all"], &["find", "a"], &["stats"]] {
+        todoln.run(&[&["--stable-read"], args].concat());
+        assert_eq!(fingerprint(), before, "todoln {} wrote to the database", args.join(" "));
+    }
+
+    assert!(todoln.run(&["list", "all"]).starts_with("Recovered the journal of "));
+    assert_ne!(fingerprint(), before);
+}
+
+#[test]
+fn wrapup_skips_each_combination_of_steps() {
+    for no_archive in [false, true] {
+        for no_sort in [false, true] {
+            for no_backup in [false, true] {
+                let todoln = Todoln::new();
+                todoln.run(&["add", "b", "a", "c"]);
+                todoln.run(&["done", "1"]);
+
+                let mut args = vec!["wrapup", "--yes"];
+                for (skip, flag) in [(no_archive, "--no-archive"), (no_sort, "--no-sort"), (no_backup, "--no-backup")] {
+                    if skip {
+                        args.push(flag);
+                    }
+                }
+                let output = strip_ansi(&todoln.run(&args));
+                let context = format!("{}\n{}", args.join(" "), output);
+
+                let expected_names = match (no_archive, no_sort) {
+                    (false, _) => "a\nc\n",
+                    (true, false) => "a\nc\nb\n",
+                    (true, true) => "b\na\nc\n",
+                };
+                assert_eq!(todoln.names(), expected_names, "{}", context);
+
+                assert_eq!(output.contains("1 completed task(s) archived successfully"), !no_archive, "{}", context);
+                assert_eq!(output.contains("Tasks sorted successfully"), !no_sort, "{}", context);
+                let snapshots = fs::read_dir(todoln.path("snapshots")).map_or(0, |entries| entries.count());
+                assert_eq!(snapshots, if no_backup { 0 } else { 1 }, "{}", context);
+                assert_eq!(output.contains("Nothing to do."), no_archive && no_sort && no_backup, "{}", context);
+            }
+        }
+    }
+}
+
+#[test]
+fn wrapup_cancelled_at_the_preview_changes_nothing() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "b", "a"]);
+    todoln.run(&["done", "1"]);
+
+    todoln.cmd(&["wrapup"])
+        .write_stdin("n\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("This will:").and(predicate::str::contains("Wrap-up cancelled.")));
+
+    assert_eq!(todoln.names(), "b\na\n");
+    assert!(!todoln.path("snapshots").exists());
+}
+
+#[test]
+fn done_and_remove_by_name_reject_ambiguous_matches() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "deploy staging", "deploy prod", "Deploy", "write docs"]);
+
+    for command in ["done", "remove"] {
+        let output = todoln.cmd(&[command, "--name", "eplo"])
+            .assert()
+            .code(1)
+            .stderr(predicate::str::contains("3 tasks match 'eplo', pass --all to act on all of them"))
+            .get_output()
+            .stdout
+            .clone();
+        let candidates = strip_ansi(&String::from_utf8(output).unwrap());
+        assert_eq!(candidates, "  [1] deploy staging\n  [2] deploy prod\n  [3] Deploy\n", "{}", command);
+
+        todoln.cmd(&[command, "--name", "nothing"])
+            .assert()
+            .code(1)
+            .stderr(predicate::str::contains("No task matches 'nothing'"));
+    }
+    assert_eq!(todoln.names(), "deploy staging\ndeploy prod\nDeploy\nwrite docs\n");
+    assert_eq!(todoln.run(&["raw", "done"]), "");
+
+    // An exact name wins over names that only contain it, ignoring case
+    todoln.run(&["done", "--name", "deploy"]);
+    assert_eq!(todoln.run(&["raw", "done"]), "Deploy\n");
+    todoln.run(&["remove", "--name", "DEPLOY"]);
+    assert_eq!(todoln.names(), "deploy staging\ndeploy prod\nwrite docs\n");
+
+    // A single match needs no exact name
+    todoln.run(&["remove", "--name", "docs"]);
+    assert_eq!(todoln.names(), "deploy staging\ndeploy prod\n");
+
+    todoln.run(&["remove", "--name", "deploy", "--all"]);
+    assert_eq!(todoln.names(), "");
+}
+
+#[test]
+fn clear_keep_holds_on_to_the_most_recent_completions() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b", "c", "d", "e"]);
+    todoln.run(&["done", "1", "2", "3", "4"]);
+
+    // a and d tie on the newest completion time, which the higher index wins; b has none
+    let conn = rusqlite::Connection::open(todoln.path("todoln.db")).unwrap();
+    conn.execute_batch(
+        "UPDATE tasks SET completed_at = 2000 WHERE name IN ('a', 'd');
+         UPDATE tasks SET completed_at = 1000 WHERE name = 'c';
+         UPDATE tasks SET completed_at = NULL WHERE name = 'b';",
+    ).unwrap();
+    drop(conn);
+
+    todoln.cmd(&["clear", "--keep", "4"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Keeping all 4 completed task(s), nothing to clear."));
+    todoln.run(&["clear", "--keep", "9"]);
+    assert_eq!(todoln.names(), "a\nb\nc\nd\ne\n");
+
+    todoln.run(&["clear", "--keep", "1"]);
+    assert_eq!(todoln.names(), "d\ne\n");
+
+    todoln.run(&["add", "f"]);
+    todoln.run(&["done", "1", "3"]);
+    let output = strip_ansi(&todoln.run(&["clear", "--keep", "0"]));
+    assert!(!output.contains("keeping"), "{}", output);
+    assert_eq!(todoln.names(), "e\n");
+}
+
+#[test]
+fn file_errors_name_the_paths_involved() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a"]);
+    fs::write(todoln.path("junk.db"), "not a database").unwrap();
+    let junk = todoln.path("junk.db").display().to_string();
+
+    let fails_naming = |args: &[&str], path: &str| {
+        todoln.cmd(args)
+            .assert()
+            .code(1)
+            .stderr(predicate::str::contains(format!("'{}'", path)));
+    };
+
+    // The backup files are written to the current directory, which is the test's directory
+    fs::create_dir(todoln.path("todoln_backup.db")).unwrap();
+    fs::create_dir(todoln.path("todoln_backup.json")).unwrap();
+    fails_naming(&["backup"], &todoln.path("todoln_backup.db").display().to_string());
+    fails_naming(&["backup", "--json"], &todoln.path("todoln_backup.json").display().to_string());
+
+    fails_naming(&["restore", &junk], &junk);
+    fails_naming(&["restore", &todoln.path("missing.db").display().to_string()], &todoln.path("missing.db").display().to_string());
+    fails_naming(&["import", "db", &junk], &junk);
+    fails_naming(&["--db", &junk, "list", "all"], &junk);
+    assert_eq!(todoln.names(), "a\n");
+
+    let config = todoln.path("Todoln/config.toml");
+    fs::create_dir_all(&config).unwrap();
+    todoln.cmd(&["raw", "all"])
+        .assert()
+        .stderr(predicate::str::contains(format!("Failed to read config file '{}'", config.display())));
+
+    fs::remove_dir(&config).unwrap();
+    fs::write(&config, "busy_timeout = 'soon'").unwrap();
+    todoln.cmd(&["raw", "all"])
+        .assert()
+        .stderr(predicate::str::contains(format!("Failed to parse config file '{}'", config.display())));
+}
+
+#[test]
+fn auto_archive_and_trash_purge_notes_follow_quiet() {
+    for quiet in [false, true] {
+        let todoln = Todoln::new();
+        fs::create_dir_all(todoln.path("Todoln")).unwrap();
+        fs::write(todoln.path("Todoln/config.toml"), "auto_archive_done_after = \"1d\"\n").unwrap();
+        todoln.run(&["add", "a", "b", "c"]);
+        todoln.run(&["done", "1"]);
+        todoln.run(&["remove", "3"]);
+
+        let conn = rusqlite::Connection::open(todoln.path("todoln.db")).unwrap();
+        conn.execute_batch(
+            "UPDATE tasks SET completed_at = '2000-01-01 00:00:00' WHERE done;
+             UPDATE trash SET deleted_at = '2000-01-01 00:00:00';
+             DELETE FROM metadata WHERE key IN ('last_auto_archive', 'last_trash_purge');",
+        ).unwrap();
+        drop(conn);
+
+        let args: &[&str] = if quiet { &["--quiet", "add", "d"] } else { &["add", "d"] };
+        let output = todoln.cmd(args).assert().success().stderr("").get_output().stdout.clone();
+        let output = strip_ansi(&String::from_utf8(output).unwrap());
+
+        assert_eq!(output.contains("Auto-archived 1 old task(s)."), !quiet, "{}", output);
+        assert_eq!(output.contains("Deleted 1 task(s) that were in the trash for over 30 days."), !quiet, "{}", output);
+        if quiet {
+            assert_eq!(output, "");
+        }
+        assert_eq!(todoln.names(), "b\nd\n");
+    }
+}
+
+#[test]
+fn quiet_leaves_out_notices_but_not_what_was_asked_for() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "buy milk"]);
+    todoln.run(&["start", "1"]);
+    todoln.run(&["stop"]);
+
+    for args in [&["note", "1"][..], &["today"], &["week"], &["trash"], &["trash", "empty", "--all"], &["start", "1"], &["start", "1"]] {
+        let mut quiet = vec!["--quiet"];
+        quiet.extend_from_slice(args);
+        assert_eq!(todoln.run(&quiet), "", "{:?}", args);
+    }
+
+    assert!(strip_ansi(&todoln.run(&["--quiet", "show", "1"])).starts_with("buy milk\n\n  Index:   1\n"));
+    assert!(strip_ansi(&todoln.run(&["--quiet", "time", "1"])).starts_with("Time tracked on buy milk:\n\n"));
+}
+
+#[test]
+fn restore_of_a_corrupt_file_fails_and_keeps_the_tasks() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b"]);
+    fs::write(todoln.path("broken.db"), "not a database").unwrap();
+
+    todoln.cmd(&["restore", "broken.db"])
+        .assert()
+        .failure()
+        .code(1)
+        .stderr(predicate::str::contains("file is not a database"));
+
+    assert_eq!(todoln.names(), "a\nb\n");
+}
+
+#[test]
+fn backup_and_restore_see_changes_still_in_the_write_ahead_log() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a"]);
+
+    // An open connection keeps the log from being folded into the database file when todoln exits
+    let reader = rusqlite::Connection::open(todoln.path("todoln.db")).unwrap();
+    let count = |conn: &rusqlite::Connection| conn.query_row("SELECT COUNT(*) FROM tasks", [], |row| row.get::<_, i64>(0)).unwrap();
+    assert_eq!(count(&reader), 1);
+    todoln.run(&["add", "b"]);
+    assert!(todoln.path("todoln.db-wal").exists());
+
+    todoln.run(&["backup"]);
+    let backup = rusqlite::Connection::open(todoln.path("todoln_backup.db")).unwrap();
+    let names: Vec<String> = backup.prepare("SELECT name FROM tasks ORDER BY idx").unwrap()
+        .query_map([], |row| row.get(0)).unwrap()
+        .collect::<Result<_, _>>().unwrap();
+    assert_eq!(names, ["a", "b"]);
+    drop(backup);
+
+    todoln.run(&["add", "c"]);
+    todoln.run(&["restore", "todoln_backup.db"]);
+    assert_eq!(todoln.names(), "a\nb\n");
+    assert_eq!(count(&reader), 2);
+}
+
+#[test]
+fn restore_dry_run_counts_match_the_real_run() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b", "c"]);
+    todoln.run(&["backup", "--json"]);
+    todoln.run(&["add", "d", "e"]);
+
+    let plan = todoln.run(&["restore", "todoln_backup.json", "--dry-run", "--report", "dry-run.tsv"]);
+    assert!(plan.contains("Would restore 3 task(s)"), "{}", plan);
+    assert!(plan.contains("replacing 5 current task(s)"), "{}", plan);
+    assert!(plan.contains("Dry run: 3 added"), "{}", plan);
+    assert_eq!(todoln.names(), "a\nb\nc\nd\ne\n");
+
+    let result = todoln.run(&["restore", "todoln_backup.json", "--report", "real.tsv"]);
+    assert!(result.contains("Tasks imported successfully: 3 added"), "{}", result);
+    assert_eq!(todoln.names(), "a\nb\nc\n");
+
+    let dry_run = fs::read_to_string(todoln.path("dry-run.tsv")).unwrap();
+    let real = fs::read_to_string(todoln.path("real.tsv")).unwrap();
+    assert_eq!(dry_run.replace("would-", ""), real);
+}
+
+#[test]
+fn sync_md_dry_run_counts_match_the_real_run() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b"]);
+    todoln.run(&["done", "1"]);
+    fs::write(todoln.path("list.md"), "- [ ] a\n- [x] b\n- [ ] new\n").unwrap();
+
+    let plan = todoln.run(&["sync-md", "list.md", "--dry-run", "--report", "dry-run.tsv"]);
+    assert!(plan.contains("Dry run: 1 added, 1 marked done, 1 marked todo, 0 removed"), "{}", plan);
+    assert_eq!(todoln.run(&["raw", "done"]), "a\n");
+
+    let result = todoln.run(&["sync-md", "list.md", "--report", "real.tsv"]);
+    assert!(result.contains("Tasks synced successfully: 1 added, 1 marked done, 1 marked todo, 0 removed"), "{}", result);
+    assert_eq!(todoln.run(&["raw", "done"]), "b\n");
+
+    let dry_run = fs::read_to_string(todoln.path("dry-run.tsv")).unwrap();
+    let real = fs::read_to_string(todoln.path("real.tsv")).unwrap();
+    assert_eq!(dry_run.replace("would-", ""), real);
+}
+
+#[cfg(unix)]
+#[test]
+fn edit_all_uses_a_private_file_it_removes_afterwards() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let todoln = Todoln::new();
+    todoln.run(&["add", "buy milk"]);
+    let editor = todoln.path("editor.sh");
+    fs::write(&editor, "#!/bin/sh\necho \"$1\" > seen\nstat -c %a \"$1\" >> seen\n").unwrap();
+    fs::set_permissions(&editor, fs::Permissions::from_mode(0o755)).unwrap();
+
+    todoln.cmd(&["edit-all"])
+        .env("VISUAL", &editor)
+        .env("TMPDIR", todoln.dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No changes made."));
+
+    let seen = fs::read_to_string(todoln.path("seen")).unwrap();
+    let (path, mode) = seen.trim().split_once('\n').unwrap();
+    assert_eq!(mode, "600");
+    assert!(path.starts_with(todoln.dir.path().to_str().unwrap()), "{}", path);
+    assert!(!PathBuf::from(path).exists());
+}
+
+#[test]
+fn db_flag_overrides_the_environment() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "default"]);
+    todoln.run(&["--db", "other.db", "add", "other"]);
+
+    assert_eq!(todoln.names(), "default\n");
+    assert_eq!(todoln.run(&["--db", "other.db", "raw", "all"]), "other\n");
+}
+
+#[test]
+fn aliases_from_the_config_file() {
+    let todoln = Todoln::new();
+    fs::create_dir_all(todoln.path("Todoln")).unwrap();
+    fs::write(todoln.path("Todoln/config.toml"), "[aliases]\nt = [\"list\", \"todo\"]\ngg = [\"add\", \"--\"]\nlist = [\"reset\"]\n").unwrap();
+
+    todoln.run(&["gg", "buy milk"]);
+    assert_eq!(strip_ansi(&todoln.run(&["t"])), "Tasks todo:\n\n  [1] · buy milk\n");
+    todoln.cmd(&["list", "all"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Warning: Alias 'list' is ignored"));
+    assert_eq!(todoln.names(), "buy milk\n");
+
+    assert_eq!(
+        strip_ansi(&todoln.run(&["aliases"])),
+        "Aliases:\n\n  gg   = add --\n  list = reset (ignored, todoln already has a command with that name)\n  t    = list todo\n"
+    );
+}
+
+#[test]
+fn bare_todoln_runs_the_default_command() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "a", "b"]);
+    todoln.run(&["done", "1"]);
+    fs::create_dir_all(todoln.path("Todoln")).unwrap();
+
+    fs::write(todoln.path("Todoln/config.toml"), "default_command = \"d\"\n[aliases]\nd = [\"raw\", \"done\"]\n").unwrap();
+    assert_eq!(todoln.run(&[]), "a\n");
+    todoln.cmd(&["--help"]).assert().success().stdout(predicate::str::contains("Running todoln without a command runs `d`"));
+
+    fs::write(todoln.path("Todoln/config.toml"), "default_command = \"bogus\"\n").unwrap();
+    let output = todoln.cmd(&["--quiet"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Warning: default_command 'bogus' is ignored"))
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(strip_ansi(&String::from_utf8(output).unwrap()), "  [1] ✔ a\n  [2] · b\n");
+}
+
+#[test]
+fn messages_follow_the_language() {
+    let todoln = Todoln::new();
+    todoln.cmd(&["add", "a"])
+        .env("LANG", "de_DE.UTF-8")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Aufgabe(n) hinzugefügt: a"));
+    todoln.cmd(&["done", "1", "1"])
+        .env("LANG", "fr_FR.UTF-8")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Task(s) completed successfully: 1"));
+
+    fs::create_dir_all(todoln.path("Todoln")).unwrap();
+    fs::write(todoln.path("Todoln/config.toml"), "language = \"de\"\n").unwrap();
+    assert_eq!(strip_ansi(&todoln.run(&["list", "done"])), "Erledigte Aufgaben:\n\n  [1] ✔ a\n");
+    todoln.cmd(&["clear"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Erledigte Aufgaben entfernt: a (1 insgesamt)"));
+
+    // Titles and notices of the other commands too
+    assert_eq!(todoln.run(&["due"]), "Keine Aufgaben haben ein Fälligkeitsdatum.\n");
+    assert!(strip_ansi(&todoln.run(&["trash"])).starts_with("Papierkorb:\n\n  [1] a "));
+    todoln.run(&["add", "b"]);
+    assert_eq!(todoln.run(&["remove", "1", "--dry-run"]), "Würde entfernen: [1] b\n");
+}
+
+#[test]
+fn introspect_describes_every_subcommand() {
+    let todoln = Todoln::new();
+    let described: serde_json::Value = serde_json::from_str(&todoln.run(&["introspect", "--json"])).unwrap();
+    let names: Vec<&str> = described["subcommands"].as_array().unwrap().iter().map(|command| command["name"].as_str().unwrap()).collect();
+
+    for command in Cli::command().get_subcommands() {
+        assert_eq!(names.contains(&command.get_name()), !command.is_hide_set(), "`introspect` gets `{}` wrong", command.get_name());
+    }
+}
+
+#[test]
+fn introspect_output_shape_is_stable() {
+    let todoln = Todoln::new();
+    let described: serde_json::Value = serde_json::from_str(&todoln.run(&["introspect", "--json"])).unwrap();
+    assert_eq!(described["format_version"], INTROSPECT_VERSION);
+
+    let subcommand = |name: &str| described["subcommands"].as_array().unwrap().iter().find(|command| command["name"] == name).unwrap().clone();
+    let flag = |id: &str, help: &str| serde_json::json!({
+        "id": id, "long": id, "short": null, "aliases": [], "positional": false, "takes_value": false, "multiple": false,
+        "required": false, "global": false, "value_names": [], "default_values": [], "help": help,
+    });
+
+    assert_eq!(subcommand("pick"), serde_json::json!({
+        "name": "pick",
+        "aliases": ["random", "roll"],
+        "about": "Suggests a random todo task to work on next",
+        "arguments": [
+            {
+                "id": "count", "long": "count", "short": "n", "aliases": [], "positional": false, "takes_value": true, "multiple": false,
+                "required": false, "global": false, "value_names": ["COUNT"], "default_values": ["1"], "help": "How many different tasks to suggest",
+            },
+            flag("any", "Picks from every task, including done, snoozed and blocked ones"),
+            flag("weighted", "Favours older tasks and tasks with a higher `priority` field"),
+        ],
+        "subcommands": [],
+    }));
+    assert_eq!(subcommand("insert"), serde_json::json!({
+        "name": "insert",
+        "aliases": ["ins", "i"],
+        "about": "Adds new tasks at a given index",
+        "arguments": [
+            {
+                "id": "index", "long": null, "short": null, "aliases": [], "positional": true, "takes_value": true, "multiple": false,
+                "required": false, "global": false, "value_names": ["index"], "default_values": [],
+                "help": "The index to insert at: 0 or 1 is the top and -1 before the last task; leave it out to add at the end. \
+                    A number given first is always taken as the index, so put names that are numbers after `--`",
+            },
+            {
+                "id": "task_names", "long": null, "short": null, "aliases": [], "positional": true, "takes_value": true, "multiple": true,
+                "required": false, "global": false, "value_names": ["task_names"], "default_values": [], "help": "The task(s) to add",
+            },
+            {
+                "id": "literal_names", "long": null, "short": null, "aliases": [], "positional": true, "takes_value": true, "multiple": true,
+                "required": false, "global": false, "value_names": ["task_names"], "default_values": [],
+                "help": "Task(s) to add given after `--`, which are never taken for an index: `insert -- 42` adds \"42\" at the end",
+            },
+            flag("force", "Adds tasks even if a task with the same name apart from case and spacing exists"),
+        ],
+        "subcommands": [],
+    }));
+}
+
+#[test]
+fn man_page_has_a_section_per_command() {
+    let todoln = Todoln::new();
+    let page = todoln.run(&["man"]);
+
+    assert!(page.starts_with(".ie \\n(.g .ds Aq \\(aq\n.el .ds Aq '\n.TH todoln 1"));
+    for command in Cli::command().get_subcommands().filter(|command| !command.is_hide_set()) {
+        let title = format!("\n.SH \"TODOLN {}\"\n", command.get_name().to_uppercase());
+        assert!(page.contains(&title), "the man page leaves out `{}`", command.get_name());
+    }
+    assert!(!page.contains("TODOLN MAN"));
+    assert!(page.contains("\n.SH EXAMPLES\n.TP\n\\fBtodoln add \"buy milk\" \"walk the dog\"\\fR\n"));
+    assert!(page.contains("\\fBtodoln done 1,3 5\\fR"));
+    assert!(!page.contains('\x1b'));
+}
+
+#[test]
+fn demo_transcript() {
+    let todoln = Todoln::new();
+    todoln.run(&["add", "my own task"]);
+
+    let transcript = strip_ansi(&todoln.run(&["demo"]));
+    let transcript = Regex::new(r"\(due \d{4}-\d{2}-\d{2} \d{2}:\d{2}\)").unwrap().replace_all(&transcript, "(due <date>)");
+
+    assert_eq!(transcript, DEMO_TRANSCRIPT);
+    assert_eq!(todoln.names(), "my own task\n");
+}
+
+const DEMO_TRANSCRIPT: &str = r#"$ todoln list all
+
+Tasks:
+
+  [1] · Write the quarterly report for the finance team, with the updated forecast charts * [1/3]
+    [1.1] ✔ Draft the outline
+    [1.2] · Collect the figures from sales
+    [1.3] · Make the charts
+  [5] · Renew passport
+  [6] ✔ Call the plumber about the kitchen sink
+  [7] · Buy groceries (every sat)
+  [8] · Read "The Pragmatic Programmer"
+
+2/8 done (25%)
+
+$ todoln add "Book a dentist appointment"
+
+Task(s) added successfully: Book a dentist appointment
+
+$ todoln done 3
+
+Task(s) completed successfully: 3
+
+$ todoln find report
+
+  [1] Write the quarterly report for the finance team, with the updated forecast charts *
+
+1 match
+
+$ todoln set 8 priority=low
+
+Field(s) of task 8 set successfully: priority
+
+$ todoln sort priority
+
+Tasks sorted successfully
+
+$ todoln list todo
+
+Tasks todo:
+
+  [1] · Write the quarterly report for the finance team, with the updated forecast charts * [2/3]
+    [1.3] · Make the charts
+  [5] · Renew passport
+  [6] · Read "The Pragmatic Programmer"
+  [8] · Buy groceries (every sat)
+  [9] · Book a dentist appointment
+
+$ todoln due
+
+Due tasks:
+
+  [1] Write the quarterly report for the finance team, with the updated forecast charts * (due <date>)
+  [5] Renew passport (due <date>)
+
+$ todoln undo
+
+Undid sort, previous task order restored
+
+$ todoln list all
+
+Tasks:
+
+  [1] · Write the quarterly report for the finance team, with the updated forecast charts * [2/3]
+    [1.1] ✔ Draft the outline
+    [1.2] ✔ Collect the figures from sales
+    [1.3] · Make the charts
+  [5] · Renew passport
+  [6] ✔ Call the plumber about the kitchen sink
+  [7] · Buy groceries (every sat)
+  [8] · Read "The Pragmatic Programmer"
+  [9] · Book a dentist appointment
+
+3/9 done (33%)
+"#;
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use todoln::commands::{match_edit_lines, parse_edit_lines, parse_template, Task, DEFAULT_RAW_TEMPLATE};
+
+fn task(id: i32, name: &str, done: bool) -> Task {
+    Task { id: Some(id), idx: Some(id), name: name.to_string(), done, ..Default::default() }
+}
+
+fn render(template: &str, task: &Task, indented: bool) -> String {
+    parse_template(template).unwrap().render(task, indented, &HashMap::new())
+}
+
+#[test]
+fn default_template_prints_the_indented_name() {
+    let task = task(1, "buy milk", false);
+    assert_eq!(render(DEFAULT_RAW_TEMPLATE, &task, false), "buy milk");
+    assert_eq!(render(DEFAULT_RAW_TEMPLATE, &task, true), "  buy milk");
+}
+
+#[test]
+fn template_placeholders_and_escapes() {
+    let task = task(3, "walk dog", true);
+    assert_eq!(render(r"{idx}\t{done:x| }\t{name}", &task, false), "3\tx\twalk dog");
+    assert_eq!(render(r"{{{done}}}\n\\", &task, false), "{1}\n\\");
+
+    let mut fields = HashMap::new();
+    fields.insert((3, String::from("priority")), String::from("high"));
+    assert_eq!(parse_template("{priority}/{field:size}").unwrap().render(&task, false, &fields), "high/");
+}
+
+#[test]
+fn template_errors() {
+    assert!(parse_template("{colour}").is_err());
+    assert!(parse_template("{name").is_err());
+    assert!(parse_template("name}").is_err());
+}
+
+fn match_lines(tasks: &[Task], contents: &str) -> Vec<(Option<i32>, String, bool)> {
+    match_edit_lines(tasks, &parse_edit_lines(contents))
+        .into_iter()
+        .map(|edited| (edited.id, edited.name, edited.done))
+        .collect()
+}
+
+fn edit_all_tasks() -> Vec<Task> {
+    vec![task(1, "buy milk", false), task(2, "walk the dog", false), task(3, "call mum", false)]
+}
+
+#[test]
+fn edit_lines_match_moved_tasks() {
+    assert_eq!(
+        match_lines(&edit_all_tasks(), "call mum\nbuy milk\nwalk the dog\n"),
+        [(Some(3), "call mum".into(), false), (Some(1), "buy milk".into(), false), (Some(2), "walk the dog".into(), false)],
+    );
+}
+
+#[test]
+fn edit_lines_match_renames_by_shared_words() {
+    assert_eq!(
+        match_lines(&edit_all_tasks(), "buy oat milk\nwalk the cat\ncall mum\n"),
+        [(Some(1), "buy oat milk".into(), false), (Some(2), "walk the cat".into(), false), (Some(3), "call mum".into(), false)],
+    );
+}
+
+#[test]
+fn edit_lines_add_delete_and_tick_off() {
+    assert_eq!(
+        match_lines(&edit_all_tasks(), "x buy milk\n\ncall mum\nwater plants\n"),
+        [(Some(1), "buy milk".into(), true), (Some(3), "call mum".into(), false), (None, "water plants".into(), false)],
+    );
+}
@@ -0,0 +1,40 @@
+use std::env;
+use std::process;
+
+use rusqlite::Connection;
+use todoln::commands::Task;
+use todoln::config::{set_config, Config};
+use todoln::database::{add_tasks_to_db, get_tasks_from_db, init_db, set_db_path};
+
+/// Points todoln at the default config and looks for the files kept next to the database, like the
+/// session, in a temporary directory, so nothing here reads or writes the real task list
+pub fn init_test_config() {
+    set_config(Config::default());
+    set_db_path(env::temp_dir().join(format!("todoln-test-{}", process::id())).join("todoln.db"));
+}
+
+/// A fresh in-memory task database, after `init_test_config`
+pub fn test_db() -> Connection {
+    init_test_config();
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    init_db(&mut conn).unwrap();
+    conn
+}
+
+pub fn task(name: &str) -> Task {
+    Task { name: name.to_string(), ..Default::default() }
+}
+
+pub fn add(conn: &mut Connection, names: &[&str]) {
+    let tasks: Vec<Task> = names.iter().map(|name| task(name)).collect();
+    add_tasks_to_db(conn, &tasks).unwrap();
+}
+
+/// The tasks in index order as "idx name", with " x" after done ones
+pub fn listing(conn: &Connection) -> Vec<String> {
+    get_tasks_from_db(conn).unwrap()
+        .iter()
+        .map(|task| format!("{} {}{}", task.idx.map_or(String::from("-"), |idx| idx.to_string()), task.name, if task.done { " x" } else { "" }))
+        .collect()
+}
@@ -0,0 +1,37 @@
+use std::collections::BTreeMap;
+
+use todoln::config::expand_alias;
+
+fn aliases(entries: &[(&str, &[&str])]) -> BTreeMap<String, Vec<String>> {
+    entries.iter().map(|(name, args)| (name.to_string(), args.iter().map(|arg| arg.to_string()).collect())).collect()
+}
+
+fn is_builtin(name: &str) -> bool {
+    ["add", "list"].contains(&name)
+}
+
+#[test]
+fn alias_expands_through_other_aliases() {
+    let aliases = aliases(&[("t", &["list", "todo"]), ("w", &["t", "--watch"]), ("x", &["add"])]);
+
+    assert_eq!(expand_alias(&aliases, "t", is_builtin), Ok(Some(vec!["list".into(), "todo".into()])));
+    assert_eq!(expand_alias(&aliases, "w", is_builtin), Ok(Some(vec!["list".into(), "todo".into(), "--watch".into()])));
+    assert_eq!(expand_alias(&aliases, "nope", is_builtin), Ok(None));
+}
+
+#[test]
+fn alias_cannot_replace_a_command() {
+    let aliases = aliases(&[("list", &["add"]), ("l", &["list", "all"])]);
+
+    assert_eq!(expand_alias(&aliases, "list", is_builtin), Ok(None));
+    assert_eq!(expand_alias(&aliases, "l", is_builtin), Ok(Some(vec!["list".into(), "all".into()])));
+}
+
+#[test]
+fn recursive_and_empty_aliases_are_rejected() {
+    let aliases = aliases(&[("a", &["b", "x"]), ("b", &["a"]), ("e", &[]), ("f", &["e", "y"])]);
+
+    assert_eq!(expand_alias(&aliases, "a", is_builtin), Err("refers back to itself: a -> b -> a".into()));
+    assert_eq!(expand_alias(&aliases, "e", is_builtin), Err("expands to nothing".into()));
+    assert_eq!(expand_alias(&aliases, "f", is_builtin), Err("goes through 'e', which expands to nothing".into()));
+}
@@ -0,0 +1,533 @@
+mod common;
+
+use common::{add, init_test_config, listing, task, test_db};
+use std::fs;
+use std::thread;
+use std::time::Duration;
+
+use rusqlite::{Connection, TransactionBehavior};
+use tempfile::TempDir;
+use todoln::commands::{SortKey, Task};
+use todoln::database::{
+    add_dependency_in_db, add_tasks_to_db, apply_task_order, check_db, edit_tasks_in_db, get_subtask_progress_from_db, get_task_fields_from_db, get_task_from_db, get_tasks_from_db,
+    find_tasks_from_db, init_db, insert_tasks_to_db, mark_task_in_db_as_todo, mark_tasks_in_db_as_done, mark_tasks_in_db_as_done_by_ids, remove_tasks_from_db,
+    remove_tasks_by_id_from_db, set_task_fields_in_db, shift_task_indices,
+    renormalize_task_indices, sort_tasks_in_db, undo_last_in_db, undo_sort_in_db, validate_file_path, PathError, ReorderError, SearchMode, SortUndo, TaskSubset,
+};
+
+fn ids(conn: &Connection) -> Vec<i32> {
+    get_tasks_from_db(conn).unwrap().iter().map(|task| task.id.unwrap()).collect()
+}
+
+#[test]
+fn add_appends_in_order() {
+    let mut conn = test_db();
+    add(&mut conn, &["buy milk", "walk dog"]);
+    add(&mut conn, &["call mum"]);
+
+    assert_eq!(listing(&conn), ["1 buy milk", "2 walk dog", "3 call mum"]);
+}
+
+#[test]
+fn insert_shifts_later_tasks() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b", "c"]);
+    insert_tasks_to_db(&mut conn, &2, &[Task { idx: Some(2), ..task("new") }]).unwrap();
+
+    assert_eq!(listing(&conn), ["1 a", "2 new", "3 b", "4 c"]);
+}
+
+#[test]
+fn an_insert_killed_after_the_shift_leaves_the_indices_consistent() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b", "c"]);
+
+    // Killed inside the insert's transaction: the shift is rolled back with it
+    let transaction = conn.transaction().unwrap();
+    shift_task_indices(&transaction, &2, &1).unwrap();
+    drop(transaction);
+    assert_eq!(listing(&conn), ["1 a", "2 b", "3 c"]);
+
+    // A shift that did get committed on its own, as before both steps shared a transaction,
+    // leaves a gap that opening the database closes again
+    shift_task_indices(&conn, &2, &1).unwrap();
+    assert_eq!(listing(&conn), ["1 a", "3 b", "4 c"]);
+    renormalize_task_indices(&mut conn).unwrap();
+    assert_eq!(listing(&conn), ["1 a", "2 b", "3 c"]);
+
+    insert_tasks_to_db(&mut conn, &2, &[Task { idx: Some(2), ..task("new") }]).unwrap();
+    assert_eq!(listing(&conn), ["1 a", "2 new", "3 b", "4 c"]);
+    assert!(check_db(&conn).unwrap().is_empty());
+}
+
+#[test]
+fn inserting_several_tasks_into_a_large_list_keeps_the_order() {
+    let mut conn = test_db();
+    let names: Vec<String> = (1..=5000).map(|i| format!("task {}", i)).collect();
+    add(&mut conn, &names.iter().map(String::as_str).collect::<Vec<_>>());
+
+    let new = |names: &[&str], idx: i32| names.iter().enumerate().map(|(i, name)| Task { idx: Some(idx + i as i32), ..task(name) }).collect::<Vec<_>>();
+    insert_tasks_to_db(&mut conn, &1, &new(&["first", "second"], 1)).unwrap();
+    insert_tasks_to_db(&mut conn, &2501, &new(&["middle"], 2501)).unwrap();
+
+    let expected: Vec<String> = ["first", "second"].iter().map(|name| name.to_string())
+        .chain(names[..2498].iter().cloned())
+        .chain(["middle".to_string()])
+        .chain(names[2498..].iter().cloned())
+        .enumerate()
+        .map(|(i, name)| format!("{} {}", i + 1, name))
+        .collect();
+    assert_eq!(listing(&conn), expected);
+}
+
+#[test]
+fn done_and_todo_round_trip() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b", "c"]);
+
+    mark_tasks_in_db_as_done(&mut conn, &[1, 3], false).unwrap();
+    assert_eq!(listing(&conn), ["1 a x", "2 b", "3 c x"]);
+    assert!(get_task_from_db(&conn, &1).unwrap().unwrap().completed_at.is_some());
+
+    mark_task_in_db_as_todo(&mut conn, &3).unwrap();
+    assert_eq!(listing(&conn), ["1 a x", "2 b", "3 c"]);
+}
+
+#[test]
+fn bulk_done_and_remove_by_id_tolerate_shifted_indices() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b", "c", "d"]);
+    mark_tasks_in_db_as_done(&mut conn, &[2], false).unwrap();
+    let all_ids = ids(&conn);
+
+    let completed = mark_tasks_in_db_as_done_by_ids(&mut conn, &all_ids).unwrap();
+    assert_eq!(completed.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(), ["a", "c", "d"]);
+    assert_eq!(listing(&conn), ["1 a x", "2 b x", "3 c x", "4 d x"]);
+
+    remove_tasks_by_id_from_db(&mut conn, "remove", &[all_ids[0], all_ids[2]], false).unwrap();
+    assert_eq!(listing(&conn), ["1 b x", "2 d x"]);
+}
+
+#[test]
+fn done_reports_already_done_and_unknown_indices() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b", "c"]);
+    mark_tasks_in_db_as_done(&mut conn, &[1], false).unwrap();
+
+    let report = mark_tasks_in_db_as_done(&mut conn, &[1, 2, 2, 9], false).unwrap();
+    assert_eq!((report.completed, report.already_done, report.not_found), (vec![2], vec![1], vec![9]));
+    assert_eq!(listing(&conn), ["1 a x", "2 b x", "3 c"]);
+
+    // Nothing to change leaves no history entry for undo to take back
+    mark_tasks_in_db_as_done(&mut conn, &[1, 2], false).unwrap();
+    assert_eq!(undo_last_in_db(&mut conn).unwrap().unwrap().summary, "b");
+}
+
+#[test]
+fn done_toggles_both_ways() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b"]);
+    mark_tasks_in_db_as_done(&mut conn, &[1], false).unwrap();
+
+    let report = mark_tasks_in_db_as_done(&mut conn, &[1, 2], true).unwrap();
+    assert_eq!((report.completed, report.already_done), (vec![2], vec![1]));
+    assert_eq!(listing(&conn), ["1 a", "2 b x"]);
+    assert!(get_task_from_db(&conn, &1).unwrap().unwrap().completed_at.is_none());
+
+    undo_last_in_db(&mut conn).unwrap();
+    assert_eq!(listing(&conn), ["1 a x", "2 b"]);
+}
+
+#[test]
+fn edit_tasks_renames_as_one_change() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b", "c"]);
+    let tasks = get_tasks_from_db(&conn).unwrap();
+
+    edit_tasks_in_db(&mut conn, &[(tasks[0].clone(), String::from("new a")), (tasks[2].clone(), String::from("new c"))]).unwrap();
+    assert_eq!(listing(&conn), ["1 new a", "2 b", "3 new c"]);
+
+    let entry = undo_last_in_db(&mut conn).unwrap().unwrap();
+    assert_eq!(entry.summary, "a -> new a, c -> new c");
+    assert_eq!(listing(&conn), ["1 a", "2 b", "3 c"]);
+}
+
+#[test]
+fn remove_closes_the_gap() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b", "c", "d"]);
+    remove_tasks_from_db(&mut conn, &[2, 3], false).unwrap();
+
+    assert_eq!(listing(&conn), ["1 a", "2 d"]);
+}
+
+#[test]
+fn remove_keeps_subtasks_unless_asked() {
+    let mut conn = test_db();
+    add(&mut conn, &["parent", "other"]);
+    let parent_id = get_task_from_db(&conn, &1).unwrap().unwrap().id;
+    add_tasks_to_db(&mut conn, &[Task { parent_id, ..task("child") }]).unwrap();
+
+    remove_tasks_from_db(&mut conn, &[1], false).unwrap();
+    assert_eq!(listing(&conn), ["1 child", "2 other"]);
+    assert_eq!(get_task_from_db(&conn, &1).unwrap().unwrap().parent_id, None);
+
+    let mut conn = test_db();
+    add(&mut conn, &["parent", "other"]);
+    let parent_id = get_task_from_db(&conn, &1).unwrap().unwrap().id;
+    add_tasks_to_db(&mut conn, &[Task { parent_id, ..task("child") }]).unwrap();
+
+    remove_tasks_from_db(&mut conn, &[1], true).unwrap();
+    assert_eq!(listing(&conn), ["1 other"]);
+}
+
+#[test]
+fn remove_looks_up_the_index_once_it_holds_the_write_lock() {
+    init_test_config();
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("todoln.db");
+    let mut conn = Connection::open(&path).unwrap();
+    conn.pragma_update(None, "journal_mode", "wal").unwrap();
+    init_db(&mut conn).unwrap();
+    add(&mut conn, &["a", "b", "c"]);
+
+    // Another run reverses the list while `remove 3` waits for the lock
+    let mut other = Connection::open(&path).unwrap();
+    let transaction = other.transaction_with_behavior(TransactionBehavior::Immediate).unwrap();
+    let remover = thread::spawn(move || {
+        let removed = remove_tasks_from_db(&mut conn, &[3], false).unwrap();
+        (conn, removed)
+    });
+    thread::sleep(Duration::from_millis(200));
+    transaction.execute_batch("UPDATE tasks SET idx = idx + 10; UPDATE tasks SET idx = 14 - idx;").unwrap();
+    transaction.commit().unwrap();
+
+    let (conn, removed) = remover.join().unwrap();
+    assert_eq!(removed.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(), ["a"]);
+    assert_eq!(listing(&conn), ["1 c", "2 b"]);
+}
+
+#[test]
+fn sort_puts_todo_before_done_and_undo_restores_the_order() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b", "c", "d"]);
+    mark_tasks_in_db_as_done(&mut conn, &[1, 3], false).unwrap();
+
+    sort_tasks_in_db(&mut conn, SortKey::Done, false).unwrap();
+    assert_eq!(listing(&conn), ["1 b", "2 d", "3 a x", "4 c x"]);
+
+    undo_last_in_db(&mut conn).unwrap();
+    assert_eq!(listing(&conn), ["1 a x", "2 b", "3 c x", "4 d"]);
+}
+
+/// Every column of every task row, in index order
+fn task_rows(conn: &Connection) -> Vec<Vec<rusqlite::types::Value>> {
+    let mut stmt = conn.prepare("SELECT * FROM tasks ORDER BY idx").unwrap();
+    let columns = stmt.column_count();
+    stmt.query_map([], |row| (0..columns).map(|i| row.get(i)).collect())
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+#[test]
+fn undo_sort_restores_the_previous_order_exactly() {
+    let mut conn = test_db();
+    add(&mut conn, &["pear", "Apple", "banana"]);
+    mark_tasks_in_db_as_done(&mut conn, &[2], false).unwrap();
+    assert!(matches!(undo_sort_in_db(&mut conn).unwrap(), SortUndo::NothingToUndo));
+
+    let before = task_rows(&conn);
+    sort_tasks_in_db(&mut conn, SortKey::Name, false).unwrap();
+    assert_eq!(listing(&conn), ["1 Apple x", "2 banana", "3 pear"]);
+    assert_ne!(task_rows(&conn), before);
+
+    assert!(matches!(undo_sort_in_db(&mut conn).unwrap(), SortUndo::Restored));
+    assert_eq!(task_rows(&conn), before);
+    assert!(matches!(undo_sort_in_db(&mut conn).unwrap(), SortUndo::NothingToUndo));
+}
+
+#[test]
+fn undo_sort_refuses_after_the_order_changed() {
+    let mut conn = test_db();
+    add(&mut conn, &["pear", "Apple", "banana"]);
+    sort_tasks_in_db(&mut conn, SortKey::Name, false).unwrap();
+
+    add(&mut conn, &["cherry"]);
+    let after = task_rows(&conn);
+    assert!(matches!(undo_sort_in_db(&mut conn).unwrap(), SortUndo::OrderChanged));
+    assert_eq!(task_rows(&conn), after);
+}
+
+#[test]
+fn sort_by_name_ignores_case() {
+    let mut conn = test_db();
+    add(&mut conn, &["pear", "Apple", "banana"]);
+
+    sort_tasks_in_db(&mut conn, SortKey::Name, false).unwrap();
+    assert_eq!(listing(&conn), ["1 Apple", "2 banana", "3 pear"]);
+
+    sort_tasks_in_db(&mut conn, SortKey::Name, true).unwrap();
+    assert_eq!(listing(&conn), ["1 pear", "2 banana", "3 Apple"]);
+}
+
+#[test]
+fn undo_takes_back_add_and_remove() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b"]);
+    add(&mut conn, &["c"]);
+    remove_tasks_from_db(&mut conn, &[1], false).unwrap();
+
+    let entry = undo_last_in_db(&mut conn).unwrap().unwrap();
+    assert_eq!(entry.operation, "remove");
+    assert_eq!(listing(&conn), ["1 a", "2 b", "3 c"]);
+
+    let entry = undo_last_in_db(&mut conn).unwrap().unwrap();
+    assert_eq!(entry.operation, "add");
+    assert_eq!(listing(&conn), ["1 a", "2 b"]);
+}
+
+#[test]
+fn undo_of_a_remove_brings_back_fields_and_dependencies_in_place() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b", "c", "d", "e"]);
+    let [a, b, _, d, _] = ids(&conn)[..] else { unreachable!() };
+    set_task_fields_in_db(&mut conn, &2, &[("tags".to_string(), "home".to_string())]).unwrap();
+    add_dependency_in_db(&conn, &b, &a).unwrap();
+    add_dependency_in_db(&conn, &d, &b).unwrap();
+
+    remove_tasks_from_db(&mut conn, &[2, 4], false).unwrap();
+    assert_eq!(listing(&conn), ["1 a", "2 c", "3 e"]);
+
+    undo_last_in_db(&mut conn).unwrap();
+    assert_eq!(listing(&conn), ["1 a", "2 b", "3 c", "4 d", "5 e"]);
+    assert_eq!(get_task_fields_from_db(&conn).unwrap(), [(b, "tags".to_string(), "home".to_string())]);
+
+    let mut dependencies: Vec<(i32, i32)> = conn.prepare("SELECT task_id, blocker_id FROM dependencies").unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+        .collect::<Result<_, _>>().unwrap();
+    dependencies.sort_unstable();
+    assert_eq!(dependencies, [(b, a), (d, b)]);
+}
+
+#[test]
+fn adding_a_task_records_no_task_order() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b"]);
+
+    let order: String = conn.query_row("SELECT task_order FROM history ORDER BY id DESC LIMIT 1", [], |row| row.get(0)).unwrap();
+    assert_eq!(order, "");
+}
+
+#[test]
+fn subtask_progress_counts_done_subtasks() {
+    let mut conn = test_db();
+    add(&mut conn, &["trip", "errand"]);
+    let trip_id = get_task_from_db(&conn, &1).unwrap().unwrap().id;
+    let subtasks: Vec<Task> = ["book", "pack", "go"].iter().map(|name| Task { parent_id: trip_id, ..task(name) }).collect();
+    add_tasks_to_db(&mut conn, &subtasks).unwrap();
+
+    assert_eq!(listing(&conn), ["1 trip", "2 book", "3 pack", "4 go", "5 errand"]);
+
+    mark_tasks_in_db_as_done(&mut conn, &[2, 4], false).unwrap();
+    assert_eq!(get_subtask_progress_from_db(&conn).unwrap(), [(trip_id.unwrap(), 2, 3)]);
+}
+
+#[test]
+fn apply_task_order_rejects_orders_that_dont_match_the_tasks() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b", "c"]);
+    let [a, b, c] = ids(&conn)[..] else { panic!() };
+
+    assert!(matches!(apply_task_order(&conn, &[a, b]), Err(ReorderError::Missing(missing)) if missing == [c]));
+    assert!(matches!(apply_task_order(&conn, &[a, b, b, c]), Err(ReorderError::Duplicate(duplicates)) if duplicates == [b]));
+    assert!(matches!(apply_task_order(&conn, &[a, b, c, 99]), Err(ReorderError::Unknown(unknown)) if unknown == [99]));
+    assert_eq!(listing(&conn), ["1 a", "2 b", "3 c"]);
+
+    apply_task_order(&conn, &[c, a, b]).unwrap();
+    assert_eq!(listing(&conn), ["1 c", "2 a", "3 b"]);
+}
+
+#[test]
+fn apply_task_order_accepts_an_empty_order_for_no_tasks() {
+    let conn = test_db();
+    apply_task_order(&conn, &[]).unwrap();
+    assert!(listing(&conn).is_empty());
+}
+
+#[test]
+fn out_of_range_indices_read_as_none_and_are_reported() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b"]);
+    conn.execute("UPDATE tasks SET idx = 0 WHERE name = 'a'", []).unwrap();
+    conn.execute("INSERT INTO tasks (idx, name) VALUES (3000000000, 'huge')", []).unwrap();
+
+    assert!(get_task_from_db(&conn, &0).unwrap().unwrap().idx.is_none());
+    assert_eq!(listing(&conn), ["2 b", "- a", "- huge"]);
+
+    let problems = check_db(&conn).unwrap();
+    let out_of_range: Vec<(&str, i64)> = problems.out_of_range_indices.iter().map(|(task, idx)| (task.name.as_str(), *idx)).collect();
+    assert_eq!(out_of_range, [("a", 0), ("huge", 3000000000)]);
+    assert!(!problems.is_empty());
+
+    // Renumbering gives them indices after the tasks that had one
+    renormalize_task_indices(&mut conn).unwrap();
+    assert_eq!(listing(&conn), ["1 b", "2 a", "3 huge"]);
+    assert!(check_db(&conn).unwrap().is_empty());
+}
+
+#[test]
+fn check_only_reports_copies_that_match_in_every_column() {
+    let mut conn = test_db();
+    add(&mut conn, &["a", "b"]);
+    let copy = |condition: &str| {
+        conn.execute(
+            &format!("INSERT INTO tasks (name, done, note, created_at) SELECT name, done, note, created_at FROM tasks WHERE {}", condition),
+            [],
+        ).unwrap();
+        conn.last_insert_rowid()
+    };
+
+    let exact = copy("name = 'a'");
+    let with_field = copy("name = 'a' AND idx = 1");
+    conn.execute("INSERT INTO task_fields (task_id, key, value) VALUES (?1, 'tags', 'home')", [with_field]).unwrap();
+    let with_note = copy("name = 'b'");
+    conn.execute("UPDATE tasks SET note = 'second one' WHERE id = ?1", [with_note]).unwrap();
+
+    let problems = check_db(&conn).unwrap();
+    let duplicates: Vec<i64> = problems.exact_duplicates.iter().map(|task| task.id.unwrap() as i64).collect();
+    assert_eq!(duplicates, [exact]);
+}
+
+#[test]
+fn adding_a_task_leaves_the_other_rows_alone() {
+    let mut conn = test_db();
+    let names: Vec<String> = (1..=500).map(|i| format!("task {}", i)).collect();
+    add(&mut conn, &names.iter().map(String::as_str).collect::<Vec<_>>());
+    // A gap a reindex would close, so any reindex after the add shows up as updates
+    conn.execute("DELETE FROM tasks WHERE idx = 250", []).unwrap();
+
+    conn.execute_batch(
+        "CREATE TEMP TABLE task_writes (kind TEXT);
+        CREATE TEMP TRIGGER task_inserted AFTER INSERT ON tasks BEGIN INSERT INTO task_writes VALUES ('insert'); END;
+        CREATE TEMP TRIGGER task_updated AFTER UPDATE ON tasks BEGIN INSERT INTO task_writes VALUES ('update'); END;",
+    ).unwrap();
+
+    add(&mut conn, &["one more"]);
+
+    let writes: Vec<String> = conn.prepare("SELECT kind FROM task_writes").unwrap()
+        .query_map([], |row| row.get(0)).unwrap()
+        .collect::<Result<_, _>>().unwrap();
+    assert_eq!(writes, ["insert"]);
+    assert_eq!(get_task_from_db(&conn, &501).unwrap().unwrap().name, "one more");
+}
+
+#[test]
+fn init_db_is_idempotent() {
+    let mut conn = test_db();
+    add(&mut conn, &["a"]);
+    init_db(&mut conn).unwrap();
+
+    assert_eq!(listing(&conn), ["1 a"]);
+}
+
+#[test]
+fn find_searches_only_the_given_subset() {
+    let mut conn = test_db();
+    add(&mut conn, &["Buy milk", "buy eggs", "walk dog"]);
+    mark_tasks_in_db_as_done(&mut conn, &[2], false).unwrap();
+
+    let names = |mode: &SearchMode, subset| -> Vec<String> {
+        find_tasks_from_db(&conn, "buy", mode, subset).unwrap().into_iter().map(|task| task.name).collect()
+    };
+    let mode = SearchMode::Substring { case_sensitive: false };
+
+    assert_eq!(names(&mode, TaskSubset::All), ["Buy milk", "buy eggs"]);
+    assert_eq!(names(&mode, TaskSubset::Todo), ["Buy milk"]);
+    assert_eq!(names(&mode, TaskSubset::Done), ["buy eggs"]);
+    assert_eq!(names(&SearchMode::Substring { case_sensitive: true }, TaskSubset::Todo), Vec::<String>::new());
+    assert_eq!(names(&SearchMode::Regex(regex::Regex::new("^B").unwrap()), TaskSubset::Todo), ["Buy milk"]);
+}
+
+#[test]
+fn validate_file_path_accepts_files_and_creatable_paths() {
+    let dir = TempDir::new().unwrap();
+    let file = dir.path().join("todoln.db");
+
+    assert!(matches!(validate_file_path(&file, false), Err(PathError::NotFound(_))));
+    assert_eq!(validate_file_path(&file, true).unwrap(), file);
+    // Missing parent directories are created with the file
+    assert!(validate_file_path(&dir.path().join("new/todoln.db"), true).is_ok());
+
+    fs::write(&file, "").unwrap();
+    assert_eq!(validate_file_path(&file, false).unwrap(), file);
+}
+
+#[test]
+fn validate_file_path_rejects_a_directory() {
+    let dir = TempDir::new().unwrap();
+
+    assert!(matches!(validate_file_path(dir.path(), true), Err(PathError::IsDirectory(_))));
+    assert!(matches!(validate_file_path(dir.path(), false), Err(PathError::IsDirectory(_))));
+}
+
+#[test]
+fn validate_file_path_rejects_a_parent_that_is_not_a_directory() {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("notes.txt"), "").unwrap();
+
+    let error = validate_file_path(&dir.path().join("notes.txt/todoln.db"), true).unwrap_err();
+    assert!(matches!(error, PathError::ParentNotDirectory(_)));
+    assert!(error.to_string().ends_with("notes.txt/todoln.db': parent is not a directory"), "{}", error);
+}
+
+#[cfg(unix)]
+#[test]
+fn validate_file_path_rejects_an_unwritable_parent() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new().unwrap();
+    let locked = dir.path().join("locked");
+    fs::create_dir(&locked).unwrap();
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o555)).unwrap();
+
+    let result = validate_file_path(&locked.join("todoln.db"), true);
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+    assert!(matches!(result, Err(PathError::ParentNotWritable(_))), "{:?}", result);
+}
+
+#[cfg(unix)]
+#[test]
+fn validate_file_path_follows_symlinks_up_to_a_limit() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new().unwrap();
+    let file = dir.path().join("todoln.db");
+    fs::write(&file, "").unwrap();
+
+    // Each link points at the one before it, the first at the file
+    let link = |i: usize| dir.path().join(format!("link{}", i));
+    symlink(&file, link(1)).unwrap();
+    for i in 2..=41 {
+        symlink(link(i - 1), link(i)).unwrap();
+    }
+
+    assert_eq!(validate_file_path(&link(40), false).unwrap(), file);
+    let error = validate_file_path(&link(41), false).unwrap_err();
+    assert!(matches!(error, PathError::TooManySymlinks(_)));
+    assert!(error.to_string().ends_with("link41': too many symlink levels"), "{}", error);
+}
+
+#[cfg(unix)]
+#[test]
+fn validate_file_path_gives_up_on_a_symlink_loop() {
+    use std::os::unix::fs::symlink;
+
+    let dir = TempDir::new().unwrap();
+    let (a, b) = (dir.path().join("a.db"), dir.path().join("b.db"));
+    symlink(&b, &a).unwrap();
+    symlink(&a, &b).unwrap();
+
+    assert!(matches!(validate_file_path(&a, true), Err(PathError::TooManySymlinks(_))));
+}
@@ -0,0 +1,63 @@
+use crossterm::event::KeyCode;
+use todoln::editor::LineBuffer;
+
+fn edit(text: &str, keys: &[KeyCode]) -> LineBuffer {
+    let mut buffer = LineBuffer::new(text);
+    for key in keys {
+        assert!(buffer.apply_key(*key), "{:?} wasn't handled", key);
+    }
+    buffer
+}
+
+#[test]
+fn line_buffer_starts_with_the_cursor_at_the_end() {
+    let buffer = edit("buy milk", &[KeyCode::Char('!')]);
+    assert_eq!(buffer.text(), "buy milk!");
+    assert_eq!(buffer.cursor_width(), 9);
+}
+
+#[test]
+fn line_buffer_fixes_a_typo_in_the_middle() {
+    let buffer = edit("buy mlik", &[KeyCode::Left, KeyCode::Left, KeyCode::Backspace, KeyCode::Right, KeyCode::Char('l')]);
+    assert_eq!(buffer.text(), "buy milk");
+    assert_eq!(buffer.cursor_width(), 7);
+}
+
+#[test]
+fn line_buffer_home_and_end() {
+    let buffer = edit("milk", &[KeyCode::Home, KeyCode::Char('b'), KeyCode::Char('u'), KeyCode::Char('y'), KeyCode::Char(' '), KeyCode::End, KeyCode::Char('s')]);
+    assert_eq!(buffer.text(), "buy milks");
+
+    let buffer = edit("milk", &[KeyCode::Home, KeyCode::Delete]);
+    assert_eq!(buffer.text(), "ilk");
+    assert_eq!(buffer.cursor_width(), 0);
+}
+
+#[test]
+fn line_buffer_edits_whole_characters_and_graphemes() {
+    // Multibyte characters, a letter with a combining accent and an emoji joined from several
+    let buffer = edit("größe", &[KeyCode::Left, KeyCode::Left, KeyCode::Backspace]);
+    assert_eq!(buffer.text(), "grße");
+
+    let buffer = edit("cafe\u{301}", &[KeyCode::Backspace]);
+    assert_eq!(buffer.text(), "caf");
+
+    let buffer = edit("hi 👩‍💻", &[KeyCode::Left, KeyCode::Delete]);
+    assert_eq!(buffer.text(), "hi ");
+}
+
+#[test]
+fn line_buffer_cursor_width_counts_wide_characters_twice() {
+    let buffer = edit("日本語", &[KeyCode::Left]);
+    assert_eq!(buffer.cursor_width(), 4);
+}
+
+#[test]
+fn line_buffer_stops_at_either_end() {
+    let buffer = edit("ab", &[KeyCode::Right, KeyCode::Delete, KeyCode::Home, KeyCode::Left, KeyCode::Backspace]);
+    assert_eq!(buffer.text(), "ab");
+    assert_eq!(buffer.cursor_width(), 0);
+
+    assert!(!LineBuffer::new("ab").apply_key(KeyCode::Enter));
+    assert!(!LineBuffer::new("ab").apply_key(KeyCode::Esc));
+}
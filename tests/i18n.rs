@@ -0,0 +1,34 @@
+use std::collections::BTreeSet;
+
+use todoln::i18n::{format_message, CATALOGS};
+
+fn placeholders(template: &str) -> BTreeSet<String> {
+    regex::Regex::new(r"\{\d+\}").unwrap().find_iter(template).map(|m| m.as_str().to_string()).collect()
+}
+
+#[test]
+fn format_message_fills_arguments_in_any_order() {
+    assert_eq!(format_message("{0} of {1}", &[&3, &"tasks"]), "3 of tasks");
+    assert_eq!(format_message("{1}: {0}", &[&3, &"tasks"]), "tasks: 3");
+    assert_eq!(format_message("{0} and {0}", &[&"a"]), "a and a");
+}
+
+#[test]
+fn format_message_leaves_other_braces_alone() {
+    assert_eq!(format_message("{name} {2} {", &[&1]), "{name} {2} {");
+    assert_eq!(format_message("{0}}", &[&1]), "1}");
+}
+
+#[test]
+fn translations_match_the_english_messages() {
+    let (_, english) = CATALOGS[0];
+
+    for (language, catalog) in CATALOGS {
+        for (key, template) in *catalog {
+            let Some((_, original)) = english.iter().find(|(k, _)| k == key) else {
+                panic!("'{}' in the {} catalog has no English message", key, language);
+            };
+            assert_eq!(placeholders(template), placeholders(original), "'{}' in the {} catalog", key, language);
+        }
+    }
+}
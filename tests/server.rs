@@ -0,0 +1,237 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+use tempfile::TempDir;
+
+/// `todoln serve` running on a free port against a temporary database, stopped when dropped
+struct Server {
+    child: Child,
+    port: u16,
+    dir: TempDir,
+}
+
+impl Server {
+    fn start(token: Option<&str>) -> Self {
+        let dir = TempDir::new().unwrap();
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let mut command = Command::new(assert_cmd::cargo::cargo_bin("todoln"));
+        command.args(["serve", "--port", &port.to_string()])
+            .env("TODOLN_DB", dir.path().join("todoln.db"))
+            .env("XDG_DATA_HOME", dir.path())
+            .env("XDG_CONFIG_HOME", dir.path())
+            .env_remove("TODOLN_TOKEN")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        if let Some(token) = token {
+            command.args(["--token", token]);
+        }
+
+        let server = Server { child: command.spawn().unwrap(), port, dir };
+        for _ in 0..100 {
+            if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                return server;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("todoln serve didn't start listening");
+    }
+
+    /// Sends one request with a JSON body, as a script would, and returns the status code and the
+    /// JSON body of the response
+    fn request(&self, method: &str, path: &str, headers: &[&str], body: &str) -> (u16, Value) {
+        let mut all_headers = vec!["Host: localhost", "Content-Type: application/json"];
+        all_headers.extend_from_slice(headers);
+        self.request_with_only(method, path, &all_headers, body)
+    }
+
+    /// Sends one request with no headers but `headers` and the content length
+    fn request_with_only(&self, method: &str, path: &str, headers: &[&str], body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        let headers: String = headers.iter().map(|header| format!("{}\r\n", header)).collect();
+        write!(stream, "{} {} HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n{}", method, path, headers, body.len(), body).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+fn names(body: &Value) -> Vec<&str> {
+    body["tasks"].as_array().unwrap().iter().map(|task| task["name"].as_str().unwrap()).collect()
+}
+
+#[test]
+fn serve_adds_lists_changes_and_removes_tasks() {
+    let server = Server::start(None);
+
+    let (status, body) = server.request("POST", "/tasks", &[], r#"{"names": ["b", "a", "c"]}"#);
+    assert_eq!(status, 201);
+    assert_eq!(names(&body), ["b", "a", "c"]);
+
+    let (status, body) = server.request("PATCH", "/tasks/1", &[], r#"{"done": true, "name": "B"}"#);
+    assert_eq!(status, 200);
+    assert_eq!((&body["task"]["name"], &body["task"]["done"]), (&Value::from("B"), &Value::from(true)));
+
+    assert_eq!(names(&server.request("GET", "/tasks?filter=todo", &[], "").1), ["a", "c"]);
+    assert_eq!(names(&server.request("POST", "/sort", &[], r#"{"key": "name"}"#).1), ["a", "B", "c"]);
+
+    assert_eq!(server.request("DELETE", "/tasks/3", &[], "").0, 200);
+    assert_eq!(names(&server.request("GET", "/tasks", &[], "").1), ["a", "B"]);
+
+    assert_eq!(server.request("DELETE", "/tasks/9", &[], "").0, 404);
+    assert_eq!(server.request("GET", "/tasks?filter=soon", &[], "").0, 400);
+    assert_eq!(server.request("GET", "/nowhere", &[], "").0, 404);
+}
+
+#[test]
+fn serve_checks_new_names_like_add() {
+    let server = Server::start(None);
+
+    let (status, body) = server.request("POST", "/tasks", &[], r#"{"name": "  buy   milk "}"#);
+    assert_eq!(status, 201);
+    assert_eq!(names(&body), ["buy milk"]);
+
+    let (status, body) = server.request("POST", "/tasks", &[], r#"{"names": ["walk dog", "Buy Milk"]}"#);
+    assert_eq!(status, 409);
+    assert!(body["error"].as_str().unwrap().contains("'Buy Milk' not saved, a similar task already exists at index 1"), "{}", body);
+    assert_eq!(server.request("POST", "/tasks", &[], r#"{"names": ["walk dog", "Walk  dog"]}"#).0, 409);
+    assert_eq!(names(&server.request("GET", "/tasks", &[], "").1), ["buy milk"]);
+
+    assert_eq!(server.request("POST", "/tasks", &[], r#"{"names": [" ", ""]}"#).0, 400);
+    assert_eq!(server.request("POST", "/tasks", &[], r#"{"names": ["walk dog", 7]}"#).0, 400);
+    assert_eq!(server.request("POST", "/tasks", &[], r#"{"name": "Buy Milk", "force": "yes"}"#).0, 400);
+
+    assert_eq!(server.request("POST", "/tasks", &[], r#"{"name": "Buy Milk", "force": true}"#).0, 201);
+    assert_eq!(names(&server.request("GET", "/tasks", &[], "").1), ["buy milk", "Buy Milk"]);
+}
+
+#[test]
+fn serve_checks_renamed_names_like_modify() {
+    let server = Server::start(None);
+    assert_eq!(server.request("POST", "/tasks", &[], r#"{"names": ["buy milk", "walk dog"]}"#).0, 201);
+
+    let (status, body) = server.request("PATCH", "/tasks/2", &[], r#"{"name": "Buy  Milk", "done": true}"#);
+    assert_eq!(status, 409);
+    assert!(body["error"].as_str().unwrap().contains("'Buy Milk' not saved, a similar task already exists at index 1"), "{}", body);
+    assert_eq!(server.request("PATCH", "/tasks/2", &[], r#"{"name": "Buy Milk", "force": 1}"#).0, 400);
+    assert_eq!(server.request("GET", "/tasks", &[], "").1["tasks"][1]["done"], false);
+
+    let (status, body) = server.request("PATCH", "/tasks/2", &[], r#"{"name": "  Walk   Dog "}"#);
+    assert_eq!(status, 200);
+    assert_eq!(body["task"]["name"], "Walk Dog");
+
+    assert_eq!(server.request("PATCH", "/tasks/2", &[], r#"{"name": "Buy Milk", "force": true}"#).0, 200);
+    assert_eq!(names(&server.request("GET", "/tasks", &[], "").1), ["buy milk", "Buy Milk"]);
+}
+
+#[test]
+fn serve_turns_away_requests_a_web_page_could_send() {
+    let server = Server::start(None);
+    let host = format!("Host: 127.0.0.1:{}", server.port);
+
+    // A cross-site form or `fetch` without a preflight can only send these content types
+    for content_type in ["Content-Type: text/plain", "Content-Type: application/x-www-form-urlencoded", "Content-Type: multipart/form-data; boundary=x"] {
+        assert_eq!(server.request_with_only("POST", "/tasks", &[&host, content_type], r#"{"name": "a"}"#).0, 415);
+    }
+    assert_eq!(server.request_with_only("POST", "/sort", &[&host], "").0, 415);
+    assert_eq!(server.request_with_only("PATCH", "/tasks/1", &[&host, "Content-Type: text/plain"], r#"{"done": true}"#).0, 415);
+    assert_eq!(server.request_with_only("OPTIONS", "/tasks", &[&host], "").0, 405);
+
+    // A domain name pointed at 127.0.0.1 by a web page, or the right host on another port
+    for other_host in ["Host: evil.example", "Host: evil.example:80", "Host: localhost:1"] {
+        assert_eq!(server.request_with_only("GET", "/tasks", &[other_host], "").0, 421);
+        assert_eq!(server.request_with_only("POST", "/tasks", &[other_host, "Content-Type: application/json"], r#"{"name": "a"}"#).0, 421);
+    }
+
+    assert_eq!(names(&server.request("GET", "/tasks", &[], "").1), Vec::<&str>::new());
+    for own_host in [host.clone(), String::from("Host: localhost"), format!("Host: [::1]:{}", server.port)] {
+        assert_eq!(server.request_with_only("POST", "/tasks", &[&own_host, "Content-Type: application/json; charset=utf-8"], r#"{"name": "a", "force": true}"#).0, 201);
+    }
+    assert_eq!(names(&server.request("GET", "/tasks", &[], "").1), ["a", "a", "a"]);
+}
+
+#[test]
+fn serve_with_a_token_only_lets_reads_through_without_it() {
+    let server = Server::start(Some("s3cret"));
+
+    assert_eq!(server.request("POST", "/tasks", &[], r#"{"name": "a"}"#).0, 401);
+    assert_eq!(server.request("POST", "/tasks", &["Authorization: Bearer wrong"], r#"{"name": "a"}"#).0, 401);
+    assert_eq!(server.request("POST", "/tasks", &["Authorization: Bearer s3cret"], r#"{"name": "a"}"#).0, 201);
+    assert_eq!(names(&server.request("GET", "/tasks", &[], "").1), ["a"]);
+}
+
+#[test]
+fn serve_answers_database_errors_with_a_500_and_keeps_serving() {
+    let server = Server::start(None);
+    assert_eq!(server.request("POST", "/tasks", &[], r#"{"name": "a"}"#).0, 201);
+
+    let conn = rusqlite::Connection::open(server.dir.path().join("todoln.db")).unwrap();
+    conn.execute_batch("CREATE TRIGGER refuse BEFORE INSERT ON tasks BEGIN SELECT RAISE(ABORT, 'no new tasks'); END;").unwrap();
+
+    let (status, body) = server.request("POST", "/tasks", &[], r#"{"name": "b"}"#);
+    assert_eq!(status, 500);
+    assert!(body["error"].as_str().unwrap().contains("no new tasks"), "{}", body);
+
+    conn.execute_batch("DROP TRIGGER refuse;").unwrap();
+    assert_eq!(server.request("POST", "/tasks", &[], r#"{"name": "b"}"#).0, 201);
+    assert_eq!(names(&server.request("GET", "/tasks", &[], "").1), ["a", "b"]);
+}
+
+/// Reads what the server answered on `stream`, down to its status code
+fn status_of(mut stream: TcpStream) -> u16 {
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok();
+    response.split_whitespace().nth(1).unwrap().parse().unwrap()
+}
+
+#[test]
+fn serve_turns_away_a_request_head_that_never_ends() {
+    let server = Server::start(None);
+
+    // Exactly as long as the server reads before giving up, so nothing unread is left to reset
+    // the connection before the answer arrives
+    let head = format!("GET /tasks HTTP/1.1\r\nX-Padding: {}", "x".repeat(16 * 1024));
+    let mut stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    stream.write_all(&head.as_bytes()[..16 * 1024]).unwrap();
+    assert_eq!(status_of(stream), 431);
+
+    assert_eq!(server.request("GET", "/tasks", &[], "").0, 200);
+}
+
+#[test]
+fn serve_gives_a_trickling_client_a_deadline_for_the_whole_request() {
+    let server = Server::start(None);
+    let started = Instant::now();
+
+    // A byte every second keeps each read well under the timeout, but not the request as a whole
+    let mut stream = TcpStream::connect(("127.0.0.1", server.port)).unwrap();
+    let trickle = thread::spawn(move || {
+        for byte in "GET /tasks HTTP/1.1\r\nX-Slow: ".bytes().cycle().take(30) {
+            if stream.write_all(&[byte]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+        stream
+    });
+
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(server.request("GET", "/tasks", &[], "").0, 200);
+    assert!(started.elapsed() < Duration::from_secs(15), "waited {:?}", started.elapsed());
+
+    assert_eq!(status_of(trickle.join().unwrap()), 408);
+}
@@ -0,0 +1,110 @@
+use chrono::NaiveDate;
+use todoln::utils::{format_age, highlight_matches, join_comment, local_midnight, normalize_name, parse_period, split_comment, truncate_text};
+
+fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+fn period(start: NaiveDate, end: NaiveDate) -> Option<(chrono::DateTime<chrono::Utc>, chrono::DateTime<chrono::Utc>)> {
+    Some((local_midnight(start).unwrap(), local_midnight(end).unwrap()))
+}
+
+#[test]
+fn parse_period_reads_iso_weeks() {
+    assert_eq!(parse_period("2020-W53"), period(day(2020, 12, 28), day(2021, 1, 4)));
+    assert_eq!(parse_period("2021-W01"), period(day(2021, 1, 4), day(2021, 1, 11)));
+    assert_eq!(parse_period("2023-W53"), None);
+}
+
+#[test]
+fn parse_period_reads_months_and_days() {
+    assert_eq!(parse_period("2024-02"), period(day(2024, 2, 1), day(2024, 3, 1)));
+    assert_eq!(parse_period("2024-06-03"), period(day(2024, 6, 3), day(2024, 6, 4)));
+    assert_eq!(parse_period("2024-13"), None);
+}
+
+#[test]
+fn parse_period_reads_ranges() {
+    assert_eq!(parse_period("2024-06-01..2024-06-15"), period(day(2024, 6, 1), day(2024, 6, 16)));
+    assert_eq!(parse_period("2024-05..2024-W26"), period(day(2024, 5, 1), day(2024, 7, 1)));
+    assert_eq!(parse_period("2024-06-15..2024-06-01"), None);
+}
+
+#[test]
+fn truncate_text_counts_columns() {
+    assert_eq!(truncate_text("buy milk", 8), "buy milk");
+    assert_eq!(truncate_text("buy milk", 6), "buy m…");
+    // Wide characters take two columns and are never cut in half
+    assert_eq!(truncate_text("日本語のタスク", 6), "日本…");
+    assert_eq!(truncate_text("🎉🎉🎉", 4), "🎉…");
+    assert_eq!(truncate_text("abc", 0), "");
+}
+
+#[test]
+fn normalize_name_folds_case_without_turkish_rules() {
+    assert_eq!(normalize_name("Istanbul"), "istanbul");
+    // Default folding keeps the dotless ı apart from i, and the dotted İ keeps its dot as a combining mark
+    assert_eq!(normalize_name("ıstanbul"), "ıstanbul");
+    assert_ne!(normalize_name("ıstanbul"), normalize_name("istanbul"));
+    assert_eq!(normalize_name("İstanbul"), "i\u{307}stanbul");
+    assert_ne!(normalize_name("İstanbul"), normalize_name("Istanbul"));
+}
+
+#[test]
+fn normalize_name_folds_sharp_s_to_ss() {
+    assert_eq!(normalize_name("Straße"), "strasse");
+    assert_eq!(normalize_name("STRASSE"), normalize_name("straße"));
+    assert_eq!(normalize_name("ẞ"), "ss");
+}
+
+#[test]
+fn normalize_name_trims_and_collapses_whitespace() {
+    assert_eq!(normalize_name("  Buy \t Milk\n"), "buy milk");
+    assert_eq!(normalize_name("buy\u{a0}\u{a0}milk"), "buy milk");
+    assert_eq!(normalize_name("   "), "");
+}
+
+#[test]
+fn format_age_uses_the_largest_whole_unit() {
+    assert_eq!(format_age(-5), "0m");
+    assert_eq!(format_age(59 * 60), "59m");
+    assert_eq!(format_age(23 * 3600), "23h");
+    assert_eq!(format_age(13 * 86400), "13d");
+    assert_eq!(format_age(20 * 86400), "2w");
+    assert_eq!(format_age(800 * 86400), "2y");
+}
+
+#[test]
+fn highlight_matches_marks_every_occurrence_only_with_color() {
+    assert_eq!(highlight_matches("a ba ca", false, &[(0, 1), (3, 4), (6, 7)], true), "\x1b[1ma\x1b[0m b\x1b[1ma\x1b[0m c\x1b[1ma\x1b[0m");
+    assert_eq!(highlight_matches("a ba ca", false, &[(0, 1), (3, 4), (6, 7)], false), "a ba ca");
+}
+
+#[test]
+fn split_comment_ignores_delimiters_inside_quotes() {
+    assert_eq!(split_comment("buy milk # 2 litres", "#"), ("buy milk", Some("2 litres")));
+    assert_eq!(split_comment(r##"print "#1" labels # for the shelf"##, "#"), (r##"print "#1" labels"##, Some("for the shelf")));
+    assert_eq!(split_comment(r##"read "a # b""##, "#"), (r##"read "a # b""##, None));
+    assert_eq!(split_comment(r##"fix "x # y" # later # maybe"##, "#"), (r##"fix "x # y""##, Some("later # maybe")));
+}
+
+#[test]
+fn split_comment_treats_an_unclosed_quote_as_running_to_the_end() {
+    assert_eq!(split_comment(r##"say "hi # there"##, "#"), (r##"say "hi # there"##, None));
+    assert_eq!(split_comment(r##"a "b" "c # d"##, "#"), (r##"a "b" "c # d"##, None));
+}
+
+#[test]
+fn split_comment_with_longer_and_empty_delimiters() {
+    assert_eq!(split_comment(r##"open "http://x" // soon"##, "//"), (r##"open "http://x""##, Some("soon")));
+    assert_eq!(split_comment("a / b // c", "//"), ("a / b", Some("c")));
+    assert_eq!(split_comment("a # b", ""), ("a # b", None));
+    assert_eq!(split_comment("nothing to see #", "#"), ("nothing to see", Some("")));
+}
+
+#[test]
+fn join_comment_puts_a_split_name_back_together() {
+    let (name, comment) = split_comment(r##"print "#1"   #  for the shelf "##, "#");
+    assert_eq!(join_comment(name, comment, "#"), r##"print "#1" # for the shelf"##);
+    assert_eq!(join_comment("tidy", Some(""), "#"), "tidy");
+}